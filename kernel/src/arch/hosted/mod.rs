@@ -543,7 +543,32 @@ pub fn idle() -> bool {
         }
     }
 
-    while let Ok(msg) = message_receiver.recv() {
+    // The clock that syscalls with a timeout are measured against
+    let start_time = std::time::Instant::now();
+    let elapsed_ms = || start_time.elapsed().as_millis() as u64;
+
+    loop {
+        // Wait for the next message, but no longer than the earliest timeout.
+        let msg = match SystemServices::with(|ss| ss.next_deadline()) {
+            Some(deadline) => {
+                let wait = deadline.saturating_sub(elapsed_ms());
+                match message_receiver.recv_timeout(std::time::Duration::from_millis(wait)) {
+                    Ok(msg) => msg,
+                    Err(RecvTimeoutError::Timeout) => {
+                        // Wake the threads from the kernel's own process, as the
+                        // last process to make a syscall may be gone.
+                        crate::arch::process::set_current_pid(PID::new(1).unwrap());
+                        SystemServices::with_mut(|ss| ss.advance_clock(elapsed_ms()));
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            None => match message_receiver.recv() {
+                Ok(msg) => msg,
+                Err(RecvError) => break,
+            },
+        };
         match msg {
             ThreadMessage::NewConnection(conn, access_key) => {
                 // The new process should already have a PID registered. Convert its access key
@@ -579,6 +604,7 @@ pub fn idle() -> bool {
                 // println!("KERNEL({}): Received syscall {:?}", pid, call);
                 crate::arch::process::set_current_pid(pid);
                 // println!("KERNEL({}): Now running as the new process", pid);
+                SystemServices::with_mut(|ss| ss.advance_clock(elapsed_ms()));
 
                 // If the call being made is to terminate the current process, we need to know
                 // because we won't be able to send a response.
@@ -2,6 +2,9 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub const MAX_THREAD: TID = 31;
+/// One more than the highest thread ID, for tables indexed by TID. Hosted threads are
+/// numbered from 1, so this is one more than on hardware.
+pub const TID_COUNT: usize = MAX_THREAD + 2;
 use crate::services::ProcessInner;
 use core::cell::RefCell;
 use std::io::Write;
@@ -273,6 +276,22 @@ impl Process {
         })
     }
 
+    /// Returns a bitmask of the threads that have been set up.
+    pub fn thread_mask(&self) -> usize {
+        PROCESS_TABLE.with(|pt| {
+            let process_table = pt.borrow();
+            let current_pid_idx = process_table.current.get() as usize - 1;
+            let process = &process_table.table[current_pid_idx].as_ref().unwrap();
+            let mut mask = 0;
+            for (index, thread) in process.threads.iter().enumerate() {
+                if thread.allocated {
+                    mask |= 1 << (index + 1);
+                }
+            }
+            mask
+        })
+    }
+
     pub fn thread_exists(&self, _tid: TID) -> bool {
         false
     }
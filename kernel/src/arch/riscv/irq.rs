@@ -8,6 +8,7 @@ use crate::arch::process::{Process as ArchProcess, RETURN_FROM_EXCEPTION_HANDLER
 use crate::arch::process::{Thread, EXIT_THREAD, RETURN_FROM_ISR};
 use crate::services::SystemServices;
 use riscv::register::{scause, sepc, sstatus, stval, vexriscv::sim, vexriscv::sip};
+use utralib::generated::*;
 use xous_kernel::{SysCall, PID, TID};
 
 extern "Rust" {
@@ -16,6 +17,10 @@ extern "Rust" {
 
 // use RAM-based backing so this variable is automatically saved on suspend
 static mut SIM_BACKING: usize = 0;
+
+/// Milliseconds the system has been running for, counted in preemption ticks
+static mut ELAPSED_MS: u64 = 0;
+
 /// Disable external interrupts
 pub fn disable_all_irqs() {
     unsafe { SIM_BACKING = sim::read() };
@@ -51,6 +56,12 @@ pub unsafe fn take_isr_return_pair() -> Option<(PID, TID)> {
 
 /// Convert a RISC-V `Exception` into a Xous exception argument list.
 fn generate_exception_args(ex: &RiscvException) -> Option<[usize; 3]> {
+    exception_args(ex, crate::arch::mem::is_guard_page)
+}
+
+/// Convert a RISC-V `Exception` into a Xous exception argument list, using
+/// `is_guard_page` to tell stack overflows apart from other page faults.
+fn exception_args(ex: &RiscvException, is_guard_page: fn(usize) -> bool) -> Option<[usize; 3]> {
     match *ex {
         RiscvException::InstructionAddressMisaligned(epc, addr) => Some([
             xous_kernel::ExceptionType::InstructionAddressMisaligned as usize,
@@ -92,6 +103,15 @@ fn generate_exception_args(ex: &RiscvException) -> Option<[usize; 3]> {
             epc,
             addr,
         ]),
+        RiscvException::LoadPageFault(epc, addr) | RiscvException::StorePageFault(epc, addr)
+            if is_guard_page(addr) =>
+        {
+            Some([
+                xous_kernel::ExceptionType::StackOverflow as usize,
+                epc,
+                addr,
+            ])
+        }
         RiscvException::LoadPageFault(epc, addr) => Some([
             xous_kernel::ExceptionType::LoadPageFault as usize,
             epc,
@@ -270,7 +290,26 @@ pub extern "C" fn trap_handler(
         // This exception is not due to something we're aware of. In this case,
         // determine if there is an exception handler in this particular program
         // and call that handler if so.
-        if let Some(args) = generate_exception_args(&ex) {
+        let handler_args = generate_exception_args(&ex);
+        let is_stack_overflow = matches!(
+            handler_args,
+            Some([kind, _, _]) if kind == xous_kernel::ExceptionType::StackOverflow as usize
+        );
+        // Report overflows even if the process handles them, as the handler
+        // may well just exit the thread or the process.
+        if is_stack_overflow {
+            let tid = ArchProcess::with_current(|process| process.current_tid());
+            SystemServices::with(|ss| {
+                println!(
+                    "Stack overflow in PID {} ({}) thread {}: {}",
+                    pid,
+                    ss.process_name(pid).unwrap_or(""),
+                    tid,
+                    ex
+                )
+            });
+        }
+        if let Some(args) = handler_args {
             klog!("Generated exception args -- invoking handler");
             if let Some(handler) = SystemServices::with_mut(|ss| ss.begin_exception_handler(pid)) {
                 klog!("Exception handler for process exists ({:x?})", handler);
@@ -305,14 +344,19 @@ pub extern "C" fn trap_handler(
         // immediately obvious that we screwed up. On harware this will trigger
         // a watchdog reset.
         println!(
-            "{}: CPU Exception on PID {}: {}",
+            "{}: CPU Exception on PID {}: {}{}",
             if is_kernel_failure {
                 "!!! KERNEL FAILURE !!!"
             } else {
                 "PROGRAM HALT"
             },
             pid,
-            ex
+            ex,
+            if is_stack_overflow {
+                " (stack overflow)"
+            } else {
+                ""
+            }
         );
         ArchProcess::with_current(|process| {
             println!("Current thread {}:", process.current_tid());
@@ -354,6 +398,14 @@ pub extern "C" fn trap_handler(
                 let tid = crate::arch::process::current_tid();
                 PREVIOUS_PAIR = Some((pid, tid));
             }
+
+            // The preemption timer is the only clock the kernel has, so use it
+            // to expire syscalls that have a timeout. Its handler is in userspace,
+            // which acknowledges it, so this sees each tick once.
+            if irqs_pending & (1 << utra::timer0::TIMER0_IRQ) != 0 {
+                ELAPSED_MS += xous_kernel::BASE_QUANTA_MS as u64;
+                SystemServices::with_mut(|ss| ss.advance_clock(ELAPSED_MS));
+            }
         }
         crate::irq::handle(irqs_pending).expect("Couldn't handle IRQ");
         ArchProcess::with_current_mut(|process| {
@@ -361,3 +413,58 @@ pub extern "C" fn trap_handler(
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUARD_PAGE: usize = 0x4000_0000;
+
+    fn guard_page_at_0x4000_0000(addr: usize) -> bool {
+        addr & !0xfff == GUARD_PAGE
+    }
+
+    #[test]
+    fn guard_page_hit_is_stack_overflow() {
+        let stack_overflow = xous_kernel::ExceptionType::StackOverflow as usize;
+        assert_eq!(
+            exception_args(
+                &RiscvException::LoadPageFault(0x2000_0100, GUARD_PAGE + 0xffc),
+                guard_page_at_0x4000_0000
+            ),
+            Some([stack_overflow, 0x2000_0100, GUARD_PAGE + 0xffc])
+        );
+        assert_eq!(
+            exception_args(
+                &RiscvException::StorePageFault(0x2000_0100, GUARD_PAGE),
+                guard_page_at_0x4000_0000
+            ),
+            Some([stack_overflow, 0x2000_0100, GUARD_PAGE])
+        );
+
+        // Faults anywhere else, and instruction fetches from a guard page, are
+        // ordinary page faults
+        assert_eq!(
+            exception_args(
+                &RiscvException::StorePageFault(0x2000_0100, GUARD_PAGE + 0x1000),
+                guard_page_at_0x4000_0000
+            ),
+            Some([
+                xous_kernel::ExceptionType::StorePageFault as usize,
+                0x2000_0100,
+                GUARD_PAGE + 0x1000
+            ])
+        );
+        assert_eq!(
+            exception_args(
+                &RiscvException::InstructionPageFault(GUARD_PAGE, GUARD_PAGE),
+                guard_page_at_0x4000_0000
+            ),
+            Some([
+                xous_kernel::ExceptionType::InstructionPageFault as usize,
+                GUARD_PAGE,
+                GUARD_PAGE
+            ])
+        );
+    }
+}
@@ -26,6 +26,9 @@ pub const FLG_U: usize = 0x10;
 pub const FLG_A: usize = 0x40;
 pub const FLG_D: usize = 0x80;
 
+/// Pagetable entry for a guard page, see `make_guard_page()`
+const GUARD_PAGE_FLAGS: usize = FLG_U;
+
 extern "C" {
     fn flush_mmu();
 }
@@ -888,8 +891,9 @@ pub fn ensure_page_exists_inner(address: usize) -> Result<usize, xous_kernel::Er
 
     // If the flags are nonzero, but the "Valid" bit is not 1 and
     // the page isn't shared, then this is a reserved page. Allocate
-    // a real page to back it and resume execution.
-    if flags == 0 || flags & MMUFlags::S.bits() != 0 {
+    // a real page to back it and resume execution. Guard pages must
+    // never be backed.
+    if flags == 0 || flags & MMUFlags::S.bits() != 0 || flags == GUARD_PAGE_FLAGS {
         return Err(xous_kernel::Error::BadAddress);
     }
 
@@ -931,6 +935,28 @@ pub fn address_available(virt: usize) -> bool {
     }
 }
 
+/// Turn the page at `virt` into a guard page: an address that is never backed by
+/// memory, so any access to it faults. The page must not be backed by memory
+/// already, and its pagetable must exist.
+///
+/// A guard page has only the `USER` bit set, which no other unmapped page has.
+/// This keeps it from being handed out by `find_virtual_address()`, and tells
+/// `ensure_page_exists_inner()` not to back it with a real page.
+pub fn make_guard_page(virt: usize) -> Result<(), xous_kernel::Error> {
+    let entry = pagetable_entry(virt & !0xfff)?;
+    if *entry & (MMUFlags::VALID | MMUFlags::S).bits() != 0 {
+        return Err(xous_kernel::Error::MemoryInUse);
+    }
+    *entry = GUARD_PAGE_FLAGS;
+    unsafe { flush_mmu() };
+    Ok(())
+}
+
+/// Determine whether a virtual address falls within a guard page
+pub fn is_guard_page(virt: usize) -> bool {
+    pagetable_entry(virt & !0xfff).map_or(false, |entry| *entry == GUARD_PAGE_FLAGS)
+}
+
 /// Get the `MemoryFlags` for the requested virtual address. The address must
 /// be valid and page-aligned, and must not be Shared.
 ///
@@ -4,6 +4,8 @@
 use core::mem;
 static mut PROCESS: *mut ProcessImpl = 0xff80_1000 as *mut ProcessImpl;
 pub const MAX_THREAD: TID = 31;
+/// One more than the highest thread ID, for tables indexed by TID
+pub const TID_COUNT: usize = MAX_THREAD + 1;
 pub const EXCEPTION_TID: TID = 1;
 pub const INITIAL_TID: TID = 2;
 pub const IRQ_TID: TID = 0;
@@ -14,6 +16,9 @@ use xous_kernel::{ProcessInit, ProcessStartup, ThreadInit, PID, TID};
 
 // use crate::args::KernelArguments;
 pub const DEFAULT_STACK_SIZE: usize = 131072;
+/// Size of the guard area at the bottom of each thread's stack. It is never
+/// mapped, so overflowing the stack faults rather than running into other memory.
+pub const STACK_GUARD_SIZE: usize = PAGE_SIZE;
 pub const MAX_PROCESS_COUNT: usize = 64;
 // pub use crate::arch::mem::DEFAULT_STACK_TOP;
 
@@ -244,6 +249,13 @@ impl Process {
         }
     }
 
+    /// Returns a bitmask of the threads that exist, not counting the ISR thread.
+    pub fn thread_mask(&self) -> usize {
+        let mut mask = 0;
+        self.for_each_thread_mut(|tid, _thread| mask |= 1 << tid);
+        mask
+    }
+
     pub fn find_free_thread(&self) -> Option<TID> {
         let process = unsafe { &mut *PROCESS };
         let start_tid = process.last_tid_allocated as usize;
@@ -388,6 +400,7 @@ impl Process {
         if sp <= 16 {
             return Err(xous_kernel::Error::BadAddress);
         }
+        guard_stack(&setup.stack);
         crate::arch::syscall::invoke(
             thread,
             pid == 1,
@@ -607,3 +620,23 @@ pub fn current_pid() -> PID {
 pub fn current_tid() -> TID {
     unsafe { ((*PROCESS).hardware_thread) - 1 }
 }
+
+/// Turn the bottom `STACK_GUARD_SIZE` bytes of a new thread's stack into guard
+/// pages, so that a stack overflow faults instead of running into whatever is
+/// mapped below it. Stacks that are too small or not page-aligned are left as
+/// they are, as are pages that are already backed by memory.
+fn guard_stack(stack: &xous_kernel::MemoryRange) {
+    let bottom = stack.as_ptr() as usize;
+    if bottom & (PAGE_SIZE - 1) != 0 || stack.len() <= STACK_GUARD_SIZE {
+        return;
+    }
+    for page in (bottom..bottom + STACK_GUARD_SIZE).step_by(PAGE_SIZE) {
+        if let Err(_e) = crate::arch::mem::make_guard_page(page) {
+            klog!(
+                "couldn't place a stack guard page at {:08x}: {:?}",
+                page,
+                _e
+            );
+        }
+    }
+}
@@ -41,6 +41,10 @@ impl Target for XousTarget {
     fn breakpoints(&mut self) -> Option<gdbstub::target::ext::breakpoints::BreakpointsOps<Self>> {
         Some(self)
     }
+    #[cfg(feature = "ipc-trace")]
+    fn monitor_cmd(&mut self) -> Option<gdbstub::target::ext::monitor_cmd::MonitorCmdOps<Self>> {
+        Some(self)
+    }
 }
 
 impl MultiThreadOps for XousTarget {
@@ -262,6 +266,29 @@ impl gdbstub::target::ext::breakpoints::HwBreakpoint for XousTarget {
     }
 }
 
+#[cfg(feature = "ipc-trace")]
+impl gdbstub::target::ext::monitor_cmd::MonitorCmd for XousTarget {
+    fn handle_monitor_cmd(
+        &mut self,
+        cmd: &[u8],
+        mut out: gdbstub::target::ext::monitor_cmd::ConsoleOutput<'_>,
+    ) -> Result<(), Self::Error> {
+        match cmd {
+            b"ipctrace" => crate::services::SystemServices::with(|system_services| {
+                let (oldest, next) = system_services.ipc_trace.range();
+                gdbstub::outputln!(out, "IPC events {} to {}:", oldest, next);
+                for seq in oldest..next {
+                    if let Some(event) = system_services.ipc_trace.get(seq) {
+                        gdbstub::outputln!(out, "{:6} {}", seq, event);
+                    }
+                }
+            }),
+            _ => gdbstub::outputln!(out, "unrecognized command -- try `monitor ipctrace`"),
+        }
+        Ok(())
+    }
+}
+
 pub fn handle(b: u8) -> bool {
     if let Some(XousDebugState {
         mut target,
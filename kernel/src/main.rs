@@ -24,6 +24,8 @@ mod mem;
 mod server;
 mod services;
 mod syscall;
+#[cfg(feature = "ipc-trace")]
+mod trace;
 
 use services::SystemServices;
 use xous_kernel::*;
@@ -105,7 +107,8 @@ pub unsafe extern "C" fn init(arg_offset: *const u32, init_offset: *const u32, r
 }
 
 /// Loop through the SystemServices list to determine the next PID to be run.
-/// If no process is ready, return `None`.
+/// The process with the most urgent ready thread wins, and processes of equal
+/// priority are taken round-robin. If no process is ready, return `None`.
 fn next_pid_to_run(last_pid: Option<PID>) -> Option<PID> {
     // PIDs are 1-indexed but arrays are 0-indexed.  By not subtracting
     // 1 from the PID when we use it as an array index, we automatically
@@ -113,33 +116,22 @@ fn next_pid_to_run(last_pid: Option<PID>) -> Option<PID> {
     let current_pid = last_pid.unwrap_or(unsafe { PID::new_unchecked(1) }).get() as usize;
 
     SystemServices::with(|system_services| {
-        for test_idx in current_pid..system_services.processes.len() {
-            if system_services.processes[test_idx].ppid.get() == 1 {
-                // print!("PID {} is owned by PID1... ", test_idx + 1);
-                if system_services.processes[test_idx].runnable() {
-                    // println!(" and is runnable");
-                    return match pid_from_usize(test_idx + 1) {
-                        Ok(x) => Some(x),
-                        Err(_) => None,
-                    };
-                }
-                // println!(" and is NOT RUNNABLE");
+        let process_count = system_services.processes.len();
+        let mut best: Option<(usize, xous_kernel::ThreadPriority)> = None;
+        for offset in 0..process_count {
+            let test_idx = (current_pid + offset) % process_count;
+            let process = &system_services.processes[test_idx];
+            if process.ppid.get() != 1 {
+                continue;
             }
-        }
-        for test_idx in 0..current_pid {
-            if system_services.processes[test_idx].ppid.get() == 1 {
-                // print!("PID {} is owned by PID1... ", test_idx + 1);
-                if system_services.processes[test_idx].runnable() {
-                    // println!(" and is runnable");
-                    return match pid_from_usize(test_idx + 1) {
-                        Ok(x) => Some(x),
-                        Err(_) => None,
-                    };
+            // print!("PID {} is owned by PID1... ", test_idx + 1);
+            if let Some(priority) = process.ready_priority() {
+                if best.map(|(_, p)| priority > p).unwrap_or(true) {
+                    best = Some((test_idx, priority));
                 }
-                // println!(" and is NOT RUNNABLE");
             }
         }
-        None
+        best.and_then(|(test_idx, _)| pid_from_usize(test_idx + 1).ok())
     })
 }
 
@@ -4,7 +4,9 @@
 pub use crate::arch::process::Thread;
 use crate::{mem::MemoryManager, services::SystemServices};
 use core::mem;
-use xous_kernel::{MemoryAddress, MemoryRange, MemorySize, Message, MessageSender, PID, SID, TID};
+use xous_kernel::{
+    MemoryAddress, MemoryRange, MemorySize, Message, MessageSender, ThreadPriority, PID, SID, TID,
+};
 
 /// A pointer to resolve a server ID to a particular process
 #[derive(PartialEq, Debug)]
@@ -45,7 +47,7 @@ pub struct SenderID {
     /// The index into the queue array
     pub idx: usize,
     /// The process ID that sent this message
-    pid: Option<PID>,
+    pub pid: Option<PID>,
 }
 
 impl SenderID {
@@ -100,6 +102,9 @@ pub enum WaitingMessage {
 
     /// This memory should be returned to the system.
     ForgetMemory(MemoryRange),
+
+    /// The sender gave up waiting for the response, so it should be discarded.
+    Abandoned,
 }
 
 /// Internal representation of a queued message for a server. This should be
@@ -238,6 +243,16 @@ enum QueuedMessage {
         u8,    /* message index */
         usize, /* server return address */
     ),
+
+    /// A `WaitingReturnScalar` whose sender stopped waiting because its
+    /// `SendMessageTimeout` expired. The server still holds the message, and
+    /// its response is discarded.
+    AbandonedReturnScalar(
+        u16,   /* client PID */
+        u8,    /* client TID */
+        u8,    /* message index */
+        usize, /* server return address */
+    ),
 }
 
 impl QueuedMessage {
@@ -252,6 +267,7 @@ impl QueuedMessage {
             &QueuedMessage::WaitingForget(_, _, _, _, _, _)
                 | &QueuedMessage::WaitingReturnMemory(_, _, _, _, _, _)
                 | &QueuedMessage::WaitingReturnScalar(_, _, _, _)
+                | &QueuedMessage::AbandonedReturnScalar(_, _, _, _)
         )
     }
 }
@@ -326,7 +342,8 @@ impl Server {
                 // we already determined above that this wouldn't happen.
                 QueuedMessage::WaitingForget(_, _, _, _, _, _)
                 | QueuedMessage::WaitingReturnMemory(_, _, _, _, _, _)
-                | QueuedMessage::WaitingReturnScalar(_, _, _, _) => panic!("message was waiting"),
+                | QueuedMessage::WaitingReturnScalar(_, _, _, _)
+                | QueuedMessage::AbandonedReturnScalar(_, _, _, _) => panic!("message was waiting"),
 
                 // For `Empty` and `Scalar` messages, all we have to do is ignore them.
                 // The sending process will not be blocked. These messages will be dropped,
@@ -554,6 +571,53 @@ impl Server {
         }
     }
 
+    /// Detach `pid:tid` from the blocking scalar it is waiting on, as it has
+    /// given up on it. A message that is still queued is turned into a plain
+    /// scalar, so the server won't try to respond to it, and a response to a
+    /// message the server has already taken is discarded. Returns `false` if
+    /// `pid:tid` isn't waiting on this server, e.g. because it has already
+    /// been answered.
+    pub fn abandon_blocking_scalar(&mut self, pid: PID, tid: TID) -> bool {
+        for entry in self.queue.iter_mut() {
+            match *entry {
+                QueuedMessage::BlockingScalarMessage(
+                    msg_pid,
+                    msg_tid,
+                    idx,
+                    reserved,
+                    id,
+                    arg1,
+                    arg2,
+                    arg3,
+                    arg4,
+                ) if msg_pid == pid.get() as _ && msg_tid == tid as _ => {
+                    *entry = QueuedMessage::ScalarMessage(
+                        msg_pid, msg_tid, idx, reserved, id, arg1, arg2, arg3, arg4,
+                    );
+                    return true;
+                }
+                QueuedMessage::WaitingReturnScalar(msg_pid, msg_tid, idx, return_address)
+                    if msg_pid == pid.get() as _ && msg_tid == tid as _ =>
+                {
+                    *entry =
+                        QueuedMessage::AbandonedReturnScalar(msg_pid, msg_tid, idx, return_address);
+                    return true;
+                }
+                _ => (),
+            }
+        }
+        false
+    }
+
+    /// Returns the number of messages that are waiting for the server to
+    /// receive them. Messages that the server is working on are not counted.
+    pub fn queued_messages(&self) -> usize {
+        self.queue
+            .iter()
+            .filter(|entry| **entry != QueuedMessage::Empty && !entry.is_in_server())
+            .count()
+    }
+
     /// Convert a `QueuedMesage::WaitingReturnMemory` into `QueuedMessage::Empty`
     /// and return the pair.  Advance the tail.  Note that the `idx` could be
     /// somewhere other than the tail, but as long as it points to a valid
@@ -584,6 +648,10 @@ impl Server {
             QueuedMessage::WaitingReturnScalar(pid, tid, idx, return_address) => {
                 (pid, tid, idx, return_address, 0, 0, true, false)
             }
+            // The sender won't be resumed, so there is no PID to report.
+            QueuedMessage::AbandonedReturnScalar(_pid, _tid, idx, return_address) => {
+                (0, 0, idx, return_address, 0, 0, true, false)
+            }
             _ => return Ok(WaitingMessage::None),
         };
 
@@ -621,10 +689,10 @@ impl Server {
         // );
 
         if !is_memory {
-            return Ok(WaitingMessage::ScalarMessage(
-                PID::new(pid as _).unwrap(),
-                tid as _,
-            ));
+            return Ok(match PID::new(pid as _) {
+                Some(pid) => WaitingMessage::ScalarMessage(pid, tid as _),
+                None => WaitingMessage::Abandoned,
+            });
         }
 
         if forget {
@@ -1178,6 +1246,20 @@ impl Server {
         }
     }
 
+    /// Like `take_available_thread()`, but picks the waiting thread with the
+    /// highest priority, as given by the server process' `priorities`.
+    pub fn take_available_thread_by_priority(
+        &mut self,
+        priorities: &[ThreadPriority],
+    ) -> Option<TID> {
+        // Start the search from TID 0, so threads of equal priority are taken
+        // in the same order as `take_available_thread()`.
+        let tid =
+            crate::services::next_thread(priorities, self.ready_threads, priorities.len() - 1)?;
+        self.ready_threads &= !(1 << tid);
+        Some(tid)
+    }
+
     /// Return an available context to the blocking list.  This is part of the
     /// error condition when a message cannot be handled but the context has
     /// already been claimed.
@@ -1202,4 +1284,14 @@ impl Server {
         self.ready_threads |= 1 << tid;
         klog!("ready threads now: {:08b}", self.ready_threads);
     }
+
+    /// Remove the given context from the list of ready and waiting contexts.
+    /// Returns `false` if it wasn't waiting on this server.
+    pub fn unpark_thread(&mut self, tid: TID) -> bool {
+        if self.ready_threads & (1 << tid) == 0 {
+            return false;
+        }
+        self.ready_threads &= !(1 << tid);
+        true
+    }
 }
@@ -14,11 +14,15 @@ use crate::filled_array;
 use crate::server::Server;
 // use core::mem;
 use xous_kernel::{
-    pid_from_usize, Error, MemoryAddress, Message, ProcessInit, ThreadInit, CID, PID, SID, TID,
+    pid_from_usize, Error, IpcTraceKind, MemoryAddress, Message, ProcessInit, ProcessStats,
+    ThreadInit, ThreadPriority, CID, PID, SID, TID,
 };
 
 const MAX_SERVER_COUNT: usize = 128;
 
+/// How many threads can be blocked in a syscall with a timeout at once
+const MAX_TIMEOUT_COUNT: usize = 32;
+
 pub use crate::arch::process::{INITIAL_TID, MAX_PROCESS_COUNT};
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -30,6 +34,31 @@ pub struct ExceptionHandler {
     pub sp: usize,
 }
 
+/// What a thread with a pending timeout is blocked on
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TimeoutKind {
+    /// Waiting in `ReceiveMessageTimeout` for a message to arrive
+    Receive,
+
+    /// Waiting in `SendMessageTimeout` for the server to take and answer its message
+    Send,
+}
+
+/// A thread that gives up on a blocking syscall once `deadline` has passed
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Timeout {
+    pid: PID,
+    tid: TID,
+
+    /// The server the thread is waiting on
+    sidx: usize,
+
+    /// The time, in milliseconds since the kernel started, when the thread gives up
+    deadline: u64,
+
+    kind: TimeoutKind,
+}
+
 // fn log_process_update(f: &str, l: u32, process: &Process, old_state: ProcessState) {
 //     if process.pid.get() == 3 {
 //         println!("[{}:{}] Updated PID {:?} state: {:?} -> {:?}", f, l, process.pid, old_state, process.state);
@@ -44,6 +73,16 @@ pub struct SystemServices {
 
     /// A table of all servers in the system
     pub servers: [Option<Server>; MAX_SERVER_COUNT],
+
+    /// Threads that are blocked in a syscall with a timeout
+    timeouts: [Option<Timeout>; MAX_TIMEOUT_COUNT],
+
+    /// Milliseconds since the kernel started, as of the last call to `advance_clock()`
+    now_ms: u64,
+
+    /// The most recent messages sent, received, and returned
+    #[cfg(feature = "ipc-trace")]
+    pub ipc_trace: crate::trace::IpcTrace,
 }
 
 #[derive(Copy, Clone, PartialEq)]
@@ -135,6 +174,14 @@ pub struct Process {
 
     /// When an exception is hit, the kernel will switch to this Thread.
     exception_handler: Option<ExceptionHandler>,
+
+    /// The priority of each thread, indexed by TID.
+    pub thread_priorities: [ThreadPriority; arch::process::TID_COUNT],
+
+    /// Whether the process was started by the kernel as part of the boot image,
+    /// rather than created later by another process. Only these processes may
+    /// inspect other processes or read the IPC trace.
+    pub trusted: bool,
 }
 
 impl Default for Process {
@@ -147,6 +194,8 @@ impl Default for Process {
             previous_thread: 0,
             exception_handler: None,
             mapping: Default::default(),
+            thread_priorities: [ThreadPriority::Normal; arch::process::TID_COUNT],
+            trusted: false,
         }
     }
 }
@@ -215,6 +264,29 @@ impl Process {
         )
     }
 
+    /// Returns the priority of the most urgent thread that is ready to run, or
+    /// `None` if the process isn't runnable.
+    pub fn ready_priority(&self) -> Option<ThreadPriority> {
+        if !self.runnable() {
+            return None;
+        }
+        match self.state {
+            ProcessState::Ready(x) => (0..arch::process::TID_COUNT)
+                .filter(|tid| x & (1 << tid) != 0)
+                .map(|tid| self.thread_priorities[tid])
+                .max(),
+            // The initial thread and the exception handler run at the default priority
+            _ => Some(ThreadPriority::default()),
+        }
+    }
+
+    /// Picks the thread to run out of the `ready` bitmask: the one with the
+    /// highest priority, going round-robin between threads of equal priority
+    /// starting with the one after `after`.
+    fn next_thread(&self, ready: usize, after: TID) -> Option<TID> {
+        next_thread(&self.thread_priorities, ready, after)
+    }
+
     /// This process slot is unallocated and may be turn into a process
     pub fn free(&self) -> bool {
         matches!(self.state, ProcessState::Free)
@@ -247,6 +319,27 @@ impl Process {
     }
 }
 
+/// Returns the thread in the `ready` bitmask with the highest priority. Threads
+/// of equal priority are considered in order, starting with the one after `after`,
+/// so that they take turns.
+pub fn next_thread(priorities: &[ThreadPriority], ready: usize, after: TID) -> Option<TID> {
+    let count = priorities.len();
+    let mut best: Option<TID> = None;
+    for offset in 1..=count {
+        let tid = (after + offset) % count;
+        if ready & (1 << tid) == 0 {
+            continue;
+        }
+        if best
+            .map(|b| priorities[tid] > priorities[b])
+            .unwrap_or(true)
+        {
+            best = Some(tid);
+        }
+    }
+    best
+}
+
 #[cfg(not(baremetal))]
 std::thread_local!(static SYSTEM_SERVICES: core::cell::RefCell<SystemServices> = core::cell::RefCell::new(SystemServices {
     processes: [Process {
@@ -257,10 +350,16 @@ std::thread_local!(static SYSTEM_SERVICES: core::cell::RefCell<SystemServices> =
         current_thread: 0_usize,
         previous_thread: INITIAL_TID as TID,
         exception_handler: None,
+        thread_priorities: [ThreadPriority::Normal; arch::process::TID_COUNT],
+        trusted: false,
     }; MAX_PROCESS_COUNT],
     // Note we can't use MAX_SERVER_COUNT here because of how Rust's
     // macro tokenization works
     servers: filled_array![None; 128],
+    timeouts: [None; MAX_TIMEOUT_COUNT],
+    now_ms: 0,
+    #[cfg(feature = "ipc-trace")]
+    ipc_trace: crate::trace::IpcTrace::new(),
 }));
 
 #[cfg(baremetal)]
@@ -273,10 +372,16 @@ static mut SYSTEM_SERVICES: SystemServices = SystemServices {
         current_thread: 0_usize,
         previous_thread: INITIAL_TID as TID,
         exception_handler: None,
+        thread_priorities: [ThreadPriority::Normal; arch::process::TID_COUNT],
+        trusted: false,
     }; MAX_PROCESS_COUNT],
     // Note we can't use MAX_SERVER_COUNT here because of how Rust's
     // macro tokenization works
     servers: filled_array![None; 128],
+    timeouts: [None; MAX_TIMEOUT_COUNT],
+    now_ms: 0,
+    #[cfg(feature = "ipc-trace")]
+    ipc_trace: crate::trace::IpcTrace::new(),
 };
 
 impl core::fmt::Debug for Process {
@@ -359,6 +464,7 @@ impl SystemServices {
                 process.ppid = PID::new_unchecked(1);
                 process.pid = PID::new(pid as _).unwrap();
             };
+            process.trusted = true;
             // let old_state = process.state;
             if pid == 1 {
                 process.state = ProcessState::Running(0);
@@ -396,7 +502,7 @@ impl SystemServices {
     ) -> Result<ProcessStartup, xous_kernel::Error> {
         let mut entry_idx = None;
         let mut new_pid = None;
-        let _ppid = crate::arch::process::current_pid();
+        let creator = crate::arch::process::current_pid();
 
         for (idx, entry) in self.processes.iter_mut().enumerate() {
             if entry.state != ProcessState::Free {
@@ -407,6 +513,9 @@ impl SystemServices {
             entry.pid = new_pid.unwrap();
             entry.ppid = PID::new(1).unwrap();
             entry.state = ProcessState::Allocated;
+            entry.thread_priorities = [ThreadPriority::default(); arch::process::TID_COUNT];
+            // processes the kernel starts itself are part of the boot image
+            entry.trusted = creator.get() == 1;
             unsafe {
                 entry
                     .mapping
@@ -430,7 +539,7 @@ impl SystemServices {
             // this process.
             entry.state = ProcessState::Ready(1 << INITIAL_TID);
         }
-        // entry.ppid = _ppid;
+        // entry.ppid = creator;
         klog!("created new process for PID {} with PPID {}", new_pid, ppid);
         return Ok(startup);
     }
@@ -753,17 +862,9 @@ impl SystemServices {
             }
             ProcessState::Ready(x) => {
                 let new_thread = match tid {
-                    None => {
-                        let mut new_context = 0;
-
-                        while x & (1 << new_context) == 0 {
-                            new_context += 1;
-                            if new_context > arch::process::MAX_THREAD {
-                                new_context = 0;
-                            }
-                        }
-                        new_context
-                    }
+                    None => process
+                        .next_thread(x, process.current_thread)
+                        .expect("no thread was ready, even though the process was Ready"),
                     Some(ctx) => {
                         // Ensure the specified context is ready to run
                         if x & (1 << ctx) == 0 {
@@ -794,17 +895,9 @@ impl SystemServices {
                 let mut p = ArchProcess::current();
                 // let current_thread = p.current_thread();
                 let new_thread = match tid {
-                    None => {
-                        let mut new_thread = 0;
-
-                        while ready_threads & (1 << new_thread) == 0 {
-                            new_thread += 1;
-                            if new_thread > arch::process::MAX_THREAD {
-                                new_thread = 0;
-                            }
-                        }
-                        new_thread
-                    }
+                    None => process
+                        .next_thread(ready_threads, process.current_thread)
+                        .expect("no thread was ready, even though the process was Running"),
                     Some(tid) => {
                         // Ensure the specified context is ready to run, or is
                         // currently running.
@@ -967,26 +1060,18 @@ impl SystemServices {
                     // new.current_thread = new_tid;
                 }
                 ProcessState::Running(x) | ProcessState::Ready(x) => {
-                    // If no new context is specified, take the previous
-                    // context.  If that is not runnable, do a round-robin
-                    // search for the next available context.
+                    // If no new context is specified, take the ready context
+                    // with the highest priority, going round-robin between
+                    // contexts of the same priority.
                     assert!(
                         x != 0,
                         "process was {:?} but had no free contexts",
                         new.state
                     );
                     if new_tid == 0 {
-                        new_tid = (new.current_thread + 1) as usize;
-                        while x & (1 << new_tid) == 0 {
-                            new_tid += 1;
-                            if new_tid > arch::process::MAX_THREAD {
-                                new_tid = 0;
-                            } else if new_tid == (new.current_thread + 1) as usize {
-                                // If we've looped around, return an error.
-                                // println!("Looked through all contexts and couldn't find one that was ready");
-                                return Err(xous_kernel::Error::ProcessNotFound);
-                            }
-                        }
+                        new_tid = new
+                            .next_thread(x, new.current_thread)
+                            .ok_or(xous_kernel::Error::ProcessNotFound)?;
                         new.current_thread = new_tid as _;
                         klog!("picked thread ID {}", new_tid);
                     } else if x & (1 << new_tid) == 0 {
@@ -1117,20 +1202,13 @@ impl SystemServices {
             // let old_state = new.state;
             new.state = if let ProcessState::Running(x) = new.state {
                 let previous_tid = new.current_thread;
-                // If no new thread is specified, take the previous
-                // thread.  If that is not runnable, do a round-robin
-                // search for the next available thread.
+                // If no new thread is specified, take the ready thread with
+                // the highest priority, going round-robin between threads of
+                // the same priority.
                 if new_tid == 0 {
-                    new_tid = (new.current_thread + 1) as usize;
-                    while x & (1 << new_tid) == 0 {
-                        new_tid += 1;
-                        if new_tid > arch::process::MAX_THREAD {
-                            new_tid = 0;
-                        } else if new_tid == (new.current_thread + 1) as usize {
-                            // If we've looped around, return an error.
-                            return Err(xous_kernel::Error::ProcessNotFound);
-                        }
-                    }
+                    new_tid = new
+                        .next_thread(x, new.current_thread)
+                        .ok_or(xous_kernel::Error::ProcessNotFound)?;
                     new.current_thread = new_tid as _;
                 } else if x & (1 << new_tid) == 0 {
                     return Err(xous_kernel::Error::ProcessNotFound);
@@ -1553,6 +1631,7 @@ impl SystemServices {
             .ok_or(xous_kernel::Error::ThreadNotAvailable)?;
 
         arch_process.setup_thread(new_tid, thread_init)?;
+        process.thread_priorities[new_tid] = ThreadPriority::default();
 
         // println!("KERNEL({}): Created new thread {}", pid, new_tid);
 
@@ -1628,6 +1707,277 @@ impl SystemServices {
         Ok(new_pid != pid)
     }
 
+    /// Set the priority of thread `tid` in process `pid`. The new priority takes
+    /// effect the next time a thread is picked to run or to receive a message.
+    /// A thread above `Normal` can starve the rest of the system, so only
+    /// trusted processes may use those priorities.
+    ///
+    /// # Errors
+    ///
+    /// * **AccessDenied**: The priority is above `Normal`, and `pid` isn't trusted
+    /// * **ThreadNotAvailable**: The thread ID is out of range
+    pub fn set_thread_priority(
+        &mut self,
+        pid: PID,
+        tid: TID,
+        priority: ThreadPriority,
+    ) -> Result<(), xous_kernel::Error> {
+        if tid == 0 || tid >= arch::process::TID_COUNT {
+            return Err(xous_kernel::Error::ThreadNotAvailable);
+        }
+        let process = self.get_process_mut(pid)?;
+        if priority > ThreadPriority::Normal && !process.trusted {
+            return Err(xous_kernel::Error::AccessDenied);
+        }
+        process.thread_priorities[tid] = priority;
+        Ok(())
+    }
+
+    /// Tally up the resources held by process `pid` on behalf of `caller`. A
+    /// process may always ask about itself, but only trusted processes may ask
+    /// about others.
+    ///
+    /// # Errors
+    ///
+    /// * **AccessDenied**: `caller` isn't trusted, and asked about another process
+    /// * **ProcessNotFound**: The process doesn't exist
+    pub fn process_stats(&self, caller: PID, pid: PID) -> Result<ProcessStats, xous_kernel::Error> {
+        if caller != pid && !self.get_process(caller)?.trusted {
+            return Err(xous_kernel::Error::AccessDenied);
+        }
+        // Look the process up directly, as `get_process()` complains about free
+        // slots and this is called on every PID to list the processes.
+        let process = match self.processes.get(pid.get() as usize - 1) {
+            Some(process) if !process.free() => process,
+            _ => return Err(xous_kernel::Error::ProcessNotFound),
+        };
+        let (ready, running) = match process.state {
+            ProcessState::Setup(_) => (1 << INITIAL_TID, false),
+            ProcessState::Ready(x) | ProcessState::Debug(x) => (x, false),
+            ProcessState::Running(x) => (x, true),
+            ProcessState::Exception(x) | ProcessState::BlockedException(x) => (x, false),
+            ProcessState::Free | ProcessState::Allocated | ProcessState::Sleeping => (0, false),
+        };
+
+        // Threads and connections are kept in the process' own memory space, so
+        // temporarily switch into it.
+        let current_pid = self.current_pid();
+        process.activate()?;
+        let threads = ArchProcess::current().thread_mask() | ready;
+        let (heap_size, connections) = ArchProcess::with_inner(|process_inner| {
+            (
+                process_inner.mem_heap_size,
+                process_inner.connection_map.iter().flatten().count(),
+            )
+        });
+        self.get_process(current_pid)
+            .expect("couldn't switch back after counting threads")
+            .activate()?;
+
+        #[cfg(baremetal)]
+        let mapped_pages =
+            Some(crate::mem::MemoryManager::with(|mm| mm.ram_used_by(pid)) / arch::mem::PAGE_SIZE);
+        #[cfg(not(baremetal))]
+        let mapped_pages = None;
+
+        let mut servers = 0;
+        let mut queued_messages = 0;
+        for server in self.servers.iter().flatten() {
+            if server.pid == pid {
+                servers += 1;
+                queued_messages += server.queued_messages();
+            }
+        }
+
+        let thread_count = threads.count_ones() as u8;
+        let ready_threads = (ready & threads).count_ones() as u8;
+        Ok(ProcessStats {
+            mapped_pages,
+            heap_size,
+            threads: thread_count,
+            ready_threads,
+            blocked_threads: thread_count.saturating_sub(ready_threads + running as u8),
+            servers,
+            connections,
+            queued_messages,
+        })
+    }
+
+    /// Make thread `pid:tid` give up waiting on server `sidx` after `timeout_ms`
+    /// milliseconds. The clock advances in whole ticks -- a millisecond when
+    /// hosted, a preemption quantum on hardware -- so one tick is added to make
+    /// sure the thread waits for at least `timeout_ms`.
+    ///
+    /// # Errors
+    ///
+    /// * **OutOfMemory**: Too many threads are already waiting with a timeout
+    pub fn add_timeout(
+        &mut self,
+        pid: PID,
+        tid: TID,
+        sidx: usize,
+        timeout_ms: usize,
+        kind: TimeoutKind,
+    ) -> Result<(), xous_kernel::Error> {
+        let tick = if cfg!(baremetal) {
+            xous_kernel::BASE_QUANTA_MS as u64
+        } else {
+            1
+        };
+        let deadline = self.now_ms + timeout_ms as u64 + tick;
+        let slot = self
+            .timeouts
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(xous_kernel::Error::OutOfMemory)?;
+        *slot = Some(Timeout {
+            pid,
+            tid,
+            sidx,
+            deadline,
+            kind,
+        });
+        Ok(())
+    }
+
+    /// Forget any timeout for thread `pid:tid`. This is done whenever a thread
+    /// makes a syscall, as it can't still be blocked in an earlier one.
+    pub fn clear_timeout(&mut self, pid: PID, tid: TID) {
+        for slot in self.timeouts.iter_mut() {
+            if matches!(slot, Some(timeout) if timeout.pid == pid && timeout.tid == tid) {
+                *slot = None;
+            }
+        }
+    }
+
+    /// Returns the earliest deadline of any pending timeout
+    #[cfg(not(baremetal))]
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.timeouts.iter().flatten().map(|t| t.deadline).min()
+    }
+
+    /// Sets the current time to `now_ms` and wakes every thread whose deadline
+    /// has passed with a `Timeout` error. A thread that is no longer waiting
+    /// on its server, because a message arrived or its message was answered
+    /// after all, is left alone.
+    ///
+    /// The sender of a timed-out `SendMessageTimeout` is detached from its
+    /// message: if it is still queued it becomes a plain scalar, and if the
+    /// server has already taken it, its eventual response is discarded.
+    pub fn advance_clock(&mut self, now_ms: u64) {
+        self.now_ms = now_ms;
+        for idx in 0..self.timeouts.len() {
+            let timeout = match self.timeouts[idx] {
+                Some(timeout) if timeout.deadline <= now_ms => timeout,
+                _ => continue,
+            };
+            self.timeouts[idx] = None;
+
+            let still_waiting = match self.server_from_sidx_mut(timeout.sidx) {
+                Some(server) => match timeout.kind {
+                    TimeoutKind::Receive => {
+                        server.pid == timeout.pid && server.unpark_thread(timeout.tid)
+                    }
+                    TimeoutKind::Send => server.abandon_blocking_scalar(timeout.pid, timeout.tid),
+                },
+                None => false,
+            };
+            if !still_waiting {
+                continue;
+            }
+            klog!(
+                "timeout expired for {}:{} waiting on sidx {}",
+                timeout.pid,
+                timeout.tid,
+                timeout.sidx
+            );
+            if let Err(e) = self.set_thread_result(
+                timeout.pid,
+                timeout.tid,
+                xous_kernel::Result::Error(xous_kernel::Error::Timeout),
+            ) {
+                println!(
+                    "WARNING: couldn't set result for timed out thread {}:{}: {:?}",
+                    timeout.pid, timeout.tid, e
+                );
+                continue;
+            }
+            if cfg!(baremetal) {
+                if let Err(e) = self.ready_thread(timeout.pid, timeout.tid) {
+                    println!(
+                        "WARNING: couldn't ready timed out thread {}:{}: {:?}",
+                        timeout.pid, timeout.tid, e
+                    );
+                }
+            }
+        }
+    }
+
+    /// Record an IPC event made by `pid:tid` on server `sidx` in the IPC trace.
+    /// `peer` is the process on the other end, if it's known.
+    #[cfg(feature = "ipc-trace")]
+    pub fn trace_ipc(
+        &mut self,
+        kind: IpcTraceKind,
+        pid: PID,
+        tid: TID,
+        peer: Option<PID>,
+        sidx: usize,
+        opcode: usize,
+    ) {
+        let sid_prefix = match self.server_from_sidx(sidx) {
+            Some(server) => server.sid.to_u32().0,
+            None => return,
+        };
+        self.ipc_trace.record(xous_kernel::IpcTraceEvent {
+            timestamp_ms: self.now_ms as usize,
+            opcode,
+            sid_prefix,
+            kind,
+            pid,
+            tid: tid as u8,
+            peer,
+        });
+    }
+
+    /// The kernel was built without `ipc-trace`, so there is nothing to record.
+    #[cfg(not(feature = "ipc-trace"))]
+    pub fn trace_ipc(
+        &mut self,
+        _kind: IpcTraceKind,
+        _pid: PID,
+        _tid: TID,
+        _peer: Option<PID>,
+        _sidx: usize,
+        _opcode: usize,
+    ) {
+    }
+
+    /// Returns the IPC trace event with sequence number `seq`, or the range of
+    /// sequence numbers in the trace if it's no longer there. Only trusted
+    /// processes may read the trace.
+    ///
+    /// # Errors
+    ///
+    /// * **AccessDenied**: The calling process wasn't started by the kernel
+    #[cfg(feature = "ipc-trace")]
+    pub fn ipc_trace_event(
+        &self,
+        pid: PID,
+        seq: usize,
+    ) -> Result<xous_kernel::Result, xous_kernel::Error> {
+        if !self.get_process(pid)?.trusted {
+            return Err(xous_kernel::Error::AccessDenied);
+        }
+        Ok(match self.ipc_trace.get(seq) {
+            Some(event) => xous_kernel::Result::IpcTraceEvent(event),
+            None => {
+                let (oldest, next) = self.ipc_trace.range();
+                xous_kernel::Result::Scalar2(oldest, next)
+            }
+        })
+    }
+
     /// Park this thread if the target thread is currently running. Otherwise,
     /// return the value of the given thread.
     pub fn join_thread(
@@ -2112,6 +2462,13 @@ impl SystemServices {
             }
         }
 
+        // Its threads won't be waiting on anything anymore.
+        for slot in self.timeouts.iter_mut() {
+            if matches!(slot, Some(timeout) if timeout.pid == target_pid) {
+                *slot = None;
+            }
+        }
+
         let process = self.get_process_mut(target_pid)?;
         process.activate()?;
         let parent_pid = process.ppid;
@@ -6,7 +6,7 @@ use crate::arch::process::Process as ArchProcess;
 use crate::irq::interrupt_claim;
 use crate::mem::{MemoryManager, PAGE_SIZE};
 use crate::server::{SenderID, WaitingMessage};
-use crate::services::SystemServices;
+use crate::services::{SystemServices, TimeoutKind};
 use core::mem;
 use xous_kernel::*;
 
@@ -145,11 +145,12 @@ fn send_message(pid: PID, thread: TID, cid: CID, message: Message) -> SysCallRes
         };
 
         // If the server has an available thread to receive the message,
-        // transfer it right away.
+        // transfer it right away, to the most urgent one if there are several.
+        let priorities = ss.get_process(server_pid)?.thread_priorities;
         let server = ss
             .server_from_sidx_mut(sidx)
             .expect("server couldn't be located");
-        if let Some(server_tid) = server.take_available_thread() {
+        if let Some(server_tid) = server.take_available_thread_by_priority(&priorities) {
             // klog!(
             //     "there are threads available in PID {} to handle this message -- marking as Ready",
             //     server_pid
@@ -186,6 +187,22 @@ fn send_message(pid: PID, thread: TID, cid: CID, message: Message) -> SysCallRes
                     .return_available_thread(server_tid);
                 e
             })?;
+            ss.trace_ipc(
+                IpcTraceKind::from_message(&envelope.body),
+                pid,
+                thread,
+                Some(server_pid),
+                sidx,
+                envelope.body.id(),
+            );
+            ss.trace_ipc(
+                IpcTraceKind::Receive,
+                server_pid,
+                server_tid,
+                Some(pid),
+                sidx,
+                envelope.body.id(),
+            );
 
             let runnable = ss
                 .runnable(server_pid, Some(server_tid))
@@ -253,8 +270,10 @@ fn send_message(pid: PID, thread: TID, cid: CID, message: Message) -> SysCallRes
         );
         // Add this message to the queue.  If the queue is full, this
         // returns an error.
+        let (trace_kind, opcode) = (IpcTraceKind::from_message(&message), message.id());
         let _queue_idx = ss.queue_server_message(sidx, pid, thread, message, client_address)?;
         klog!("queued into index {:x}", _queue_idx);
+        ss.trace_ipc(trace_kind, pid, thread, Some(server_pid), sidx, opcode);
 
         // Park this context if it's blocking.  This is roughly
         // equivalent to a "Yield".
@@ -328,7 +347,7 @@ fn return_memory(
                     result
                 })
             }
-            WaitingMessage::ScalarMessage(_pid, _tid) => {
+            WaitingMessage::ScalarMessage(_pid, _tid) | WaitingMessage::Abandoned => {
                 println!("WARNING: Tried to wait on a message that was a scalar");
                 return Err(xous_kernel::Error::InternalError);
             }
@@ -360,6 +379,14 @@ fn return_memory(
             client_addr.get() as _,
             len.get(),
         )?;
+        ss.trace_ipc(
+            IpcTraceKind::Return,
+            server_pid,
+            server_tid,
+            Some(client_pid),
+            sender.sidx,
+            0,
+        );
 
         let client_is_runnable = ss.runnable(client_pid, Some(client_tid))?;
 
@@ -411,6 +438,8 @@ fn return_scalar(
         let result = server.take_waiting_message(sender.idx, None)?;
         let (client_pid, client_tid) = match result {
             WaitingMessage::ScalarMessage(pid, tid) => (pid, tid),
+            // The client timed out and moved on, so the response goes nowhere.
+            WaitingMessage::Abandoned => return Ok(xous_kernel::Result::Ok),
             WaitingMessage::ForgetMemory(_) => {
                 println!(
                     "WARNING: Tried to wait on a scalar message that was actually forgettingmemory"
@@ -434,6 +463,14 @@ fn return_scalar(
                 return Err(xous_kernel::Error::ProcessNotFound);
             }
         };
+        ss.trace_ipc(
+            IpcTraceKind::Return,
+            server_pid,
+            server_tid,
+            Some(client_pid),
+            sender.sidx,
+            0,
+        );
 
         let client_is_runnable = ss.runnable(client_pid, Some(client_tid))?;
 
@@ -484,6 +521,8 @@ fn return_scalar2(
         let result = server.take_waiting_message(sender.idx, None)?;
         let (client_pid, client_tid) = match result {
             WaitingMessage::ScalarMessage(pid, tid) => (pid, tid),
+            // The client timed out and moved on, so the response goes nowhere.
+            WaitingMessage::Abandoned => return Ok(xous_kernel::Result::Ok),
             WaitingMessage::ForgetMemory(_) => {
                 println!("WARNING: Tried to wait on a scalar message that was actually forgetting memory");
                 return Err(xous_kernel::Error::ProcessNotFound);
@@ -505,6 +544,14 @@ fn return_scalar2(
                 return Err(xous_kernel::Error::ProcessNotFound);
             }
         };
+        ss.trace_ipc(
+            IpcTraceKind::Return,
+            server_pid,
+            server_tid,
+            Some(client_pid),
+            sender.sidx,
+            0,
+        );
 
         let client_is_runnable = ss.runnable(client_pid, Some(client_tid))?;
 
@@ -538,7 +585,13 @@ fn return_scalar2(
     })
 }
 
-fn receive_message(pid: PID, tid: TID, sid: SID, blocking: ExecutionType) -> SysCallResult {
+fn receive_message(
+    pid: PID,
+    tid: TID,
+    sid: SID,
+    blocking: ExecutionType,
+    timeout_ms: Option<usize>,
+) -> SysCallResult {
     SystemServices::with_mut(|ss| {
         assert!(
             ss.thread_is_running(pid, tid),
@@ -561,6 +614,14 @@ fn receive_message(pid: PID, tid: TID, sid: SID, blocking: ExecutionType) -> Sys
         // If there is a pending message, return it immediately.
         if let Some(msg) = server.take_next_message(sidx) {
             klog!("waiting messages found -- returning {:x?}", msg);
+            ss.trace_ipc(
+                IpcTraceKind::Receive,
+                pid,
+                tid,
+                SenderID::from(msg.sender).pid,
+                sidx,
+                msg.body.id(),
+            );
             return Ok(xous_kernel::Result::Message(msg));
         }
 
@@ -577,7 +638,12 @@ fn receive_message(pid: PID, tid: TID, sid: SID, blocking: ExecutionType) -> Sys
             "did not have any waiting messages -- parking thread {}",
             tid
         );
-        server.park_thread(tid);
+        if let Some(timeout_ms) = timeout_ms {
+            ss.add_timeout(pid, tid, sidx, timeout_ms, TimeoutKind::Receive)?;
+        }
+        ss.server_from_sidx_mut(sidx)
+            .expect("server couldn't be located")
+            .park_thread(tid);
 
         // For baremetal targets, switch away from this process.
         if cfg!(baremetal) {
@@ -602,6 +668,11 @@ pub fn handle(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallResult
     print!("KERNEL({}:{}): Syscall {:x?}", pid, tid, call);
     // let call_string = format!("{:x?}", call);
     // let start_time = std::time::Instant::now();
+
+    // A thread making a syscall isn't blocked in an earlier one anymore, so
+    // it can't time out of it.
+    SystemServices::with_mut(|ss| ss.clear_timeout(pid, tid));
+
     #[allow(clippy::let_and_return)]
     let result = if in_irq && !call.can_call_from_interrupt() {
         Err(xous_kernel::Error::InvalidSyscall)
@@ -775,9 +846,14 @@ pub fn handle_inner(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallR
             };
             Ok(xous_kernel::Result::ResumeProcess)
         }
-        SysCall::ReceiveMessage(sid) => receive_message(pid, tid, sid, ExecutionType::Blocking),
+        SysCall::ReceiveMessage(sid) => {
+            receive_message(pid, tid, sid, ExecutionType::Blocking, None)
+        }
+        SysCall::ReceiveMessageTimeout(sid, timeout_ms) => {
+            receive_message(pid, tid, sid, ExecutionType::Blocking, Some(timeout_ms))
+        }
         SysCall::TryReceiveMessage(sid) => {
-            receive_message(pid, tid, sid, ExecutionType::NonBlocking)
+            receive_message(pid, tid, sid, ExecutionType::NonBlocking, None)
         }
         SysCall::WaitEvent => SystemServices::with_mut(|ss| {
             let process = ss.get_process(pid).expect("Can't get current process");
@@ -871,6 +947,23 @@ pub fn handle_inner(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallR
                 Err(e) => Err(e),
             }
         }
+        // The deadline covers the whole call, whether the message is still
+        // queued or the server is working on it when it passes. Unlike
+        // `SendMessage`, a full queue isn't retried, as that could wait past
+        // the deadline.
+        SysCall::SendMessageTimeout(cid, scalar, timeout_ms) => {
+            SystemServices::with_mut(|ss| {
+                let sidx = ss
+                    .sidx_from_cid(cid)
+                    .ok_or(xous_kernel::Error::ServerNotFound)?;
+                ss.add_timeout(pid, tid, sidx, timeout_ms, TimeoutKind::Send)
+            })?;
+            let result = send_message(pid, tid, cid, Message::BlockingScalar(scalar));
+            if result.is_err() {
+                SystemServices::with_mut(|ss| ss.clear_timeout(pid, tid));
+            }
+            result
+        }
         SysCall::Disconnect(cid) => SystemServices::with_mut(|ss| {
             ss.disconnect_from_server(cid)
                 .and(Ok(xous_kernel::Result::Ok))
@@ -893,6 +986,29 @@ pub fn handle_inner(pid: PID, tid: TID, in_irq: bool, call: SysCall) -> SysCallR
             MemoryManager::with_mut(|mm| mm.update_memory_flags(range, flags))?;
             Ok(xous_kernel::Result::Ok)
         }
+        #[cfg(baremetal)]
+        SysCall::GetProcessName(other_pid) => SystemServices::with(|ss| {
+            ss.get_process(other_pid)?;
+            const WORD: usize = core::mem::size_of::<usize>();
+            let mut words = [0usize; 5];
+            let name = ss.process_name(other_pid).unwrap_or("");
+            for (i, &b) in name.as_bytes().iter().take(words.len() * WORD).enumerate() {
+                words[i / WORD] |= (b as usize) << (8 * (i % WORD));
+            }
+            Ok(xous_kernel::Result::Scalar5(
+                words[0], words[1], words[2], words[3], words[4],
+            ))
+        }),
+        SysCall::SetThreadPriority(target_tid, priority) => SystemServices::with_mut(|ss| {
+            ss.set_thread_priority(pid, target_tid, priority)
+                .map(|_| xous_kernel::Result::Ok)
+        }),
+        SysCall::GetProcessStats(other_pid) => SystemServices::with(|ss| {
+            ss.process_stats(pid, other_pid)
+                .map(xous_kernel::Result::ProcessStats)
+        }),
+        #[cfg(feature = "ipc-trace")]
+        SysCall::GetIpcTraceEvent(seq) => SystemServices::with(|ss| ss.ipc_trace_event(pid, seq)),
         /* https://github.com/betrusted-io/xous-core/issues/90
         SysCall::SetExceptionHandler(pc, sp) => SystemServices::with_mut(|ss| {
             ss.set_exception_handler(pid, pc, sp)
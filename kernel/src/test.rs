@@ -1177,3 +1177,580 @@ fn process_restart_server() {
 
     main_thread.join().expect("couldn't join kernel process");
 }
+
+/// Test that the most urgent ready thread is picked, and that threads of equal
+/// priority take turns
+#[test]
+fn thread_priority_selection() {
+    use crate::services::next_thread;
+    use xous_kernel::ThreadPriority;
+
+    let mut priorities = [ThreadPriority::Normal; crate::arch::process::TID_COUNT];
+    let ready = (1 << 2) | (1 << 3) | (1 << 5);
+
+    // With equal priorities, threads are taken round-robin
+    assert_eq!(next_thread(&priorities, ready, 2), Some(3));
+    assert_eq!(next_thread(&priorities, ready, 3), Some(5));
+    assert_eq!(next_thread(&priorities, ready, 5), Some(2));
+
+    // A more urgent thread always wins
+    priorities[5] = ThreadPriority::High;
+    assert_eq!(next_thread(&priorities, ready, 2), Some(5));
+    assert_eq!(next_thread(&priorities, ready, 5), Some(5));
+
+    // ...unless it's not ready
+    assert_eq!(next_thread(&priorities, ready & !(1 << 5), 2), Some(3));
+
+    // Less urgent threads are skipped over
+    priorities[3] = ThreadPriority::Low;
+    assert_eq!(next_thread(&priorities, ready & !(1 << 5), 2), Some(2));
+    assert_eq!(next_thread(&priorities, 0, 2), None);
+}
+
+/// Test that a message goes to the most urgent thread waiting on a server
+#[test]
+fn thread_priority_message_delivery() {
+    use xous_kernel::ThreadPriority;
+
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let xous_server = xous_kernel::create_process_as_thread(
+        xous_kernel::ProcessArgsAsThread::new("thread_priority server", move || {
+            assert_eq!(
+                xous_kernel::set_thread_priority(0, ThreadPriority::High),
+                Err(xous_kernel::Error::ThreadNotAvailable)
+            );
+            assert_eq!(
+                xous_kernel::set_thread_priority(crate::arch::process::TID_COUNT, ThreadPriority::High),
+                Err(xous_kernel::Error::ThreadNotAvailable)
+            );
+
+            let server = xous_kernel::create_server().expect("couldn't create server");
+            let connection =
+                xous_kernel::try_connect(server).expect("couldn't connect to our own server");
+            let (parked_send, parked_recv) = unbounded();
+            let (result_send, result_recv) = unbounded();
+
+            // Spawn the Low thread first, so that it has the lower thread ID and
+            // would get the first message if priorities were ignored.
+            let mut server_threads = vec![];
+            for priority in [ThreadPriority::Low, ThreadPriority::High].iter().copied() {
+                let parked_send = parked_send.clone();
+                let result_send = result_send.clone();
+                server_threads.push(
+                    xous_kernel::create_thread(move || {
+                        let tid = xous_kernel::current_tid().expect("couldn't get thread ID");
+                        xous_kernel::set_thread_priority(tid, priority)
+                            .expect("couldn't set thread priority");
+                        parked_send.send(()).unwrap();
+                        let msg =
+                            xous_kernel::receive_message(server).expect("couldn't receive message");
+                        result_send.send((priority, msg.body.id())).unwrap();
+                    })
+                    .expect("couldn't spawn server thread"),
+                );
+            }
+
+            // Give both threads time to block in `receive_message()`
+            parked_recv.recv().unwrap();
+            parked_recv.recv().unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(100));
+
+            for id in 1..=2 {
+                xous_kernel::try_send_message(
+                    connection,
+                    xous_kernel::Message::Scalar(xous_kernel::ScalarMessage {
+                        id,
+                        arg1: 0,
+                        arg2: 0,
+                        arg3: 0,
+                        arg4: 0,
+                    }),
+                )
+                .expect("couldn't send message");
+            }
+            for server_thread in server_threads.into_iter() {
+                xous_kernel::wait_thread(server_thread).expect("couldn't wait for thread");
+            }
+
+            let mut results: Vec<(ThreadPriority, usize)> = result_recv.try_iter().collect();
+            results.sort();
+            assert_eq!(
+                results,
+                vec![(ThreadPriority::Low, 2), (ThreadPriority::High, 1)]
+            );
+        }),
+    )
+    .expect("couldn't spawn server process");
+
+    xous_kernel::wait_process_as_thread(xous_server).expect("couldn't join server process");
+
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that only trusted processes may raise a thread above `Normal`
+#[test]
+fn thread_priority_trust() {
+    use xous_kernel::ThreadPriority;
+
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    // Processes started by the test harness stand in for the boot image, so
+    // they're trusted, but the ones they start in turn are not.
+    let trusted = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "thread_priority trusted",
+        move || {
+            let tid = xous_kernel::current_tid().expect("couldn't get thread ID");
+            xous_kernel::set_thread_priority(tid, ThreadPriority::Realtime)
+                .expect("couldn't raise thread priority");
+            xous_kernel::set_thread_priority(tid, ThreadPriority::Normal)
+                .expect("couldn't lower thread priority");
+
+            let untrusted = xous_kernel::create_process_as_thread(
+                xous_kernel::ProcessArgsAsThread::new("thread_priority untrusted", move || {
+                    let tid = xous_kernel::current_tid().expect("couldn't get thread ID");
+                    for priority in [ThreadPriority::High, ThreadPriority::Realtime]
+                        .iter()
+                        .copied()
+                    {
+                        assert_eq!(
+                            xous_kernel::set_thread_priority(tid, priority),
+                            Err(xous_kernel::Error::AccessDenied)
+                        );
+                    }
+                    xous_kernel::set_thread_priority(tid, ThreadPriority::Low)
+                        .expect("couldn't lower thread priority");
+                    xous_kernel::set_thread_priority(tid, ThreadPriority::Normal)
+                        .expect("couldn't restore thread priority");
+                }),
+            )
+            .expect("couldn't spawn untrusted process");
+            xous_kernel::wait_process_as_thread(untrusted)
+                .expect("couldn't join untrusted process");
+        },
+    ))
+    .expect("couldn't spawn trusted process");
+
+    xous_kernel::wait_process_as_thread(trusted).expect("couldn't join trusted process");
+
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+/// Test that the resources held by a process are accounted for
+#[test]
+fn process_stats() {
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let xous_server = xous_kernel::create_process_as_thread(
+        xous_kernel::ProcessArgsAsThread::new("process_stats server", move || {
+            let pid = xous_kernel::current_pid().expect("couldn't get process ID");
+            // In hosted mode the thread that started the process lingers on, so
+            // thread counts are compared against this baseline.
+            let baseline = xous_kernel::process_stats(pid).expect("couldn't get process stats");
+            assert_eq!(baseline.servers, 0);
+            assert_eq!(baseline.connections, 0);
+            assert_eq!(baseline.queued_messages, 0);
+            assert!(baseline.threads >= 1);
+            assert_eq!(baseline.ready_threads, 0);
+
+            let server = xous_kernel::create_server().expect("couldn't create server");
+            let connection =
+                xous_kernel::try_connect(server).expect("couldn't connect to our own server");
+            for id in 1..=2 {
+                xous_kernel::try_send_message(
+                    connection,
+                    xous_kernel::Message::Scalar(xous_kernel::ScalarMessage {
+                        id,
+                        arg1: 0,
+                        arg2: 0,
+                        arg3: 0,
+                        arg4: 0,
+                    }),
+                )
+                .expect("couldn't send message");
+            }
+            let stats = xous_kernel::process_stats(pid).expect("couldn't get process stats");
+            assert_eq!(stats.servers, 1);
+            assert_eq!(stats.connections, 1);
+            assert_eq!(stats.queued_messages, 2);
+
+            // Drain the queue, then park a thread waiting for another message
+            for _ in 1..=2 {
+                xous_kernel::receive_message(server).expect("couldn't receive message");
+            }
+            let (parked_send, parked_recv) = unbounded();
+            let server_thread = xous_kernel::create_thread(move || {
+                parked_send.send(()).unwrap();
+                xous_kernel::receive_message(server).expect("couldn't receive message");
+            })
+            .expect("couldn't spawn server thread");
+            parked_recv.recv().unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(100));
+
+            let stats = xous_kernel::process_stats(pid).expect("couldn't get process stats");
+            assert_eq!(stats.queued_messages, 0);
+            assert_eq!(stats.threads, baseline.threads + 1);
+            assert_eq!(stats.blocked_threads, baseline.blocked_threads + 1);
+
+            xous_kernel::try_send_message(
+                connection,
+                xous_kernel::Message::Scalar(xous_kernel::ScalarMessage {
+                    id: 3,
+                    arg1: 0,
+                    arg2: 0,
+                    arg3: 0,
+                    arg4: 0,
+                }),
+            )
+            .expect("couldn't send message");
+            xous_kernel::wait_thread(server_thread).expect("couldn't wait for thread");
+
+            assert_eq!(
+                xous_kernel::process_stats(xous_kernel::PID::new(255).unwrap()),
+                Err(xous_kernel::Error::ProcessNotFound)
+            );
+        }),
+    )
+    .expect("couldn't spawn server process");
+
+    xous_kernel::wait_process_as_thread(xous_server).expect("couldn't join server process");
+
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+#[test]
+fn receive_message_timeout() {
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let (server_addr_send, server_addr_recv) = unbounded();
+    let (connected_send, connected_recv) = unbounded();
+    let (send_now_send, send_now_recv) = unbounded();
+
+    let xous_server = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "receive_message_timeout server",
+        move || {
+            let sid = xous_kernel::create_server().expect("couldn't create test server");
+            server_addr_send.send(sid).unwrap();
+            connected_recv.recv().unwrap();
+
+            // Nobody is sending, so this times out
+            let start = std::time::Instant::now();
+            assert_eq!(
+                xous_kernel::receive_message_timeout(sid, 50),
+                Err(xous_kernel::Error::Timeout)
+            );
+            assert!(start.elapsed() >= std::time::Duration::from_millis(50));
+
+            // A message arriving before the deadline is returned as usual
+            send_now_send.send(()).unwrap();
+            let envelope =
+                xous_kernel::receive_message_timeout(sid, 300).expect("couldn't receive message");
+            assert_eq!(
+                envelope.body,
+                xous_kernel::Message::Scalar(xous_kernel::ScalarMessage {
+                    id: 1,
+                    arg1: 2,
+                    arg2: 3,
+                    arg3: 4,
+                    arg4: 5
+                })
+            );
+
+            // Let the deadline pass without making a syscall. The expired timeout
+            // must not wake us up with an error on the next receive.
+            std::thread::sleep(std::time::Duration::from_millis(400));
+            send_now_send.send(()).unwrap();
+            let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+            assert_eq!(
+                envelope.body,
+                xous_kernel::Message::Scalar(xous_kernel::ScalarMessage {
+                    id: 6,
+                    arg1: 7,
+                    arg2: 8,
+                    arg3: 9,
+                    arg4: 10
+                })
+            );
+        },
+    ))
+    .expect("couldn't spawn server process");
+
+    let xous_client = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "receive_message_timeout client",
+        move || {
+            let sid = server_addr_recv.recv().unwrap();
+            let conn = xous_kernel::try_connect(sid).expect("couldn't connect to server");
+            connected_send.send(()).unwrap();
+
+            send_now_recv.recv().unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            xous_kernel::try_send_message(
+                conn,
+                xous_kernel::Message::Scalar(xous_kernel::ScalarMessage {
+                    id: 1,
+                    arg1: 2,
+                    arg2: 3,
+                    arg3: 4,
+                    arg4: 5,
+                }),
+            )
+            .expect("couldn't send message");
+
+            send_now_recv.recv().unwrap();
+            xous_kernel::try_send_message(
+                conn,
+                xous_kernel::Message::Scalar(xous_kernel::ScalarMessage {
+                    id: 6,
+                    arg1: 7,
+                    arg2: 8,
+                    arg3: 9,
+                    arg4: 10,
+                }),
+            )
+            .expect("couldn't send message");
+        },
+    ))
+    .expect("couldn't spawn client process");
+
+    crate::wait_process_as_thread(xous_server).expect("couldn't join server process");
+    crate::wait_process_as_thread(xous_client).expect("couldn't join client process");
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+#[test]
+fn send_message_timeout() {
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let (server_addr_send, server_addr_recv) = unbounded();
+    let (timed_out_send, timed_out_recv) = unbounded();
+    let (late_send, late_recv) = unbounded();
+
+    let xous_server = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "send_message_timeout server",
+        move || {
+            let sid = xous_kernel::create_server().expect("couldn't create test server");
+            server_addr_send.send(sid).unwrap();
+
+            // Don't receive anything until the client has given up. Its message
+            // then shows up as a plain scalar, as nobody is waiting for a reply.
+            timed_out_recv.recv().unwrap();
+            let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+            assert_eq!(
+                envelope.body,
+                xous_kernel::Message::Scalar(xous_kernel::ScalarMessage {
+                    id: 1,
+                    arg1: 2,
+                    arg2: 3,
+                    arg3: 4,
+                    arg4: 5
+                })
+            );
+
+            // A message taken before the deadline gets its reply
+            let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+            assert_eq!(
+                envelope.body,
+                xous_kernel::Message::BlockingScalar(xous_kernel::ScalarMessage {
+                    id: 6,
+                    arg1: 7,
+                    arg2: 8,
+                    arg3: 9,
+                    arg4: 10
+                })
+            );
+            xous_kernel::return_scalar(envelope.sender, 42).expect("couldn't return scalar");
+
+            // A message taken before the deadline but answered after it gets
+            // its reply discarded, without the server seeing an error.
+            let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+            assert_eq!(envelope.body.id(), 12);
+            late_recv.recv().unwrap();
+            xous_kernel::return_scalar(envelope.sender, 99).expect("couldn't return late scalar");
+
+            let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+            assert_eq!(envelope.body.id(), 13);
+            xous_kernel::return_scalar(envelope.sender, 43).expect("couldn't return scalar");
+        },
+    ))
+    .expect("couldn't spawn server process");
+
+    let xous_client = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "send_message_timeout client",
+        move || {
+            let sid = server_addr_recv.recv().unwrap();
+            let conn = xous_kernel::try_connect(sid).expect("couldn't connect to server");
+
+            let start = std::time::Instant::now();
+            assert_eq!(
+                xous_kernel::send_message_timeout(
+                    conn,
+                    xous_kernel::Message::BlockingScalar(xous_kernel::ScalarMessage {
+                        id: 1,
+                        arg1: 2,
+                        arg2: 3,
+                        arg3: 4,
+                        arg4: 5,
+                    }),
+                    50,
+                ),
+                Err(xous_kernel::Error::Timeout)
+            );
+            assert!(start.elapsed() >= std::time::Duration::from_millis(50));
+            timed_out_send.send(()).unwrap();
+
+            let result = xous_kernel::send_message_timeout(
+                conn,
+                xous_kernel::Message::BlockingScalar(xous_kernel::ScalarMessage {
+                    id: 6,
+                    arg1: 7,
+                    arg2: 8,
+                    arg3: 9,
+                    arg4: 10,
+                }),
+                10_000,
+            )
+            .expect("couldn't send message");
+            assert_eq!(result, xous_kernel::Result::Scalar1(42));
+
+            let start = std::time::Instant::now();
+            assert_eq!(
+                xous_kernel::send_message_timeout(
+                    conn,
+                    xous_kernel::Message::BlockingScalar(xous_kernel::ScalarMessage {
+                        id: 12,
+                        arg1: 0,
+                        arg2: 0,
+                        arg3: 0,
+                        arg4: 0,
+                    }),
+                    50,
+                ),
+                Err(xous_kernel::Error::Timeout)
+            );
+            assert!(start.elapsed() >= std::time::Duration::from_millis(50));
+            late_send.send(()).unwrap();
+
+            // The late reply of 99 must not be mistaken for this one
+            let result = xous_kernel::send_message_timeout(
+                conn,
+                xous_kernel::Message::BlockingScalar(xous_kernel::ScalarMessage {
+                    id: 13,
+                    arg1: 0,
+                    arg2: 0,
+                    arg3: 0,
+                    arg4: 0,
+                }),
+                10_000,
+            )
+            .expect("couldn't send message");
+            assert_eq!(result, xous_kernel::Result::Scalar1(43));
+
+            // Only blocking scalars can be sent with a timeout
+            assert_eq!(
+                xous_kernel::send_message_timeout(
+                    conn,
+                    xous_kernel::Message::Scalar(xous_kernel::ScalarMessage {
+                        id: 11,
+                        arg1: 0,
+                        arg2: 0,
+                        arg3: 0,
+                        arg4: 0,
+                    }),
+                    50,
+                ),
+                Err(xous_kernel::Error::InvalidSyscall)
+            );
+        },
+    ))
+    .expect("couldn't spawn client process");
+
+    crate::wait_process_as_thread(xous_server).expect("couldn't join server process");
+    crate::wait_process_as_thread(xous_client).expect("couldn't join client process");
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
+
+#[cfg(feature = "ipc-trace")]
+#[test]
+fn ipc_trace() {
+    let main_thread = start_kernel(SERVER_SPEC);
+
+    let xous_server = xous_kernel::create_process_as_thread(xous_kernel::ProcessArgsAsThread::new(
+        "ipc_trace server",
+        move || {
+            let pid = xous_kernel::current_pid().expect("couldn't get process ID");
+            let sid = xous_kernel::create_server().expect("couldn't create test server");
+            let conn = xous_kernel::try_connect(sid).expect("couldn't connect to our own server");
+            let (_, start) = xous_kernel::ipc_trace_range().expect("couldn't get trace range");
+
+            xous_kernel::try_send_message(
+                conn,
+                xous_kernel::Message::Scalar(xous_kernel::ScalarMessage {
+                    id: 5,
+                    arg1: 0,
+                    arg2: 0,
+                    arg3: 0,
+                    arg4: 0,
+                }),
+            )
+            .expect("couldn't send message");
+            xous_kernel::receive_message(sid).expect("couldn't receive message");
+
+            let client = xous_kernel::create_thread(move || {
+                xous_kernel::send_message(
+                    conn,
+                    xous_kernel::Message::BlockingScalar(xous_kernel::ScalarMessage {
+                        id: 6,
+                        arg1: 0,
+                        arg2: 0,
+                        arg3: 0,
+                        arg4: 0,
+                    }),
+                )
+                .expect("couldn't send message")
+            })
+            .expect("couldn't spawn client thread");
+            let envelope = xous_kernel::receive_message(sid).expect("couldn't receive message");
+            xous_kernel::return_scalar(envelope.sender, 7).expect("couldn't return scalar");
+            xous_kernel::wait_thread(client).expect("couldn't wait for thread");
+
+            let (oldest, next) = xous_kernel::ipc_trace_range().expect("couldn't get trace range");
+            assert!(oldest <= start);
+            let events: Vec<(xous_kernel::IpcTraceKind, usize)> = (start..next)
+                .filter_map(|seq| xous_kernel::ipc_trace_event(seq).expect("couldn't read trace"))
+                .filter(|event| event.sid_prefix == sid.to_u32().0)
+                .map(|event| {
+                    assert_eq!(event.pid, pid);
+                    assert_eq!(event.peer, Some(pid));
+                    (event.kind, event.opcode)
+                })
+                .collect();
+            assert_eq!(
+                events,
+                vec![
+                    (xous_kernel::IpcTraceKind::Send, 5),
+                    (xous_kernel::IpcTraceKind::Receive, 5),
+                    (xous_kernel::IpcTraceKind::BlockingSend, 6),
+                    (xous_kernel::IpcTraceKind::Receive, 6),
+                    (xous_kernel::IpcTraceKind::Return, 0),
+                ]
+            );
+            assert_eq!(xous_kernel::ipc_trace_event(next), Ok(None));
+        },
+    ))
+    .expect("couldn't spawn server process");
+
+    xous_kernel::wait_process_as_thread(xous_server).expect("couldn't join server process");
+    shutdown_kernel();
+
+    main_thread.join().expect("couldn't join kernel process");
+}
@@ -0,0 +1,43 @@
+use xous_kernel::IpcTraceEvent;
+
+/// How many IPC events are kept. Older events are overwritten.
+pub const IPC_TRACE_LEN: usize = 256;
+
+/// A ring buffer of the most recent IPC events. Each event gets a sequence
+/// number, starting from zero, so a reader can tell whether it has missed any.
+pub struct IpcTrace {
+    events: [Option<IpcTraceEvent>; IPC_TRACE_LEN],
+
+    /// The sequence number the next event will get
+    next: usize,
+}
+
+impl IpcTrace {
+    pub const fn new() -> Self {
+        IpcTrace {
+            events: [None; IPC_TRACE_LEN],
+            next: 0,
+        }
+    }
+
+    /// Add an event to the buffer, overwriting the oldest one if it's full
+    pub fn record(&mut self, event: IpcTraceEvent) {
+        self.events[self.next % IPC_TRACE_LEN] = Some(event);
+        self.next = self.next.wrapping_add(1);
+    }
+
+    /// Returns the sequence number of the oldest event still in the buffer, and
+    /// the sequence number of the next event to be recorded.
+    pub fn range(&self) -> (usize, usize) {
+        (self.next.saturating_sub(IPC_TRACE_LEN), self.next)
+    }
+
+    /// Returns the event with the given sequence number, if it's still in the buffer
+    pub fn get(&self, seq: usize) -> Option<IpcTraceEvent> {
+        let (oldest, next) = self.range();
+        if seq < oldest || seq >= next {
+            return None;
+        }
+        self.events[seq % IPC_TRACE_LEN]
+    }
+}
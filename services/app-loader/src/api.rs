@@ -0,0 +1,54 @@
+pub(crate) const SERVER_NAME_APP_LOADER: &str = "_App loader_";
+
+/// The PDDB dictionary that holds the signed app images. Each key is one app, and the key's
+/// name is the name the app is listed under.
+pub const APP_DICT: &str = "sys.apps";
+/// Apps with names this long or longer are not listed
+pub const APP_NAME_LEN: usize = 64;
+pub(crate) const APP_LIST_LEN: usize = 16;
+
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+pub(crate) enum Opcode {
+    /// List the apps stored in the PDDB, a page at a time
+    ListApps,
+    /// Verify, load and start an app
+    Launch,
+    /// Exits the server
+    Quit,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub enum LoadError {
+    /// The PDDB isn't mounted, so there are no apps to load
+    NotMounted,
+    /// There is no app by that name
+    NotFound,
+    /// The image isn't signed with a key the loader accepts
+    BadSignature,
+    /// The image isn't a 32-bit RISC-V ELF executable
+    BadElf,
+    /// The image would overlap the startup stub or the stack, or mixes code and writable data in one page
+    BadLayout,
+    /// The process couldn't be created, or couldn't be loaded
+    SpawnFailed,
+    /// Apps can't be started in hosted mode
+    Unsupported,
+    /// The loader couldn't be reached
+    InternalError,
+}
+
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct AppList {
+    pub start: u32,
+    pub total: u32,
+    pub list: [Option<xous_ipc::String<APP_NAME_LEN>>; APP_LIST_LEN],
+}
+
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct LaunchRequest {
+    pub name: xous_ipc::String<APP_NAME_LEN>,
+    /// raw PID of the new process, filled in by the loader
+    pub pid: Option<u8>,
+    /// why the app couldn't be started, filled in by the loader
+    pub error: Option<LoadError>,
+}
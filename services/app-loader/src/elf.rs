@@ -0,0 +1,107 @@
+//! Lays out the loadable segments of an app's ELF image as the page-aligned blocks of memory that the
+//! startup stub copies into the new process.
+
+use core::ops::Range;
+use xmas_elf::program::Type as ProgramType;
+use xmas_elf::ElfFile;
+
+use crate::api::LoadError;
+
+const PAGE_SIZE: usize = 4096;
+/// `EI_CLASS` for 32-bit images
+const ELFCLASS32: u8 = 1;
+/// `e_machine` for RISC-V
+const EM_RISCV: u16 = 243;
+
+/// A page-aligned block of the new process' memory, along with its contents
+pub(crate) struct Block {
+    pub virt: usize,
+    pub data: Vec<u8>,
+    /// true if the block holds code. Code is mapped read-only, so a block can't hold both.
+    pub executable: bool,
+    writable: bool,
+}
+
+pub(crate) struct AppLayout {
+    pub entry_point: usize,
+    pub blocks: Vec<Block>,
+}
+
+/// Parses `image` and lays out its loadable segments. Segments that share a page end up in the same
+/// block, because the stub can only map each page once. `reserved` lists the address ranges that the
+/// app must stay clear of, such as the stub itself and the stack.
+pub(crate) fn layout(image: &[u8], reserved: &[Range<usize>]) -> Result<AppLayout, LoadError> {
+    // xmas-elf checks the magic number, but not what the image was built for
+    if image.len() < 20 || image[4] != ELFCLASS32 || u16::from_le_bytes([image[18], image[19]]) != EM_RISCV {
+        log::warn!("app is not a 32-bit RISC-V ELF image");
+        return Err(LoadError::BadElf);
+    }
+    let elf = ElfFile::new(image).map_err(|e| {
+        log::warn!("couldn't parse app: {}", e);
+        LoadError::BadElf
+    })?;
+    let entry_point = elf.header.pt2.entry_point() as usize;
+
+    let mut segments = Vec::new();
+    for ph in elf.program_iter() {
+        if ph.get_type() != Ok(ProgramType::Load) || ph.mem_size() == 0 {
+            continue;
+        }
+        let virt = ph.virtual_addr() as usize;
+        let mem_size = ph.mem_size() as usize;
+        let file_size = ph.file_size() as usize;
+        let offset = ph.offset() as usize;
+        if file_size > mem_size || offset.checked_add(file_size).map_or(true, |end| end > image.len()) {
+            log::warn!("app segment at {:08x} points outside of the image", virt);
+            return Err(LoadError::BadElf);
+        }
+        segments.push((virt, mem_size, &image[offset..offset + file_size], ph.flags()));
+    }
+    segments.sort_by_key(|&(virt, _, _, _)| virt);
+
+    let mut blocks: Vec<Block> = Vec::new();
+    for (virt, mem_size, contents, flags) in segments {
+        let start = virt & !(PAGE_SIZE - 1);
+        let end = virt
+            .checked_add(mem_size)
+            .and_then(|end| end.checked_add(PAGE_SIZE - 1))
+            .ok_or(LoadError::BadLayout)?
+            & !(PAGE_SIZE - 1);
+        if start == 0 || reserved.iter().any(|r| start < r.end && r.start < end) {
+            log::warn!("app segment {:08x}-{:08x} overlaps memory the loader needs", start, end);
+            return Err(LoadError::BadLayout);
+        }
+        // start a new block, unless this segment shares a page with the previous one
+        let shares_page = blocks.last().map_or(false, |last| start < last.virt + last.data.len());
+        if !shares_page {
+            blocks.push(Block {
+                virt: start,
+                data: Vec::new(),
+                executable: false,
+                writable: false,
+            });
+        }
+        let block = blocks.last_mut().unwrap();
+        if end - block.virt > block.data.len() {
+            block.data.resize(end - block.virt, 0);
+        }
+        block.executable |= flags.is_execute();
+        block.writable |= flags.is_write();
+        if block.executable && block.writable {
+            log::warn!("app has code and writable data in the same page at {:08x}", block.virt);
+            return Err(LoadError::BadLayout);
+        }
+        // anything past the end of the file contents, such as .bss, stays zeroed
+        let at = virt - block.virt;
+        block.data[at..at + contents.len()].copy_from_slice(contents);
+    }
+
+    if !blocks
+        .iter()
+        .any(|b| b.executable && b.virt <= entry_point && entry_point < b.virt + b.data.len())
+    {
+        log::warn!("app entry point {:08x} is not in its code", entry_point);
+        return Err(LoadError::BadElf);
+    }
+    Ok(AppLayout { entry_point, blocks })
+}
@@ -0,0 +1,80 @@
+#![cfg_attr(target_os = "none", no_std)]
+
+pub mod api;
+pub use api::*;
+use xous::CID;
+use xous_ipc::Buffer;
+use num_traits::ToPrimitive;
+
+pub struct AppLoader {
+    conn: CID,
+}
+impl AppLoader {
+    /// The loader is an optional part of the image, so unlike most servers this doesn't wait for it
+    /// to show up: it returns `ServerNotFound` if the loader isn't running.
+    pub fn new(xns: &xous_names::XousNames) -> Result<Self, xous::Error> {
+        let conn = xns.request_connection(api::SERVER_NAME_APP_LOADER)?;
+        REFCOUNT.fetch_add(1, Ordering::Relaxed);
+        Ok(AppLoader {
+            conn
+        })
+    }
+
+    /// Lists the apps stored in the PDDB, in alphabetical order. Signatures are only checked when an app is
+    /// launched, so an app in this list may still be refused. The list is empty if the PDDB isn't mounted.
+    pub fn list_apps(&self) -> Result<Vec<String>, xous::Error> {
+        let mut names = Vec::new();
+        loop {
+            let request = api::AppList {
+                start: names.len() as u32,
+                total: 0,
+                list: Default::default(),
+            };
+            let mut buf = Buffer::into_buf(request).or(Err(xous::Error::InternalError))?;
+            buf.lend_mut(self.conn, api::Opcode::ListApps.to_u32().unwrap())
+                .or(Err(xous::Error::InternalError))?;
+            let page = buf.to_original::<api::AppList, _>().unwrap();
+            let before = names.len();
+            names.extend(page.list.iter().flatten().map(|name| String::from(name.as_str().unwrap_or(""))));
+            // stop at the end of the list, or if it shrank while we were paging through it
+            if names.len() == before || names.len() >= page.total as usize {
+                break;
+            }
+        }
+        Ok(names)
+    }
+
+    /// Checks the signature on the app stored under `name`, loads it into a new process and starts it.
+    /// Returns the PID of the new process.
+    pub fn launch(&self, name: &str) -> Result<xous::PID, LoadError> {
+        if name.len() >= APP_NAME_LEN {
+            return Err(LoadError::NotFound);
+        }
+        let request = api::LaunchRequest {
+            name: xous_ipc::String::from_str(name),
+            pid: None,
+            error: None,
+        };
+        let mut buf = Buffer::into_buf(request).or(Err(LoadError::InternalError))?;
+        buf.lend_mut(self.conn, api::Opcode::Launch.to_u32().unwrap())
+            .or(Err(LoadError::InternalError))?;
+        let response = buf.to_original::<api::LaunchRequest, _>().unwrap();
+        match (response.pid.and_then(xous::PID::new), response.error) {
+            (Some(pid), None) => Ok(pid),
+            (_, Some(e)) => Err(e),
+            (None, None) => Err(LoadError::InternalError),
+        }
+    }
+}
+
+use core::sync::atomic::{AtomicU32, Ordering};
+static REFCOUNT: AtomicU32 = AtomicU32::new(0);
+impl Drop for AppLoader {
+    fn drop(&mut self) {
+        // the connection to the server side must be reference counted, so that multiple instances of this object within
+        // a single process do not end up de-allocating the CID on other threads before they go out of scope.
+        if REFCOUNT.fetch_sub(1, Ordering::Relaxed) == 1 {
+            unsafe{xous::disconnect(self.conn).unwrap();}
+        }
+    }
+}
@@ -0,0 +1,214 @@
+#![cfg_attr(target_os = "none", no_std)]
+#![cfg_attr(target_os = "none", no_main)]
+
+mod api;
+use api::*;
+mod elf;
+
+use num_traits::*;
+use xous_ipc::Buffer;
+use std::convert::TryInto;
+use std::io::Read;
+
+use log::info;
+
+/// Size of the signature block at the start of a signed image, see `tools/src/bin/sign-image.rs`
+const SIGBLOCK_SIZE: usize = 0x1000;
+/// `sign-image` appends its version and the length of the image to the data it signs
+const SIGNED_TRAILER_LEN: usize = 8;
+
+#[cfg(any(target_os = "none", target_os = "xous"))]
+mod implementation {
+    use core::ops::Range;
+    use crate::api::LoadError;
+    use crate::elf::AppLayout;
+
+    /// The startup stub from `services/test-spawn`, which is linked to run at `STUB_ADDRESS`. It takes
+    /// the app's memory over IPC and then jumps to its entry point.
+    const STUB: &[u8] = include_bytes!("../../test-spawn/src/spawn-stub");
+    const STUB_ADDRESS: usize = 0x2050_1000;
+    /// Where `ProcessArgs` puts the stack by default
+    const STACK_TOP: usize = 0x8000_0000;
+    const STACK_SIZE: usize = 128 * 1024;
+
+    // opcodes understood by the stub, see `services/test-spawn/spawn/README.md`
+    const STUB_WRITE_MEMORY: usize = 1;
+    const STUB_WRITE_TEXT: usize = 5;
+    const STUB_FINISH_STARTUP: usize = 255;
+
+    /// The address ranges an app can't be loaded into
+    pub fn reserved() -> [Range<usize>; 2] {
+        [
+            STUB_ADDRESS..STUB_ADDRESS + ((STUB.len() + 0xFFF) & !0xFFF),
+            // everything from the stack up is off limits, including the kernel
+            STACK_TOP - STACK_SIZE..usize::MAX,
+        ]
+    }
+
+    pub fn spawn(layout: AppLayout) -> Result<xous::PID, LoadError> {
+        let args = xous::ProcessArgs::new(
+            STUB,
+            xous::MemoryAddress::new(STUB_ADDRESS).unwrap(),
+            xous::MemoryAddress::new(STUB_ADDRESS).unwrap(),
+        );
+        let process = xous::create_process(args).map_err(|e| {
+            log::error!("couldn't create a process for the app: {:?}", e);
+            LoadError::SpawnFailed
+        })?;
+
+        for block in layout.blocks.iter() {
+            let mut buf = xous::map_memory(
+                None,
+                None,
+                block.data.len(),
+                xous::MemoryFlags::R | xous::MemoryFlags::W,
+            ).map_err(|_| LoadError::SpawnFailed)?;
+            buf.as_slice_mut::<u8>()[..block.data.len()].copy_from_slice(&block.data);
+            let result = xous::send_message(
+                process.cid,
+                xous::Message::Borrow(xous::MemoryMessage {
+                    id: if block.executable { STUB_WRITE_TEXT } else { STUB_WRITE_MEMORY },
+                    buf,
+                    // the stub maps the block at this address in the new process
+                    offset: xous::MemoryAddress::new(block.virt),
+                    valid: None,
+                }),
+            );
+            xous::unmap_memory(buf).ok();
+            if let Err(e) = result {
+                // the half-loaded process is left waiting in the stub, where it does no harm
+                log::error!("couldn't load app block at {:08x} into PID {}: {:?}", block.virt, process.pid, e);
+                return Err(LoadError::SpawnFailed);
+            }
+        }
+
+        xous::send_message(
+            process.cid,
+            xous::Message::new_scalar(STUB_FINISH_STARTUP, layout.entry_point, 0, 0, 0),
+        ).map_err(|_| LoadError::SpawnFailed)?;
+        Ok(process.pid)
+    }
+}
+
+// a stub to try to avoid breaking hosted mode for as long as possible.
+#[cfg(not(any(target_os = "none", target_os = "xous")))]
+mod implementation {
+    use core::ops::Range;
+    use crate::api::LoadError;
+    use crate::elf::AppLayout;
+
+    pub fn reserved() -> [Range<usize>; 0] {
+        []
+    }
+
+    pub fn spawn(_layout: AppLayout) -> Result<xous::PID, LoadError> {
+        log::warn!("apps can't be loaded in hosted mode");
+        Err(LoadError::Unsupported)
+    }
+}
+
+/// Lists the apps in the PDDB. Names that are too long to launch are left out.
+fn list_apps(pddb: &pddb::Pddb, poller: &pddb::PddbMountPoller) -> Vec<String> {
+    if !poller.is_mounted_nonblocking() {
+        return Vec::new();
+    }
+    // a missing dictionary just means no apps have been stored yet
+    let mut names = pddb.list_keys(APP_DICT, None).unwrap_or_default();
+    names.retain(|name| name.len() < APP_NAME_LEN);
+    names.sort();
+    names
+}
+
+fn launch(pddb: &pddb::Pddb, poller: &pddb::PddbMountPoller, keys: &root_keys::RootKeys, name: &str) -> Result<xous::PID, LoadError> {
+    if !poller.is_mounted_nonblocking() {
+        return Err(LoadError::NotMounted);
+    }
+    let mut key = pddb.get(APP_DICT, name, None, false, false, None, None::<fn()>)
+        .map_err(|_| LoadError::NotFound)?;
+    let mut image = Vec::new();
+    key.read_to_end(&mut image).map_err(|_| LoadError::NotFound)?;
+
+    match keys.check_app_signature(&image) {
+        Ok(Some(root_keys::api::AppSignature::SelfSign)) => info!("{} is self-signed", name),
+        Ok(Some(root_keys::api::AppSignature::ThirdParty)) => info!("{} is signed with the third-party key", name),
+        #[cfg(feature = "devkey-apps")]
+        Ok(Some(root_keys::api::AppSignature::Developer)) => log::warn!("{} is signed with the developer key", name),
+        Ok(result) => {
+            log::warn!("refusing to load {}, signature check result: {:?}", name, result);
+            return Err(LoadError::BadSignature);
+        }
+        Err(e) => {
+            log::error!("couldn't check the signature on {}: {:?}", name, e);
+            return Err(LoadError::BadSignature);
+        }
+    }
+
+    // the signed data is the ELF image followed by the trailer
+    let signed_len = u32::from_le_bytes(image[4..8].try_into().unwrap()) as usize;
+    let elf = signed_len
+        .checked_sub(SIGNED_TRAILER_LEN)
+        .and_then(|len| image.get(SIGBLOCK_SIZE..SIGBLOCK_SIZE + len))
+        .ok_or(LoadError::BadElf)?;
+    let layout = elf::layout(elf, &implementation::reserved())?;
+    let pid = implementation::spawn(layout)?;
+    info!("started {} as PID {}", name, pid);
+    Ok(pid)
+}
+
+fn main() -> ! {
+    log_server::init_wait().unwrap();
+    log::set_max_level(log::LevelFilter::Info);
+    info!("my PID is {}", xous::process::id());
+
+    let xns = xous_names::XousNames::new().unwrap();
+    // only the status bar, which puts the apps in the app menu, needs to talk to the loader
+    let loader_sid = xns.register_name(api::SERVER_NAME_APP_LOADER, Some(1)).expect("can't register server");
+    log::trace!("registered with NS -- {:?}", loader_sid);
+
+    let keys = root_keys::RootKeys::new(&xns, None).expect("couldn't connect to the root keys server");
+    let pddb = pddb::Pddb::new();
+    let poller = pddb::PddbMountPoller::new();
+    // the list is paged out to the caller, so it's kept between calls to give the caller a consistent view
+    let mut listing = Vec::<String>::new();
+
+    log::trace!("ready to accept requests");
+    loop {
+        let mut msg = xous::receive_message(loader_sid).unwrap();
+        match FromPrimitive::from_usize(msg.body.id()) {
+            Some(Opcode::ListApps) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut list = buffer.to_original::<AppList, _>().unwrap();
+                if list.start == 0 {
+                    listing = list_apps(&pddb, &poller);
+                }
+                list.total = listing.len() as u32;
+                for (entry, name) in list.list.iter_mut().zip(listing.iter().skip(list.start as usize)) {
+                    *entry = Some(xous_ipc::String::from_str(name));
+                }
+                buffer.replace(list).unwrap();
+            }
+            Some(Opcode::Launch) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut request = buffer.to_original::<LaunchRequest, _>().unwrap();
+                match launch(&pddb, &poller, &keys, request.name.as_str().unwrap_or("")) {
+                    Ok(pid) => request.pid = Some(pid.get()),
+                    Err(e) => request.error = Some(e),
+                }
+                buffer.replace(request).unwrap();
+            }
+            Some(Opcode::Quit) => {
+                log::warn!("Quit received, goodbye world!");
+                break;
+            }
+            None => {
+                log::error!("couldn't convert opcode: {:?}", msg);
+            }
+        }
+    }
+    // clean up our program
+    log::trace!("main loop exit, destroying servers");
+    xns.unregister_server(loader_sid).unwrap();
+    xous::destroy_server(loader_sid).unwrap();
+    log::trace!("quitting");
+    xous::terminate_process(0)
+}
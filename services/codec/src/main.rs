@@ -29,6 +29,8 @@ fn main() -> ! {
     // unlimited connections allowed; authentication via token is used
     let codec_sid = xns.register_name(api::SERVER_NAME_CODEC, None).expect("can't register server");
     log::trace!("registered with NS -- {:?}", codec_sid);
+    // audio frames have hard deadlines, so the codec gets ahead of everything else when it's ready
+    xous::set_thread_priority(xous::current_tid().unwrap(), xous::ThreadPriority::Realtime).expect("couldn't set codec thread priority");
 
     let codec_conn = xous::connect(codec_sid).expect("couldn't make connection for the codec implementation");
     let mut codec = Codec::new(codec_conn, &xns);
@@ -3,6 +3,7 @@
 pub(crate) const SERVER_NAME_DNS: &str = "_DNS Resolver Middleware_";
 use net::NetIpAddr;
 use rkyv::{Archive, Deserialize, Serialize};
+use std::convert::TryInto;
 
 #[allow(dead_code)]
 pub(crate) const DNS_NAME_LENGTH_LIMIT: usize = 256;
@@ -49,6 +50,86 @@ pub(crate) enum Opcode {
     ///     * 4: Ipv4 Address -- 4 octets follow, for a total of 5 bytes
     ///     * 6: Ipv6 Address -- 16 octets follow, for a total of 17 bytes
     RawLookup = 6,
+
+    /// Perform a DNS lookup for a record type other than an address, and return
+    /// the records in a raw format. `lookup_txt()`, `lookup_mx()` and `lookup_srv()`
+    /// in the library wrap this into structured records.
+    ///
+    /// The query should be a `MutableBorrow` of a page-sized buffer. The first two
+    /// octets are the `QueryType` (little endian), the next two octets are the length
+    /// of the name (little endian), and the name follows starting at offset 4.
+    ///
+    /// The result overwrites the query, and follows the same conventions as `RawLookup`:
+    /// the first field is `0` on success and `1` on error, and the second field
+    /// is the number of records or the `DnsResponseCode`, respectively.
+    ///
+    /// # Success
+    ///
+    /// A series of records begins at offset 2. Each record is the `QueryType` as
+    /// a single octet, followed by the length of the record data as two octets (little endian),
+    /// followed by the record data as encoded by `DnsRecord::encode()`.
+    TypedLookup = 7,
+
+    /// Publish a service over mDNS / DNS-SD. The argument is an `MdnsServiceIpc`, lent with `lend_mut` so
+    /// the server can report back whether it was accepted in the `port` field, which is zeroed on failure.
+    MdnsRegister = 8,
+
+    /// Withdraw a service published by `MdnsRegister`. Only the `instance` and `service` fields are used.
+    MdnsUnregister = 9,
+
+    /// Browse the local link for instances of a service, such as `_http._tcp`.
+    ///
+    /// The query should be a `MutableBorrow` of a page-sized buffer. Offsets 0..2 are unused, the
+    /// next two octets are the length of the service type (little endian), and the service type
+    /// follows starting at offset 4.
+    ///
+    /// The result overwrites the query with the same conventions as `TypedLookup`, except that
+    /// each record is a two-octet length (little endian) followed by an `MdnsInstance` as
+    /// encoded by `MdnsInstance::encode()`.
+    MdnsBrowse = 10,
+
+    /// Set the name that the device answers to as `<name>.local`. The argument is a `String<MDNS_LABEL_LIMIT>`,
+    /// lent with `lend_mut`; it is replaced by the name in effect afterwards, which is unchanged if the
+    /// requested name wasn't a valid host name.
+    MdnsSetHostname = 11,
+
+    /// Get the name that the device answers to, without the `.local` suffix.
+    MdnsGetHostname = 12,
+}
+
+/// The longest label that a DNS name can contain, which bounds mDNS host and instance names
+pub(crate) const MDNS_LABEL_LIMIT: usize = 63;
+/// The most TXT strings that can be published with a service
+pub(crate) const MDNS_TXT_LIMIT: usize = 8;
+
+/// A service to publish with mDNS, as sent with `MdnsRegister`
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
+pub(crate) struct MdnsServiceIpc {
+    /// the user-visible name of the instance, e.g. "Precursor demo"
+    pub instance: xous_ipc::String<MDNS_LABEL_LIMIT>,
+    /// the service type, e.g. "_http._tcp"
+    pub service: xous_ipc::String<MDNS_LABEL_LIMIT>,
+    pub port: u16,
+    /// key=value pairs for the TXT record
+    pub txt: [Option<xous_ipc::String<MDNS_LABEL_LIMIT>>; MDNS_TXT_LIMIT],
+}
+
+/// Record types that can be queried. Only the types that the resolver understands are listed.
+#[allow(dead_code)]
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u16)]
+pub(crate) enum QueryType {
+    A = 1,
+    // NS = 2,
+    // MD = 3,
+    // MF = 4,
+    CNAME = 5,
+    // SOA = 6,
+    PTR = 12,
+    MX = 15,
+    TXT = 16,
+    AAAA = 28,
+    SRV = 33,
 }
 
 #[derive(
@@ -80,3 +161,160 @@ pub struct DnsResponse {
     pub addr: Option<NetIpAddr>,
     pub code: DnsResponseCode,
 }
+
+/// A mail exchanger for a domain, as returned by `Dns::lookup_mx()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MxRecord {
+    /// lower values are preferred
+    pub preference: u16,
+    pub exchange: std::string::String,
+}
+
+/// The location of a service, as returned by `Dns::lookup_srv()`. See RFC 2782.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrvRecord {
+    /// lower values are preferred
+    pub priority: u16,
+    /// relative weight for records with the same priority
+    pub weight: u16,
+    pub port: u16,
+    pub target: std::string::String,
+}
+
+/// A structured record returned by a `TypedLookup`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DnsRecord {
+    /// The character-strings of the record, concatenated without separators (as is done for SPF, RFC 7208).
+    /// Octets that aren't valid UTF-8 are replaced.
+    Txt(std::string::String),
+    Mx(MxRecord),
+    Srv(SrvRecord),
+}
+#[allow(dead_code)]
+impl DnsRecord {
+    pub(crate) fn query_type(&self) -> QueryType {
+        match self {
+            DnsRecord::Txt(_) => QueryType::TXT,
+            DnsRecord::Mx(_) => QueryType::MX,
+            DnsRecord::Srv(_) => QueryType::SRV,
+        }
+    }
+    /// Appends the record data, without the type and length fields, to `out`. Numbers are little endian,
+    /// and names and text run to the end of the record.
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            DnsRecord::Txt(text) => out.extend_from_slice(text.as_bytes()),
+            DnsRecord::Mx(mx) => {
+                out.extend_from_slice(&mx.preference.to_le_bytes());
+                out.extend_from_slice(mx.exchange.as_bytes());
+            }
+            DnsRecord::Srv(srv) => {
+                out.extend_from_slice(&srv.priority.to_le_bytes());
+                out.extend_from_slice(&srv.weight.to_le_bytes());
+                out.extend_from_slice(&srv.port.to_le_bytes());
+                out.extend_from_slice(srv.target.as_bytes());
+            }
+        }
+    }
+    /// The inverse of `encode()`. Returns `None` if the data is malformed.
+    pub(crate) fn decode(qtype: QueryType, data: &[u8]) -> Option<Self> {
+        let u16_at = |i: usize| data.get(i..i + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+        let str_from = |i: usize| data.get(i..).and_then(|b| core::str::from_utf8(b).ok()).map(std::string::String::from);
+        match qtype {
+            QueryType::TXT => str_from(0).map(DnsRecord::Txt),
+            QueryType::MX => Some(DnsRecord::Mx(MxRecord {
+                preference: u16_at(0)?,
+                exchange: str_from(2)?,
+            })),
+            QueryType::SRV => Some(DnsRecord::Srv(SrvRecord {
+                priority: u16_at(0)?,
+                weight: u16_at(2)?,
+                port: u16_at(4)?,
+                target: str_from(6)?,
+            })),
+            _ => None,
+        }
+    }
+}
+
+/// An instance of a service found by `Dns::mdns_browse()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MdnsInstance {
+    /// the user-visible name of the instance, e.g. "Precursor demo"
+    pub instance: std::string::String,
+    /// the host that runs the instance, e.g. "precursor-1234.local"
+    pub host: std::string::String,
+    pub port: u16,
+    /// the addresses of the host that were learned while browsing; this may be empty
+    pub addrs: Vec<std::net::IpAddr>,
+    /// the strings of the TXT record, usually key=value pairs
+    pub txt: Vec<std::string::String>,
+}
+#[allow(dead_code)]
+impl MdnsInstance {
+    /// Appends the instance to `out`. Strings are preceded by their length as one octet, the
+    /// port is little endian, and addresses are tagged as they are for `RawLookup`.
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        fn push_str(out: &mut Vec<u8>, s: &str) {
+            let bytes = &s.as_bytes()[..s.len().min(u8::MAX as usize)];
+            out.push(bytes.len() as u8);
+            out.extend_from_slice(bytes);
+        }
+        push_str(out, &self.instance);
+        push_str(out, &self.host);
+        out.extend_from_slice(&self.port.to_le_bytes());
+        out.push(self.addrs.len().min(u8::MAX as usize) as u8);
+        for addr in self.addrs.iter().take(u8::MAX as usize) {
+            match addr {
+                std::net::IpAddr::V4(v4) => {
+                    out.push(4);
+                    out.extend_from_slice(&v4.octets());
+                }
+                std::net::IpAddr::V6(v6) => {
+                    out.push(6);
+                    out.extend_from_slice(&v6.octets());
+                }
+            }
+        }
+        out.push(self.txt.len().min(u8::MAX as usize) as u8);
+        for txt in self.txt.iter().take(u8::MAX as usize) {
+            push_str(out, txt);
+        }
+    }
+    /// The inverse of `encode()`. Returns `None` if the data is malformed.
+    pub(crate) fn decode(data: &[u8]) -> Option<Self> {
+        fn take<'a>(data: &'a [u8], index: &mut usize, len: usize) -> Option<&'a [u8]> {
+            let slice = data.get(*index..*index + len)?;
+            *index += len;
+            Some(slice)
+        }
+        fn take_str(data: &[u8], index: &mut usize) -> Option<std::string::String> {
+            let len = take(data, index, 1)?[0] as usize;
+            core::str::from_utf8(take(data, index, len)?).ok().map(std::string::String::from)
+        }
+        let mut index = 0;
+        let instance = take_str(data, &mut index)?;
+        let host = take_str(data, &mut index)?;
+        let port = take(data, &mut index, 2).map(|b| u16::from_le_bytes([b[0], b[1]]))?;
+        let mut addrs = Vec::new();
+        for _ in 0..take(data, &mut index, 1)?[0] {
+            let addr = match take(data, &mut index, 1)?[0] {
+                4 => {
+                    let octets: [u8; 4] = take(data, &mut index, 4)?.try_into().ok()?;
+                    std::net::IpAddr::from(octets)
+                }
+                6 => {
+                    let octets: [u8; 16] = take(data, &mut index, 16)?.try_into().ok()?;
+                    std::net::IpAddr::from(octets)
+                }
+                _ => return None,
+            };
+            addrs.push(addr);
+        }
+        let mut txt = Vec::new();
+        for _ in 0..take(data, &mut index, 1)?[0] {
+            txt.push(take_str(data, &mut index)?);
+        }
+        Some(MdnsInstance { instance, host, port, addrs, txt })
+    }
+}
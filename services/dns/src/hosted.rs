@@ -1,6 +1,6 @@
 use net::NetIpAddr;
 use std::net::ToSocketAddrs;
-use crate::DnsResponseCode;
+use crate::{DnsResponseCode, MdnsInstance, MxRecord, SrvRecord};
 
 #[derive(Debug)]
 pub struct Dns {
@@ -34,6 +34,38 @@ impl Dns {
             }
         }
     }
+    pub fn lookup_txt(&self, name: &str) -> Result<Vec<String>, DnsResponseCode> {
+        log::warn!("TXT lookup of {} not implemented in hosted mode!", name);
+        Err(DnsResponseCode::NotImplemented)
+    }
+    pub fn lookup_mx(&self, name: &str) -> Result<Vec<MxRecord>, DnsResponseCode> {
+        log::warn!("MX lookup of {} not implemented in hosted mode!", name);
+        Err(DnsResponseCode::NotImplemented)
+    }
+    pub fn lookup_srv(&self, name: &str) -> Result<Vec<SrvRecord>, DnsResponseCode> {
+        log::warn!("SRV lookup of {} not implemented in hosted mode!", name);
+        Err(DnsResponseCode::NotImplemented)
+    }
+    pub fn mdns_register(&self, instance: &str, service: &str, _port: u16, _txt: &[&str]) -> Result<(), xous::Error> {
+        log::warn!("mDNS registration of {}.{} not implemented in hosted mode!", instance, service);
+        Err(xous::Error::UnhandledSyscall)
+    }
+    pub fn mdns_unregister(&self, instance: &str, service: &str) -> Result<(), xous::Error> {
+        log::warn!("mDNS unregistration of {}.{} not implemented in hosted mode!", instance, service);
+        Err(xous::Error::UnhandledSyscall)
+    }
+    pub fn mdns_browse(&self, service: &str) -> Result<Vec<MdnsInstance>, DnsResponseCode> {
+        log::warn!("mDNS browsing for {} not implemented in hosted mode!", service);
+        Err(DnsResponseCode::NotImplemented)
+    }
+    pub fn mdns_hostname(&self) -> Result<String, xous::Error> {
+        log::warn!("mDNS not implemented in hosted mode!");
+        Err(xous::Error::UnhandledSyscall)
+    }
+    pub fn mdns_set_hostname(&self, _name: &str) -> Result<(), xous::Error> {
+        log::warn!("mDNS not implemented in hosted mode!");
+        Err(xous::Error::UnhandledSyscall)
+    }
     pub fn flush_cache(&self) -> Result<(), xous::Error> {
         log::warn!("DNS cache flush not implemented in hosted mode!");
         Ok(())
@@ -1,7 +1,7 @@
 #![cfg_attr(target_os = "none", no_std)]
 use xous::CID;
 use xous_ipc::{Buffer, String};
-use num_traits::ToPrimitive;
+use num_traits::{ToPrimitive, FromPrimitive};
 
 use net::NetIpAddr;
 use std::net::IpAddr;
@@ -39,6 +39,154 @@ impl Dns {
             }
         }
     }
+    fn lookup_typed(&self, name: &str, qtype: QueryType) -> Result<Vec<DnsRecord>, DnsResponseCode> {
+        if name.len() < 1 || name.len() > DNS_NAME_LENGTH_LIMIT {
+            return Err(DnsResponseCode::FormatError);
+        }
+        let mut buf = Buffer::new(4096);
+        {
+            let raw = buf.as_mut();
+            raw[..2].copy_from_slice(&(qtype as u16).to_le_bytes());
+            raw[2..4].copy_from_slice(&(name.len() as u16).to_le_bytes());
+            raw[4..4 + name.len()].copy_from_slice(name.as_bytes());
+        }
+        buf.lend_mut(self.conn, Opcode::TypedLookup.to_u32().unwrap())
+            .or(Err(DnsResponseCode::UnknownError))?;
+        let raw = buf.as_ref();
+        if raw[0] != 0 {
+            return Err(FromPrimitive::from_u8(raw[1]).unwrap_or(DnsResponseCode::UnknownError));
+        }
+        let mut records = Vec::<DnsRecord>::new();
+        let mut index = 2;
+        for _ in 0..raw[1] {
+            let header = raw.get(index..index + 3).ok_or(DnsResponseCode::UnknownError)?;
+            let rtype: Option<QueryType> = FromPrimitive::from_u8(header[0]);
+            let len = u16::from_le_bytes([header[1], header[2]]) as usize;
+            index += 3;
+            let record = rtype
+                .and_then(|rtype| DnsRecord::decode(rtype, raw.get(index..index + len)?))
+                .ok_or(DnsResponseCode::UnknownError)?;
+            records.push(record);
+            index += len;
+        }
+        Ok(records)
+    }
+    /// Returns the text of every TXT record of `name`.
+    pub fn lookup_txt(&self, name: &str) -> Result<Vec<std::string::String>, DnsResponseCode> {
+        Ok(self.lookup_typed(name, QueryType::TXT)?.into_iter().filter_map(|r|
+            if let DnsRecord::Txt(text) = r { Some(text) } else { None }
+        ).collect())
+    }
+    /// Returns the mail exchangers of `name`, sorted by preference.
+    pub fn lookup_mx(&self, name: &str) -> Result<Vec<MxRecord>, DnsResponseCode> {
+        let mut mx: Vec<MxRecord> = self.lookup_typed(name, QueryType::MX)?.into_iter().filter_map(|r|
+            if let DnsRecord::Mx(mx) = r { Some(mx) } else { None }
+        ).collect();
+        mx.sort_by_key(|r| r.preference);
+        Ok(mx)
+    }
+    /// Returns the SRV records of `name`, which is of the form `_service._proto.domain`. Records are
+    /// sorted by priority; picking among records of the same priority by weight is left to the caller.
+    pub fn lookup_srv(&self, name: &str) -> Result<Vec<SrvRecord>, DnsResponseCode> {
+        let mut srv: Vec<SrvRecord> = self.lookup_typed(name, QueryType::SRV)?.into_iter().filter_map(|r|
+            if let DnsRecord::Srv(srv) = r { Some(srv) } else { None }
+        ).collect();
+        srv.sort_by_key(|r| r.priority);
+        Ok(srv)
+    }
+    /// Publishes a service with mDNS / DNS-SD, so that it can be found by browsing for `service`.
+    /// `instance` is the user-visible name of the service, which must not contain dots, and
+    /// `service` is the service type, e.g. `_http._tcp`. Up to eight strings of at most 63 bytes can be published
+    /// in the TXT record of the service; these are usually of the form `key=value`.
+    pub fn mdns_register(&self, instance: &str, service: &str, port: u16, txt: &[&str]) -> Result<(), xous::Error> {
+        if txt.len() > MDNS_TXT_LIMIT
+        || instance.len() > MDNS_LABEL_LIMIT || service.len() > MDNS_LABEL_LIMIT
+        || txt.iter().any(|s| s.len() > MDNS_LABEL_LIMIT) {
+            return Err(xous::Error::InvalidString);
+        }
+        let mut record = MdnsServiceIpc {
+            instance: String::<MDNS_LABEL_LIMIT>::from_str(instance),
+            service: String::<MDNS_LABEL_LIMIT>::from_str(service),
+            port,
+            txt: [None; MDNS_TXT_LIMIT],
+        };
+        for (dest, src) in record.txt.iter_mut().zip(txt.iter()) {
+            *dest = Some(String::<MDNS_LABEL_LIMIT>::from_str(src));
+        }
+        let mut buf = Buffer::into_buf(record).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, Opcode::MdnsRegister.to_u32().unwrap())?;
+        let response = buf.to_original::<MdnsServiceIpc, _>().or(Err(xous::Error::InternalError))?;
+        if response.port == port && port != 0 {
+            Ok(())
+        } else {
+            Err(xous::Error::InvalidString)
+        }
+    }
+    /// Withdraws a service published with `mdns_register()`.
+    pub fn mdns_unregister(&self, instance: &str, service: &str) -> Result<(), xous::Error> {
+        let record = MdnsServiceIpc {
+            instance: String::<MDNS_LABEL_LIMIT>::from_str(instance),
+            service: String::<MDNS_LABEL_LIMIT>::from_str(service),
+            port: 0,
+            txt: [None; MDNS_TXT_LIMIT],
+        };
+        let buf = Buffer::into_buf(record).or(Err(xous::Error::InternalError))?;
+        buf.lend(self.conn, Opcode::MdnsUnregister.to_u32().unwrap()).map(|_| ())
+    }
+    /// Finds the instances of `service`, e.g. `_http._tcp`, on the local network. This takes a
+    /// second or two, as the responses of other hosts are collected.
+    pub fn mdns_browse(&self, service: &str) -> Result<Vec<MdnsInstance>, DnsResponseCode> {
+        if service.len() < 1 || service.len() > DNS_NAME_LENGTH_LIMIT {
+            return Err(DnsResponseCode::FormatError);
+        }
+        let mut buf = Buffer::new(4096);
+        {
+            let raw = buf.as_mut();
+            raw[2..4].copy_from_slice(&(service.len() as u16).to_le_bytes());
+            raw[4..4 + service.len()].copy_from_slice(service.as_bytes());
+        }
+        buf.lend_mut(self.conn, Opcode::MdnsBrowse.to_u32().unwrap())
+            .or(Err(DnsResponseCode::UnknownError))?;
+        let raw = buf.as_ref();
+        if raw[0] != 0 {
+            return Err(FromPrimitive::from_u8(raw[1]).unwrap_or(DnsResponseCode::UnknownError));
+        }
+        let mut instances = Vec::<MdnsInstance>::new();
+        let mut index = 2;
+        for _ in 0..raw[1] {
+            let header = raw.get(index..index + 2).ok_or(DnsResponseCode::UnknownError)?;
+            let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+            index += 2;
+            let instance = raw.get(index..index + len)
+                .and_then(MdnsInstance::decode)
+                .ok_or(DnsResponseCode::UnknownError)?;
+            instances.push(instance);
+            index += len;
+        }
+        Ok(instances)
+    }
+    /// The name that this device answers to with mDNS, without the `.local` suffix.
+    pub fn mdns_hostname(&self) -> Result<std::string::String, xous::Error> {
+        let mut buf = Buffer::into_buf(String::<MDNS_LABEL_LIMIT>::new()).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, Opcode::MdnsGetHostname.to_u32().unwrap())?;
+        let name = buf.to_original::<String<MDNS_LABEL_LIMIT>, _>().or(Err(xous::Error::InternalError))?;
+        Ok(std::string::String::from(name.as_str().unwrap_or("")))
+    }
+    /// Sets the name that this device answers to with mDNS. `name` is a host name without a domain,
+    /// so it may only contain letters, digits and hyphens.
+    pub fn mdns_set_hostname(&self, name: &str) -> Result<(), xous::Error> {
+        if name.len() > MDNS_LABEL_LIMIT {
+            return Err(xous::Error::InvalidString);
+        }
+        let mut buf = Buffer::into_buf(String::<MDNS_LABEL_LIMIT>::from_str(name)).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, Opcode::MdnsSetHostname.to_u32().unwrap())?;
+        let set = buf.to_original::<String<MDNS_LABEL_LIMIT>, _>().or(Err(xous::Error::InternalError))?;
+        if set.as_str().unwrap_or("") == name {
+            Ok(())
+        } else {
+            Err(xous::Error::InvalidString)
+        }
+    }
     pub fn flush_cache(&self) -> Result<(), xous::Error> {
         xous::send_message(
             self.conn,
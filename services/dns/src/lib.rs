@@ -1,6 +1,7 @@
 pub mod api;
 #[allow(unused_imports)]
 use api::*;
+pub use api::{DnsRecord, MdnsInstance, MxRecord, SrvRecord};
 
 #[cfg(any(target_os = "none", target_os = "xous"))]
 mod hw;
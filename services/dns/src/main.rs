@@ -3,6 +3,9 @@
 
 mod api;
 use api::*;
+mod message;
+use message::*;
+mod mdns;
 
 use net::NetIpAddr;
 use num_traits::*;
@@ -10,305 +13,25 @@ use xous::msg_scalar_unpack;
 
 use std::collections::HashMap;
 use std::convert::TryInto;
-use std::io::ErrorKind;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
-use std::time::Duration;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
 use std::thread;
 use xous_ipc::{Buffer, String};
 
-// KISS DNS
-
-// The DNS implementation here is based on https://github.com/vinc/moros/blob/43ac7cdc8ccc860dc1b6f0f060b5dbcd01424c03/src/usr/host.rs
-// MOROS is MIT licensed.
-// See RFC 1035 for implementation details
-
-#[repr(u16)]
-enum QueryType {
-    A = 1,
-    // NS = 2,
-    // MD = 3,
-    // MF = 4,
-    // CNAME = 5,
-    // SOA = 6,
-    // MX = 15,
-    // TXT = 16,
-}
-
-#[repr(u16)]
-enum QueryClass {
-    IN = 1,
-}
-
-struct Message {
-    pub datagram: Vec<u8>,
-}
-
-const FLAG_RD: u16 = 0x0100; // Recursion desired
-
-impl Message {
-    pub fn from(datagram: &[u8]) -> Self {
-        Self {
-            datagram: Vec::from(datagram),
-        }
-    }
-
-    pub fn query(qname: &str, qtype: QueryType, qclass: QueryClass, id: u16) -> Self {
-        let mut datagram = Vec::new();
-
-        for b in id.to_be_bytes().iter() {
-            datagram.push(*b); // Transaction ID
-        }
-        for b in FLAG_RD.to_be_bytes().iter() {
-            datagram.push(*b); // Flags
-        }
-        for b in (1 as u16).to_be_bytes().iter() {
-            datagram.push(*b); // Questions
-        }
-        for _ in 0..6 {
-            datagram.push(0); // Answer + Authority + Additional
-        }
-        for label in qname.split('.') {
-            datagram.push(label.len() as u8); // QNAME label length
-            for b in label.bytes() {
-                datagram.push(b); // QNAME label bytes
-            }
-        }
-        datagram.push(0); // Root null label
-        for b in (qtype as u16).to_be_bytes().iter() {
-            datagram.push(*b); // QTYPE
-        }
-        for b in (qclass as u16).to_be_bytes().iter() {
-            datagram.push(*b); // QCLASS
-        }
-
-        Self { datagram }
-    }
-
-    pub fn id(&self) -> u16 {
-        u16::from_be_bytes(self.datagram[0..2].try_into().unwrap())
-    }
-
-    pub fn header(&self) -> u16 {
-        u16::from_be_bytes(self.datagram[2..4].try_into().unwrap())
-    }
-
-    pub fn is_response(&self) -> bool {
-        if (self.header() & (1 << 15)) == 0 {
-            false
-        } else {
-            true
-        }
-    }
-
-    fn fast_foward_name(&self, start: usize) -> Result<usize, DnsResponseCode> {
-        use DnsResponseCode::FormatError;
-        let mut index = start;
-        loop {
-            log::trace!("cname index: {}", index);
-            if *(self.datagram.get(index).ok_or(FormatError)?) == 0 {
-                index += 1;
-                break;
-            } else {
-                index += *(self.datagram.get(index).ok_or(FormatError)?) as usize;
-                index += 1;
-            }
-        }
-        Ok(index)
-    }
-
-    pub fn parse_response(&self) -> Result<HashMap<IpAddr, u32>, DnsResponseCode> {
-        use DnsResponseCode::FormatError;
-        log::trace!("parsing packet: {:?}", self.datagram);
-
-        let mut map = HashMap::<IpAddr, u32>::new();
-        // ASSUME: the query ID and response bit fields have already been checked
-        // and that the rcode is valid
-        let qdcount = u16::from_be_bytes(self.datagram[4..6].try_into().unwrap());
-        let ancount = u16::from_be_bytes(self.datagram[6..8].try_into().unwrap());
-
-        let mut index = 12;
-        // fast forward past the qname
-        for queries in 0..qdcount {
-            log::trace!("parsing query{}, index {}", queries, index);
-            index = self.fast_foward_name(index)?;
-            log::trace!("fast forward through qname to {}", index);
-            // index is now at qtype
-            let qtype = u16::from_be_bytes(self.datagram[index..index + 2].try_into().unwrap());
-            // A = 1, AAAA = 28
-            if qtype != 1 && qtype != 28 {
-                log::error!("Problem parsing qname, qtype is not 1 or 28: {}", qtype);
-                return Err(FormatError);
-            }
-            index += 2;
-            let qclass = u16::from_be_bytes(self.datagram[index..index + 2].try_into().unwrap());
-            if qclass != 1 {
-                log::error!("Problem parsing qname, qclass is not 1: {}", qclass);
-                return Err(FormatError);
-            }
-            index += 2;
-        }
-        // index is now at the aname section
-        for aname in 0..ancount {
-            log::trace!("parsing aname{}, index {}", aname, index);
-            // first check to see if we're dealing with a pointer or a name
-            if self.datagram[index] >= 0xc0 {
-                // pointer
-                index += 1;
-                if self.datagram[index] != 0xc {
-                    log::error!(
-                        "Found aname pointer, but value does not conform to length of aname header"
-                    );
-                    return Err(FormatError);
-                }
-                index += 1;
-            } else {
-                // name, fast forward past the name
-                index = self.fast_foward_name(index)?;
-                log::trace!("fast forward aname to {}", index);
-            }
-            // index is now at type
-            let atype = u16::from_be_bytes(self.datagram[index..index + 2].try_into().unwrap());
-            // A = 1, AAAA = 28
-            if atype != 1 && atype != 28 {
-                log::error!("Problem parsing aname, type is not 1 or 28: {}", atype);
-                return Err(FormatError);
-            }
-            index += 2;
-            let aclass = u16::from_be_bytes(self.datagram[index..index + 2].try_into().unwrap());
-            if aclass != 1 {
-                log::error!("Problem parsing aname, aclass is not 1: {}", aclass);
-                return Err(FormatError);
-            }
-            index += 2;
-            // this is our TTL
-            let ttl = u32::from_be_bytes(self.datagram[index..index + 4].try_into().unwrap());
-            log::trace!("got ttl: {}", ttl);
-            index += 4;
-            // this is the payload length
-            let addr_len = u16::from_be_bytes(self.datagram[index..index + 2].try_into().unwrap());
-            index += 2;
-            match addr_len {
-                // ipv4
-                4 => {
-                    if atype != 1 {
-                        log::error!("Got a 4-byte address, but ATYPE != A (1)");
-                        return Err(FormatError);
-                    }
-                    // this copy happens because I can't figure out how to get Ipv4Addr::from() to realize it's casting from a [u8;4]
-                    let mut rdata: [u8; 4] = [0; 4];
-                    for (&src, dst) in self.datagram[index..index + 4].iter().zip(rdata.iter_mut())
-                    {
-                        *dst = src;
-                    }
-                    let addr = IpAddr::V4(Ipv4Addr::from(rdata));
-                    index += 4;
-                    map.insert(addr, ttl);
-                }
-                // ipv6
-                16 => {
-                    if atype != 28 {
-                        log::error!("Got a 16-byte address, but ATYPE != AAAA (28)");
-                        return Err(FormatError);
-                    }
-                    // this copy happens because I can't figure out how to get Ipv6Addr::from() to realize it's casting from a [u8;4]
-                    let mut rdata: [u8; 16] = [0; 16];
-                    for (&src, dst) in self.datagram[index..index + 16]
-                        .iter()
-                        .zip(rdata.iter_mut())
-                    {
-                        *dst = src;
-                    }
-                    let addr = IpAddr::V6(Ipv6Addr::from(rdata));
-                    index += 16;
-                    map.insert(addr, ttl);
-                }
-                _ => {
-                    log::error!("Length field does not match a known record type");
-                    return Err(FormatError);
-                }
-            }
-        }
-
-        Ok(map)
-    }
-
-    /*
-         example response for: betrusted.io->185.199.111.153
-    Header:
-          61, ca,   id
-          81, 80,   header
-          0, 1,     qdcount
-          0, 4,     ancount
-          0, 0,     nscount
-          0, 0,     arcount
-    qname:
-          9,        length 9
-          62, 65, 74, 72, 75, 73, 74, 65, 64,    "betrusted"
-          2,        length 2
-          69, 6f,   "io"
-          0,        end of name
-    qtype:
-          0, 1,     type A
-    qclass:
-          0, 1,     type IN
-    aname0:
-          c0,       name is a pointer (any value > 192 is a pointer)
-          c,        offset of 12 from start of aname0
-          0, 1,     type A
-          0, 1,     class IN
-          0, 0, e, 10,   0xe10 = 3600 seconds TTL
-          0, 4,     4 bytes address
-          b9, c7, 6c, 99,  address
-    aname1:
-          c0,       name is a pointer
-          c,
-          0, 1,     type A
-          0, 1,     class IN
-          0, 0, e, 10,  TTL
-          0, 4,     4 byte address
-          b9, c7, 6d, 99,  address
-    aname2:
-          c0,
-          c,
-          0, 1,
-          0, 1,
-          0, 0, e, 10,
-          0, 4,
-          b9, c7, 6e, 99,
-    aname3:
-          c0,
-          c,
-          0, 1,
-          0, 1,
-          0, 0, e, 10,
-          0, 4,
-          b9, c7, 6f, 99
-         */
-
-    /*
-    pub fn is_query(&self) -> bool {
-        !self.is_response()
-    }
-    */
-
-    pub fn rcode(&self) -> DnsResponseCode {
-        match (self.header() >> 11) & 0xF {
-            0 => DnsResponseCode::NoError,
-            1 => DnsResponseCode::FormatError,
-            2 => DnsResponseCode::ServerFailure,
-            3 => DnsResponseCode::NameError,
-            4 => DnsResponseCode::NotImplemented,
-            5 => DnsResponseCode::Refused,
-            _ => DnsResponseCode::UnknownError,
-        }
-    }
-}
+/// Maximum number of queries made to follow a chain of CNAMEs that the server did not resolve for us
+const CNAME_CHASE_LIMIT: usize = 8;
+/// How long to wait for a server to respond to a query, over either UDP or TCP
+const DNS_TIMEOUT_MS: u64 = 10_000;
+/// The UDP payload size advertised with EDNS0. This is the size recommended by DNS flag day 2020,
+/// which avoids IP fragmentation on practically every path.
+const DNS_EDNS_PAYLOAD_LEN: usize = 1232;
 
 pub struct Resolver {
     /// DnsServerManager is a service of the Net crate that automatically updates the DNS server list
     mgr: net::DnsServerManager,
     socket: UdpSocket,
-    buf: [u8; DNS_PKT_MAX_LEN],
+    buf: [u8; DNS_EDNS_PAYLOAD_LEN],
     trng: trng::Trng,
     freeze: bool,
 }
@@ -320,7 +43,7 @@ impl Resolver {
             format!("0.0.0.0:{}", local_port),
         )
         .expect("couldn't create socket for DNS resolver");
-        let timeout = Duration::from_millis(10_000); // 10 seconds for DNS to resolve by default
+        let timeout = Duration::from_millis(DNS_TIMEOUT_MS); // 10 seconds for DNS to resolve by default
         socket.set_read_timeout(Some(timeout)).unwrap();
         socket.set_nonblocking(false).unwrap(); // we want this to block.
                                                 // we /could/ do a non-blocking DNS resolver, but...what would you do in the meantime??
@@ -330,7 +53,7 @@ impl Resolver {
             mgr: net::DnsServerManager::register(&xns)
                 .expect("Couldn't register the DNS server list auto-manager"),
             socket,
-            buf: [0; DNS_PKT_MAX_LEN],
+            buf: [0; DNS_EDNS_PAYLOAD_LEN],
             trng,
             freeze: false,
         }
@@ -355,41 +78,190 @@ impl Resolver {
     pub fn trng_u32(&self) -> u32 {
         self.trng.get_u32().unwrap()
     }
-    pub fn resolve(&mut self, name: &str) -> Result<HashMap<IpAddr, u32>, DnsResponseCode> {
-        if let Some(dns_address) = self.mgr.get_random() {
-            let dns_port = 53;
-            let server = SocketAddr::new(dns_address, dns_port);
-
-            let qname = name;
-            let qtype = QueryType::A;
-            let qclass = QueryClass::IN;
-            let query = Message::query(qname, qtype, qclass, self.trng.get_u32().unwrap() as u16);
-
-            self.socket
-                .send_to(&query.datagram, &server)
-                .map_err(|_| DnsResponseCode::NetworkError)?;
-
+    /// Sends all of `queries` over UDP, and then waits for their responses, which may come back in any order.
+    /// Returns the outcome of each query, in the same order as `queries`.
+    fn exchange_udp(&mut self, server: &SocketAddr, queries: &[&Message]) -> Vec<Result<Message, DnsResponseCode>> {
+        let mut responses: Vec<Option<Result<Message, DnsResponseCode>>> = queries.iter().map(|_| None).collect();
+        for (query, response) in queries.iter().zip(responses.iter_mut()) {
+            if self.socket.send_to(&query.datagram, server).is_err() {
+                *response = Some(Err(DnsResponseCode::NetworkError));
+            }
+        }
+        // the read timeout applies to every datagram, so it's cut down to what's left of the overall
+        // deadline before each read; otherwise a trickle of stray responses could keep us here forever
+        let deadline = Instant::now() + Duration::from_millis(DNS_TIMEOUT_MS);
+        while responses.iter().any(|r| r.is_none()) {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            if self.socket.set_read_timeout(Some((deadline - now).max(Duration::from_millis(1)))).is_err() {
+                log::warn!("couldn't set the DNS socket read timeout");
+            }
             match self.socket.recv(&mut self.buf) {
                 Ok(len) => {
                     let message = Message::from(&self.buf[..len]);
-                    if message.id() == query.id() && message.is_response() {
-                        return match message.rcode() {
-                            DnsResponseCode::NoError => message.parse_response(),
-                            rcode => Err(rcode),
-                        };
-                    } else {
-                        Err(DnsResponseCode::NetworkError)
+                    match queries.iter().position(|query| message.is_response_to(query)) {
+                        Some(index) if responses[index].is_none() => responses[index] = Some(Ok(message)),
+                        // most likely a late answer to a query that already timed out
+                        _ => log::debug!("discarding a response that doesn't match any outstanding query"),
+                    }
+                }
+                Err(e) => {
+                    let code = match e.kind() {
+                        ErrorKind::WouldBlock => DnsResponseCode::NetworkError,
+                        _ => DnsResponseCode::UnknownError,
+                    };
+                    for response in responses.iter_mut().filter(|r| r.is_none()) {
+                        *response = Some(Err(code));
                     }
                 }
-                Err(e) => match e.kind() {
-                    ErrorKind::WouldBlock => Err(DnsResponseCode::NetworkError),
-                    _ => Err(DnsResponseCode::UnknownError),
-                },
             }
+        }
+        responses.into_iter().map(|r| r.unwrap_or(Err(DnsResponseCode::NetworkError))).collect()
+    }
+    /// Sends `query` over TCP (RFC 7766), which is used when a UDP response comes back truncated.
+    /// A new connection is made for every query, as they are rare enough that it isn't worth keeping one open.
+    fn exchange_tcp(&mut self, server: &SocketAddr, query: &Message) -> Result<Message, DnsResponseCode> {
+        let timeout = Duration::from_millis(DNS_TIMEOUT_MS);
+        let mut stream = TcpStream::connect_timeout(server, timeout)
+            .map_err(|_| DnsResponseCode::NetworkError)?;
+        stream.set_read_timeout(Some(timeout)).map_err(|_| DnsResponseCode::UnknownError)?;
+        stream.set_write_timeout(Some(timeout)).map_err(|_| DnsResponseCode::UnknownError)?;
+
+        stream.write_all(&query.to_tcp()).map_err(|_| DnsResponseCode::NetworkError)?;
+        let mut len = [0u8; 2];
+        stream.read_exact(&mut len).map_err(|_| DnsResponseCode::NetworkError)?;
+        let mut datagram = vec![0u8; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut datagram).map_err(|_| DnsResponseCode::NetworkError)?;
+        stream.shutdown(Shutdown::Both).ok();
+
+        let message = Message::from(&datagram);
+        if message.is_response_to(query) {
+            Ok(message)
         } else {
-            Err(DnsResponseCode::NoServerSpecified)
+            Err(DnsResponseCode::NetworkError)
+        }
+    }
+    /// Sends a query for each of `questions` to one of the configured servers, and returns the answer
+    /// section of each response, in the same order as `questions`. All the queries are sent before
+    /// waiting on any of the responses.
+    ///
+    /// Queries go out over UDP with an EDNS0 OPT record, so the server can send responses larger than 512 bytes.
+    /// If the server doesn't understand EDNS0, the query is repeated without it; if the response is
+    /// still too large for UDP, the query is repeated over TCP.
+    fn query(&mut self, questions: &[(&str, QueryType)]) -> Vec<Result<Vec<ResourceRecord>, DnsResponseCode>> {
+        let dns_address = match self.mgr.get_random() {
+            Some(addr) => addr,
+            None => return questions.iter().map(|_| Err(DnsResponseCode::NoServerSpecified)).collect(),
+        };
+        let dns_port = 53;
+        let server = SocketAddr::new(dns_address, dns_port);
+
+        let qclass = QueryClass::IN;
+        // responses are matched up by ID alone, so the IDs of outstanding queries must differ
+        let mut ids = Vec::<u16>::new();
+        while ids.len() < questions.len() {
+            let id = self.trng.get_u32().unwrap() as u16;
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+        let queries: Vec<Message> = questions.iter().zip(ids.iter())
+            .map(|(&(name, qtype), &id)| Message::query(name, qtype, qclass, id).with_edns(DNS_EDNS_PAYLOAD_LEN as u16))
+            .collect();
+        let responses = self.exchange_udp(&server, &queries.iter().collect::<Vec<&Message>>());
+
+        let mut answers = Vec::new();
+        for ((&(name, qtype), &id), (query, response)) in questions.iter().zip(ids.iter()).zip(queries.iter().zip(responses.into_iter())) {
+            answers.push(self.finish_query(&server, name, qtype, id, query, response));
+        }
+        answers
+    }
+    /// Takes care of the EDNS0 and TCP fallbacks for a response to `query`, and decodes the answers.
+    fn finish_query(&mut self, server: &SocketAddr, name: &str, qtype: QueryType, id: u16, query: &Message,
+        response: Result<Message, DnsResponseCode>
+    ) -> Result<Vec<ResourceRecord>, DnsResponseCode> {
+        let mut message = response?;
+        let mut plain_query = None;
+        if let DnsResponseCode::FormatError = message.rcode() {
+            log::info!("{} rejected an EDNS0 query, retrying without", server.ip());
+            let retry = Message::query(name, qtype, QueryClass::IN, id);
+            message = self.exchange_udp(server, &[&retry]).pop().unwrap()?;
+            plain_query = Some(retry);
+        }
+        if message.is_truncated() {
+            log::debug!("response for {} is truncated, retrying over TCP", name);
+            message = self.exchange_tcp(server, plain_query.as_ref().unwrap_or(query))?;
+        }
+        match message.rcode() {
+            DnsResponseCode::NoError => message.parse_response(),
+            rcode => Err(rcode),
         }
     }
+    /// Looks up the records of type `qtype` for `name`, following CNAMEs. Recursive servers usually
+    /// include the records for the canonical name along with the alias, but if they don't, the
+    /// canonical name is queried in turn.
+    pub fn lookup(&mut self, name: &str, qtype: QueryType) -> Result<Vec<ResourceRecord>, DnsResponseCode> {
+        self.lookup_many(name, &[qtype]).pop().unwrap()
+    }
+    /// Like `lookup()`, for several record types at once. The queries for the different types are sent
+    /// together, so this takes about as long as looking up a single type.
+    pub fn lookup_many(&mut self, name: &str, qtypes: &[QueryType]) -> Vec<Result<Vec<ResourceRecord>, DnsResponseCode>> {
+        let mut results: Vec<Option<Result<Vec<ResourceRecord>, DnsResponseCode>>> = qtypes.iter().map(|_| None).collect();
+        let mut current: Vec<std::string::String> = qtypes.iter().map(|_| std::string::String::from(name)).collect();
+        for _ in 0..CNAME_CHASE_LIMIT {
+            let pending: Vec<usize> = (0..qtypes.len()).filter(|&i| results[i].is_none()).collect();
+            if pending.len() == 0 {
+                break;
+            }
+            let questions: Vec<(&str, QueryType)> = pending.iter().map(|&i| (current[i].as_str(), qtypes[i])).collect();
+            let answers = self.query(&questions);
+            for (&i, answer) in pending.iter().zip(answers.into_iter()) {
+                let records = match answer {
+                    Ok(records) => records,
+                    Err(e) => {
+                        results[i] = Some(Err(e));
+                        continue;
+                    }
+                };
+                let canonical = canonical_name(&records, &current[i]);
+                let matches: Vec<ResourceRecord> = records
+                    .into_iter()
+                    .filter(|rr| rr.is_type(qtypes[i]) && rr.name.eq_ignore_ascii_case(&canonical))
+                    .collect();
+                if matches.len() > 0 || canonical.eq_ignore_ascii_case(&current[i]) {
+                    results[i] = Some(Ok(matches));
+                } else {
+                    log::debug!("chasing CNAME {} -> {}", current[i], canonical);
+                    current[i] = canonical;
+                }
+            }
+        }
+        results.into_iter().map(|result| result.unwrap_or_else(|| {
+            log::warn!("CNAME chain for {} is too long", name);
+            Err(DnsResponseCode::ServerFailure)
+        })).collect()
+    }
+    /// Resolves `name` to both its IPv4 and IPv6 addresses. Either family may be missing, but
+    /// an error is returned if neither lookup succeeded. Both lookups are in flight at the same time.
+    pub fn resolve(&mut self, name: &str) -> Result<HashMap<IpAddr, u32>, DnsResponseCode> {
+        let mut map = HashMap::<IpAddr, u32>::new();
+        let mut results = self.lookup_many(name, &[QueryType::A, QueryType::AAAA]).into_iter();
+        let v4 = results.next().unwrap();
+        let v6 = results.next().unwrap();
+        if let (Err(e), Err(_)) = (&v4, &v6) {
+            return Err(*e);
+        }
+        for rr in v4.unwrap_or_default().into_iter().chain(v6.unwrap_or_default().into_iter()) {
+            match rr.data {
+                RecordData::A(addr) => { map.insert(IpAddr::V4(addr), rr.ttl); }
+                RecordData::Aaaa(addr) => { map.insert(IpAddr::V6(addr), rr.ttl); }
+                _ => {}
+            }
+        }
+        Ok(map)
+    }
 }
 
 #[derive(PartialEq, Debug)]
@@ -403,6 +275,9 @@ enum NameConversionError {
 
     /// The message was not a mutable memory message
     InvalidMessageType = 4,
+
+    /// The record type can't be looked up with a `TypedLookup`
+    InvalidQueryType = 5,
 }
 
 fn name_from_msg(env: &xous::MessageEnvelope) -> Result<&str, NameConversionError> {
@@ -423,6 +298,94 @@ fn name_from_msg(env: &xous::MessageEnvelope) -> Result<&str, NameConversionErro
     Ok(name_string)
 }
 
+/// Decodes the name that starts at offset 4 of a `TypedLookup` or `MdnsBrowse` query, with its length at offset 2.
+fn query_name(s: &[u8]) -> Result<std::string::String, NameConversionError> {
+    if s.len() < 4 {
+        return Err(NameConversionError::InvalidMemoryBuffer);
+    }
+    let name_len = u16::from_le_bytes([s[2], s[3]]) as usize;
+    if name_len > DNS_NAME_LENGTH_LIMIT || name_len < 1 || 4 + name_len > s.len() {
+        log::error!("name length in query is invalid: {}", name_len);
+        return Err(NameConversionError::InvalidMemoryBuffer);
+    }
+    let name = core::str::from_utf8(&s[4..4 + name_len]).map_err(|_| NameConversionError::InvalidString)?;
+    Ok(std::string::String::from(name))
+}
+
+/// Decodes the query of a `TypedLookup`; see the `Opcode` documentation for the layout.
+fn typed_query_from_msg(env: &xous::MessageEnvelope) -> Result<(QueryType, std::string::String), NameConversionError> {
+    let msg = env
+        .body
+        .memory_message()
+        .ok_or(NameConversionError::InvalidMessageType)?;
+    let s = msg.buf.as_slice::<u8>();
+    if s.len() < 4 {
+        return Err(NameConversionError::InvalidMemoryBuffer);
+    }
+    let qtype = match FromPrimitive::from_u16(u16::from_le_bytes([s[0], s[1]])) {
+        Some(qtype @ QueryType::TXT) | Some(qtype @ QueryType::MX) | Some(qtype @ QueryType::SRV) => qtype,
+        _ => return Err(NameConversionError::InvalidQueryType),
+    };
+    Ok((qtype, query_name(s)?))
+}
+
+/// Fills in the response to an `MdnsBrowse`. Instances that don't fit in the buffer are dropped.
+fn fill_browse_response(mut env: xous::MessageEnvelope, instances: &[MdnsInstance]) -> Option<()> {
+    let mem = env.body.memory_message_mut()?;
+    let s: &mut [u8] = mem.buf.as_slice_mut();
+
+    let mut index = 2;
+    let mut count = 0u8;
+    let mut encoded = Vec::<u8>::new();
+    for instance in instances.iter() {
+        encoded.clear();
+        instance.encode(&mut encoded);
+        if count == u8::MAX || index + 2 + encoded.len() > s.len() {
+            log::warn!("browse response is full, dropping {} instances", instances.len() - count as usize);
+            break;
+        }
+        s[index..index + 2].copy_from_slice(&(encoded.len() as u16).to_le_bytes());
+        s[index + 2..index + 2 + encoded.len()].copy_from_slice(&encoded);
+        index += 2 + encoded.len();
+        count += 1;
+    }
+    s[0] = 0;
+    s[1] = count;
+    None
+}
+
+/// Fills in the response to a `TypedLookup`. Records that don't fit in the buffer are dropped.
+fn fill_typed_response(mut env: xous::MessageEnvelope, records: &[ResourceRecord]) -> Option<()> {
+    let mem = env.body.memory_message_mut()?;
+    let s: &mut [u8] = mem.buf.as_slice_mut();
+
+    let mut index = 2;
+    let mut count = 0u8;
+    let mut encoded = Vec::<u8>::new();
+    for rr in records.iter() {
+        let record = match &rr.data {
+            RecordData::Txt(strings) => DnsRecord::Txt(strings.concat()),
+            RecordData::Mx(mx) => DnsRecord::Mx(mx.clone()),
+            RecordData::Srv(srv) => DnsRecord::Srv(srv.clone()),
+            _ => continue,
+        };
+        encoded.clear();
+        record.encode(&mut encoded);
+        if count == u8::MAX || encoded.len() > u16::MAX as usize || index + 3 + encoded.len() > s.len() {
+            log::warn!("typed lookup response is full, dropping {} records", records.len() - count as usize);
+            break;
+        }
+        s[index] = record.query_type() as u16 as u8;
+        s[index + 1..index + 3].copy_from_slice(&(encoded.len() as u16).to_le_bytes());
+        s[index + 3..index + 3 + encoded.len()].copy_from_slice(&encoded);
+        index += 3 + encoded.len();
+        count += 1;
+    }
+    s[0] = 0;
+    s[1] = count;
+    None
+}
+
 fn fill_response(mut env: xous::MessageEnvelope, entries: &HashMap<IpAddr, u32>) -> Option<()> {
     let mem = env.body.memory_message_mut()?;
 
@@ -441,8 +404,10 @@ fn fill_response(mut env: xous::MessageEnvelope, entries: &HashMap<IpAddr, u32>)
     }
     *i.next()? = entry_count.try_into().ok()?;
 
-    // Start filling in the addreses
-    for addr in entries.keys() {
+    // Start filling in the addreses. IPv4 goes first, as that is what callers are most likely to be able to reach.
+    let mut addrs: Vec<&IpAddr> = entries.keys().collect();
+    addrs.sort_by_key(|addr| addr.is_ipv6());
+    for addr in addrs.into_iter().take(entry_count) {
         match addr {
             &IpAddr::V4(a) => {
                 // IPv4
@@ -453,10 +418,10 @@ fn fill_response(mut env: xous::MessageEnvelope, entries: &HashMap<IpAddr, u32>)
             }
             &IpAddr::V6(a) => {
                 // IPv6
+                *i.next()? = 6;
                 for entry in a.octets() {
                     *i.next()? = entry;
                 }
-                *i.next()? = 6;
             }
         }
     }
@@ -479,6 +444,23 @@ fn fill_error(mut env: xous::MessageEnvelope, code: DnsResponseCode) -> Option<(
     None
 }
 
+/// Resolves `name` with mDNS if it's in the `.local` domain, and with the configured servers otherwise.
+fn resolve(resolver: &mut Resolver, mdns: &mdns::Mdns, name: &str) -> Result<HashMap<IpAddr, u32>, DnsResponseCode> {
+    if mdns::is_local(name) {
+        mdns.resolve(name)
+    } else {
+        resolver.resolve(name)
+    }
+}
+
+/// Picks a random address out of a non-empty cache entry. IPv4 addresses are preferred, as the
+/// `Lookup` API only returns a single address and not every network has an IPv6 route.
+fn pick_addr(resolver: &Resolver, entry: &HashMap<IpAddr, u32>) -> IpAddr {
+    let v4: Vec<&IpAddr> = entry.keys().filter(|addr| addr.is_ipv4()).collect();
+    let candidates = if v4.len() > 0 { v4 } else { entry.keys().collect() };
+    *candidates[resolver.trng_u32() as usize % candidates.len()]
+}
+
 fn main() -> ! {
     log_server::init_wait().unwrap();
     log::set_max_level(log::LevelFilter::Info);
@@ -495,6 +477,29 @@ fn main() -> ! {
     // if you wanted to force a server into the initial config, you can do it here, for example:
     // resolver.add_server(IpAddr::V4(Ipv4Addr::new(1,1,1,1)));
 
+    // answers for <hostname>.local and published services, and resolves other .local names
+    let mut mdns = mdns::Mdns::new();
+
+    // browsing waits a second or two for other hosts to answer, so it's done on a thread of its own to keep
+    // the rest of the server responsive. Browse requests are answered one at a time, in the order they arrive.
+    let browse_queue = {
+        let (tx, rx) = std::sync::mpsc::channel::<(xous::MessageEnvelope, std::string::String)>();
+        let querier = mdns.querier();
+        thread::spawn(move || {
+            for (msg, service_type) in rx.iter() {
+                match querier.browse(&service_type) {
+                    Ok(instances) => {
+                        fill_browse_response(msg, &instances);
+                    }
+                    Err(e) => {
+                        fill_error(msg, e);
+                    }
+                }
+            }
+        });
+        tx
+    };
+
     let mut dns_cache = HashMap::<std::string::String, HashMap<IpAddr, u32>>::new();
 
     // build a thread that pings the UpdateTtl function once every few minutes to expire the DNS cache
@@ -535,7 +540,7 @@ fn main() -> ! {
                         }
 
                         // This entry is not in the cache, so perform a lookup
-                        match resolver.resolve(&owned_name) {
+                        match resolve(&mut resolver, &mdns, &owned_name) {
                             Ok(cache_entry) => {
                                 fill_response(msg, &cache_entry);
                                 dns_cache.insert(owned_name, cache_entry);
@@ -554,6 +559,30 @@ fn main() -> ! {
                     }
                 };
             }
+            Some(Opcode::TypedLookup) => {
+                match typed_query_from_msg(&msg) {
+                    Ok((qtype, name)) => {
+                        log::trace!("performing a {:?} lookup of {}", qtype, name);
+                        match resolver.lookup(&name, qtype) {
+                            Ok(records) => {
+                                fill_typed_response(msg, &records);
+                            }
+                            Err(e) => {
+                                fill_error(msg, e);
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        log::error!("unable to do typed lookup: {:?}", e);
+                        let code = if e == NameConversionError::InvalidQueryType {
+                            DnsResponseCode::NotImplemented
+                        } else {
+                            DnsResponseCode::FormatError
+                        };
+                        fill_error(msg, code);
+                    }
+                }
+            }
             Some(Opcode::Lookup) => {
                 let mut buf = unsafe {
                     Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
@@ -564,20 +593,15 @@ fn main() -> ! {
                 let name_std = std::string::String::from(name.as_str().unwrap());
                 if let Some(cache_entry) = dns_cache.get(&name_std) {
                     // pick a random entry
-                    let rand = resolver.trng_u32() as usize % cache_entry.len();
-                    for (index, (ip_addr, _)) in cache_entry.iter().enumerate() {
-                        if rand == index {
-                            log::debug!("DNS cached: {}->{:?}", name, ip_addr);
-                            let response = DnsResponse {
-                                addr: Some(NetIpAddr::from(*ip_addr)),
-                                code: DnsResponseCode::NoError,
-                            };
-                            buf.replace(response).unwrap();
-                            break;
-                        }
-                    }
+                    let ip_addr = pick_addr(&resolver, cache_entry);
+                    log::debug!("DNS cached: {}->{:?}", name, ip_addr);
+                    let response = DnsResponse {
+                        addr: Some(NetIpAddr::from(ip_addr)),
+                        code: DnsResponseCode::NoError,
+                    };
+                    buf.replace(response).unwrap();
                 } else {
-                    match resolver.resolve(name.as_str().unwrap()) {
+                    match resolve(&mut resolver, &mdns, name.as_str().unwrap()) {
                         Ok(cache_entry) => {
                             if cache_entry.len() > 0 {
                                 dns_cache.insert(name_std, cache_entry);
@@ -587,17 +611,11 @@ fn main() -> ! {
                                 let cache_entry = dns_cache.get(&name_std).unwrap();
 
                                 // pick a random entry from the query response
-                                let rand = resolver.trng_u32() as usize % cache_entry.len();
-                                for (index, (ip_addr, _)) in cache_entry.iter().enumerate() {
-                                    if rand == index {
-                                        let response = DnsResponse {
-                                            addr: Some(NetIpAddr::from(*ip_addr)),
-                                            code: DnsResponseCode::NoError,
-                                        };
-                                        buf.replace(response).unwrap();
-                                        break;
-                                    }
-                                }
+                                let response = DnsResponse {
+                                    addr: Some(NetIpAddr::from(pick_addr(&resolver, cache_entry))),
+                                    code: DnsResponseCode::NoError,
+                                };
+                                buf.replace(response).unwrap();
                             } else {
                                 // no names found
                                 let response = DnsResponse {
@@ -618,6 +636,52 @@ fn main() -> ! {
                     }
                 }
             }
+            Some(Opcode::MdnsRegister) => {
+                let mut buf = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let mut service = buf.to_original::<MdnsServiceIpc, _>().unwrap();
+                if !mdns.register(mdns::Service::from(&service)) {
+                    service.port = 0;
+                }
+                buf.replace(service).unwrap();
+            }
+            Some(Opcode::MdnsUnregister) => {
+                let buf = unsafe {
+                    Buffer::from_memory_message(msg.body.memory_message().unwrap())
+                };
+                let service = buf.to_original::<MdnsServiceIpc, _>().unwrap();
+                mdns.unregister(service.instance.as_str().unwrap_or(""), service.service.as_str().unwrap_or(""));
+            }
+            Some(Opcode::MdnsBrowse) => {
+                let query = msg.body.memory_message()
+                    .ok_or(NameConversionError::InvalidMessageType)
+                    .and_then(|m| query_name(m.buf.as_slice::<u8>()));
+                match query {
+                    Ok(service_type) => {
+                        // the caller stays blocked until the browse thread drops the message
+                        browse_queue.send((msg, service_type)).expect("mDNS browse thread is gone");
+                    }
+                    Err(e) => {
+                        log::error!("unable to browse: {:?}", e);
+                        fill_error(msg, DnsResponseCode::FormatError);
+                    }
+                }
+            }
+            Some(Opcode::MdnsSetHostname) => {
+                let mut buf = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let name = buf.to_original::<String<MDNS_LABEL_LIMIT>, _>().unwrap();
+                mdns.set_hostname(name.as_str().unwrap_or(""));
+                buf.replace(String::<MDNS_LABEL_LIMIT>::from_str(&mdns.hostname())).unwrap();
+            }
+            Some(Opcode::MdnsGetHostname) => {
+                let mut buf = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                buf.replace(String::<MDNS_LABEL_LIMIT>::from_str(&mdns.hostname())).unwrap();
+            }
             Some(Opcode::UpdateTtl) => msg_scalar_unpack!(msg, incr_secs, _, _, _, {
                 let increment = if incr_secs < u32::MAX as usize {
                     incr_secs as u32
@@ -0,0 +1,784 @@
+use crate::api::*;
+use crate::message::*;
+
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Multicast DNS (RFC 6762) and DNS-Based Service Discovery (RFC 6763)
+//
+// A responder thread listens on the mDNS group. It answers questions about our host name and the
+// services that other servers have registered, and it caches the records that other hosts multicast.
+// Browsing and `.local` lookups are built on that cache: a query goes out, the caller waits a moment
+// for the answers to come back, and then reads them out of the cache.
+//
+// Probing for name conflicts (RFC 6762 section 8) and known-answer suppression are not implemented,
+// so two devices configured with the same host name will both answer to it.
+
+pub(crate) const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+pub(crate) const MDNS_PORT: u16 = 5353;
+/// mDNS packets are limited by the link MTU rather than the 512 bytes of unicast DNS
+const MDNS_PKT_MAX_LEN: usize = 1500;
+/// TTL of the records that name the host, as recommended in RFC 6762 section 10
+const HOST_TTL: u32 = 120;
+/// TTL of the records that describe services
+const SERVICE_TTL: u32 = 4500;
+/// Responses to legacy unicast queries must not be cached for long (RFC 6762 section 6.7)
+const LEGACY_TTL: u32 = 10;
+/// How long to collect responses after sending a query
+const QUERY_WAIT_MS: usize = 1000;
+/// How often the responder checks the address of the host, and the interval between announcements
+const POLL_MS: u64 = 1000;
+/// Number of times that new records are announced (RFC 6762 section 8.3)
+const ANNOUNCE_COUNT: u8 = 2;
+const CACHE_LIMIT: usize = 256;
+/// The name that enumerates the types of services that are published (RFC 6763 section 9)
+const SERVICES_META: &str = "_services._dns-sd._udp.local";
+const DEFAULT_HOSTNAME: &str = "precursor";
+
+/// True if `name` is in the link-local domain, and so has to be resolved with mDNS.
+pub(crate) fn is_local(name: &str) -> bool {
+    let name = name.trim_end_matches('.').as_bytes();
+    name.len() > 6 && name[name.len() - 6..].eq_ignore_ascii_case(b".local")
+}
+
+/// Host names are a single label of letters, digits and hyphens (RFC 1123 section 2.1).
+fn valid_hostname(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MDNS_LABEL_LIMIT
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+}
+
+/// Service types are of the form `_name._tcp` or `_name._udp` (RFC 6763 section 7).
+fn valid_service_type(service: &str) -> bool {
+    let mut labels = service.split('.');
+    match (labels.next(), labels.next(), labels.next()) {
+        (Some(name), Some(proto), None) => {
+            name.len() > 1
+                && name.len() <= 16
+                && name.starts_with('_')
+                && name[1..].bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+                && (proto.eq_ignore_ascii_case("_tcp") || proto.eq_ignore_ascii_case("_udp"))
+        }
+        _ => false,
+    }
+}
+
+/// If `name` ends with a dot followed by `suffix` (ignoring case), returns what comes before the dot.
+fn strip_suffix_ignore_case<'a>(name: &'a str, suffix: &str) -> Option<&'a str> {
+    let split = name.len().checked_sub(suffix.len() + 1)?;
+    if name.is_char_boundary(split)
+        && name.as_bytes()[split] == b'.'
+        && name[split + 1..].eq_ignore_ascii_case(suffix)
+    {
+        Some(&name[..split])
+    } else {
+        None
+    }
+}
+
+/// A service published by this host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Service {
+    /// the user-visible name of the instance, which is a single label that may contain spaces
+    pub instance: String,
+    /// the service type, e.g. `_http._tcp`
+    pub service: String,
+    pub port: u16,
+    pub txt: Vec<String>,
+}
+impl Service {
+    fn service_name(&self) -> String {
+        format!("{}.local", self.service)
+    }
+    fn instance_name(&self) -> String {
+        format!("{}.{}.local", self.instance, self.service)
+    }
+    fn is_valid(&self) -> bool {
+        !self.instance.is_empty()
+            && self.instance.len() <= MDNS_LABEL_LIMIT
+            && !self.instance.contains('.')
+            && self.port != 0
+            && valid_service_type(&self.service)
+    }
+}
+impl From<&MdnsServiceIpc> for Service {
+    fn from(ipc: &MdnsServiceIpc) -> Service {
+        Service {
+            instance: String::from(ipc.instance.as_str().unwrap_or("")),
+            service: String::from(ipc.service.as_str().unwrap_or("")),
+            port: ipc.port,
+            txt: ipc.txt.iter().flatten().filter_map(|s| s.as_str().ok().map(String::from)).collect(),
+        }
+    }
+}
+
+/// What the responder needs to know about this host to answer for it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Host {
+    /// the fully qualified name, e.g. `precursor-1234.local`
+    pub name: String,
+    /// `None` until DHCP has assigned us an address
+    pub addr: Option<Ipv4Addr>,
+}
+
+fn host_records(host: &Host, ttl: u32) -> Vec<ResourceRecord> {
+    host.addr
+        .map(|addr| ResourceRecord { name: host.name.clone(), ttl, data: RecordData::A(addr) })
+        .into_iter()
+        .collect()
+}
+
+/// The PTR, SRV and TXT records of a service, in that order.
+fn service_records(service: &Service, host: &Host, ttl: u32) -> [ResourceRecord; 3] {
+    [
+        ResourceRecord {
+            name: service.service_name(),
+            ttl,
+            data: RecordData::Ptr(service.instance_name()),
+        },
+        ResourceRecord {
+            name: service.instance_name(),
+            ttl: ttl.min(HOST_TTL),
+            data: RecordData::Srv(SrvRecord {
+                priority: 0,
+                weight: 0,
+                port: service.port,
+                target: host.name.clone(),
+            }),
+        },
+        ResourceRecord {
+            name: service.instance_name(),
+            ttl,
+            data: RecordData::Txt(service.txt.clone()),
+        },
+    ]
+}
+
+struct CacheEntry {
+    record: ResourceRecord,
+    expiry_ms: u64,
+}
+
+/// State shared between the responder thread and the server's main loop
+#[derive(Default)]
+pub(crate) struct State {
+    /// the host name set with `MdnsSetHostname`; if `None`, a name is derived from the MAC address
+    hostname: Option<String>,
+    services: Vec<Service>,
+    cache: Vec<CacheEntry>,
+    /// number of announcements still to be sent
+    announcements: u8,
+}
+impl State {
+    /// Collects the records that answer `questions`, and the additional records that the asker is
+    /// likely to need next (RFC 6763 section 12).
+    pub fn answer(&self, questions: &[Question], host: &Host) -> (Vec<ResourceRecord>, Vec<ResourceRecord>) {
+        fn push_unique(records: &mut Vec<ResourceRecord>, rr: ResourceRecord) {
+            if !records.contains(&rr) {
+                records.push(rr);
+            }
+        }
+        let mut answers = Vec::<ResourceRecord>::new();
+        let mut additional = Vec::<ResourceRecord>::new();
+        for q in questions.iter() {
+            let wants = |qtype: QueryType| q.qtype == qtype as u16 || q.qtype == TYPE_ANY;
+            if q.name.eq_ignore_ascii_case(&host.name) {
+                if wants(QueryType::A) {
+                    for rr in host_records(host, HOST_TTL) {
+                        push_unique(&mut answers, rr);
+                    }
+                }
+                continue;
+            }
+            for service in self.services.iter() {
+                let [ptr, srv, txt] = service_records(service, host, SERVICE_TTL);
+                if q.name.eq_ignore_ascii_case(SERVICES_META) && wants(QueryType::PTR) {
+                    push_unique(&mut answers, ResourceRecord {
+                        name: String::from(SERVICES_META),
+                        ttl: SERVICE_TTL,
+                        data: RecordData::Ptr(service.service_name()),
+                    });
+                } else if q.name.eq_ignore_ascii_case(&ptr.name) && wants(QueryType::PTR) {
+                    push_unique(&mut answers, ptr);
+                    push_unique(&mut additional, srv);
+                    push_unique(&mut additional, txt);
+                    for rr in host_records(host, HOST_TTL) {
+                        push_unique(&mut additional, rr);
+                    }
+                } else if q.name.eq_ignore_ascii_case(&srv.name) {
+                    if wants(QueryType::SRV) {
+                        push_unique(&mut answers, srv);
+                        for rr in host_records(host, HOST_TTL) {
+                            push_unique(&mut additional, rr);
+                        }
+                    }
+                    if wants(QueryType::TXT) {
+                        push_unique(&mut answers, txt);
+                    }
+                }
+            }
+        }
+        additional.retain(|rr| !answers.contains(rr));
+        (answers, additional)
+    }
+
+    /// Builds the reply to `query`, which was received from `src`, and picks where to send it.
+    /// Returns `None` if we have nothing to say.
+    pub fn respond(&self, query: &Message, host: &Host, src: SocketAddr) -> Option<(Message, SocketAddr)> {
+        let questions = query.parse_questions().ok()?;
+        let (mut answers, mut additional) = self.answer(&questions, host);
+        if answers.is_empty() {
+            return None;
+        }
+        if src.port() != MDNS_PORT {
+            // a legacy resolver that sent a one-shot query expects a conventional DNS response
+            for rr in answers.iter_mut().chain(additional.iter_mut()) {
+                rr.ttl = rr.ttl.min(LEGACY_TTL);
+            }
+            Some((Message::response(query.id(), &questions, &answers, &additional, false), src))
+        } else if questions.iter().all(|q| q.unicast_response) {
+            Some((Message::response(0, &[], &answers, &additional, true), src))
+        } else {
+            Some((Message::response(0, &[], &answers, &additional, true), SocketAddr::from((MDNS_GROUP, MDNS_PORT))))
+        }
+    }
+
+    /// Every record that we publish, for an unsolicited announcement.
+    fn announcement(&self, host: &Host) -> Message {
+        let mut records = host_records(host, HOST_TTL);
+        for service in self.services.iter() {
+            records.push(ResourceRecord {
+                name: String::from(SERVICES_META),
+                ttl: SERVICE_TTL,
+                data: RecordData::Ptr(service.service_name()),
+            });
+            records.extend(service_records(service, host, SERVICE_TTL));
+        }
+        Message::response(0, &[], &records, &[], true)
+    }
+
+    /// Stores the records of a response multicast by another host. Records with a TTL of zero are
+    /// "goodbyes", and remove the record from the cache (RFC 6762 section 10.1).
+    pub fn absorb(&mut self, records: Vec<ResourceRecord>, now: u64) {
+        self.cache.retain(|entry| entry.expiry_ms > now);
+        for record in records.into_iter() {
+            if matches!(record.data, RecordData::Other(_)) {
+                continue;
+            }
+            self.cache.retain(|entry|
+                !(entry.record.data == record.data && entry.record.name.eq_ignore_ascii_case(&record.name))
+            );
+            if record.ttl == 0 {
+                continue;
+            }
+            if self.cache.len() >= CACHE_LIMIT {
+                if let Some(oldest) = self.cache.iter().enumerate().min_by_key(|(_, e)| e.expiry_ms).map(|(i, _)| i) {
+                    self.cache.swap_remove(oldest);
+                }
+            }
+            let expiry_ms = now + record.ttl as u64 * 1000;
+            self.cache.push(CacheEntry { record, expiry_ms });
+        }
+    }
+
+    fn cached<'a>(&'a self, name: &'a str, now: u64) -> impl Iterator<Item = &'a ResourceRecord> + 'a {
+        self.cache.iter()
+            .filter(move |entry| entry.expiry_ms > now && entry.record.name.eq_ignore_ascii_case(name))
+            .map(|entry| &entry.record)
+    }
+
+    /// The cached addresses of `name`, with the number of seconds for which they remain valid.
+    pub fn addresses(&self, name: &str, now: u64) -> HashMap<IpAddr, u32> {
+        let mut addrs = HashMap::<IpAddr, u32>::new();
+        for entry in self.cache.iter().filter(|e| e.expiry_ms > now && e.record.name.eq_ignore_ascii_case(name)) {
+            let ttl = ((entry.expiry_ms - now) / 1000) as u32;
+            match entry.record.data {
+                RecordData::A(addr) => { addrs.insert(IpAddr::V4(addr), ttl); }
+                RecordData::Aaaa(addr) => { addrs.insert(IpAddr::V6(addr), ttl); }
+                _ => (),
+            }
+        }
+        addrs
+    }
+
+    /// The cached instances of `service`. Instances whose SRV record hasn't been seen have an empty
+    /// `host` and a port of zero.
+    pub fn instances(&self, service: &str, now: u64) -> Vec<MdnsInstance> {
+        let service_name = format!("{}.local", service);
+        let mut instances = Vec::<MdnsInstance>::new();
+        for rr in self.cached(&service_name, now) {
+            let full_name = match &rr.data {
+                RecordData::Ptr(full_name) => full_name,
+                _ => continue,
+            };
+            let instance = match strip_suffix_ignore_case(full_name, &service_name) {
+                Some(instance) => instance,
+                None => continue,
+            };
+            if instances.iter().any(|i| i.instance == instance) {
+                continue;
+            }
+            let mut found = MdnsInstance {
+                instance: String::from(instance),
+                host: String::new(),
+                port: 0,
+                addrs: Vec::new(),
+                txt: Vec::new(),
+            };
+            for rr in self.cached(full_name, now) {
+                match &rr.data {
+                    RecordData::Srv(srv) => {
+                        found.host = srv.target.clone();
+                        found.port = srv.port;
+                    }
+                    RecordData::Txt(txt) => {
+                        found.txt = txt.iter().filter(|s| !s.is_empty()).cloned().collect();
+                    }
+                    _ => (),
+                }
+            }
+            found.addrs = self.addresses(&found.host, now).keys().copied().collect();
+            found.addrs.sort();
+            instances.push(found);
+        }
+        instances
+    }
+}
+
+fn default_hostname(mac: Option<[u8; 6]>) -> String {
+    match mac {
+        Some(mac) if mac != [0; 6] => format!("{}-{:02x}{:02x}", DEFAULT_HOSTNAME, mac[4], mac[5]),
+        _ => String::from(DEFAULT_HOSTNAME),
+    }
+}
+
+fn local_host(net: &net::NetManager, state: &State) -> Host {
+    let config = net.get_ipv4_config();
+    let name = match &state.hostname {
+        Some(name) => name.clone(),
+        None => default_hostname(config.as_ref().map(|c| c.mac)),
+    };
+    Host {
+        name: format!("{}.local", name),
+        addr: config.map(|c| Ipv4Addr::from(c.addr)).filter(|addr| !addr.is_unspecified()),
+    }
+}
+
+fn send(socket: &UdpSocket, msg: &Message, dest: SocketAddr) {
+    if let Err(e) = socket.send_to(&msg.datagram, dest) {
+        log::warn!("couldn't send mDNS packet to {:?}: {:?}", dest, e);
+    }
+}
+
+fn responder(socket: Arc<UdpSocket>, state: Arc<Mutex<State>>) {
+    let net = net::NetManager::new();
+    let tt = ticktimer_server::Ticktimer::new().unwrap();
+    let group = SocketAddr::from((MDNS_GROUP, MDNS_PORT));
+    let mut buf = [0u8; MDNS_PKT_MAX_LEN];
+    let mut last_poll = 0;
+    let mut last_addr: Option<Ipv4Addr> = None;
+    loop {
+        match socket.recv_from(&mut buf) {
+            Ok((len, src)) if len >= 12 => {
+                let msg = Message::from(&buf[..len]);
+                let mut state = state.lock().unwrap();
+                if msg.is_response() {
+                    // responses must come from the mDNS port; anything else is a misbehaving host (RFC 6762 section 11)
+                    if src.port() == MDNS_PORT {
+                        match msg.parse_all_records() {
+                            Ok(records) => state.absorb(records, tt.elapsed_ms()),
+                            Err(e) => log::debug!("ignoring malformed mDNS response from {:?}: {:?}", src, e),
+                        }
+                    }
+                } else if msg.opcode() == 0 {
+                    let host = local_host(&net, &state);
+                    if let Some((reply, dest)) = state.respond(&msg, &host, src) {
+                        log::debug!("answering mDNS query from {:?}", src);
+                        send(&socket, &reply, dest);
+                    }
+                }
+            }
+            Ok(_) => (),
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => (),
+            Err(e) => {
+                // this happens routinely until the network is up
+                log::debug!("mDNS receive error: {:?}", e);
+                tt.sleep_ms(POLL_MS as usize).ok();
+            }
+        }
+
+        let now = tt.elapsed_ms();
+        if now < last_poll + POLL_MS {
+            continue;
+        }
+        last_poll = now;
+        let mut state = state.lock().unwrap();
+        let host = local_host(&net, &state);
+        if host.addr != last_addr {
+            // a new address has to be announced, or other hosts will keep using the one they cached
+            last_addr = host.addr;
+            state.announcements = ANNOUNCE_COUNT;
+        }
+        if state.announcements > 0 && host.addr.is_some() {
+            send(&socket, &state.announcement(&host), group);
+            state.announcements -= 1;
+        }
+    }
+}
+
+/// The server's handle on mDNS. mDNS is disabled, and every call fails, in hosted mode and when the
+/// mDNS port can't be bound.
+///
+/// In hosted mode the net server runs on a virtual network, so the address it reports for us isn't
+/// one the host's LAN can reach. Sockets go out on the host's network, though, so a responder would
+/// announce that address to the real LAN.
+pub(crate) struct Mdns {
+    socket: Option<Arc<UdpSocket>>,
+    state: Arc<Mutex<State>>,
+    net: net::NetManager,
+    querier: Querier,
+}
+impl Mdns {
+    pub fn new() -> Mdns {
+        let state = Arc::new(Mutex::new(State::default()));
+        let net = net::NetManager::new();
+        #[cfg(any(target_os = "none", target_os = "xous"))]
+        let socket = match UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, MDNS_PORT))) {
+            Ok(socket) => Some(Arc::new(socket)),
+            Err(e) => {
+                log::warn!("couldn't bind the mDNS port, mDNS is disabled: {:?}", e);
+                None
+            }
+        };
+        #[cfg(not(any(target_os = "none", target_os = "xous")))]
+        let socket: Option<Arc<UdpSocket>> = {
+            log::info!("mDNS is disabled in hosted mode");
+            None
+        };
+        if let Some(socket) = &socket {
+            // libstd doesn't implement multicast options on Xous, so fall back to asking the net server
+            if socket.join_multicast_v4(&MDNS_GROUP, &Ipv4Addr::UNSPECIFIED).is_err() {
+                if let Err(e) = net.join_multicast_v4(MDNS_GROUP) {
+                    log::warn!("couldn't join the mDNS group: {:?}", e);
+                }
+            }
+            socket.set_read_timeout(Some(Duration::from_millis(POLL_MS))).unwrap();
+            // RFC 6762 section 11: mDNS packets are sent with an IP TTL of 255
+            socket.set_ttl(255).ok();
+            thread::spawn({
+                let socket = socket.clone();
+                let state = state.clone();
+                move || responder(socket, state)
+            });
+        }
+        Mdns {
+            querier: Querier {
+                socket: socket.clone(),
+                state: state.clone(),
+                tt: ticktimer_server::Ticktimer::new().unwrap(),
+            },
+            socket,
+            state,
+            net,
+        }
+    }
+
+    /// Returns a handle for making queries from another thread.
+    pub fn querier(&self) -> Querier {
+        Querier {
+            socket: self.socket.clone(),
+            state: self.state.clone(),
+            tt: ticktimer_server::Ticktimer::new().unwrap(),
+        }
+    }
+
+    /// The name that we answer to, without the `.local` suffix.
+    pub fn hostname(&self) -> String {
+        let host = local_host(&self.net, &self.state.lock().unwrap());
+        String::from(host.name.trim_end_matches(".local"))
+    }
+
+    pub fn set_hostname(&mut self, name: &str) -> bool {
+        if !valid_hostname(name) {
+            log::warn!("invalid mDNS host name: {}", name);
+            return false;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.hostname = Some(String::from(name));
+        state.announcements = ANNOUNCE_COUNT;
+        true
+    }
+
+    /// Publishes `service`, replacing any service with the same instance name and type.
+    pub fn register(&mut self, service: Service) -> bool {
+        if self.socket.is_none() || !service.is_valid() {
+            log::warn!("couldn't register mDNS service {:?}", service);
+            return false;
+        }
+        log::info!("publishing {} on port {}", service.instance_name(), service.port);
+        let mut state = self.state.lock().unwrap();
+        state.services.retain(|s| s.instance_name() != service.instance_name());
+        state.services.push(service);
+        state.announcements = ANNOUNCE_COUNT;
+        true
+    }
+
+    /// Withdraws a service, telling other hosts to forget it.
+    pub fn unregister(&mut self, instance: &str, service_type: &str) {
+        let socket = match &self.socket {
+            Some(socket) => socket,
+            None => return,
+        };
+        let mut state = self.state.lock().unwrap();
+        let host = local_host(&self.net, &state);
+        let mut removed = Vec::<ResourceRecord>::new();
+        state.services.retain(|s| {
+            if s.instance == instance && s.service == service_type {
+                removed.extend(service_records(s, &host, 0));
+                false
+            } else {
+                true
+            }
+        });
+        if !removed.is_empty() {
+            log::info!("withdrawing {}.{}.local", instance, service_type);
+            send(socket, &Message::response(0, &[], &removed, &[], true), SocketAddr::from((MDNS_GROUP, MDNS_PORT)));
+        }
+    }
+
+    /// Resolves a `.local` name to its addresses.
+    pub fn resolve(&self, name: &str) -> Result<HashMap<IpAddr, u32>, DnsResponseCode> {
+        let name = name.trim_end_matches('.');
+        let host = local_host(&self.net, &self.state.lock().unwrap());
+        if name.eq_ignore_ascii_case(&host.name) {
+            let mut addrs = HashMap::new();
+            if let Some(addr) = host.addr {
+                addrs.insert(IpAddr::V4(addr), HOST_TTL);
+            }
+            return Ok(addrs);
+        }
+        let addrs = self.state.lock().unwrap().addresses(name, self.querier.tt.elapsed_ms());
+        if !addrs.is_empty() {
+            return Ok(addrs);
+        }
+        self.querier.query(&[(name, QueryType::A)])?;
+        let addrs = self.state.lock().unwrap().addresses(name, self.querier.tt.elapsed_ms());
+        if !addrs.is_empty() {
+            Ok(addrs)
+        } else {
+            Err(DnsResponseCode::NameError)
+        }
+    }
+}
+
+/// Sends mDNS queries, and reads the answers out of the cache once they've had a moment to come in.
+/// Waiting on the answers takes a while, so this can be split off of `Mdns` to run on another thread.
+pub(crate) struct Querier {
+    socket: Option<Arc<UdpSocket>>,
+    state: Arc<Mutex<State>>,
+    tt: ticktimer_server::Ticktimer,
+}
+impl Querier {
+    fn query(&self, questions: &[(&str, QueryType)]) -> Result<(), DnsResponseCode> {
+        let socket = self.socket.as_ref().ok_or(DnsResponseCode::NetworkError)?;
+        socket.send_to(&Message::mdns_query(questions).datagram, SocketAddr::from((MDNS_GROUP, MDNS_PORT)))
+            .or(Err(DnsResponseCode::NetworkError))?;
+        self.tt.sleep_ms(QUERY_WAIT_MS).ok();
+        Ok(())
+    }
+
+    /// Finds the instances of `service_type`, such as `_http._tcp`, on the local link.
+    pub fn browse(&self, service_type: &str) -> Result<Vec<MdnsInstance>, DnsResponseCode> {
+        if !valid_service_type(service_type) {
+            return Err(DnsResponseCode::FormatError);
+        }
+        let service_name = format!("{}.local", service_type);
+        self.query(&[(&service_name, QueryType::PTR)])?;
+        let instances = self.state.lock().unwrap().instances(service_type, self.tt.elapsed_ms());
+        // most responders send the SRV and TXT records along with the PTR, but they don't have to
+        let missing: Vec<String> = instances.iter()
+            .filter(|i| i.host.is_empty())
+            .map(|i| format!("{}.{}", i.instance, service_name))
+            .collect();
+        if missing.is_empty() {
+            return Ok(instances);
+        }
+        let mut questions = Vec::<(&str, QueryType)>::new();
+        for name in missing.iter() {
+            questions.push((name, QueryType::SRV));
+            questions.push((name, QueryType::TXT));
+        }
+        self.query(&questions)?;
+        Ok(self.state.lock().unwrap().instances(service_type, self.tt.elapsed_ms()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host() -> Host {
+        Host { name: String::from("precursor-1234.local"), addr: Some(Ipv4Addr::new(10, 0, 245, 7)) }
+    }
+
+    fn state() -> State {
+        let mut state = State::default();
+        state.services.push(Service {
+            instance: String::from("Precursor demo"),
+            service: String::from("_http._tcp"),
+            port: 80,
+            txt: vec![String::from("path=/")],
+        });
+        state
+    }
+
+    const MDNS_SRC: ([u8; 4], u16) = ([10, 0, 245, 1], MDNS_PORT);
+
+    #[test]
+    fn names() {
+        assert!(is_local("precursor.local"));
+        assert!(is_local("Precursor.LOCAL."));
+        assert!(!is_local(".local"));
+        assert!(!is_local("betrusted.io"));
+        assert!(!is_local("ünïcödé"));
+        assert!(valid_hostname("precursor-1234"));
+        assert!(!valid_hostname("precursor.local"));
+        assert!(!valid_hostname("-precursor"));
+        assert!(valid_service_type("_http._tcp"));
+        assert!(!valid_service_type("_http._tcp.local"));
+        assert!(!valid_service_type("http._tcp"));
+        assert_eq!(strip_suffix_ignore_case("Demo._http._tcp.local", "_HTTP._tcp.local"), Some("Demo"));
+        assert_eq!(strip_suffix_ignore_case("_http._tcp.local", "_http._tcp.local"), None);
+        assert_eq!(default_hostname(Some([0x02, 0, 0, 0, 0x12, 0x34])), "precursor-1234");
+        assert_eq!(default_hostname(None), "precursor");
+    }
+
+    #[test]
+    fn answer_browse() {
+        let state = state();
+        let query = Message::mdns_query(&[("_http._tcp.local", QueryType::PTR)]);
+        let (reply, dest) = state.respond(&query, &host(), SocketAddr::from(MDNS_SRC)).unwrap();
+        assert_eq!(dest, SocketAddr::from((MDNS_GROUP, MDNS_PORT)));
+        assert!(reply.is_response());
+        assert_eq!(u16::from_be_bytes([reply.datagram[4], reply.datagram[5]]), 0);
+        // the PTR answers the question, and everything needed to connect comes along as additional records
+        assert_eq!(u16::from_be_bytes([reply.datagram[6], reply.datagram[7]]), 1);
+        assert_eq!(u16::from_be_bytes([reply.datagram[10], reply.datagram[11]]), 3);
+        let records = reply.parse_all_records().unwrap();
+        assert_eq!(records[0].data, RecordData::Ptr(String::from("Precursor demo._http._tcp.local")));
+        assert!(records.contains(&ResourceRecord {
+            name: String::from("Precursor demo._http._tcp.local"),
+            ttl: HOST_TTL,
+            data: RecordData::Srv(SrvRecord { priority: 0, weight: 0, port: 80, target: String::from("precursor-1234.local") }),
+        }));
+        assert!(records.iter().any(|rr| rr.data == RecordData::Txt(vec![String::from("path=/")])));
+        assert!(records.iter().any(|rr| rr.data == RecordData::A(Ipv4Addr::new(10, 0, 245, 7))));
+
+        // questions about other names are ignored
+        let query = Message::mdns_query(&[("_ipp._tcp.local", QueryType::PTR), ("other.local", QueryType::A)]);
+        assert!(state.respond(&query, &host(), SocketAddr::from(MDNS_SRC)).is_none());
+    }
+
+    #[test]
+    fn answer_host() {
+        let state = state();
+        let mut query = Message::mdns_query(&[("PRECURSOR-1234.local", QueryType::A)]);
+        // ask for a unicast response
+        let qclass = query.datagram.len() - 2;
+        query.datagram[qclass] |= 0x80;
+        let (reply, dest) = state.respond(&query, &host(), SocketAddr::from(MDNS_SRC)).unwrap();
+        assert_eq!(dest, SocketAddr::from(MDNS_SRC));
+        let records = reply.parse_all_records().unwrap();
+        assert_eq!(records, vec![ResourceRecord {
+            name: String::from("precursor-1234.local"),
+            ttl: HOST_TTL,
+            data: RecordData::A(Ipv4Addr::new(10, 0, 245, 7)),
+        }]);
+        // the A record is unique to us, so the cache-flush bit is set
+        let class = reply.datagram.len() - 4 - 2 - 4 - 2;
+        assert_eq!(reply.datagram[class] & 0x80, 0x80);
+
+        // without an address, there's nothing to say
+        let no_addr = Host { addr: None, ..host() };
+        assert!(state.respond(&query, &no_addr, SocketAddr::from(MDNS_SRC)).is_none());
+    }
+
+    #[test]
+    fn answer_legacy_unicast() {
+        let state = state();
+        let query = Message::query("precursor-1234.local", QueryType::A, QueryClass::IN, 0x4242);
+        let src = SocketAddr::from(([10, 0, 245, 1], 50000));
+        let (reply, dest) = state.respond(&query, &host(), src).unwrap();
+        assert_eq!(dest, src);
+        assert!(reply.is_response_to(&query));
+        // a conventional response, with the question echoed back and a short TTL
+        let records = reply.parse_response().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].ttl, LEGACY_TTL);
+        let class = reply.datagram.len() - 4 - 2 - 4 - 2;
+        assert_eq!(reply.datagram[class] & 0x80, 0);
+    }
+
+    #[test]
+    fn browse_cache() {
+        // what we publish is what another host's browser sees
+        let announcement = state().announcement(&host());
+        let mut browser = State::default();
+        browser.absorb(announcement.parse_all_records().unwrap(), 1000);
+        assert_eq!(browser.instances("_http._tcp", 2000), vec![MdnsInstance {
+            instance: String::from("Precursor demo"),
+            host: String::from("precursor-1234.local"),
+            port: 80,
+            addrs: vec![IpAddr::V4(Ipv4Addr::new(10, 0, 245, 7))],
+            txt: vec![String::from("path=/")],
+        }]);
+        let addrs = browser.addresses("Precursor-1234.local", 2000);
+        assert_eq!(addrs.get(&IpAddr::V4(Ipv4Addr::new(10, 0, 245, 7))), Some(&(HOST_TTL - 1)));
+        assert!(browser.instances("_ipp._tcp", 2000).is_empty());
+
+        // records expire with their TTL
+        assert!(browser.addresses("precursor-1234.local", 1000 + HOST_TTL as u64 * 1000).is_empty());
+
+        // a repeated announcement refreshes records rather than duplicating them
+        browser.absorb(announcement.parse_all_records().unwrap(), 5000);
+        assert_eq!(browser.cache.len(), 5);
+
+        // goodbyes remove the service
+        let mut goodbye: Vec<ResourceRecord> = service_records(&state().services[0], &host(), 0).to_vec();
+        goodbye.push(ResourceRecord { name: String::from("unrelated.local"), ttl: 0, data: RecordData::Other(47) });
+        browser.absorb(goodbye, 6000);
+        assert!(browser.instances("_http._tcp", 7000).is_empty());
+
+        // a PTR without its SRV is reported, so that the browser can ask for the rest
+        browser.absorb(vec![ResourceRecord {
+            name: String::from("_http._tcp.local"),
+            ttl: SERVICE_TTL,
+            data: RecordData::Ptr(String::from("Printer._http._tcp.local")),
+        }], 8000);
+        let instances = browser.instances("_http._tcp", 8000);
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0].instance, "Printer");
+        assert!(instances[0].host.is_empty());
+    }
+
+    #[test]
+    fn instance_encoding() {
+        let instance = MdnsInstance {
+            instance: String::from("Precursor demo"),
+            host: String::from("precursor-1234.local"),
+            port: 80,
+            addrs: vec![IpAddr::V4(Ipv4Addr::new(10, 0, 245, 7)), "fe80::1".parse().unwrap()],
+            txt: vec![String::from("path=/"), String::new()],
+        };
+        let mut encoded = Vec::new();
+        instance.encode(&mut encoded);
+        assert_eq!(MdnsInstance::decode(&encoded), Some(instance));
+        assert_eq!(MdnsInstance::decode(&encoded[..encoded.len() - 1]), None);
+    }
+}
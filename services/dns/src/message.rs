@@ -0,0 +1,775 @@
+use crate::api::*;
+
+use num_traits::FromPrimitive;
+
+use std::convert::TryInto;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+// KISS DNS
+
+// The DNS implementation here is based on https://github.com/vinc/moros/blob/43ac7cdc8ccc860dc1b6f0f060b5dbcd01424c03/src/usr/host.rs
+// MOROS is MIT licensed.
+// See RFC 1035 for implementation details
+
+#[repr(u16)]
+pub(crate) enum QueryClass {
+    IN = 1,
+}
+
+const FLAG_RD: u16 = 0x0100; // Recursion desired
+const FLAG_TC: u16 = 0x0200; // Truncated
+
+/// Authoritative answer, which is set on every mDNS response (RFC 6762 section 18.4)
+const FLAG_AA: u16 = 0x0400;
+const FLAG_QR: u16 = 0x8000;
+
+/// The type of the EDNS0 pseudo-record (RFC 6891)
+const TYPE_OPT: u16 = 41;
+/// A question for records of any type
+pub(crate) const TYPE_ANY: u16 = 255;
+
+/// mDNS reuses the top bit of the class: in a question it requests a unicast response, and in a
+/// record it marks the record as unique, so that caches flush any other records of the same name and
+/// type (RFC 6762 sections 5.4 and 10.2). It is masked off when comparing classes.
+const CLASS_MASK: u16 = 0x7fff;
+const CLASS_TOP_BIT: u16 = 0x8000;
+
+const MAX_LABEL_LEN: usize = 63;
+
+/// Upper bound on the number of compression pointers followed while reading a single name. A
+/// well-formed name can't need more than one pointer per label, so this only trips on pointer loops.
+const MAX_POINTER_HOPS: usize = 64;
+
+/// The payload of a resource record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum RecordData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(String),
+    Ptr(String),
+    /// the <character-string>s of the record, in order
+    Txt(Vec<String>),
+    Mx(MxRecord),
+    Srv(SrvRecord),
+    /// a record type we don't interpret; the type is recorded so it can be logged
+    Other(u16),
+}
+
+/// A resource record from the answer section of a response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ResourceRecord {
+    /// the owner name of the record, with compression pointers expanded
+    pub name: String,
+    pub ttl: u32,
+    pub data: RecordData,
+}
+impl ResourceRecord {
+    pub fn is_type(&self, qtype: QueryType) -> bool {
+        matches!(
+            (&self.data, qtype),
+            (RecordData::A(_), QueryType::A)
+                | (RecordData::Aaaa(_), QueryType::AAAA)
+                | (RecordData::Cname(_), QueryType::CNAME)
+                | (RecordData::Ptr(_), QueryType::PTR)
+                | (RecordData::Txt(_), QueryType::TXT)
+                | (RecordData::Mx(_), QueryType::MX)
+                | (RecordData::Srv(_), QueryType::SRV)
+        )
+    }
+
+    /// Appends the record to `out` in wire format, without name compression. `cache_flush` sets the
+    /// mDNS cache-flush bit. Returns `false`, leaving `out` untouched, if the record is of a type that
+    /// can't be encoded.
+    pub fn encode(&self, cache_flush: bool, out: &mut Vec<u8>) -> bool {
+        let mut rdata = Vec::<u8>::new();
+        let rtype = match &self.data {
+            RecordData::A(addr) => {
+                rdata.extend_from_slice(&addr.octets());
+                QueryType::A
+            }
+            RecordData::Aaaa(addr) => {
+                rdata.extend_from_slice(&addr.octets());
+                QueryType::AAAA
+            }
+            RecordData::Cname(name) => {
+                push_name(&mut rdata, name);
+                QueryType::CNAME
+            }
+            RecordData::Ptr(name) => {
+                push_name(&mut rdata, name);
+                QueryType::PTR
+            }
+            RecordData::Txt(strings) => {
+                for string in strings.iter() {
+                    let bytes = &string.as_bytes()[..string.len().min(u8::MAX as usize)];
+                    rdata.push(bytes.len() as u8);
+                    rdata.extend_from_slice(bytes);
+                }
+                if strings.is_empty() {
+                    // a TXT record must contain at least one string, even if it's empty (RFC 6763 section 6.1)
+                    rdata.push(0);
+                }
+                QueryType::TXT
+            }
+            RecordData::Mx(mx) => {
+                rdata.extend_from_slice(&mx.preference.to_be_bytes());
+                push_name(&mut rdata, &mx.exchange);
+                QueryType::MX
+            }
+            RecordData::Srv(srv) => {
+                rdata.extend_from_slice(&srv.priority.to_be_bytes());
+                rdata.extend_from_slice(&srv.weight.to_be_bytes());
+                rdata.extend_from_slice(&srv.port.to_be_bytes());
+                push_name(&mut rdata, &srv.target);
+                QueryType::SRV
+            }
+            RecordData::Other(_) => return false,
+        };
+        let class = QueryClass::IN as u16 | if cache_flush { CLASS_TOP_BIT } else { 0 };
+        push_name(out, &self.name);
+        out.extend_from_slice(&(rtype as u16).to_be_bytes());
+        out.extend_from_slice(&class.to_be_bytes());
+        out.extend_from_slice(&self.ttl.to_be_bytes());
+        out.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        out.extend_from_slice(&rdata);
+        true
+    }
+}
+
+/// An entry in the question section of a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Question {
+    pub name: String,
+    /// the raw type, as questions may ask for types that `QueryType` doesn't list, such as `TYPE_ANY`
+    pub qtype: u16,
+    /// the mDNS "QU" bit: the asker would like a unicast response
+    pub unicast_response: bool,
+}
+
+/// Appends `name` to `out` as a sequence of labels, without compression. Labels are cut to the
+/// maximum length that the wire format allows.
+fn push_name(out: &mut Vec<u8>, name: &str) {
+    for label in name.trim_end_matches('.').split('.').filter(|label| !label.is_empty()) {
+        let label = &label.as_bytes()[..label.len().min(MAX_LABEL_LEN)];
+        out.push(label.len() as u8);
+        out.extend_from_slice(label);
+    }
+    out.push(0);
+}
+
+/// Follows the CNAME records in `records`, starting at `name`, and returns the canonical name. If
+/// `name` isn't an alias, it is returned unchanged. Chains that are too long (or loop) stop at the
+/// last name reached, so the result is always something that can be queried.
+pub(crate) fn canonical_name(records: &[ResourceRecord], name: &str) -> String {
+    let mut current = String::from(name);
+    for _ in 0..records.len() {
+        let next = records.iter().find_map(|rr| match &rr.data {
+            RecordData::Cname(target) if rr.name.eq_ignore_ascii_case(&current) => Some(target.clone()),
+            _ => None,
+        });
+        match next {
+            Some(target) => {
+                log::trace!("{} is an alias for {}", current, target);
+                current = target;
+            }
+            None => break,
+        }
+    }
+    current
+}
+
+pub(crate) struct Message {
+    pub datagram: Vec<u8>,
+}
+
+impl Message {
+    pub fn from(datagram: &[u8]) -> Self {
+        Self {
+            datagram: Vec::from(datagram),
+        }
+    }
+
+    pub fn query(qname: &str, qtype: QueryType, qclass: QueryClass, id: u16) -> Self {
+        let mut datagram = Vec::new();
+
+        for b in id.to_be_bytes().iter() {
+            datagram.push(*b); // Transaction ID
+        }
+        for b in FLAG_RD.to_be_bytes().iter() {
+            datagram.push(*b); // Flags
+        }
+        for b in (1 as u16).to_be_bytes().iter() {
+            datagram.push(*b); // Questions
+        }
+        for _ in 0..6 {
+            datagram.push(0); // Answer + Authority + Additional
+        }
+        for label in qname.trim_end_matches('.').split('.') {
+            datagram.push(label.len() as u8); // QNAME label length
+            for b in label.bytes() {
+                datagram.push(b); // QNAME label bytes
+            }
+        }
+        datagram.push(0); // Root null label
+        for b in (qtype as u16).to_be_bytes().iter() {
+            datagram.push(*b); // QTYPE
+        }
+        for b in (qclass as u16).to_be_bytes().iter() {
+            datagram.push(*b); // QCLASS
+        }
+
+        Self { datagram }
+    }
+
+    /// Builds an mDNS query, which has no ID and doesn't ask for recursion (RFC 6762 section 18).
+    pub fn mdns_query(questions: &[(&str, QueryType)]) -> Self {
+        let mut datagram = vec![0; 12];
+        datagram[4..6].copy_from_slice(&(questions.len() as u16).to_be_bytes());
+        for (qname, qtype) in questions.iter() {
+            push_name(&mut datagram, qname);
+            datagram.extend_from_slice(&(*qtype as u16).to_be_bytes());
+            datagram.extend_from_slice(&(QueryClass::IN as u16).to_be_bytes());
+        }
+        Self { datagram }
+    }
+
+    /// Builds an authoritative response. `questions` are echoed back, which is only done for unicast
+    /// replies; multicast mDNS responses leave the question section empty. If `mdns` is set, the cache-flush
+    /// bit is set on every record except PTRs, which are shared between hosts.
+    pub fn response(id: u16, questions: &[Question], answers: &[ResourceRecord], additional: &[ResourceRecord], mdns: bool) -> Self {
+        let mut datagram = Vec::new();
+        datagram.extend_from_slice(&id.to_be_bytes());
+        datagram.extend_from_slice(&(FLAG_QR | FLAG_AA).to_be_bytes());
+        datagram.extend_from_slice(&[0; 8]);
+        for q in questions.iter() {
+            push_name(&mut datagram, &q.name);
+            datagram.extend_from_slice(&q.qtype.to_be_bytes());
+            datagram.extend_from_slice(&(QueryClass::IN as u16).to_be_bytes());
+        }
+        let mut counts = [questions.len() as u16, 0, 0];
+        for (section, records) in [answers, additional].iter().enumerate() {
+            for rr in records.iter() {
+                let cache_flush = mdns && !matches!(rr.data, RecordData::Ptr(_));
+                if rr.encode(cache_flush, &mut datagram) {
+                    counts[section + 1] += 1;
+                }
+            }
+        }
+        datagram[4..6].copy_from_slice(&counts[0].to_be_bytes());
+        datagram[6..8].copy_from_slice(&counts[1].to_be_bytes());
+        datagram[10..12].copy_from_slice(&counts[2].to_be_bytes());
+        Self { datagram }
+    }
+
+    /// Appends an EDNS0 OPT record to a query, advertising that responses of up to `payload_len`
+    /// bytes can be received over UDP.
+    pub fn with_edns(mut self, payload_len: u16) -> Self {
+        let arcount = u16::from_be_bytes(self.datagram[10..12].try_into().unwrap()) + 1;
+        self.datagram[10..12].copy_from_slice(&arcount.to_be_bytes());
+        self.datagram.push(0); // NAME: the root domain
+        self.datagram.extend_from_slice(&TYPE_OPT.to_be_bytes()); // TYPE
+        self.datagram.extend_from_slice(&payload_len.to_be_bytes()); // CLASS: the UDP payload size
+        self.datagram.extend_from_slice(&[0, 0, 0, 0]); // TTL: extended RCODE, version 0, no flags
+        self.datagram.extend_from_slice(&[0, 0]); // RDLEN: no options
+        self
+    }
+
+    /// Returns the message framed for TCP, which is the message preceded by its length.
+    pub fn to_tcp(&self) -> Vec<u8> {
+        let mut framed = Vec::with_capacity(self.datagram.len() + 2);
+        framed.extend_from_slice(&(self.datagram.len() as u16).to_be_bytes());
+        framed.extend_from_slice(&self.datagram);
+        framed
+    }
+
+    /// Checks that this is a well-formed response with the same ID as `query`.
+    pub fn is_response_to(&self, query: &Message) -> bool {
+        self.datagram.len() >= 12 && self.id() == query.id() && self.is_response()
+    }
+
+    /// True if the server had to cut the response short to fit it into a UDP datagram.
+    pub fn is_truncated(&self) -> bool {
+        self.header() & FLAG_TC != 0
+    }
+
+    pub fn id(&self) -> u16 {
+        u16::from_be_bytes(self.datagram[0..2].try_into().unwrap())
+    }
+
+    pub fn header(&self) -> u16 {
+        u16::from_be_bytes(self.datagram[2..4].try_into().unwrap())
+    }
+
+    pub fn is_response(&self) -> bool {
+        if (self.header() & (1 << 15)) == 0 {
+            false
+        } else {
+            true
+        }
+    }
+
+    fn u16_at(&self, index: usize) -> Result<u16, DnsResponseCode> {
+        Ok(u16::from_be_bytes(
+            self.datagram.get(index..index + 2).ok_or(DnsResponseCode::FormatError)?.try_into().unwrap()
+        ))
+    }
+
+    fn u32_at(&self, index: usize) -> Result<u32, DnsResponseCode> {
+        Ok(u32::from_be_bytes(
+            self.datagram.get(index..index + 4).ok_or(DnsResponseCode::FormatError)?.try_into().unwrap()
+        ))
+    }
+
+    /// Reads the name starting at `start`, following compression pointers (RFC 1035 section 4.1.4).
+    /// Returns the name in dotted form, and the index of the first octet after the name as it
+    /// appears at `start`.
+    fn read_name(&self, start: usize) -> Result<(String, usize), DnsResponseCode> {
+        use DnsResponseCode::FormatError;
+        let mut name = String::new();
+        let mut index = start;
+        let mut end: Option<usize> = None;
+        let mut hops = 0;
+        loop {
+            let len = *(self.datagram.get(index).ok_or(FormatError)?) as usize;
+            match len & 0xc0 {
+                0xc0 => {
+                    // pointer: the remaining 14 bits are an offset from the start of the message
+                    let lsb = *(self.datagram.get(index + 1).ok_or(FormatError)?) as usize;
+                    if end.is_none() {
+                        end = Some(index + 2);
+                    }
+                    hops += 1;
+                    if hops > MAX_POINTER_HOPS {
+                        log::error!("Too many compression pointers in name at {}", start);
+                        return Err(FormatError);
+                    }
+                    index = ((len & 0x3f) << 8) | lsb;
+                }
+                0 => {
+                    if len == 0 {
+                        break;
+                    }
+                    let label = self.datagram.get(index + 1..index + 1 + len).ok_or(FormatError)?;
+                    if !name.is_empty() {
+                        name.push('.');
+                    }
+                    name.push_str(&std::string::String::from_utf8_lossy(label));
+                    if name.len() > DNS_NAME_LENGTH_LIMIT {
+                        log::error!("Name at {} exceeds the length limit", start);
+                        return Err(FormatError);
+                    }
+                    index += 1 + len;
+                }
+                _ => {
+                    // 0x40 and 0x80 are reserved label types
+                    log::error!("Unsupported label type {:x} in name at {}", len, start);
+                    return Err(FormatError);
+                }
+            }
+        }
+        Ok((name, end.unwrap_or(index + 1)))
+    }
+
+    fn parse_rdata(&self, rtype: u16, start: usize, len: usize) -> Result<RecordData, DnsResponseCode> {
+        use DnsResponseCode::FormatError;
+        let rdata = self.datagram.get(start..start + len).ok_or(FormatError)?;
+        let record = match FromPrimitive::from_u16(rtype) {
+            Some(QueryType::A) => {
+                let octets: [u8; 4] = rdata.try_into().or(Err(FormatError))?;
+                RecordData::A(Ipv4Addr::from(octets))
+            }
+            Some(QueryType::AAAA) => {
+                let octets: [u8; 16] = rdata.try_into().or(Err(FormatError))?;
+                RecordData::Aaaa(Ipv6Addr::from(octets))
+            }
+            Some(QueryType::CNAME) => {
+                let (name, end) = self.read_name(start)?;
+                if end != start + len {
+                    return Err(FormatError);
+                }
+                RecordData::Cname(name)
+            }
+            Some(QueryType::PTR) => {
+                let (name, end) = self.read_name(start)?;
+                if end != start + len {
+                    return Err(FormatError);
+                }
+                RecordData::Ptr(name)
+            }
+            Some(QueryType::TXT) => {
+                // one or more <character-string>s, each a length octet followed by that many octets
+                let mut strings = Vec::<String>::new();
+                let mut i = 0;
+                while i < rdata.len() {
+                    let seg_len = rdata[i] as usize;
+                    let seg = rdata.get(i + 1..i + 1 + seg_len).ok_or(FormatError)?;
+                    strings.push(std::string::String::from_utf8_lossy(seg).into_owned());
+                    i += 1 + seg_len;
+                }
+                RecordData::Txt(strings)
+            }
+            Some(QueryType::MX) => {
+                let preference = self.u16_at(start)?;
+                let (exchange, end) = self.read_name(start + 2)?;
+                if end != start + len {
+                    return Err(FormatError);
+                }
+                RecordData::Mx(MxRecord { preference, exchange })
+            }
+            Some(QueryType::SRV) => {
+                let priority = self.u16_at(start)?;
+                let weight = self.u16_at(start + 2)?;
+                let port = self.u16_at(start + 4)?;
+                let (target, end) = self.read_name(start + 6)?;
+                if end != start + len {
+                    return Err(FormatError);
+                }
+                RecordData::Srv(SrvRecord { priority, weight, port, target })
+            }
+            None => RecordData::Other(rtype),
+        };
+        Ok(record)
+    }
+
+    /// Reads the question section, returning the questions and the index of the first octet after it.
+    fn read_questions(&self) -> Result<(Vec<Question>, usize), DnsResponseCode> {
+        let qdcount = self.u16_at(4)?;
+        let mut questions = Vec::<Question>::new();
+        let mut index = 12;
+        for queries in 0..qdcount {
+            log::trace!("parsing query{}, index {}", queries, index);
+            let (name, next) = self.read_name(index)?;
+            // index is now at qtype
+            let qtype = self.u16_at(next)?;
+            let qclass = self.u16_at(next + 2)?;
+            if qclass & CLASS_MASK != QueryClass::IN as u16 {
+                log::error!("Problem parsing qname, qclass is not 1: {}", qclass);
+                return Err(DnsResponseCode::FormatError);
+            }
+            questions.push(Question { name, qtype, unicast_response: qclass & CLASS_TOP_BIT != 0 });
+            index = next + 4;
+        }
+        Ok((questions, index))
+    }
+
+    /// Reads the resource record starting at `index`. Returns the record, its class with the mDNS
+    /// cache-flush bit masked off, and the index of the first octet after the record.
+    fn read_record(&self, index: usize) -> Result<(ResourceRecord, u16, usize), DnsResponseCode> {
+        let (name, next) = self.read_name(index)?;
+        let rtype = self.u16_at(next)?;
+        let rclass = self.u16_at(next + 2)? & CLASS_MASK;
+        let ttl = self.u32_at(next + 4)?;
+        let rdlength = self.u16_at(next + 8)? as usize;
+        let start = next + 10;
+        let data = if rclass == QueryClass::IN as u16 {
+            self.parse_rdata(rtype, start, rdlength)?
+        } else {
+            // the payload of a record in another class (or of an OPT record, which reuses the class field)
+            // can't be interpreted, but it still has to fit in the message
+            self.datagram.get(start..start + rdlength).ok_or(DnsResponseCode::FormatError)?;
+            RecordData::Other(rtype)
+        };
+        log::trace!("got {}: {:?}, ttl {}", name, data, ttl);
+        Ok((ResourceRecord { name, ttl, data }, rclass, start + rdlength))
+    }
+
+    /// Parses the answer section of a response. Records of any type in class IN are returned;
+    /// the authority and additional sections are ignored.
+    pub fn parse_response(&self) -> Result<Vec<ResourceRecord>, DnsResponseCode> {
+        log::trace!("parsing packet: {:?}", self.datagram);
+
+        // ASSUME: the query ID and response bit fields have already been checked
+        // and that the rcode is valid
+        let ancount = self.u16_at(6)?;
+        // fast forward past the questions
+        let mut index = self.read_questions()?.1;
+        // index is now at the answer section
+        let mut records = Vec::<ResourceRecord>::new();
+        for aname in 0..ancount {
+            log::trace!("parsing aname{}, index {}", aname, index);
+            let (record, rclass, next) = self.read_record(index)?;
+            if rclass != QueryClass::IN as u16 {
+                log::error!("Problem parsing aname, aclass is not 1: {}", rclass);
+                return Err(DnsResponseCode::FormatError);
+            }
+            records.push(record);
+            index = next;
+        }
+
+        Ok(records)
+    }
+
+    /// Parses the question section of a query.
+    pub fn parse_questions(&self) -> Result<Vec<Question>, DnsResponseCode> {
+        Ok(self.read_questions()?.0)
+    }
+
+    /// Parses the answer, authority and additional sections together. mDNS responders put the records
+    /// that they expect to be useful in the additional section, so a browser wants all of them. Records
+    /// that aren't in class IN are skipped rather than treated as errors.
+    pub fn parse_all_records(&self) -> Result<Vec<ResourceRecord>, DnsResponseCode> {
+        let count = self.u16_at(6)? as usize + self.u16_at(8)? as usize + self.u16_at(10)? as usize;
+        let mut index = self.read_questions()?.1;
+        let mut records = Vec::<ResourceRecord>::new();
+        for _ in 0..count {
+            let (record, rclass, next) = self.read_record(index)?;
+            if rclass == QueryClass::IN as u16 {
+                records.push(record);
+            }
+            index = next;
+        }
+        Ok(records)
+    }
+
+    /*
+         example response for: betrusted.io->185.199.111.153
+    Header:
+          61, ca,   id
+          81, 80,   header
+          0, 1,     qdcount
+          0, 4,     ancount
+          0, 0,     nscount
+          0, 0,     arcount
+    qname:
+          9,        length 9
+          62, 65, 74, 72, 75, 73, 74, 65, 64,    "betrusted"
+          2,        length 2
+          69, 6f,   "io"
+          0,        end of name
+    qtype:
+          0, 1,     type A
+    qclass:
+          0, 1,     type IN
+    aname0:
+          c0,       name is a pointer (any value > 192 is a pointer)
+          c,        offset of 12 from start of aname0
+          0, 1,     type A
+          0, 1,     class IN
+          0, 0, e, 10,   0xe10 = 3600 seconds TTL
+          0, 4,     4 bytes address
+          b9, c7, 6c, 99,  address
+    aname1:
+          c0,       name is a pointer
+          c,
+          0, 1,     type A
+          0, 1,     class IN
+          0, 0, e, 10,  TTL
+          0, 4,     4 byte address
+          b9, c7, 6d, 99,  address
+    aname2:
+          c0,
+          c,
+          0, 1,
+          0, 1,
+          0, 0, e, 10,
+          0, 4,
+          b9, c7, 6e, 99,
+    aname3:
+          c0,
+          c,
+          0, 1,
+          0, 1,
+          0, 0, e, 10,
+          0, 4,
+          b9, c7, 6f, 99
+         */
+
+    /*
+    pub fn is_query(&self) -> bool {
+        !self.is_response()
+    }
+    */
+
+    /// The kind of query; 0 is a standard query.
+    pub fn opcode(&self) -> u16 {
+        (self.header() >> 11) & 0xF
+    }
+
+    pub fn rcode(&self) -> DnsResponseCode {
+        match self.header() & 0xF {
+            0 => DnsResponseCode::NoError,
+            1 => DnsResponseCode::FormatError,
+            2 => DnsResponseCode::ServerFailure,
+            3 => DnsResponseCode::NameError,
+            4 => DnsResponseCode::NotImplemented,
+            5 => DnsResponseCode::Refused,
+            _ => DnsResponseCode::UnknownError,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the betrusted.io response documented above
+    const BETRUSTED_A: [u8; 94] = [
+        0x61, 0xca, 0x81, 0x80, 0x00, 0x01, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00,
+        0x09, b'b', b'e', b't', b'r', b'u', b's', b't', b'e', b'd', 0x02, b'i', b'o', 0x00,
+        0x00, 0x01, 0x00, 0x01,
+        0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x04, 0xb9, 0xc7, 0x6c, 0x99,
+        0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x04, 0xb9, 0xc7, 0x6d, 0x99,
+        0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x04, 0xb9, 0xc7, 0x6e, 0x99,
+        0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x04, 0xb9, 0xc7, 0x6f, 0x99,
+    ];
+
+    /// www.example.com AAAA -> CNAME example.com -> 2606:2800:220:1:248:1893:25c8:1946
+    const CNAME_AAAA: [u8; 75] = [
+        0x12, 0x34, 0x81, 0x80, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00,
+        0x03, b'w', b'w', b'w', 0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+        0x00, 0x1c, 0x00, 0x01,
+        // www.example.com CNAME example.com, the target is a pointer to offset 16
+        0xc0, 0x0c, 0x00, 0x05, 0x00, 0x01, 0x00, 0x00, 0x01, 0x2c, 0x00, 0x02, 0xc0, 0x10,
+        // example.com AAAA
+        0xc0, 0x10, 0x00, 0x1c, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x10,
+        0x26, 0x06, 0x28, 0x00, 0x02, 0x20, 0x00, 0x01, 0x02, 0x48, 0x18, 0x93, 0x25, 0xc8, 0x19, 0x46,
+    ];
+
+    /// example.com MX, with a target that is partly compressed
+    const MX: [u8; 66] = [
+        0x00, 0x07, 0x81, 0x80, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00,
+        0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+        0x00, 0x0f, 0x00, 0x01,
+        // 10 mail.example.com
+        0xc0, 0x0c, 0x00, 0x0f, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x09,
+        0x00, 0x0a, 0x04, b'm', b'a', b'i', b'l', 0xc0, 0x0c,
+        // 20 example.com
+        0xc0, 0x0c, 0x00, 0x0f, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x04,
+        0x00, 0x14, 0xc0, 0x0c,
+    ];
+
+    #[test]
+    fn parse_a() {
+        let msg = Message::from(&BETRUSTED_A);
+        assert!(msg.is_response());
+        assert_eq!(msg.id(), 0x61ca);
+        let records = msg.parse_response().unwrap();
+        assert_eq!(records.len(), 4);
+        for (rr, last) in records.iter().zip([0x6c, 0x6d, 0x6e, 0x6f]) {
+            assert_eq!(rr.name, "betrusted.io");
+            assert_eq!(rr.ttl, 3600);
+            assert_eq!(rr.data, RecordData::A(Ipv4Addr::new(0xb9, 0xc7, last, 0x99)));
+        }
+    }
+
+    #[test]
+    fn parse_cname_aaaa() {
+        let records = Message::from(&CNAME_AAAA).parse_response().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].data, RecordData::Cname(String::from("example.com")));
+        let canonical = canonical_name(&records, "WWW.example.com");
+        assert_eq!(canonical, "example.com");
+        let addrs: Vec<&ResourceRecord> = records.iter()
+            .filter(|rr| rr.is_type(QueryType::AAAA) && rr.name.eq_ignore_ascii_case(&canonical))
+            .collect();
+        assert_eq!(addrs.len(), 1);
+        assert_eq!(addrs[0].data, RecordData::Aaaa("2606:2800:220:1:248:1893:25c8:1946".parse().unwrap()));
+    }
+
+    #[test]
+    fn parse_mx() {
+        let records = Message::from(&MX).parse_response().unwrap();
+        assert_eq!(records, vec![
+            ResourceRecord {
+                name: String::from("example.com"),
+                ttl: 3600,
+                data: RecordData::Mx(MxRecord { preference: 10, exchange: String::from("mail.example.com") }),
+            },
+            ResourceRecord {
+                name: String::from("example.com"),
+                ttl: 3600,
+                data: RecordData::Mx(MxRecord { preference: 20, exchange: String::from("example.com") }),
+            },
+        ]);
+    }
+
+    #[test]
+    fn parse_txt_and_srv() {
+        let mut datagram = vec![
+            0x00, 0x08, 0x81, 0x80, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00,
+            0x05, b'_', b'x', b'm', b'p', b'p', 0x04, b'_', b't', b'c', b'p', 0x02, b'i', b'o', 0x00,
+            0x00, 0x21, 0x00, 0x01,
+            // SRV 5 0 5222 chat.io
+            0xc0, 0x0c, 0x00, 0x21, 0x00, 0x01, 0x00, 0x00, 0x00, 0x3c, 0x00, 0x0d,
+            0x00, 0x05, 0x00, 0x00, 0x14, 0x66, 0x04, b'c', b'h', b'a', b't', 0xc0, 0x17,
+            // TXT "v=spf1 " "-all", owned by the io. suffix of the qname
+            0xc0, 0x17, 0x00, 0x10, 0x00, 0x01, 0x00, 0x00, 0x00, 0x3c, 0x00, 0x0d,
+            0x07, b'v', b'=', b's', b'p', b'f', b'1', b' ', 0x04, b'-', b'a', b'l', b'l',
+        ];
+        let records = Message::from(&datagram).parse_response().unwrap();
+        assert_eq!(records[0].name, "_xmpp._tcp.io");
+        assert_eq!(records[0].data, RecordData::Srv(SrvRecord {
+            priority: 5, weight: 0, port: 5222, target: String::from("chat.io"),
+        }));
+        assert_eq!(records[1].name, "io");
+        assert_eq!(records[1].data, RecordData::Txt(vec![String::from("v=spf1 "), String::from("-all")]));
+
+        // a truncated datagram is a format error, not a panic
+        datagram.truncate(datagram.len() - 3);
+        assert!(matches!(Message::from(&datagram).parse_response(), Err(DnsResponseCode::FormatError)));
+    }
+
+    #[test]
+    fn edns_and_truncation() {
+        let query = Message::query("betrusted.io.", QueryType::A, QueryClass::IN, 0x61ca);
+        let plain_len = query.datagram.len();
+        // the trailing dot doesn't produce an empty label
+        assert_eq!(plain_len, 30);
+        let query = query.with_edns(1232);
+        assert_eq!(&query.datagram[10..12], &[0x00, 0x01]);
+        assert_eq!(&query.datagram[plain_len..], &[0x00, 0x00, 0x29, 0x04, 0xd0, 0, 0, 0, 0, 0, 0]);
+
+        let framed = query.to_tcp();
+        assert_eq!(u16::from_be_bytes([framed[0], framed[1]]) as usize, query.datagram.len());
+        assert_eq!(&framed[2..], &query.datagram[..]);
+
+        let response = Message::from(&BETRUSTED_A);
+        assert!(response.is_response_to(&query));
+        assert!(!response.is_truncated());
+        assert!(!Message::from(&BETRUSTED_A[..8]).is_response_to(&query));
+        let mut truncated = BETRUSTED_A.to_vec();
+        truncated[2] |= 0x02;
+        assert!(Message::from(&truncated).is_truncated());
+    }
+
+    #[test]
+    fn rcode() {
+        let mut datagram = BETRUSTED_A.to_vec();
+        assert!(matches!(Message::from(&datagram).rcode(), DnsResponseCode::NoError));
+        datagram[3] |= 0x01;
+        assert!(matches!(Message::from(&datagram).rcode(), DnsResponseCode::FormatError));
+        datagram[3] |= 0x03;
+        assert!(matches!(Message::from(&datagram).rcode(), DnsResponseCode::NameError));
+    }
+
+    #[test]
+    fn opcode() {
+        let mut datagram = BETRUSTED_A.to_vec();
+        assert_eq!(Message::from(&datagram).opcode(), 0);
+        datagram[2] |= 0x10; // opcode 2, server status request
+        assert_eq!(Message::from(&datagram).opcode(), 2);
+    }
+
+    #[test]
+    fn reject_pointer_loop() {
+        let mut datagram = BETRUSTED_A.to_vec();
+        // point the first answer's name at itself
+        datagram[30] = 0xc0;
+        datagram[31] = 30;
+        assert!(matches!(Message::from(&datagram).parse_response(), Err(DnsResponseCode::FormatError)));
+    }
+
+    #[test]
+    fn typed_record_roundtrip() {
+        let records = [
+            DnsRecord::Txt(String::from("hello")),
+            DnsRecord::Mx(MxRecord { preference: 10, exchange: String::from("mail.example.com") }),
+            DnsRecord::Srv(SrvRecord { priority: 1, weight: 2, port: 443, target: String::from("example.com") }),
+        ];
+        for record in records.iter() {
+            let mut encoded = Vec::new();
+            record.encode(&mut encoded);
+            assert_eq!(DnsRecord::decode(record.query_type(), &encoded).as_ref(), Some(record));
+        }
+    }
+}
@@ -1,11 +1,14 @@
 #![cfg_attr(target_os = "none", no_std)]
 #![cfg_attr(target_os = "none", no_main)]
 
+mod names;
+mod timeouts;
+
 const EXTRA_KEY: usize = 42;
 
-fn sleep_loop_4(main_conn: usize, sleep_ms: usize, ticktimer_conn: usize, pid: usize) {
+fn sleep_loop_4(main_conn: usize, sleep_ms: usize, _ticktimer_conn: usize, pid: usize) {
     let tid = xous::current_tid().unwrap();
-    let ticktimer_conn = ticktimer_conn as _;
+    let ticktimer = ticktimer_server::Ticktimer::new().expect("Couldn't connect to Ticktimer");
     // let pid = xous::current_pid().unwrap().get();
     log::info!(
         "My thread number is {}, sleeping 0x{:08x} ({}) ms and main_conn: {}",
@@ -25,7 +28,7 @@ fn sleep_loop_4(main_conn: usize, sleep_ms: usize, ticktimer_conn: usize, pid: u
         //     loop_count
         // );
         let start_time = ticktimer.elapsed_ms();
-        ticktimer.sleep_ms(ticktimer_conn, sleep_ms).unwrap();
+        ticktimer.sleep_ms(sleep_ms).unwrap();
         let end_time = ticktimer.elapsed_ms();
         log::info!(
             "TEST THREAD {}:{}: target {}ms, {} loops: Sleep finished (uptime: {}, took {} ms)",
@@ -61,8 +64,8 @@ fn sleep_loop_3(main_conn: usize, sleep_ms: usize, ticktimer_conn: usize) {
 }
 
 fn sleep_loop_2(main_conn: usize, sleep_ms: usize) {
-    let ticktimer = ticktimer_server::Ticktimer::new().expect("Couldn't connect to Ticktimer");
-    sleep_loop_3(main_conn, sleep_ms, ticktimer_conn);
+    let ticktimer_conn = xous::connect(xous::SID::from_bytes(b"ticktimer-server").unwrap()).unwrap();
+    sleep_loop_3(main_conn, sleep_ms, ticktimer_conn as _);
 }
 
 fn sleep_loop_1(main_conn: usize) {
@@ -79,7 +82,10 @@ fn sleep_loop_0() {
 fn main() -> ! {
     log_server::init_wait().unwrap();
 
-    let ticktimer = ticktimer_server::Ticktimer::new().expect("Couldn't connect to Ticktimer");
+    names::authenticated_lookup();
+    timeouts::syscall_timeouts();
+
+    let ticktimer_conn = xous::connect(xous::SID::from_bytes(b"ticktimer-server").unwrap()).unwrap();
 
     let main_server = xous::create_server().unwrap();
     let server_conn = xous::connect(main_server).unwrap();
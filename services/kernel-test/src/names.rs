@@ -0,0 +1,47 @@
+//! Exercises the name server's authenticated lookups, which broker connections to a server
+//! once its trusted connections are taken, but only for processes that know the server's key.
+
+const AUTH_SERVER_NAME: &str = "_kernel-test authenticated server_";
+const AUTH_KEY: [u8; 32] = *b"kernel-test authentication key!!";
+const WRONG_KEY: [u8; 32] = [0x55; 32];
+
+pub fn authenticated_lookup() {
+    let xns = xous_names::XousNames::new().unwrap();
+    // no trusted connections at all, so every client has to authenticate
+    let sid = xns
+        .register_name_with_key(AUTH_SERVER_NAME, Some(0), &AUTH_KEY)
+        .expect("couldn't register authenticated server");
+
+    // a plain lookup is asked to authenticate, which it can't do
+    assert_eq!(
+        xns.request_connection(AUTH_SERVER_NAME),
+        Err(xous::Error::AccessDenied)
+    );
+    // the wrong key is rejected by the name server
+    assert_eq!(
+        xns.request_authenticated_connection(AUTH_SERVER_NAME, &WRONG_KEY),
+        Err(xous::Error::AccessDenied)
+    );
+    // a server that doesn't exist can't be authenticated to
+    assert_eq!(
+        xns.request_authenticated_connection("_kernel-test no such server_", &AUTH_KEY),
+        Err(xous::Error::ServerNotFound)
+    );
+    log::info!("authenticated lookup rejections OK");
+
+    // the right key gets a working connection, and keeps working after a failed attempt
+    let cid = xns
+        .request_authenticated_connection(AUTH_SERVER_NAME, &AUTH_KEY)
+        .expect("couldn't make authenticated connection");
+    xous::send_message(cid, xous::Message::new_scalar(42, 1, 2, 3, 4)).unwrap();
+    let msg = xous::receive_message(sid).unwrap();
+    assert_eq!(msg.body.id(), 42);
+    assert_eq!(msg.body.scalar_message().map(|s| s.arg1), Some(1));
+    // each lookup gets its own challenge, so a second connection works too
+    xns.request_authenticated_connection(AUTH_SERVER_NAME, &AUTH_KEY)
+        .expect("couldn't make second authenticated connection");
+    log::info!("authenticated lookup success OK");
+
+    xns.unregister_server(sid).unwrap();
+    xous::destroy_server(sid).unwrap();
+}
@@ -0,0 +1,102 @@
+//! Exercises `ReceiveMessageTimeout` and `SendMessageTimeout` on hardware, where the kernel
+//! expires them on the preemption tick rather than against a host clock.
+
+const TIMEOUT_MS: usize = 50;
+
+/// Sends a scalar after the receiver has had time to park
+fn delayed_sender(cid: usize) {
+    let ticktimer = ticktimer_server::Ticktimer::new().expect("Couldn't connect to Ticktimer");
+    ticktimer.sleep_ms(TIMEOUT_MS / 2).unwrap();
+    xous::send_message(cid as _, xous::Message::new_scalar(2, 3, 4, 5, 6)).unwrap();
+}
+
+/// Answers one blocking scalar sent to the server `s0:s1:s2:s3`
+fn replier(s0: usize, s1: usize, s2: usize, s3: usize) {
+    let sid = xous::SID::from_u32(s0 as _, s1 as _, s2 as _, s3 as _);
+    let msg = xous::receive_message(sid).unwrap();
+    assert_eq!(msg.body.id(), 3);
+    xous::return_scalar(msg.sender, 42).unwrap();
+}
+
+/// Takes one blocking scalar sent to the server `s0:s1:s2:s3`, but answers it
+/// only after the sender has given up
+fn late_replier(s0: usize, s1: usize, s2: usize, s3: usize) {
+    let ticktimer = ticktimer_server::Ticktimer::new().expect("Couldn't connect to Ticktimer");
+    let sid = xous::SID::from_u32(s0 as _, s1 as _, s2 as _, s3 as _);
+    let msg = xous::receive_message(sid).unwrap();
+    assert_eq!(msg.body.id(), 5);
+    ticktimer.sleep_ms(4 * TIMEOUT_MS).unwrap();
+    xous::return_scalar(msg.sender, 99).unwrap();
+}
+
+pub fn syscall_timeouts() {
+    let ticktimer = ticktimer_server::Ticktimer::new().expect("Couldn't connect to Ticktimer");
+    let sid = xous::create_server().unwrap();
+    let cid = xous::connect(sid).unwrap();
+
+    // nothing is sent, so the receive gives up, but not before the deadline
+    let start = ticktimer.elapsed_ms();
+    assert_eq!(
+        xous::receive_message_timeout(sid, TIMEOUT_MS),
+        Err(xous::Error::Timeout)
+    );
+    assert!(ticktimer.elapsed_ms() - start >= TIMEOUT_MS as u64);
+    log::info!("receive timeout OK");
+
+    // a message arriving before the deadline is returned as usual
+    let sender = xous::create_thread_1(delayed_sender, cid as _).unwrap();
+    let msg = xous::receive_message_timeout(sid, 10 * TIMEOUT_MS).unwrap();
+    assert_eq!(msg.body.id(), 2);
+    xous::wait_thread(sender).unwrap();
+    // the stale deadline passes while nobody is waiting, and must not wake a later receive
+    ticktimer.sleep_ms(20 * TIMEOUT_MS).unwrap();
+    xous::send_message(cid, xous::Message::new_scalar(4, 0, 0, 0, 0)).unwrap();
+    assert_eq!(xous::receive_message(sid).unwrap().body.id(), 4);
+    log::info!("receive before timeout OK");
+
+    // nobody is receiving, so the send gives up and the message is delivered without a reply
+    let start = ticktimer.elapsed_ms();
+    assert_eq!(
+        xous::send_message_timeout(
+            cid,
+            xous::Message::new_blocking_scalar(1, 0, 0, 0, 0),
+            TIMEOUT_MS
+        ),
+        Err(xous::Error::Timeout)
+    );
+    assert!(ticktimer.elapsed_ms() - start >= TIMEOUT_MS as u64);
+    let msg = xous::receive_message(sid).unwrap();
+    assert_eq!(msg.body.id(), 1);
+    assert!(!msg.body.is_blocking());
+    log::info!("send timeout OK");
+
+    // a server that answers in time gets its reply through
+    let s = sid.to_u32();
+    let server = xous::create_thread_4(replier, s.0 as _, s.1 as _, s.2 as _, s.3 as _).unwrap();
+    assert_eq!(
+        xous::send_message_timeout(
+            cid,
+            xous::Message::new_blocking_scalar(3, 0, 0, 0, 0),
+            10 * TIMEOUT_MS
+        ),
+        Ok(xous::Result::Scalar1(42))
+    );
+    xous::wait_thread(server).unwrap();
+    log::info!("send before timeout OK");
+
+    // a server that takes the message but answers too late has its reply discarded
+    let server =
+        xous::create_thread_4(late_replier, s.0 as _, s.1 as _, s.2 as _, s.3 as _).unwrap();
+    assert_eq!(
+        xous::send_message_timeout(
+            cid,
+            xous::Message::new_blocking_scalar(5, 0, 0, 0, 0),
+            TIMEOUT_MS
+        ),
+        Err(xous::Error::Timeout)
+    );
+    xous::wait_thread(server).unwrap();
+    log::info!("late reply OK");
+
+    xous::destroy_server(sid).unwrap();
+}
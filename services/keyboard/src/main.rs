@@ -745,6 +745,8 @@ fn main() -> ! {
     #[cfg(not(any(target_os = "none", target_os = "xous")))]
     let kbd_sid = xns.register_name(api::SERVER_NAME_KBD, Some(5)).expect("can't register server");
     log::trace!("registered with NS -- {:?}", kbd_sid);
+    // keep typing responsive while background work is going on
+    xous::set_thread_priority(xous::current_tid().unwrap(), xous::ThreadPriority::High).expect("couldn't set keyboard thread priority");
 
     // Create a new kbd object
     let mut kbd = Keyboard::new(kbd_sid);
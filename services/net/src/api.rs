@@ -1,7 +1,20 @@
 pub(crate) mod ping;
 pub(crate) use ping::*;
 pub(crate) mod tcp;
+// the filter is only used by the server, but lives here next to the IPC structures it describes
+#[allow(dead_code)]
+pub(crate) mod pcap;
 pub use ping::NetPingCallback;
+pub use pcap::PcapStatus;
+pub(crate) use pcap::*;
+pub(crate) mod stats;
+pub use stats::{InterfaceStats, SocketKind, SocketState, SocketStats};
+pub(crate) use stats::*;
+// the rule checks are only used by the server, but the clients validate rules before sending them
+#[allow(dead_code)]
+pub(crate) mod policy;
+pub use policy::DEFAULT_POLICY_APP;
+pub(crate) use policy::*;
 // needed to keep hosted mode quiet, since the Tcp implementation is a bodge
 #[allow(unused_imports)]
 pub(crate) use tcp::*;
@@ -353,6 +366,42 @@ pub(crate) enum Opcode {
     StdTcpAccept = 45,
 
     StdTcpStreamShutdown = 46,
+
+    /// Joins an IPv4 multicast group, so that datagrams sent to the group are delivered to UDP sockets
+    /// bound to the unspecified address. Membership is reported to the network with IGMP.
+    ///
+    /// Blocking scalar: arg1 is the group address in network order (`u32::from(Ipv4Addr)`).
+    /// Returns 1 on success, 0 if the address isn't a multicast address, the group table is full, or the
+    /// caller's socket policy doesn't let it listen.
+    JoinMulticastV4 = 47,
+
+    /// Leaves an IPv4 multicast group joined with `JoinMulticastV4`. Same arguments and return value.
+    LeaveMulticastV4 = 48,
+
+    /// Starts, stops, clears or reports on the capture of frames at the network device.
+    /// Memory message: a `PcapControl`, returned with its `status` filled in. A capture holds
+    /// the traffic of every app, so only the shell may use this and `PcapFetch`.
+    PcapControl = 49,
+    /// Fetches the captured frames as a pcap file. Mutably lends a `PCAP_BUF_LEN` page, which
+    /// the server fills with the length of the file as a little-endian u32, followed by the file.
+    /// The length is `u32::MAX` if the sender isn't allowed to capture.
+    PcapFetch = 50,
+
+    /// Returns the traffic counters of the network interface. Memory message: an `InterfaceStats`.
+    GetInterfaceStats = 51,
+    /// Returns the traffic counters of the libstd sockets. Memory message: a `SocketStatsList`.
+    GetSocketStats = 52,
+
+    /// Lists the apps that have a socket policy, or that have used the net server since boot.
+    /// Memory message: a `PolicyAppList`.
+    PolicyListApps = 53,
+    /// Returns the socket policy of an app. Memory message: a `PolicyEntry`, returned with `rules` filled in.
+    PolicyGet = 54,
+    /// Replaces the socket policy of an app, or removes it if `rules` is empty. Memory message: a
+    /// `PolicyEntry`, returned with `ok` set if the rules parsed and were stored.
+    PolicySet = 55,
+    /// Internal: re-reads the policies from the PDDB. Scalar, no arguments.
+    PolicyReload = 56,
 }
 
 #[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone, Default)]
@@ -421,7 +470,7 @@ pub enum NetError {
     // Ok = 0,
     Unaddressable = 1,
     SocketInUse = 2,
-    // AccessDenied = 3,
+    AccessDenied = 3,
     Invalid = 4,
     // Finished = 5,
     LibraryError = 6,
@@ -0,0 +1,260 @@
+use rkyv::{Archive, Deserialize, Serialize};
+use std::convert::TryInto;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Size of the buffer that `NetManager::pcap_fetch()` lends to the server. The capture ring is sized
+/// so that a complete pcap file, preceded by its length as a u32, always fits.
+pub(crate) const PCAP_BUF_LEN: usize = 64 * 1024;
+/// Length of the pcap global header
+pub(crate) const PCAP_HEADER_LEN: usize = 24;
+/// Length of the header that precedes every frame in a pcap file
+pub(crate) const PCAP_RECORD_HEADER_LEN: usize = 16;
+pub(crate) const PCAP_FILTER_LEN: usize = 128;
+/// Frames are captured in full unless a shorter `snap` length is given
+pub(crate) const PCAP_DEFAULT_SNAPLEN: usize = com::api::NET_MTU;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_ARP: u16 = 0x0806;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERNET_HEADER_LEN: usize = 14;
+
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum PcapOp {
+    /// Clear the capture buffer and start capturing frames that match the filter
+    Start,
+    /// Stop capturing; the frames captured so far are kept
+    Stop,
+    /// Discard the captured frames
+    Clear,
+    Status,
+}
+
+/// The state of the packet capture
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone, Default, PartialEq, Eq)]
+pub struct PcapStatus {
+    pub running: bool,
+    /// number of frames in the capture buffer
+    pub frames: u32,
+    /// size of the pcap file that `NetManager::pcap_fetch()` would return
+    pub bytes: u32,
+    /// number of frames that were evicted from the buffer to make room for newer ones
+    pub dropped: u32,
+}
+
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
+pub(crate) struct PcapControl {
+    pub op: PcapOp,
+    /// the filter expression for `PcapOp::Start`, see `PcapFilter::parse()`
+    pub filter: xous_ipc::String<PCAP_FILTER_LEN>,
+    /// filled in by the server; `None` if the request was invalid
+    pub status: Option<PcapStatus>,
+    /// set by the server if the sender isn't allowed to capture
+    pub denied: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum PcapDirection {
+    Rx,
+    Tx,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Ether {
+    Arp,
+    Ipv4,
+    Ipv6,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Proto {
+    Tcp,
+    Udp,
+    /// ICMP for IPv4 and ICMPv6 for IPv6
+    Icmp,
+}
+
+/// The parts of an Ethernet frame that a filter can select on
+#[derive(Debug, Default)]
+struct FrameSummary {
+    ether: Option<Ether>,
+    proto: Option<Proto>,
+    src: Option<IpAddr>,
+    dst: Option<IpAddr>,
+    ports: Option<(u16, u16)>,
+}
+impl FrameSummary {
+    fn from_frame(frame: &[u8]) -> FrameSummary {
+        let mut summary = FrameSummary::default();
+        let ethertype = match frame.get(12..14) {
+            Some(b) => u16::from_be_bytes([b[0], b[1]]),
+            None => return summary,
+        };
+        let l3 = &frame[ETHERNET_HEADER_LEN.min(frame.len())..];
+        let (proto, l4) = match ethertype {
+            ETHERTYPE_ARP => {
+                summary.ether = Some(Ether::Arp);
+                // sender and target protocol addresses, assuming IPv4 over Ethernet
+                summary.src = l3.get(14..18).map(|b| IpAddr::V4(Ipv4Addr::new(b[0], b[1], b[2], b[3])));
+                summary.dst = l3.get(24..28).map(|b| IpAddr::V4(Ipv4Addr::new(b[0], b[1], b[2], b[3])));
+                return summary;
+            }
+            ETHERTYPE_IPV4 => {
+                summary.ether = Some(Ether::Ipv4);
+                summary.src = l3.get(12..16).map(|b| IpAddr::V4(Ipv4Addr::new(b[0], b[1], b[2], b[3])));
+                summary.dst = l3.get(16..20).map(|b| IpAddr::V4(Ipv4Addr::new(b[0], b[1], b[2], b[3])));
+                let header_len = l3.first().map(|b| (b & 0xf) as usize * 4).unwrap_or(0);
+                (l3.get(9).copied(), l3.get(header_len..))
+            }
+            ETHERTYPE_IPV6 => {
+                summary.ether = Some(Ether::Ipv6);
+                let addr = |b: &[u8]| {
+                    let octets: [u8; 16] = b.try_into().unwrap();
+                    IpAddr::V6(Ipv6Addr::from(octets))
+                };
+                summary.src = l3.get(8..24).map(addr);
+                summary.dst = l3.get(24..40).map(addr);
+                // extension headers aren't followed
+                (l3.get(6).copied(), l3.get(40..))
+            }
+            _ => return summary,
+        };
+        summary.proto = match proto {
+            Some(6) => Some(Proto::Tcp),
+            Some(17) => Some(Proto::Udp),
+            Some(1) | Some(58) => Some(Proto::Icmp),
+            _ => None,
+        };
+        if matches!(summary.proto, Some(Proto::Tcp) | Some(Proto::Udp)) {
+            summary.ports = l4.and_then(|l4| l4.get(0..4))
+                .map(|b| (u16::from_be_bytes([b[0], b[1]]), u16::from_be_bytes([b[2], b[3]])));
+        }
+        summary
+    }
+}
+
+/// Selects the frames to capture. A filter is written as a list of terms, all of which have to
+/// match, in the spirit of (but much simpler than) tcpdump's filters:
+///
+///   - `rx`, `tx`: the direction of the frame
+///   - `arp`, `ip`, `ip6`: the ethertype
+///   - `tcp`, `udp`, `icmp`: the IP protocol; `icmp` also matches ICMPv6
+///   - `port <n>`: TCP or UDP, with `n` as the source or destination port
+///   - `host <addr>`: `addr` as the source or destination address, including in ARP frames
+///   - `snap <n>`: only keep the first `n` bytes of each frame
+///
+/// An empty filter captures everything.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PcapFilter {
+    direction: Option<PcapDirection>,
+    ether: Option<Ether>,
+    proto: Option<Proto>,
+    port: Option<u16>,
+    host: Option<IpAddr>,
+    pub snaplen: usize,
+}
+impl Default for PcapFilter {
+    fn default() -> Self {
+        PcapFilter {
+            direction: None,
+            ether: None,
+            proto: None,
+            port: None,
+            host: None,
+            snaplen: PCAP_DEFAULT_SNAPLEN,
+        }
+    }
+}
+impl PcapFilter {
+    /// Parses a filter expression. Returns the offending term if the expression is invalid.
+    pub fn parse(expr: &str) -> Result<PcapFilter, &str> {
+        let mut filter = PcapFilter::default();
+        let mut terms = expr.split_whitespace();
+        while let Some(term) = terms.next() {
+            match term {
+                "rx" => filter.direction = Some(PcapDirection::Rx),
+                "tx" => filter.direction = Some(PcapDirection::Tx),
+                "arp" => filter.ether = Some(Ether::Arp),
+                "ip" => filter.ether = Some(Ether::Ipv4),
+                "ip6" => filter.ether = Some(Ether::Ipv6),
+                "tcp" => filter.proto = Some(Proto::Tcp),
+                "udp" => filter.proto = Some(Proto::Udp),
+                "icmp" => filter.proto = Some(Proto::Icmp),
+                "port" => filter.port = Some(terms.next().and_then(|n| n.parse().ok()).ok_or(term)?),
+                "host" => filter.host = Some(terms.next().and_then(|a| a.parse().ok()).ok_or(term)?),
+                "snap" => {
+                    let snaplen: usize = terms.next().and_then(|n| n.parse().ok()).ok_or(term)?;
+                    if !(ETHERNET_HEADER_LEN..=PCAP_DEFAULT_SNAPLEN).contains(&snaplen) {
+                        return Err(term);
+                    }
+                    filter.snaplen = snaplen;
+                }
+                _ => return Err(term),
+            }
+        }
+        Ok(filter)
+    }
+
+    pub(crate) fn matches(&self, direction: PcapDirection, frame: &[u8]) -> bool {
+        if self.direction.map_or(false, |d| d != direction) {
+            return false;
+        }
+        if self.ether.is_none() && self.proto.is_none() && self.port.is_none() && self.host.is_none() {
+            return true;
+        }
+        let summary = FrameSummary::from_frame(frame);
+        self.ether.map_or(true, |e| summary.ether == Some(e))
+            && self.proto.map_or(true, |p| summary.proto == Some(p))
+            && self.port.map_or(true, |p| summary.ports.map_or(false, |(src, dst)| src == p || dst == p))
+            && self.host.map_or(true, |h| summary.src == Some(h) || summary.dst == Some(h))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// an Ethernet frame carrying a DNS query from 10.0.245.7:49153 to 1.1.1.1:53
+    fn dns_query() -> Vec<u8> {
+        let mut frame = vec![0u8; 14 + 20 + 8];
+        frame[12..14].copy_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        frame[14] = 0x45;
+        frame[14 + 9] = 17;
+        frame[14 + 12..14 + 16].copy_from_slice(&[10, 0, 245, 7]);
+        frame[14 + 16..14 + 20].copy_from_slice(&[1, 1, 1, 1]);
+        frame[34..36].copy_from_slice(&49153u16.to_be_bytes());
+        frame[36..38].copy_from_slice(&53u16.to_be_bytes());
+        frame
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(PcapFilter::parse(""), Ok(PcapFilter::default()));
+        let filter = PcapFilter::parse("tx udp port 53 host 1.1.1.1 snap 64").unwrap();
+        assert_eq!(filter.direction, Some(PcapDirection::Tx));
+        assert_eq!(filter.proto, Some(Proto::Udp));
+        assert_eq!(filter.port, Some(53));
+        assert_eq!(filter.host, Some(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1))));
+        assert_eq!(filter.snaplen, 64);
+        assert_eq!(PcapFilter::parse("udp port"), Err("port"));
+        assert_eq!(PcapFilter::parse("host 1.1.1"), Err("host"));
+        assert_eq!(PcapFilter::parse("snap 4"), Err("snap"));
+        assert_eq!(PcapFilter::parse("sctp"), Err("sctp"));
+    }
+
+    #[test]
+    fn matching() {
+        let frame = dns_query();
+        let matches = |expr: &str, dir| PcapFilter::parse(expr).unwrap().matches(dir, &frame);
+        assert!(matches("", PcapDirection::Rx));
+        assert!(matches("tx ip udp port 53", PcapDirection::Tx));
+        assert!(!matches("tx", PcapDirection::Rx));
+        assert!(matches("port 49153 host 10.0.245.7", PcapDirection::Rx));
+        assert!(!matches("tcp", PcapDirection::Rx));
+        assert!(!matches("ip6", PcapDirection::Rx));
+        assert!(!matches("port 80", PcapDirection::Rx));
+        assert!(!matches("host 8.8.8.8", PcapDirection::Rx));
+        // runt frames never match a filter that needs to look inside them
+        assert!(!PcapFilter::parse("udp").unwrap().matches(PcapDirection::Rx, &frame[..20]));
+        assert!(PcapFilter::parse("rx").unwrap().matches(PcapDirection::Rx, &frame[..4]));
+    }
+}
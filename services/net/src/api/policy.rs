@@ -0,0 +1,254 @@
+use rkyv::{Archive, Deserialize, Serialize};
+use std::fmt;
+use std::net::IpAddr;
+
+/// PDDB dictionary that holds the socket policies: one key per process name, with the rules as text
+pub(crate) const POLICY_DICT: &str = "net.acl";
+/// Name of the policy that applies to every app whose own rules don't decide a request
+pub const DEFAULT_POLICY_APP: &str = "*";
+pub(crate) const POLICY_APP_LEN: usize = 64;
+pub(crate) const POLICY_RULES_LEN: usize = 1024;
+pub(crate) const MAX_POLICY_APPS: usize = 32;
+
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
+pub(crate) struct PolicyEntry {
+    pub app: xous_ipc::String<POLICY_APP_LEN>,
+    /// the rules, in the form that `AppPolicy::parse()` accepts; empty if the app has no policy
+    pub rules: xous_ipc::String<POLICY_RULES_LEN>,
+    /// filled in by the server for `Opcode::PolicySet`: false if the caller may not change policies, or
+    /// if the rules didn't parse or couldn't be stored
+    pub ok: bool,
+}
+
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone, Default)]
+pub(crate) struct PolicyAppList {
+    pub list: [Option<xous_ipc::String<POLICY_APP_LEN>>; MAX_POLICY_APPS],
+}
+
+/// The things an app can ask the net server to do that a policy can restrict
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum PolicyAction {
+    /// open a TCP connection or send a UDP datagram to a destination
+    Connect,
+    /// listen for TCP connections, or bind a UDP socket, on a local port
+    Listen,
+    /// send an ICMP echo request with the net server's ping API
+    Ping,
+}
+impl PolicyAction {
+    fn name(&self) -> &'static str {
+        match self {
+            PolicyAction::Connect => "connect",
+            PolicyAction::Listen => "listen",
+            PolicyAction::Ping => "ping",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Rule {
+    allow: bool,
+    action: PolicyAction,
+    /// an address and prefix length; `None` matches every destination
+    net: Option<(IpAddr, u8)>,
+    /// `None` matches every port
+    port: Option<u16>,
+}
+impl Rule {
+    fn matches(&self, action: PolicyAction, addr: Option<IpAddr>, port: Option<u16>) -> bool {
+        if self.action != action {
+            return false;
+        }
+        if let Some(p) = self.port {
+            if port != Some(p) {
+                return false;
+            }
+        }
+        match (self.net, addr) {
+            (None, _) => true,
+            (Some((IpAddr::V4(net), prefix)), Some(IpAddr::V4(a))) => {
+                let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(a) & mask
+            }
+            (Some((IpAddr::V6(net), prefix)), Some(IpAddr::V6(a))) => {
+                let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(a) & mask
+            }
+            _ => false,
+        }
+    }
+}
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", if self.allow { "allow" } else { "deny" }, self.action.name())?;
+        match self.net {
+            Some((addr, prefix)) => {
+                let host_prefix = if addr.is_ipv4() { 32 } else { 128 };
+                if prefix == host_prefix {
+                    write!(f, " {}", addr)?;
+                } else {
+                    write!(f, " {}/{}", addr, prefix)?;
+                }
+            }
+            None if self.action == PolicyAction::Connect => write!(f, " any")?,
+            None => (),
+        }
+        if let Some(port) = self.port {
+            write!(f, " port {}", port)?;
+        }
+        Ok(())
+    }
+}
+
+/// The rules that decide which sockets an app may open. Rules are separated by newlines or
+/// semicolons, and the first rule that matches a request decides it:
+///
+///   - `allow|deny connect <dest> [port <n>]`: outgoing TCP connections and UDP datagrams, where
+///     `dest` is `any`, an address, or a network such as `10.0.0.0/8`
+///   - `allow|deny listen [port <n>]`: TCP listeners and UDP sockets bound to a fixed port. Joining and
+///     leaving multicast groups is checked as a listen with no port, so only a rule without one decides it
+///   - `allow|deny ping [<dest>]`: pings sent through the net server
+///
+/// A request that no rule matches falls through to the next policy, and is allowed if none decides it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct AppPolicy {
+    rules: Vec<Rule>,
+}
+impl AppPolicy {
+    /// Returns a policy that denies everything
+    pub fn deny_all() -> AppPolicy {
+        AppPolicy {
+            rules: [PolicyAction::Connect, PolicyAction::Listen, PolicyAction::Ping]
+                .iter()
+                .map(|&action| Rule { allow: false, action, net: None, port: None })
+                .collect(),
+        }
+    }
+
+    /// Parses a list of rules. Returns the offending rule if one is invalid.
+    pub fn parse(text: &str) -> Result<AppPolicy, &str> {
+        let mut policy = AppPolicy::default();
+        for line in text.split(['\n', ';']) {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            policy.rules.push(Self::parse_rule(line).ok_or(line)?);
+        }
+        Ok(policy)
+    }
+
+    fn parse_rule(line: &str) -> Option<Rule> {
+        let mut terms = line.split_whitespace();
+        let allow = match terms.next()? {
+            "allow" => true,
+            "deny" => false,
+            _ => return None,
+        };
+        let action = match terms.next()? {
+            "connect" => PolicyAction::Connect,
+            "listen" => PolicyAction::Listen,
+            "ping" => PolicyAction::Ping,
+            _ => return None,
+        };
+        let mut rule = Rule { allow, action, net: None, port: None };
+        let mut next = terms.next();
+        if action != PolicyAction::Listen {
+            match next {
+                Some("any") => next = terms.next(),
+                Some(dest) if dest != "port" => {
+                    rule.net = Some(parse_net(dest)?);
+                    next = terms.next();
+                }
+                Some(_) => (),
+                // a destination is required for connect, so "allow connect" isn't mistaken for a complete rule
+                None if action == PolicyAction::Connect => return None,
+                None => (),
+            }
+        }
+        if action != PolicyAction::Ping && next == Some("port") {
+            rule.port = Some(terms.next()?.parse().ok()?);
+            next = terms.next();
+        }
+        match next {
+            None => Some(rule),
+            Some(_) => None,
+        }
+    }
+
+    /// Returns whether the rules allow `action`, or `None` if no rule covers it
+    pub fn check(&self, action: PolicyAction, addr: Option<IpAddr>, port: Option<u16>) -> Option<bool> {
+        self.rules.iter().find(|r| r.matches(action, addr, port)).map(|r| r.allow)
+    }
+}
+impl fmt::Display for AppPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, rule) in self.rules.iter().enumerate() {
+            if i != 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", rule)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_net(dest: &str) -> Option<(IpAddr, u8)> {
+    let (addr, prefix) = match dest.split_once('/') {
+        Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, Some(prefix.parse::<u8>().ok()?)),
+        None => (dest.parse::<IpAddr>().ok()?, None),
+    };
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    match prefix {
+        Some(p) if p > max => None,
+        Some(p) => Some((addr, p)),
+        None => Some((addr, max)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn parse() {
+        let text = "allow connect 10.0.0.0/8 port 443\ndeny connect any; allow listen port 8080\ndeny ping fe80::/10\n# a comment\n";
+        let policy = AppPolicy::parse(text).unwrap();
+        assert_eq!(policy.rules.len(), 4);
+        assert_eq!(
+            policy.to_string(),
+            "allow connect 10.0.0.0/8 port 443\ndeny connect any\nallow listen port 8080\ndeny ping fe80::/10"
+        );
+        assert_eq!(AppPolicy::parse(&policy.to_string()), Ok(policy));
+        assert_eq!(AppPolicy::parse("allow connect 1.1.1.1").unwrap().to_string(), "allow connect 1.1.1.1");
+        assert_eq!(AppPolicy::parse(""), Ok(AppPolicy::default()));
+
+        assert_eq!(AppPolicy::parse("allow connect"), Err("allow connect"));
+        assert_eq!(AppPolicy::parse("allow ping; permit listen"), Err("permit listen"));
+        assert_eq!(AppPolicy::parse("deny listen 10.0.0.1"), Err("deny listen 10.0.0.1"));
+        assert_eq!(AppPolicy::parse("deny ping any port 7"), Err("deny ping any port 7"));
+        assert_eq!(AppPolicy::parse("deny connect 10.0.0.0/33"), Err("deny connect 10.0.0.0/33"));
+        assert_eq!(AppPolicy::parse("deny connect any port"), Err("deny connect any port"));
+    }
+
+    #[test]
+    fn check() {
+        let policy = AppPolicy::parse("allow connect 10.0.0.0/8 port 443; deny connect any; deny listen port 22; allow ping").unwrap();
+        assert_eq!(policy.check(PolicyAction::Connect, ip("10.1.2.3"), Some(443)), Some(true));
+        assert_eq!(policy.check(PolicyAction::Connect, ip("10.1.2.3"), Some(80)), Some(false));
+        assert_eq!(policy.check(PolicyAction::Connect, ip("1.1.1.1"), Some(443)), Some(false));
+        assert_eq!(policy.check(PolicyAction::Connect, ip("::ffff:10.1.2.3"), Some(443)), Some(false));
+        assert_eq!(policy.check(PolicyAction::Listen, None, Some(22)), Some(false));
+        assert_eq!(policy.check(PolicyAction::Listen, None, Some(8080)), None);
+        assert_eq!(policy.check(PolicyAction::Ping, ip("8.8.8.8"), None), Some(true));
+
+        let everything = AppPolicy::deny_all();
+        assert_eq!(everything.check(PolicyAction::Listen, None, Some(8080)), Some(false));
+        assert_eq!(everything.check(PolicyAction::Connect, ip("::1"), Some(1)), Some(false));
+        assert_eq!(AppPolicy::parse(&everything.to_string()), Ok(everything));
+    }
+}
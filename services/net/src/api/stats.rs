@@ -0,0 +1,137 @@
+use super::NetSocketAddr;
+use rkyv::{Archive, Deserialize, Serialize};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+/// The most sockets that `NetManager::socket_stats()` reports on.
+pub(crate) const MAX_SOCKET_STATS: usize = 32;
+
+/// Traffic counters for the network interface, counted at the device since the net server started.
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone, Default, PartialEq, Eq)]
+pub struct InterfaceStats {
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    /// frames that arrived before the previous one was drained, or that the stack couldn't process
+    pub rx_dropped: u32,
+    /// frames that were dropped because of a bad IP, TCP, UDP or ICMP checksum
+    pub rx_checksum_errors: u32,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+    /// frames that the WLAN chip refused to send
+    pub tx_dropped: u32,
+    /// TCP segments that were sent again because they weren't acknowledged in time
+    pub tcp_retransmits: u32,
+}
+
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub enum SocketKind {
+    Tcp,
+    Udp,
+}
+
+/// The state of a socket: for TCP, the state of the connection as in RFC 793
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub enum SocketState {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+    /// a UDP socket that is bound to a port
+    Bound,
+}
+impl fmt::Display for SocketState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SocketState::Closed => "CLOSED",
+            SocketState::Listen => "LISTEN",
+            SocketState::SynSent => "SYN-SENT",
+            SocketState::SynReceived => "SYN-RECEIVED",
+            SocketState::Established => "ESTABLISHED",
+            SocketState::FinWait1 => "FIN-WAIT-1",
+            SocketState::FinWait2 => "FIN-WAIT-2",
+            SocketState::CloseWait => "CLOSE-WAIT",
+            SocketState::Closing => "CLOSING",
+            SocketState::LastAck => "LAST-ACK",
+            SocketState::TimeWait => "TIME-WAIT",
+            SocketState::Bound => "BOUND",
+        };
+        write!(f, "{}", name)
+    }
+}
+impl From<smoltcp::socket::TcpState> for SocketState {
+    fn from(other: smoltcp::socket::TcpState) -> SocketState {
+        use smoltcp::socket::TcpState;
+        match other {
+            TcpState::Closed => SocketState::Closed,
+            TcpState::Listen => SocketState::Listen,
+            TcpState::SynSent => SocketState::SynSent,
+            TcpState::SynReceived => SocketState::SynReceived,
+            TcpState::Established => SocketState::Established,
+            TcpState::FinWait1 => SocketState::FinWait1,
+            TcpState::FinWait2 => SocketState::FinWait2,
+            TcpState::CloseWait => SocketState::CloseWait,
+            TcpState::Closing => SocketState::Closing,
+            TcpState::LastAck => SocketState::LastAck,
+            TcpState::TimeWait => SocketState::TimeWait,
+        }
+    }
+}
+
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
+pub(crate) struct SocketStatsIpc {
+    pub pid: Option<u8>,
+    pub kind: SocketKind,
+    pub state: SocketState,
+    pub local: Option<NetSocketAddr>,
+    pub remote: Option<NetSocketAddr>,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub retransmits: u32,
+}
+
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone, Default)]
+pub(crate) struct SocketStatsList {
+    pub list: [Option<SocketStatsIpc>; MAX_SOCKET_STATS],
+    /// the number of sockets that are open, which can be more than fit in `list`
+    pub total: u32,
+}
+
+/// Traffic counters for a socket opened through libstd
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SocketStats {
+    /// the process that owns the socket
+    pub pid: Option<xous::PID>,
+    pub kind: SocketKind,
+    pub state: SocketState,
+    pub local: Option<SocketAddr>,
+    /// `None` for listening TCP sockets and for UDP sockets
+    pub remote: Option<SocketAddr>,
+    /// bytes handed to the owner of the socket
+    pub rx_bytes: u64,
+    /// bytes accepted from the owner of the socket
+    pub tx_bytes: u64,
+    /// TCP segments that were retransmitted on this connection
+    pub retransmits: u32,
+}
+impl From<SocketStatsIpc> for SocketStats {
+    fn from(other: SocketStatsIpc) -> SocketStats {
+        let addr = |a: NetSocketAddr| SocketAddr::new(IpAddr::from(a.addr), a.port);
+        SocketStats {
+            pid: other.pid.and_then(xous::PID::new),
+            kind: other.kind,
+            state: other.state,
+            local: other.local.map(addr),
+            remote: other.remote.map(addr),
+            rx_bytes: other.rx_bytes,
+            tx_bytes: other.tx_bytes,
+            retransmits: other.retransmits,
+        }
+    }
+}
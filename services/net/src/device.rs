@@ -1,5 +1,11 @@
+#[cfg(any(target_os = "none", target_os = "xous"))]
 use com::Com;
 use com::api::NET_MTU;
+use crate::api::PcapDirection;
+use crate::pcap::Capture;
+use crate::stats::PhyStats;
+
+use std::cell::RefCell;
 
 use smoltcp::Result;
 use smoltcp::phy::{self, DeviceCapabilities, Medium};
@@ -8,20 +14,40 @@ use smoltcp::{
     time::Instant,
 };
 
+/// Frames go to the WLAN chip over the COM bus on hardware, and to a virtual network in hosted mode
+#[cfg(any(target_os = "none", target_os = "xous"))]
+type Link = Com;
+#[cfg(not(any(target_os = "none", target_os = "xous")))]
+type Link = crate::virtnet::VirtualLink;
+
 pub struct NetPhy {
     rx_buffer: [u8; NET_MTU],
     tx_buffer: [u8; NET_MTU],
-    com: Com,
+    com: Link,
     rx_avail: Option<u16>,
+    capture: RefCell<Capture>,
+    stats: RefCell<PhyStats>,
 }
 
 impl<'a> NetPhy {
+    #[cfg_attr(not(any(target_os = "none", target_os = "xous")), allow(unused_variables))]
     pub fn new(xns: &xous_names::XousNames) -> NetPhy {
+        #[cfg(any(target_os = "none", target_os = "xous"))]
+        let com = Com::new(&xns).unwrap();
+        #[cfg(not(any(target_os = "none", target_os = "xous")))]
+        let com = Link::new();
+        NetPhy::with_link(com)
+    }
+    /// Builds the device on an existing link; in hosted mode, this lets tests drive a stack over the
+    /// virtual network without a name server.
+    pub(crate) fn with_link(com: Link) -> NetPhy {
         NetPhy {
             rx_buffer: [0; NET_MTU],
             tx_buffer: [0; NET_MTU],
-            com: Com::new(&xns).unwrap(),
+            com,
             rx_avail: None,
+            capture: RefCell::new(Capture::new()),
+            stats: RefCell::new(PhyStats::new()),
         }
     }
     // returns None if there was a slot to put the availability into
@@ -31,9 +57,16 @@ impl<'a> NetPhy {
             self.rx_avail = Some(len);
             None
         } else {
+            self.stats.borrow_mut().record_rx_overflow();
             Some(len)
         }
     }
+    pub fn capture(&self) -> &RefCell<Capture> {
+        &self.capture
+    }
+    pub fn stats(&self) -> &RefCell<PhyStats> {
+        &self.stats
+    }
 }
 
 impl<'a> phy::Device<'a> for NetPhy {
@@ -41,18 +74,23 @@ impl<'a> phy::Device<'a> for NetPhy {
     type TxToken = NetPhyTxToken<'a>;
 
     fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        // the virtual network has no interrupts, so its frames are picked up here
+        #[cfg(not(any(target_os = "none", target_os = "xous")))]
+        if self.rx_avail.is_none() {
+            self.rx_avail = self.com.pending_rx();
+        }
         if let Some(rx_len) = self.rx_avail.take() {
             self.com.wlan_fetch_packet(&mut self.rx_buffer[..rx_len as usize]).expect("Couldn't call wlan_fetch_packet in device adapter");
 
-            Some((NetPhyRxToken{buf: &mut self.rx_buffer[..rx_len as usize]},
-            NetPhyTxToken{buf: &mut self.tx_buffer[..], com: & self.com}))
+            Some((NetPhyRxToken{buf: &mut self.rx_buffer[..rx_len as usize], capture: &self.capture, stats: &self.stats},
+            NetPhyTxToken{buf: &mut self.tx_buffer[..], com: & self.com, capture: &self.capture, stats: &self.stats}))
         } else {
             None
         }
     }
 
     fn transmit(&'a mut self) -> Option<Self::TxToken> {
-        Some(NetPhyTxToken{buf: &mut self.tx_buffer[..], com: &self.com})
+        Some(NetPhyTxToken{buf: &mut self.tx_buffer[..], com: &self.com, capture: &self.capture, stats: &self.stats})
     }
 
     fn capabilities(&self) -> DeviceCapabilities {
@@ -66,32 +104,42 @@ impl<'a> phy::Device<'a> for NetPhy {
 
 pub struct NetPhyRxToken<'a> {
     buf: &'a mut [u8],
+    capture: &'a RefCell<Capture>,
+    stats: &'a RefCell<PhyStats>,
 }
 
 impl<'a, 'c> phy::RxToken for NetPhyRxToken<'a> {
-    fn consume<R, F>(mut self, _timestamp: Instant, f: F) -> Result<R>
+    fn consume<R, F>(mut self, timestamp: Instant, f: F) -> Result<R>
         where F: FnOnce(&mut [u8]) -> Result<R>
     {
+        self.capture.borrow_mut().record(PcapDirection::Rx, timestamp.total_millis() as u64, self.buf);
+        let len = self.buf.len();
         let result = f(&mut self.buf);
         //log::info!("rx: {:x?}", self.buf);
+        self.stats.borrow_mut().record_rx(len, &result);
         result
     }
 }
 
 pub struct NetPhyTxToken<'a> {
     buf: &'a mut [u8],
-    com: &'a Com,
+    com: &'a Link,
+    capture: &'a RefCell<Capture>,
+    stats: &'a RefCell<PhyStats>,
 }
 
 impl<'a> phy::TxToken for NetPhyTxToken<'a> {
-    fn consume<R, F>(self, _timestamp: Instant, len: usize, f: F) -> Result<R>
+    fn consume<R, F>(self, timestamp: Instant, len: usize, f: F) -> Result<R>
         where F: FnOnce(&mut [u8]) -> Result<R>
     {
         let result = f(&mut self.buf[..len]);
         //log::info!("txlen: {}", len);
 
         if result.is_ok() {
-            self.com.wlan_send_packet(&self.buf[..len]).map_err(|_| smoltcp::Error::Dropped)?;
+            self.capture.borrow_mut().record(PcapDirection::Tx, timestamp.total_millis() as u64, &self.buf[..len]);
+            let sent = self.com.wlan_send_packet(&self.buf[..len]);
+            self.stats.borrow_mut().record_tx(&self.buf[..len], sent.is_ok());
+            sent.map_err(|_| smoltcp::Error::Dropped)?;
         }
         result
     }
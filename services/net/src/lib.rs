@@ -5,6 +5,7 @@ use com::{Ipv4Conf, SsidRecord};
 use xous::{CID, send_message, Message};
 use xous_ipc::Buffer;
 use num_traits::*;
+use std::convert::TryInto;
 
 pub mod protocols;
 pub use protocols::*;
@@ -160,6 +161,130 @@ impl NetManager {
         }
         Ok(ret)
     }
+    /// Joins the IPv4 multicast group `group`. Datagrams sent to the group are then received by UDP
+    /// sockets bound to `0.0.0.0` on the destination port.
+    pub fn join_multicast_v4(&self, group: std::net::Ipv4Addr) -> Result<(), xous::Error> {
+        match send_message(self.netconn.conn(),
+            Message::new_blocking_scalar(Opcode::JoinMulticastV4.to_usize().unwrap(), u32::from(group) as usize, 0, 0, 0)
+        )? {
+            xous::Result::Scalar1(1) => Ok(()),
+            _ => Err(xous::Error::InvalidArguments),
+        }
+    }
+    pub fn leave_multicast_v4(&self, group: std::net::Ipv4Addr) -> Result<(), xous::Error> {
+        match send_message(self.netconn.conn(),
+            Message::new_blocking_scalar(Opcode::LeaveMulticastV4.to_usize().unwrap(), u32::from(group) as usize, 0, 0, 0)
+        )? {
+            xous::Result::Scalar1(1) => Ok(()),
+            _ => Err(xous::Error::InvalidArguments),
+        }
+    }
+    fn pcap_control(&self, op: PcapOp, filter: &str) -> Result<PcapStatus, xous::Error> {
+        let mut control = PcapControl {
+            op,
+            filter: xous_ipc::String::new(),
+            status: None,
+            denied: false,
+        };
+        control.filter.append(filter).or(Err(xous::Error::InvalidString))?;
+        let mut buf = Buffer::into_buf(control).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.netconn.conn(), Opcode::PcapControl.to_u32().unwrap())?;
+        let control = buf.to_original::<PcapControl, _>().or(Err(xous::Error::InternalError))?;
+        if control.denied {
+            return Err(xous::Error::AccessDenied);
+        }
+        control.status.ok_or(xous::Error::InvalidString)
+    }
+    /// Discards any previous capture and starts capturing the frames sent and received by the
+    /// network device that match `filter`. See `PcapFilter` for the filter syntax; an empty filter
+    /// captures everything. Returns `InvalidString` if the filter can't be parsed.
+    pub fn pcap_start(&self, filter: &str) -> Result<PcapStatus, xous::Error> {
+        self.pcap_control(PcapOp::Start, filter)
+    }
+    /// Stops capturing. The frames captured so far can still be fetched.
+    pub fn pcap_stop(&self) -> Result<PcapStatus, xous::Error> {
+        self.pcap_control(PcapOp::Stop, "")
+    }
+    pub fn pcap_clear(&self) -> Result<PcapStatus, xous::Error> {
+        self.pcap_control(PcapOp::Clear, "")
+    }
+    pub fn pcap_status(&self) -> Result<PcapStatus, xous::Error> {
+        self.pcap_control(PcapOp::Status, "")
+    }
+    /// Returns the captured frames as a pcap file, which can be opened with Wireshark or tcpdump.
+    pub fn pcap_fetch(&self) -> Result<Vec<u8>, xous::Error> {
+        let mut buf = Buffer::new(PCAP_BUF_LEN);
+        buf.lend_mut(self.netconn.conn(), Opcode::PcapFetch.to_u32().unwrap())?;
+        let data: &[u8] = buf.as_ref();
+        let len = u32::from_le_bytes(data[..4].try_into().unwrap());
+        if len == u32::MAX {
+            return Err(xous::Error::AccessDenied);
+        }
+        let len = len as usize;
+        if len > PCAP_BUF_LEN - 4 {
+            return Err(xous::Error::InternalError);
+        }
+        Ok(data[4..4 + len].to_vec())
+    }
+    /// Returns the traffic counters of the network interface
+    pub fn interface_stats(&self) -> Result<InterfaceStats, xous::Error> {
+        let mut buf = Buffer::into_buf(InterfaceStats::default()).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.netconn.conn(), Opcode::GetInterfaceStats.to_u32().unwrap())?;
+        buf.to_original::<InterfaceStats, _>().or(Err(xous::Error::InternalError))
+    }
+    /// Returns the traffic counters of the TCP and UDP sockets opened through libstd, along with the
+    /// number of open sockets. Only the first 32 sockets are listed if there are more.
+    pub fn socket_stats(&self) -> Result<(Vec<SocketStats>, usize), xous::Error> {
+        let mut buf = Buffer::into_buf(SocketStatsList::default()).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.netconn.conn(), Opcode::GetSocketStats.to_u32().unwrap())?;
+        let list = buf.to_original::<SocketStatsList, _>().or(Err(xous::Error::InternalError))?;
+        Ok((list.list.iter().flatten().map(|s| SocketStats::from(*s)).collect(), list.total as usize))
+    }
+    /// Returns the apps that have a socket policy, or have used the network since boot. The policy
+    /// for all apps is named `DEFAULT_POLICY_APP`.
+    pub fn policy_apps(&self) -> Result<Vec<String>, xous::Error> {
+        let mut buf = Buffer::into_buf(PolicyAppList::default()).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.netconn.conn(), Opcode::PolicyListApps.to_u32().unwrap())?;
+        let list = buf.to_original::<PolicyAppList, _>().or(Err(xous::Error::InternalError))?;
+        Ok(list.list.iter().flatten().map(|app| app.to_str().to_string()).collect())
+    }
+    /// Returns the socket policy rules of `app`, or an empty string if it has none
+    pub fn policy_get(&self, app: &str) -> Result<String, xous::Error> {
+        let entry = PolicyEntry {
+            app: xous_ipc::String::from_str(app),
+            rules: xous_ipc::String::new(),
+            ok: false,
+        };
+        let mut buf = Buffer::into_buf(entry).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.netconn.conn(), Opcode::PolicyGet.to_u32().unwrap())?;
+        let entry = buf.to_original::<PolicyEntry, _>().or(Err(xous::Error::InternalError))?;
+        Ok(entry.rules.to_str().to_string())
+    }
+    /// Replaces the socket policy of `app` with `rules`, or removes it if `rules` is empty. See the
+    /// net server's `AppPolicy` for the syntax. Returns `InvalidString` if the rules don't parse, and
+    /// `OutOfMemory` if they are too long to send. On hardware, only the status process may change
+    /// policies; for anyone else, the server refuses and this returns `InternalError`.
+    pub fn policy_set(&self, app: &str, rules: &str) -> Result<(), xous::Error> {
+        if app.is_empty() || app.len() > POLICY_APP_LEN || rules.len() > POLICY_RULES_LEN {
+            return Err(xous::Error::OutOfMemory);
+        }
+        if AppPolicy::parse(rules).is_err() {
+            return Err(xous::Error::InvalidString);
+        }
+        let entry = PolicyEntry {
+            app: xous_ipc::String::from_str(app),
+            rules: xous_ipc::String::from_str(rules),
+            ok: false,
+        };
+        let mut buf = Buffer::into_buf(entry).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.netconn.conn(), Opcode::PolicySet.to_u32().unwrap())?;
+        let entry = buf.to_original::<PolicyEntry, _>().or(Err(xous::Error::InternalError))?;
+        if entry.ok {
+            Ok(())
+        } else {
+            Err(xous::Error::InternalError)
+        }
+    }
     pub fn connection_manager_stop(&self) -> Result<(), xous::Error> {
         send_message(self.netconn.conn(),
             Message::new_scalar(Opcode::ConnMgrStartStop.to_usize().unwrap(), 0, 0,0, 0)
@@ -17,6 +17,12 @@ use num_traits::*;
 
 mod connection_manager;
 mod device;
+mod pcap;
+mod policy;
+mod stats;
+use stats::SocketCounters;
+#[cfg(not(any(target_os = "none", target_os = "xous")))]
+mod virtnet;
 
 use std::collections::{BTreeMap, HashMap, BTreeSet};
 use std::convert::TryInto;
@@ -36,6 +42,8 @@ use core::sync::atomic::{AtomicU32, Ordering};
 use smoltcp::socket::{
     TcpSocket, TcpSocketBuffer, UdpPacketMetadata, UdpSocket, UdpSocketBuffer,
 };
+#[cfg(not(any(target_os = "none", target_os = "xous")))]
+use smoltcp::socket::{Dhcpv4Event, Dhcpv4Socket};
 use smoltcp::iface::SocketHandle;
 use smoltcp::time::{Duration, Instant};
 use std::sync::Arc;
@@ -60,6 +68,66 @@ where
     });
 }
 
+/// Points the interface and the DNS server at a freshly acquired IPv4 configuration
+fn apply_ipv4_config(
+    config: &Ipv4Conf,
+    iface: &mut Interface::<NetPhy>,
+    dns_allclear_hook: &mut XousScalarEndpoint,
+    dns_ipv4_hook: &mut XousScalarEndpoint,
+) {
+    log::info!("Network config acquired: {:?}", config);
+    log::info!("{}NET.OK,{:?},{}",
+        xous::BOOKEND_START,
+        std::net::IpAddr::from(config.addr),
+        xous::BOOKEND_END);
+
+    // note: ARP cache is stale. Maybe that's ok?
+
+    let ip_addr = Ipv4Cidr::new(
+        Ipv4Address::new(
+            config.addr[0],
+            config.addr[1],
+            config.addr[2],
+            config.addr[3],
+        ),
+        24,
+    );
+    set_ipv4_addr(iface, ip_addr);
+    let default_v4_gw = Ipv4Address::new(
+        config.gtwy[0],
+        config.gtwy[1],
+        config.gtwy[2],
+        config.gtwy[3],
+    );
+
+    // reset the default route, in case it has changed
+    iface.routes_mut().remove_default_ipv4_route();
+    match iface.routes_mut().add_default_ipv4_route(default_v4_gw) {
+        Ok(route) => log::info!(
+            "routing table updated successfully [{:?}]",
+            route
+        ),
+        Err(e) => log::error!("routing table update error: {}", e),
+    }
+    dns_allclear_hook.notify();
+    dns_ipv4_hook.notify_custom_args([
+        Some(u32::from_be_bytes(config.dns1)),
+        None,
+        None,
+        None,
+    ]);
+    // the current implementation always returns 0.0.0.0 as the second dns,
+    // ignore this if that's what we've got; otherwise, pass it on.
+    if config.dns2 != [0, 0, 0, 0] {
+        dns_ipv4_hook.notify_custom_args([
+            Some(u32::from_be_bytes(config.dns2)),
+            None,
+            None,
+            None,
+        ]);
+    }
+}
+
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
 enum WaitOp {
     WaitMs,
@@ -244,10 +312,18 @@ fn main() -> ! {
     // incoming UDP socket data.
     let mut udp_rx_waiting: Vec<Option<UdpStdState>> = Vec::new();
 
+    // Bytes moved through each libstd socket, for `GetSocketStats`. Entries are created on first use, and
+    // removed along with their socket, because smoltcp reuses handles.
+    let mut socket_counters: HashMap<SocketHandle, SocketCounters> = HashMap::new();
+
+    // Per-app limits on the sockets above. Loaded once the PDDB is mounted; see `policy::NetPolicy`.
+    let mut net_policy = policy::NetPolicy::new();
+
     // --------------- other link storage -------------
     let neighbor_cache = NeighborCache::new(BTreeMap::new());
     let ip_addrs = [IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0)];
     let routes = Routes::new(BTreeMap::new());
+    let multicast_groups = BTreeMap::new();
 
     // build the device
     let hw_config = com.wlan_get_config().expect("couldn't fetch initial wifi MAC");
@@ -258,7 +334,8 @@ fn main() -> ! {
     let medium = device.capabilities().medium;
     let mut builder = InterfaceBuilder::new(device, vec![])
         .ip_addrs(ip_addrs)
-        .routes(routes);
+        .routes(routes)
+        .ipv4_multicast_groups(multicast_groups);
     if medium == Medium::Ethernet {
         builder = builder
             .hardware_addr(EthernetAddress::from_bytes(&hw_config.mac).into())
@@ -268,6 +345,10 @@ fn main() -> ! {
 
     // ------------- native variant -----------
     let icmp_handle = setup_icmp(&mut iface);
+    // in hosted mode, there is no WLAN chip to run DHCP on our behalf, so the stack gets its
+    // address from the virtual network itself
+    #[cfg(not(any(target_os = "none", target_os = "xous")))]
+    let dhcp_handle = iface.add_socket(Dhcpv4Socket::new());
     let mut seq: u16 = 0;
     // this record stores the origin time + IP address of the outgoing ping sequence number
     let mut ping_destinations = HashMap::<PingConnection, HashMap<u16, u64>>::new();
@@ -352,6 +433,20 @@ fn main() -> ! {
         }
     });
 
+    // load the socket policies once they can be read
+    thread::spawn({
+        let net_conn = net_conn.clone();
+        move || {
+            let pddb = pddb::Pddb::new();
+            pddb.is_mounted_blocking();
+            xous::send_message(net_conn, Message::new_scalar(Opcode::PolicyReload.to_usize().unwrap(), 0, 0, 0, 0)).ok();
+        }
+    });
+
+    // the virtual network doesn't raise interrupts, so the first pump is what gets DHCP going
+    #[cfg(not(any(target_os = "none", target_os = "xous")))]
+    xous::try_send_message(net_conn, Message::new_scalar(Opcode::NetPump.to_usize().unwrap(), 0, 0, 0, 0)).ok();
+
     let mut cid_to_disconnect: Option<CID> = None;
     loop {
         let mut msg = xous::receive_message(net_sid).unwrap();
@@ -379,8 +474,9 @@ fn main() -> ! {
                     Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
                 };
                 let mut pkt = buf.to_original::<NetPingPacket, _>().unwrap();
+                let permitted = net_policy.permits(msg.sender.pid(), PolicyAction::Ping, Some(IpAddress::from(pkt.endpoint)), None);
                 let socket = iface.get_socket::<IcmpSocket>(icmp_handle);
-                if socket.can_send() {
+                if permitted && socket.can_send() {
                     log::debug!("sending ping to {:?}", pkt.endpoint);
                     let remote = IpAddress::from(pkt.endpoint);
                     // we take advantage of the fact that the same CID is always returned for repeated connect requests to the same SID.
@@ -560,6 +656,7 @@ fn main() -> ! {
                     &mut iface,
                     &mut tcp_connect_waiting,
                     process_sockets.entry(pid).or_default(),
+                    &mut net_policy,
                 );
                 xous::try_send_message(
                     net_conn,
@@ -579,6 +676,7 @@ fn main() -> ! {
                     &mut iface,
                     &mut tcp_tx_waiting,
                     process_sockets.entry(pid).or_default(),
+                    &mut socket_counters,
                 );
                 xous::try_send_message(
                     net_conn,
@@ -611,6 +709,7 @@ fn main() -> ! {
                     &mut iface,
                     &mut tcp_rx_waiting,
                     process_sockets.entry(pid).or_default(),
+                    &mut socket_counters,
                 );
                 xous::try_send_message(
                     net_conn,
@@ -649,6 +748,7 @@ fn main() -> ! {
                         log::trace!("imm");
                         socket.close();
                         iface.remove_socket(handle);
+                        socket_counters.remove(&handle);
                         if let Some(response) = msg.body.memory_message_mut() {
                             response.buf.as_slice_mut::<u8>()[0] = 0;
                         } else if !msg.body.has_memory() && msg.body.is_blocking() {
@@ -765,6 +865,7 @@ fn main() -> ! {
                     msg,
                     &mut iface,
                     process_sockets.entry(pid).or_default(),
+                    &mut net_policy,
                 );
                 xous::try_send_message(
                     net_conn,
@@ -945,6 +1046,7 @@ fn main() -> ! {
                     msg,
                     &mut iface,
                     process_sockets.entry(pid).or_default(),
+                    &mut net_policy,
                 );
             }
 
@@ -957,6 +1059,7 @@ fn main() -> ! {
                     &mut iface,
                     &mut udp_rx_waiting,
                     process_sockets.entry(pid).or_default(),
+                    &mut socket_counters,
                 );
             }
 
@@ -967,6 +1070,8 @@ fn main() -> ! {
                     msg,
                     &mut iface,
                     process_sockets.entry(pid).or_default(),
+                    &mut socket_counters,
+                    &mut net_policy,
                 );
                 xous::try_send_message(
                     net_conn,
@@ -998,6 +1103,7 @@ fn main() -> ! {
                 };
                 iface.get_socket::<UdpSocket>(handle).close();
                 iface.remove_socket(handle);
+                socket_counters.remove(&handle);
                 if let Some(response) = msg.body.memory_message_mut() {
                     response.buf.as_slice_mut::<u8>()[0] = 0;
                 } else if !msg.body.has_memory() && msg.body.is_blocking() {
@@ -1048,58 +1154,8 @@ fn main() -> ! {
                                     let config = com
                                         .wlan_get_config()
                                         .expect("couldn't retrieve updated ipv4 config");
-                                    log::info!("Network config acquired: {:?}", config);
-                                    log::info!("{}NET.OK,{:?},{}",
-                                        xous::BOOKEND_START,
-                                        std::net::IpAddr::from(config.addr),
-                                        xous::BOOKEND_END);
                                     net_config = Some(config);
-
-                                    // note: ARP cache is stale. Maybe that's ok?
-
-                                    let ip_addr = Ipv4Cidr::new(
-                                        Ipv4Address::new(
-                                            config.addr[0],
-                                            config.addr[1],
-                                            config.addr[2],
-                                            config.addr[3],
-                                        ),
-                                        24,
-                                    );
-                                    set_ipv4_addr(&mut iface, ip_addr);
-                                    let default_v4_gw = Ipv4Address::new(
-                                        config.gtwy[0],
-                                        config.gtwy[1],
-                                        config.gtwy[2],
-                                        config.gtwy[3],
-                                    );
-
-                                    // reset the default route, in case it has changed
-                                    iface.routes_mut().remove_default_ipv4_route();
-                                    match iface.routes_mut().add_default_ipv4_route(default_v4_gw) {
-                                        Ok(route) => log::info!(
-                                            "routing table updated successfully [{:?}]",
-                                            route
-                                        ),
-                                        Err(e) => log::error!("routing table update error: {}", e),
-                                    }
-                                    dns_allclear_hook.notify();
-                                    dns_ipv4_hook.notify_custom_args([
-                                        Some(u32::from_be_bytes(config.dns1)),
-                                        None,
-                                        None,
-                                        None,
-                                    ]);
-                                    // the current implementation always returns 0.0.0.0 as the second dns,
-                                    // ignore this if that's what we've got; otherwise, pass it on.
-                                    if config.dns2 != [0, 0, 0, 0] {
-                                        dns_ipv4_hook.notify_custom_args([
-                                            Some(u32::from_be_bytes(config.dns2)),
-                                            None,
-                                            None,
-                                            None,
-                                        ]);
-                                    }
+                                    apply_ipv4_config(&config, &mut iface, &mut dns_allclear_hook, &mut dns_ipv4_hook);
                                 }
                                 ComIntSources::WlanRxReady => {
                                     activity_interval.store(0, Ordering::Relaxed); // reset the activity interval to 0
@@ -1187,6 +1243,27 @@ fn main() -> ! {
                         log::debug!("poll error: {}", e);
                     }
                 }
+                #[cfg(not(any(target_os = "none", target_os = "xous")))]
+                match iface.get_socket::<Dhcpv4Socket>(dhcp_handle).poll() {
+                    Some(Dhcpv4Event::Configured(lease)) => {
+                        let mut config = Ipv4Conf::default();
+                        config.mac = hw_config.mac;
+                        config.addr = lease.address.address().0;
+                        if let Some(router) = lease.router {
+                            config.gtwy = router.0;
+                        }
+                        if let Some(dns) = lease.dns_servers[0] {
+                            config.dns1 = dns.0;
+                        }
+                        net_config = Some(config);
+                        apply_ipv4_config(&config, &mut iface, &mut dns_allclear_hook, &mut dns_ipv4_hook);
+                    }
+                    Some(Dhcpv4Event::Deconfigured) => {
+                        log::info!("DHCP lease on the virtual network was lost");
+                        net_config = None;
+                    }
+                    None => {}
+                }
 
                 // Connect calls take time to establish. This block checks to see if connections
                 // have been made and issues callbacks as necessary.
@@ -1225,7 +1302,7 @@ fn main() -> ! {
                     let socket;
                     let WaitingSocket {
                         mut env,
-                        handle: _,
+                        handle,
                         expiry: _,
                     } = {
                         match connection {
@@ -1257,6 +1334,7 @@ fn main() -> ! {
                     let body = env.body.memory_message_mut().unwrap();
                     match socket.recv_slice(body.buf.as_slice_mut()) {
                         Ok(count) => {
+                            socket_counters.entry(handle).or_default().rx_bytes += count as u64;
                             body.valid = xous::MemorySize::new(count);
                             body.offset = xous::MemoryAddress::new(1);
                         }
@@ -1274,7 +1352,7 @@ fn main() -> ! {
                     let socket;
                     let WaitingSocket {
                         mut env,
-                        handle: _,
+                        handle,
                         expiry: _,
                     } = {
                         match connection {
@@ -1328,6 +1406,7 @@ fn main() -> ! {
                     };
 
                     log::trace!("sent {}", sent_octets);
+                    socket_counters.entry(handle).or_default().tx_bytes += sent_octets as u64;
                     let response_data = body.buf.as_slice_mut::<u32>();
                     response_data[0] = 0;
                     response_data[1] = sent_octets as u32;
@@ -1365,7 +1444,7 @@ fn main() -> ! {
                     let socket;
                     let UdpStdState {
                         mut msg,
-                        handle: _,
+                        handle,
                         expiry: _,
                     } = {
                         match connection {
@@ -1416,6 +1495,7 @@ fn main() -> ! {
                         match socket.recv() {
                             Ok((data, endpoint)) => {
                                 log::debug!("netpump udp rx");
+                                socket_counters.entry(handle).or_default().rx_bytes += data.len() as u64;
                                 udp_rx_success(
                                     // unwrap is safe here because the message was type-checked prior to insertion into the waiting queue
                                     msg.body.memory_message_mut().unwrap().buf.as_slice_mut(),
@@ -1440,6 +1520,7 @@ fn main() -> ! {
                             if socket.may_send() && socket.send_queue() == 0 {
                                 socket.close();
                                 iface.remove_socket(handle);
+                                socket_counters.remove(&handle);
                                 log::debug!("deferred close, socket is not active");
                                 xous::return_scalar(sender, 0).ok();
                                 closed.push(index);
@@ -1840,6 +1921,193 @@ fn main() -> ! {
                     log::error!("Got incorrect start/stop code: {}", code);
                 }
             }),
+            Some(Opcode::JoinMulticastV4) => msg_blocking_scalar_unpack!(msg, group, _, _, _, {
+                let addr = Ipv4Address::from_bytes(&(group as u32).to_be_bytes());
+                let timestamp = Instant::from_millis(timer.elapsed_ms() as i64);
+                let ok = if addr.is_multicast() && net_policy.permits_multicast(msg.sender.pid(), IpAddress::Ipv4(addr)) {
+                    match iface.join_multicast_group(addr, timestamp) {
+                        Ok(_) => true,
+                        Err(e) => {
+                            log::warn!("couldn't join multicast group {}: {:?}", addr, e);
+                            false
+                        }
+                    }
+                } else {
+                    false
+                };
+                xous::return_scalar(msg.sender, if ok { 1 } else { 0 }).unwrap();
+            }),
+            Some(Opcode::LeaveMulticastV4) => msg_blocking_scalar_unpack!(msg, group, _, _, _, {
+                let addr = Ipv4Address::from_bytes(&(group as u32).to_be_bytes());
+                let timestamp = Instant::from_millis(timer.elapsed_ms() as i64);
+                let ok = addr.is_multicast()
+                    && net_policy.permits_multicast(msg.sender.pid(), IpAddress::Ipv4(addr))
+                    && iface.leave_multicast_group(addr, timestamp).is_ok();
+                xous::return_scalar(msg.sender, if ok { 1 } else { 0 }).unwrap();
+            }),
+            Some(Opcode::PcapControl) => {
+                let mut buffer = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let mut control = buffer.to_original::<PcapControl, _>().unwrap();
+                if !net_policy.may_capture(msg.sender.pid()) {
+                    log::warn!("rejecting pcap request from {:?}", msg.sender.pid());
+                    control.denied = true;
+                    buffer.replace(control).expect("couldn't return pcap status");
+                    continue;
+                }
+                let mut capture = iface.device().capture().borrow_mut();
+                control.status = match control.op {
+                    PcapOp::Start => match PcapFilter::parse(control.filter.as_str().unwrap_or("")) {
+                        Ok(filter) => {
+                            capture.start(filter);
+                            Some(capture.status())
+                        }
+                        Err(term) => {
+                            log::warn!("invalid pcap filter at '{}'", term);
+                            None
+                        }
+                    },
+                    PcapOp::Stop => {
+                        capture.stop();
+                        Some(capture.status())
+                    }
+                    PcapOp::Clear => {
+                        capture.clear();
+                        Some(capture.status())
+                    }
+                    PcapOp::Status => Some(capture.status()),
+                };
+                buffer.replace(control).expect("couldn't return pcap status");
+            }
+            Some(Opcode::PcapFetch) => {
+                let mut buffer = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                if !net_policy.may_capture(msg.sender.pid()) {
+                    log::warn!("rejecting pcap fetch from {:?}", msg.sender.pid());
+                    let out: &mut [u8] = buffer.as_mut();
+                    out[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+                    continue;
+                }
+                // frames are timestamped with the time since boot; pcap wants the time since the epoch
+                let epoch_offset_ms = match std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+                    Ok(now) => (now.as_millis() as u64).saturating_sub(timer.elapsed_ms()),
+                    Err(_) => 0,
+                };
+                let out: &mut [u8] = buffer.as_mut();
+                let len = iface.device().capture().borrow().write_pcap(epoch_offset_ms, &mut out[4..]);
+                out[..4].copy_from_slice(&(len as u32).to_le_bytes());
+            }
+            Some(Opcode::GetInterfaceStats) => {
+                let mut buffer = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let stats = iface.device().stats().borrow().interface();
+                buffer.replace(stats).expect("couldn't return interface stats");
+            }
+            Some(Opcode::GetSocketStats) => {
+                let mut buffer = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let mut owners = HashMap::<SocketHandle, Option<xous::PID>>::new();
+                for (pid, handles) in process_sockets.iter() {
+                    for handle in handles.iter().flatten() {
+                        owners.insert(*handle, *pid);
+                    }
+                }
+                let endpoint = |ep: IpEndpoint| match ep.addr {
+                    IpAddress::Ipv4(_) | IpAddress::Ipv6(_) => Some(NetSocketAddr { addr: NetIpAddr::from(ep.addr), port: ep.port }),
+                    _ => None,
+                };
+                let mut list = SocketStatsList::default();
+                let mut live_flows = Vec::new();
+                let phy_stats = iface.device().stats();
+                for (handle, socket) in iface.sockets() {
+                    // only libstd sockets are reported; the ICMP socket belongs to the net server itself
+                    let pid = match owners.get(&handle) {
+                        Some(pid) => pid.map(|p| p.get()),
+                        None => continue,
+                    };
+                    let counters = socket_counters.get(&handle).copied().unwrap_or_default();
+                    let stats = match socket {
+                        smoltcp::socket::Socket::Tcp(tcp) => {
+                            let remote = tcp.remote_endpoint();
+                            let flow = if remote.port != 0 {
+                                stats::flow_id(tcp.local_endpoint().port, remote.addr, remote.port)
+                            } else {
+                                None
+                            };
+                            if let Some(flow) = flow {
+                                live_flows.push(flow);
+                            }
+                            SocketStatsIpc {
+                                pid,
+                                kind: SocketKind::Tcp,
+                                state: SocketState::from(tcp.state()),
+                                local: endpoint(tcp.local_endpoint()),
+                                remote: if remote.port != 0 { endpoint(remote) } else { None },
+                                rx_bytes: counters.rx_bytes,
+                                tx_bytes: counters.tx_bytes,
+                                retransmits: flow.map_or(0, |f| phy_stats.borrow().retransmits(&f)),
+                            }
+                        }
+                        smoltcp::socket::Socket::Udp(udp) => SocketStatsIpc {
+                            pid,
+                            kind: SocketKind::Udp,
+                            state: if udp.is_open() { SocketState::Bound } else { SocketState::Closed },
+                            local: endpoint(udp.endpoint()),
+                            remote: None,
+                            rx_bytes: counters.rx_bytes,
+                            tx_bytes: counters.tx_bytes,
+                            retransmits: 0,
+                        },
+                        _ => continue,
+                    };
+                    if (list.total as usize) < list.list.len() {
+                        list.list[list.total as usize] = Some(stats);
+                    }
+                    list.total += 1;
+                }
+                // connections that are gone no longer need their sequence numbers tracked
+                phy_stats.borrow_mut().retain_flows(&live_flows);
+                buffer.replace(list).expect("couldn't return socket stats");
+            }
+            Some(Opcode::PolicyListApps) => {
+                let mut buffer = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let mut ret = PolicyAppList::default();
+                for (app, entry) in net_policy.apps().iter().zip(ret.list.iter_mut()) {
+                    *entry = Some(xous_ipc::String::from_str(app));
+                }
+                buffer.replace(ret).expect("couldn't return policy app list");
+            }
+            Some(Opcode::PolicyGet) => {
+                let mut buffer = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let mut entry = buffer.to_original::<PolicyEntry, _>().unwrap();
+                entry.rules = xous_ipc::String::from_str(&net_policy.get(entry.app.to_str()));
+                entry.ok = true;
+                buffer.replace(entry).expect("couldn't return policy");
+            }
+            Some(Opcode::PolicySet) => {
+                let mut buffer = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let mut entry = buffer.to_original::<PolicyEntry, _>().unwrap();
+                entry.ok = if net_policy.may_administer(msg.sender.pid()) {
+                    net_policy.set(entry.app.to_str(), entry.rules.to_str())
+                } else {
+                    log::warn!("rejecting socket policy change from {:?}", msg.sender.pid());
+                    false
+                };
+                buffer.replace(entry).expect("couldn't return policy status");
+            }
+            Some(Opcode::PolicyReload) => {
+                net_policy.reload(net_conn);
+            }
             Some(Opcode::Reset) => {
                 // note: ARP cache isn't reset
                 iface.routes_mut().remove_default_ipv4_route();
@@ -0,0 +1,184 @@
+use crate::api::*;
+
+use std::collections::VecDeque;
+
+/// Magic number of a pcap file with microsecond timestamps, in our (little endian) byte order
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const LINKTYPE_ETHERNET: u32 = 1;
+/// The most pcap record bytes that the ring holds, chosen so that a complete file fits in the fetch buffer
+const RING_CAPACITY: usize = PCAP_BUF_LEN - 4 - PCAP_HEADER_LEN;
+
+struct Frame {
+    timestamp_ms: u64,
+    orig_len: u32,
+    data: Vec<u8>,
+}
+impl Frame {
+    fn pcap_len(&self) -> usize {
+        PCAP_RECORD_HEADER_LEN + self.data.len()
+    }
+}
+
+/// A ring buffer of the frames that pass through `NetPhy`. When the ring is full, the oldest
+/// frames are evicted to make room.
+pub(crate) struct Capture {
+    running: bool,
+    filter: PcapFilter,
+    frames: VecDeque<Frame>,
+    /// the size of `frames` in pcap records
+    len: usize,
+    dropped: u32,
+}
+impl Capture {
+    pub fn new() -> Capture {
+        Capture {
+            running: false,
+            filter: PcapFilter::default(),
+            frames: VecDeque::new(),
+            len: 0,
+            dropped: 0,
+        }
+    }
+
+    pub fn start(&mut self, filter: PcapFilter) {
+        log::info!("packet capture started: {:?}", filter);
+        self.clear();
+        self.filter = filter;
+        self.running = true;
+    }
+
+    pub fn stop(&mut self) {
+        if self.running {
+            log::info!("packet capture stopped: {} frames", self.frames.len());
+        }
+        self.running = false;
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.len = 0;
+        self.dropped = 0;
+    }
+
+    pub fn status(&self) -> PcapStatus {
+        PcapStatus {
+            running: self.running,
+            frames: self.frames.len() as u32,
+            bytes: (PCAP_HEADER_LEN + self.len) as u32,
+            dropped: self.dropped,
+        }
+    }
+
+    /// Records `frame` if capture is running and the frame passes the filter. `timestamp_ms` is
+    /// the time since boot.
+    pub fn record(&mut self, direction: PcapDirection, timestamp_ms: u64, frame: &[u8]) {
+        if !self.running || !self.filter.matches(direction, frame) {
+            return;
+        }
+        let frame = Frame {
+            timestamp_ms,
+            orig_len: frame.len() as u32,
+            data: frame[..frame.len().min(self.filter.snaplen)].to_vec(),
+        };
+        while self.len + frame.pcap_len() > RING_CAPACITY {
+            match self.frames.pop_front() {
+                Some(oldest) => {
+                    self.len -= oldest.pcap_len();
+                    self.dropped += 1;
+                }
+                None => return,
+            }
+        }
+        self.len += frame.pcap_len();
+        self.frames.push_back(frame);
+    }
+
+    /// Writes the capture into `out` as a pcap file, and returns its length. `epoch_offset_ms` is
+    /// added to the timestamps, to turn the time since boot into the time since the epoch.
+    pub fn write_pcap(&self, epoch_offset_ms: u64, out: &mut [u8]) -> usize {
+        out[0..4].copy_from_slice(&PCAP_MAGIC.to_le_bytes());
+        // version 2.4
+        out[4..6].copy_from_slice(&2u16.to_le_bytes());
+        out[6..8].copy_from_slice(&4u16.to_le_bytes());
+        // timestamps are in UTC, and their accuracy isn't known
+        out[8..16].fill(0);
+        out[16..20].copy_from_slice(&(self.filter.snaplen as u32).to_le_bytes());
+        out[20..24].copy_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        let mut index = PCAP_HEADER_LEN;
+        for frame in self.frames.iter() {
+            if index + frame.pcap_len() > out.len() {
+                log::warn!("pcap output buffer is too small, truncating the capture");
+                break;
+            }
+            let time_ms = frame.timestamp_ms + epoch_offset_ms;
+            let record: [u32; 4] = [
+                (time_ms / 1000) as u32,
+                ((time_ms % 1000) * 1000) as u32,
+                frame.data.len() as u32,
+                frame.orig_len,
+            ];
+            for word in record.iter() {
+                out[index..index + 4].copy_from_slice(&word.to_le_bytes());
+                index += 4;
+            }
+            out[index..index + frame.data.len()].copy_from_slice(&frame.data);
+            index += frame.data.len();
+        }
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn write_pcap() {
+        let mut capture = Capture::new();
+        capture.record(PcapDirection::Rx, 10, &frame(60));
+        assert_eq!(capture.status().frames, 0, "frames are only recorded while running");
+
+        capture.start(PcapFilter::parse("rx snap 32").unwrap());
+        capture.record(PcapDirection::Rx, 1_250, &frame(60));
+        capture.record(PcapDirection::Tx, 1_260, &frame(60));
+        capture.stop();
+        capture.record(PcapDirection::Rx, 1_270, &frame(60));
+        let status = capture.status();
+        assert_eq!(status.frames, 1);
+        assert_eq!(status.bytes as usize, PCAP_HEADER_LEN + PCAP_RECORD_HEADER_LEN + 32);
+
+        let mut out = vec![0u8; PCAP_BUF_LEN];
+        let len = capture.write_pcap(1_000_000, &mut out);
+        assert_eq!(len, status.bytes as usize);
+        assert_eq!(&out[0..8], &[0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0]);
+        assert_eq!(&out[16..24], &[32, 0, 0, 0, 1, 0, 0, 0]);
+        let record = &out[PCAP_HEADER_LEN..len];
+        assert_eq!(&record[0..4], &1001u32.to_le_bytes());
+        assert_eq!(&record[4..8], &250_000u32.to_le_bytes());
+        assert_eq!(&record[8..12], &32u32.to_le_bytes());
+        assert_eq!(&record[12..16], &60u32.to_le_bytes());
+        assert_eq!(&record[16..], &frame(32)[..]);
+    }
+
+    #[test]
+    fn ring_evicts_oldest() {
+        let mut capture = Capture::new();
+        capture.start(PcapFilter::default());
+        let per_frame = PCAP_RECORD_HEADER_LEN + 1000;
+        let fits = RING_CAPACITY / per_frame;
+        for i in 0..fits + 3 {
+            capture.record(PcapDirection::Rx, i as u64, &frame(1000));
+        }
+        let status = capture.status();
+        assert_eq!(status.frames as usize, fits);
+        assert_eq!(status.dropped, 3);
+        assert!(status.bytes as usize <= PCAP_BUF_LEN - 4);
+        assert_eq!(capture.frames.front().unwrap().timestamp_ms, 3);
+        capture.clear();
+        assert_eq!(capture.status(), PcapStatus { running: true, bytes: PCAP_HEADER_LEN as u32, ..Default::default() });
+    }
+}
@@ -0,0 +1,318 @@
+use crate::api::*;
+use num_traits::*;
+use std::collections::{BTreeSet, HashMap};
+use std::io::{Read, Write};
+use smoltcp::wire::IpAddress;
+use std::net::IpAddr;
+
+/// Ports from here up are handed out for outgoing connections, and aren't subject to the `listen` policy
+pub(crate) const EPHEMERAL_PORT_START: u16 = 49152;
+/// The only process that may change policies through the net server: the status bar's policy menu
+const POLICY_ADMIN_APP: &str = "status";
+/// The only process that may capture frames, as a capture holds every app's traffic: the shell's `net pcap`
+const PCAP_APP: &str = "shellchat";
+
+/// The socket policies, as loaded from the `POLICY_DICT` in the PDDB.
+///
+/// Apps are identified by the process name that the kernel reports for the sender of a request. A request
+/// is checked against the app's own rules first, then against the `DEFAULT_POLICY_APP` rules, and is allowed
+/// if neither decides it. Until the PDDB is mounted there are no policies to load, so everything is allowed;
+/// this is fine because the WLAN can't connect before then, as the AP credentials are stored in the PDDB too.
+///
+/// Only `POLICY_ADMIN_APP` may change policies with `Opcode::PolicySet`. However, the PDDB has no
+/// per-dictionary access control, so `POLICY_DICT` itself can be written by any process that talks to the
+/// PDDB, and the net server picks up such changes like any other. The policies therefore keep well-behaved
+/// apps within their limits, but are no defence against an app that is willing to rewrite them.
+pub(crate) struct NetPolicy {
+    policies: HashMap<String, AppPolicy>,
+    /// looks up the process name of a sender; replaced in tests
+    name_of: fn(Option<xous::PID>) -> String,
+    /// names of the processes that have made a request, so they can be offered in the policy menu
+    seen: BTreeSet<String>,
+    /// created on the first `reload()`, which is only sent once the PDDB is mounted
+    pddb: Option<pddb::Pddb>,
+}
+impl NetPolicy {
+    pub fn new() -> NetPolicy {
+        NetPolicy { policies: HashMap::new(), name_of: Self::app_name, seen: BTreeSet::new(), pddb: None }
+    }
+
+    /// Returns the name of `pid`'s process. The name is empty in hosted mode, where the kernel can't
+    /// report it, so only the default policy applies there.
+    fn app_name(pid: Option<xous::PID>) -> String {
+        match pid.map(xous::process_name) {
+            Some(Ok(name)) => name.to_str().to_string(),
+            _ => String::new(),
+        }
+    }
+
+    /// Returns whether `pid` is the process called `app`. In hosted mode no process names are known, so
+    /// every process passes, as with the default-only policy there.
+    fn is_app(&self, pid: Option<xous::PID>, app: &str) -> bool {
+        if cfg!(any(target_os = "none", target_os = "xous")) {
+            (self.name_of)(pid) == app
+        } else {
+            true
+        }
+    }
+
+    /// Returns whether `pid` may change policies. On hardware, only `POLICY_ADMIN_APP` may.
+    pub fn may_administer(&self, pid: Option<xous::PID>) -> bool {
+        self.is_app(pid, POLICY_ADMIN_APP)
+    }
+
+    /// Returns whether `pid` may control and fetch the frame capture. On hardware, only `PCAP_APP` may.
+    pub fn may_capture(&self, pid: Option<xous::PID>) -> bool {
+        self.is_app(pid, PCAP_APP)
+    }
+
+    pub fn permits(&mut self, pid: Option<xous::PID>, action: PolicyAction, addr: Option<IpAddress>, port: Option<u16>) -> bool {
+        let app = (self.name_of)(pid);
+        let addr = match addr {
+            Some(IpAddress::Ipv4(a)) => Some(IpAddr::from(a.0)),
+            Some(IpAddress::Ipv6(a)) => Some(IpAddr::from(a.0)),
+            _ => None,
+        };
+        if !app.is_empty() && !self.seen.contains(&app) {
+            self.seen.insert(app.clone());
+        }
+        let allowed = [app.as_str(), DEFAULT_POLICY_APP]
+            .iter()
+            .filter_map(|name| self.policies.get(*name))
+            .find_map(|policy| policy.check(action, addr, port))
+            .unwrap_or(true);
+        if !allowed {
+            log::warn!("denied {:?} to {:?} port {:?} for '{}'", action, addr, port, app);
+        }
+        allowed
+    }
+
+    /// Checks binding a UDP socket to `local_port`. Client sockets are bound to port 0 or an ephemeral
+    /// port, which isn't listening in the sense a policy means, so only fixed ports are checked.
+    pub fn permits_udp_bind(&mut self, pid: Option<xous::PID>, local_port: u16) -> bool {
+        local_port == 0
+            || local_port >= EPHEMERAL_PORT_START
+            || self.permits(pid, PolicyAction::Listen, None, Some(local_port))
+    }
+
+    /// Checks joining or leaving the multicast `group`. Membership lets an app receive traffic that isn't
+    /// addressed to the device, so it's treated as a listen on no particular port.
+    pub fn permits_multicast(&mut self, pid: Option<xous::PID>, group: IpAddress) -> bool {
+        self.permits(pid, PolicyAction::Listen, Some(group), None)
+    }
+
+    /// Re-reads every policy from the PDDB, and subscribes to changes to them on the first call.
+    pub fn reload(&mut self, net_conn: xous::CID) {
+        if self.pddb.is_none() {
+            let pddb = pddb::Pddb::new();
+            if let Err(e) = pddb.subscribe_dict(POLICY_DICT, None, move |_change| {
+                xous::try_send_message(
+                    net_conn,
+                    xous::Message::new_scalar(Opcode::PolicyReload.to_usize().unwrap(), 0, 0, 0, 0),
+                ).ok();
+            }) {
+                log::error!("couldn't subscribe to socket policy changes: {:?}", e);
+            }
+            self.pddb = Some(pddb);
+        }
+        let pddb = self.pddb.as_ref().unwrap();
+        self.policies.clear();
+        for app in pddb.list_keys(POLICY_DICT, None).unwrap_or_default() {
+            let mut text = String::new();
+            match pddb.get(POLICY_DICT, &app, None, false, false, None, Some(||{})) {
+                Ok(mut key) => {
+                    if key.read_to_string(&mut text).is_err() {
+                        log::error!("socket policy for '{}' is not valid utf-8", app);
+                    }
+                }
+                Err(e) => log::error!("couldn't read socket policy for '{}': {:?}", app, e),
+            }
+            self.load(app, &text);
+        }
+        log::info!("loaded {} socket policies", self.policies.len());
+    }
+
+    /// Installs the stored rules `text` as the policy of `app`
+    fn load(&mut self, app: String, text: &str) {
+        let policy = match AppPolicy::parse(text) {
+            Ok(policy) => policy,
+            Err(rule) => {
+                // fail closed: an app whose policy can't be understood gets no network access
+                log::error!("invalid rule '{}' in socket policy for '{}', denying all access", rule, app);
+                AppPolicy::deny_all()
+            }
+        };
+        self.policies.insert(app, policy);
+    }
+
+    /// Returns the stored rules for `app`, or an empty string if it has none.
+    pub fn get(&self, app: &str) -> String {
+        let mut text = String::new();
+        if let Some(pddb) = self.pddb.as_ref() {
+            if let Ok(mut key) = pddb.get(POLICY_DICT, app, None, false, false, None, Some(||{})) {
+                key.read_to_string(&mut text).ok();
+            }
+        }
+        text
+    }
+
+    /// Stores the rules for `app`, or removes its policy if `rules` is empty. The change takes effect
+    /// through the dictionary subscription. Returns false if the rules are invalid or couldn't be stored.
+    pub fn set(&mut self, app: &str, rules: &str) -> bool {
+        let pddb = match self.pddb.as_ref() {
+            Some(pddb) => pddb,
+            None => {
+                log::warn!("PDDB is not mounted, can't set a socket policy");
+                return false;
+            }
+        };
+        if app.is_empty() {
+            return false;
+        }
+        if rules.trim().is_empty() {
+            pddb.delete_key(POLICY_DICT, app, None).ok();
+            pddb.sync().ok();
+            return true;
+        }
+        if let Err(rule) = AppPolicy::parse(rules) {
+            log::warn!("rejecting socket policy for '{}' with invalid rule '{}'", app, rule);
+            return false;
+        }
+        // delete the old record first, so that a shorter policy doesn't leave a tail of the old one behind
+        pddb.delete_key(POLICY_DICT, app, None).ok();
+        match pddb.get(POLICY_DICT, app, None, true, true, Some(rules.len()), Some(||{})) {
+            Ok(mut key) => {
+                if key.write_all(rules.as_bytes()).and_then(|_| key.flush()).is_err() {
+                    log::error!("couldn't write socket policy for '{}'", app);
+                    return false;
+                }
+            }
+            Err(e) => {
+                log::error!("couldn't create socket policy for '{}': {:?}", app, e);
+                return false;
+            }
+        }
+        pddb.sync().ok();
+        true
+    }
+
+    /// Returns the apps that have a policy or have used the network, in order.
+    pub fn apps(&self) -> Vec<String> {
+        let mut apps: BTreeSet<String> = self.policies.keys().cloned().collect();
+        apps.extend(self.seen.iter().cloned());
+        apps.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smoltcp::wire::Ipv4Address;
+
+    const APP: u8 = 2;
+    const OTHER: u8 = 3;
+
+    fn pid(pid: u8) -> Option<xous::PID> {
+        xous::PID::new(pid)
+    }
+
+    fn policy() -> NetPolicy {
+        let mut policy = NetPolicy::new();
+        policy.name_of = |pid| match pid.map(|p| p.get()) {
+            Some(APP) => "app".to_string(),
+            Some(OTHER) => "other".to_string(),
+            _ => String::new(),
+        };
+        policy
+    }
+
+    fn connect(policy: &mut NetPolicy, pid: Option<xous::PID>, addr: [u8; 4], port: u16) -> bool {
+        policy.permits(pid, PolicyAction::Connect, Some(Ipv4Address(addr).into()), Some(port))
+    }
+
+    #[test]
+    fn precedence() {
+        let mut policy = policy();
+        // no policies at all
+        assert!(connect(&mut policy, pid(APP), [1, 1, 1, 1], 80));
+
+        policy.load(DEFAULT_POLICY_APP.to_string(), "allow connect any port 443; deny connect any");
+        policy.load("app".to_string(), "allow connect 10.0.0.0/8; deny connect any port 443");
+        // the app's own rules decide first...
+        assert!(connect(&mut policy, pid(APP), [10, 0, 0, 1], 80));
+        assert!(!connect(&mut policy, pid(APP), [1, 1, 1, 1], 443));
+        // ...then the default rules, for what the app's rules don't cover
+        assert!(!connect(&mut policy, pid(APP), [1, 1, 1, 1], 80));
+        // other apps only have the default rules
+        assert!(connect(&mut policy, pid(OTHER), [1, 1, 1, 1], 443));
+        assert!(!connect(&mut policy, pid(OTHER), [10, 0, 0, 1], 80));
+        assert!(!connect(&mut policy, None, [10, 0, 0, 1], 80));
+        // nothing decides a listen, so it is allowed
+        assert!(policy.permits(pid(APP), PolicyAction::Listen, None, Some(8080)));
+        assert_eq!(policy.apps(), vec!["*", "app", "other"]);
+    }
+
+    #[test]
+    fn fail_closed() {
+        let mut policy = policy();
+        policy.load(DEFAULT_POLICY_APP.to_string(), "allow connect any; allow listen; allow ping");
+        policy.load("app".to_string(), "allow connect any; permit everything");
+        for &action in [PolicyAction::Connect, PolicyAction::Listen, PolicyAction::Ping].iter() {
+            let addr = if action == PolicyAction::Listen { None } else { Some(Ipv4Address::new(1, 1, 1, 1).into()) };
+            let port = if action == PolicyAction::Ping { None } else { Some(80) };
+            assert!(!policy.permits(pid(APP), action, addr, port));
+            assert!(policy.permits(pid(OTHER), action, addr, port));
+        }
+    }
+
+    #[test]
+    fn udp_bind() {
+        let mut policy = policy();
+        policy.load("app".to_string(), "deny listen");
+        assert!(policy.permits_udp_bind(pid(APP), 0));
+        assert!(policy.permits_udp_bind(pid(APP), EPHEMERAL_PORT_START));
+        assert!(policy.permits_udp_bind(pid(APP), u16::MAX));
+        assert!(!policy.permits_udp_bind(pid(APP), EPHEMERAL_PORT_START - 1));
+        assert!(!policy.permits_udp_bind(pid(APP), 53));
+        assert!(policy.permits_udp_bind(pid(OTHER), 53));
+    }
+
+    #[test]
+    fn multicast() {
+        let mut policy = policy();
+        let group = Ipv4Address::new(224, 0, 0, 251).into();
+        policy.load("app".to_string(), "deny listen port 5353; allow listen");
+        policy.load("other".to_string(), "deny listen");
+        // a rule with a port never matches a group membership
+        assert!(policy.permits_multicast(pid(APP), group));
+        assert!(!policy.permits_multicast(pid(OTHER), group));
+    }
+
+    #[test]
+    fn administer() {
+        let mut policy = policy();
+        policy.name_of = |pid| match pid.map(|p| p.get()) {
+            Some(APP) => POLICY_ADMIN_APP.to_string(),
+            _ => "other".to_string(),
+        };
+        let hardware = cfg!(any(target_os = "none", target_os = "xous"));
+        assert!(policy.may_administer(pid(APP)));
+        assert_eq!(policy.may_administer(pid(OTHER)), !hardware);
+        assert_eq!(policy.may_administer(None), !hardware);
+        assert_eq!(policy.may_capture(pid(APP)), !hardware);
+    }
+
+    #[test]
+    fn capture() {
+        let mut policy = policy();
+        policy.name_of = |pid| match pid.map(|p| p.get()) {
+            Some(APP) => PCAP_APP.to_string(),
+            _ => "other".to_string(),
+        };
+        let hardware = cfg!(any(target_os = "none", target_os = "xous"));
+        assert!(policy.may_capture(pid(APP)));
+        assert_eq!(policy.may_capture(pid(OTHER)), !hardware);
+        assert_eq!(policy.may_capture(None), !hardware);
+        assert_eq!(policy.may_administer(pid(APP)), !hardware);
+    }
+}
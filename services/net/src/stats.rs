@@ -0,0 +1,241 @@
+use crate::api::*;
+
+use smoltcp::wire::IpAddress;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const IP_PROTOCOL_TCP: u8 = 6;
+const TCP_FLAG_FIN: u8 = 0x01;
+const TCP_FLAG_SYN: u8 = 0x02;
+/// The most TCP connections whose sequence numbers are tracked; beyond this, the oldest are forgotten
+const MAX_FLOWS: usize = 64;
+
+/// A TCP connection as seen from our end: (local port, remote address, remote port)
+pub(crate) type FlowId = (u16, IpAddr, u16);
+
+/// Data bytes moved through a socket by its owner
+#[derive(Debug, Default, Copy, Clone)]
+pub(crate) struct SocketCounters {
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Flow {
+    /// the sequence number after the highest one sent so far
+    next_seq: u32,
+    retransmits: u32,
+    /// used to pick the flow to forget when the table is full
+    last_seen: u64,
+}
+
+/// Counters kept by `NetPhy` for the frames that pass through it.
+///
+/// smoltcp doesn't count retransmissions, so they're inferred from the outgoing TCP segments: a
+/// segment that starts before the end of data that was already sent on its connection is a retransmit.
+pub(crate) struct PhyStats {
+    iface: InterfaceStats,
+    flows: BTreeMap<FlowId, Flow>,
+    count: u64,
+}
+impl PhyStats {
+    pub fn new() -> PhyStats {
+        PhyStats {
+            iface: InterfaceStats::default(),
+            flows: BTreeMap::new(),
+            count: 0,
+        }
+    }
+
+    pub fn interface(&self) -> InterfaceStats {
+        self.iface
+    }
+
+    /// Records a received frame. `result` is what the stack made of it.
+    pub fn record_rx<R>(&mut self, len: usize, result: &smoltcp::Result<R>) {
+        self.iface.rx_bytes += len as u64;
+        self.iface.rx_packets += 1;
+        match result {
+            Ok(_) => (),
+            Err(smoltcp::Error::Checksum) => self.iface.rx_checksum_errors += 1,
+            Err(_) => self.iface.rx_dropped += 1,
+        }
+    }
+
+    /// Records a frame that arrived while the previous one was still waiting to be processed
+    pub fn record_rx_overflow(&mut self) {
+        self.iface.rx_dropped += 1;
+    }
+
+    /// Records a transmitted frame; `sent` is false if the frame couldn't be handed to the hardware
+    pub fn record_tx(&mut self, frame: &[u8], sent: bool) {
+        if !sent {
+            self.iface.tx_dropped += 1;
+            return;
+        }
+        self.iface.tx_bytes += frame.len() as u64;
+        self.iface.tx_packets += 1;
+        if let Some((id, seq, seq_len)) = tcp_segment(frame) {
+            if seq_len == 0 {
+                // bare ACKs and RSTs don't occupy sequence space, so they are never retransmits
+                return;
+            }
+            self.count += 1;
+            let end = seq.wrapping_add(seq_len);
+            match self.flows.get_mut(&id) {
+                Some(flow) => {
+                    if seq_before(seq, flow.next_seq) {
+                        flow.retransmits += 1;
+                        self.iface.tcp_retransmits += 1;
+                    }
+                    if seq_before(flow.next_seq, end) {
+                        flow.next_seq = end;
+                    }
+                    flow.last_seen = self.count;
+                }
+                None => {
+                    if self.flows.len() >= MAX_FLOWS {
+                        if let Some(oldest) = self.flows.iter().min_by_key(|(_, f)| f.last_seen).map(|(id, _)| *id) {
+                            self.flows.remove(&oldest);
+                        }
+                    }
+                    self.flows.insert(id, Flow { next_seq: end, retransmits: 0, last_seen: self.count });
+                }
+            }
+        }
+    }
+
+    /// Returns the number of retransmits on the TCP connection `id`
+    pub fn retransmits(&self, id: &FlowId) -> u32 {
+        self.flows.get(id).map_or(0, |f| f.retransmits)
+    }
+
+    /// Forgets the connections that aren't in `live`, so a new connection that reuses the
+    /// same ports starts from zero.
+    pub fn retain_flows(&mut self, live: &[FlowId]) {
+        self.flows.retain(|id, _| live.contains(id));
+    }
+}
+
+/// Returns the flow, sequence number and sequence space length of an outgoing TCP segment
+fn tcp_segment(frame: &[u8]) -> Option<(FlowId, u32, u32)> {
+    let ethertype = u16::from_be_bytes([*frame.get(12)?, *frame.get(13)?]);
+    let l3 = frame.get(14..)?;
+    let (remote, header_len, payload_len) = match ethertype {
+        ETHERTYPE_IPV4 => {
+            if *l3.get(9)? != IP_PROTOCOL_TCP {
+                return None;
+            }
+            let header_len = (*l3.first()? & 0xf) as usize * 4;
+            let total_len = u16::from_be_bytes([*l3.get(2)?, *l3.get(3)?]) as usize;
+            let dst = l3.get(16..20)?;
+            (IpAddr::from([dst[0], dst[1], dst[2], dst[3]]), header_len, total_len.checked_sub(header_len)?)
+        }
+        ETHERTYPE_IPV6 => {
+            // smoltcp doesn't send extension headers
+            if *l3.get(6)? != IP_PROTOCOL_TCP {
+                return None;
+            }
+            let payload_len = u16::from_be_bytes([*l3.get(4)?, *l3.get(5)?]) as usize;
+            let mut dst = [0u8; 16];
+            dst.copy_from_slice(l3.get(24..40)?);
+            (IpAddr::from(dst), 40, payload_len)
+        }
+        _ => return None,
+    };
+    // the fixed part of the TCP header
+    let tcp = l3.get(header_len..header_len + 20)?;
+    let src_port = u16::from_be_bytes([tcp[0], tcp[1]]);
+    let dst_port = u16::from_be_bytes([tcp[2], tcp[3]]);
+    let seq = u32::from_be_bytes([tcp[4], tcp[5], tcp[6], tcp[7]]);
+    let data_offset = (tcp[12] >> 4) as usize * 4;
+    let flags = tcp[13];
+    let mut seq_len = payload_len.checked_sub(data_offset)? as u32;
+    if flags & TCP_FLAG_SYN != 0 {
+        seq_len += 1;
+    }
+    if flags & TCP_FLAG_FIN != 0 {
+        seq_len += 1;
+    }
+    Some(((src_port, remote, dst_port), seq, seq_len))
+}
+
+/// Compares sequence numbers modulo 2^32, as in RFC 793
+fn seq_before(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+pub(crate) fn flow_id(local_port: u16, remote: IpAddress, remote_port: u16) -> Option<FlowId> {
+    match remote {
+        IpAddress::Ipv4(a) => Some((local_port, IpAddr::from(a.0), remote_port)),
+        IpAddress::Ipv6(a) => Some((local_port, IpAddr::from(a.0), remote_port)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    /// an Ethernet frame with a TCP segment from port 49153 to 1.1.1.1:443
+    fn segment(seq: u32, flags: u8, payload: usize) -> Vec<u8> {
+        let mut frame = vec![0u8; 14 + 20 + 20 + payload];
+        frame[12..14].copy_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        let ip = &mut frame[14..];
+        ip[0] = 0x45;
+        ip[2..4].copy_from_slice(&((20 + 20 + payload) as u16).to_be_bytes());
+        ip[9] = IP_PROTOCOL_TCP;
+        ip[16..20].copy_from_slice(&[1, 1, 1, 1]);
+        let tcp = &mut frame[34..];
+        tcp[0..2].copy_from_slice(&49153u16.to_be_bytes());
+        tcp[2..4].copy_from_slice(&443u16.to_be_bytes());
+        tcp[4..8].copy_from_slice(&seq.to_be_bytes());
+        tcp[12] = 5 << 4;
+        tcp[13] = flags;
+        frame
+    }
+
+    #[test]
+    fn retransmits() {
+        let id = (49153, IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 443);
+        let mut stats = PhyStats::new();
+        // the sequence number wraps during the connection
+        let isn = u32::MAX - 100;
+        stats.record_tx(&segment(isn, TCP_FLAG_SYN, 0), true);
+        stats.record_tx(&segment(isn, TCP_FLAG_SYN, 0), true);
+        assert_eq!(stats.retransmits(&id), 1);
+        stats.record_tx(&segment(isn.wrapping_add(1), 0, 200), true);
+        // a bare ACK isn't a retransmit, even though it reuses the sequence number
+        stats.record_tx(&segment(isn.wrapping_add(201), 0, 0), true);
+        stats.record_tx(&segment(isn.wrapping_add(201), 0, 100), true);
+        assert_eq!(stats.retransmits(&id), 1);
+        stats.record_tx(&segment(isn.wrapping_add(1), 0, 200), true);
+        assert_eq!(stats.retransmits(&id), 2);
+        stats.record_tx(&segment(isn.wrapping_add(301), TCP_FLAG_FIN, 0), false);
+
+        let iface = stats.interface();
+        assert_eq!(iface.tx_packets, 6);
+        assert_eq!(iface.tx_dropped, 1);
+        assert_eq!(iface.tcp_retransmits, 2);
+        assert_eq!(iface.tx_bytes, 6 * 54 + 500);
+
+        stats.retain_flows(&[]);
+        assert_eq!(stats.retransmits(&id), 0);
+        assert_eq!(stats.interface().tcp_retransmits, 2);
+    }
+
+    #[test]
+    fn rx() {
+        let mut stats = PhyStats::new();
+        stats.record_rx(60, &Ok(()));
+        stats.record_rx(60, &Err::<(), _>(smoltcp::Error::Checksum));
+        stats.record_rx(60, &Err::<(), _>(smoltcp::Error::Unrecognized));
+        stats.record_rx_overflow();
+        let iface = stats.interface();
+        assert_eq!((iface.rx_packets, iface.rx_bytes), (3, 180));
+        assert_eq!((iface.rx_checksum_errors, iface.rx_dropped), (1, 2));
+    }
+}
@@ -6,6 +6,7 @@ pub(crate) fn std_tcp_listen(
     mut msg: xous::MessageEnvelope,
     iface: &mut Interface::<NetPhy>,
     our_sockets: &mut Vec<Option<SocketHandle>>,
+    policy: &mut crate::policy::NetPolicy,
     ) {
     // Ignore nonblocking and scalar messages
     let body = match msg.body.memory_message_mut() {
@@ -27,6 +28,10 @@ pub(crate) fn std_tcp_listen(
             return;
         }
     };
+    if !policy.permits(msg.sender.pid(), PolicyAction::Listen, None, Some(local_port)) {
+        std_failure(msg, NetError::AccessDenied);
+        return;
+    }
 
     let tcp_rx_buffer = TcpSocketBuffer::new(vec![0; TCP_BUFFER_SIZE]);
     let tcp_tx_buffer = TcpSocketBuffer::new(vec![0; TCP_BUFFER_SIZE]);
@@ -16,6 +16,7 @@ pub(crate) fn std_tcp_connect(
     iface: &mut Interface::<NetPhy>,
     tcp_connect_waiting: &mut Vec<Option<(xous::MessageEnvelope, SocketHandle, u16, u16, u16)>>,
     our_sockets: &mut Vec<Option<SocketHandle>>,
+    policy: &mut crate::policy::NetPolicy,
 ) {
     // Ignore nonblocking and scalar messages
     let body = match msg.body.memory_message_mut() {
@@ -38,6 +39,10 @@ pub(crate) fn std_tcp_connect(
             return;
         }
     };
+    if !policy.permits(msg.sender.pid(), PolicyAction::Connect, Some(address), Some(remote_port)) {
+        respond_with_error(msg, NetError::AccessDenied);
+        return;
+    }
 
     // initiates a new connection to a remote server consisting of an (Address:Port) tuple.
     // multiple connections can exist to a server, and they are further differentiated by the return port
@@ -79,6 +84,7 @@ pub(crate) fn std_tcp_tx(
     iface: &mut Interface::<NetPhy>,
     tcp_tx_waiting: &mut Vec<Option<WaitingSocket>>,
     our_sockets: &Vec<Option<SocketHandle>>,
+    socket_counters: &mut HashMap<SocketHandle, SocketCounters>,
 ) {
     let connection_handle_index = (msg.body.id() >> 16) & 0xffff;
     let body = match msg.body.memory_message_mut() {
@@ -140,6 +146,7 @@ pub(crate) fn std_tcp_tx(
     };
 
     log::trace!("sent {}", sent_octets);
+    socket_counters.entry(*handle).or_default().tx_bytes += sent_octets as u64;
     let response_data = body.buf.as_slice_mut::<u32>();
     response_data[0] = 0;
     response_data[1] = sent_octets as u32;
@@ -151,6 +158,7 @@ pub(crate) fn std_tcp_rx(
     iface: &mut Interface::<NetPhy>,
     tcp_rx_waiting: &mut Vec<Option<WaitingSocket>>,
     our_sockets: &Vec<Option<SocketHandle>>,
+    socket_counters: &mut HashMap<SocketHandle, SocketCounters>,
 ) {
     let connection_handle_index = (msg.body.id() >> 16) & 0xffff;
     let body = match msg.body.memory_message_mut() {
@@ -177,6 +185,7 @@ pub(crate) fn std_tcp_rx(
         log::trace!("receiving data right away");
         match socket.recv_slice(body.buf.as_slice_mut()) {
             Ok(bytes) => {
+                socket_counters.entry(*handle).or_default().rx_bytes += bytes as u64;
                 // it's actually valid to receive 0 bytes, but the encoding of this field doesn't allow it.
                 // so, `None` is abused to represent the value of "0" bytes, which is what is naturally returned
                 // as the "error" when you try to create a NonZeroUsize with 0.
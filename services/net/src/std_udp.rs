@@ -9,11 +9,15 @@ use ticktimer_server::Ticktimer;
 /// `recv` requests create `UpdStdState` objects, that are stored in a `udp_rx` Vec.
 
 const BUFLEN: usize = NET_MTU as usize;
+/// Datagrams that can be queued on a socket before it's read. Clients such as the DNS resolver send
+/// several requests at once, and the responses can arrive back to back.
+const RX_PACKETS: usize = 4;
 
 pub(crate) fn std_udp_bind(
     mut msg: xous::MessageEnvelope,
     iface: &mut Interface::<NetPhy>,
     our_sockets: &mut Vec<Option<SocketHandle>>,
+    policy: &mut crate::policy::NetPolicy,
     ) {
     // Ignore nonblocking and scalar messages
     let body = match msg.body.memory_message_mut() {
@@ -35,9 +39,13 @@ pub(crate) fn std_udp_bind(
             return;
         }
     };
+    if !policy.permits_udp_bind(msg.sender.pid(), local_port) {
+        std_failure(msg, NetError::AccessDenied);
+        return;
+    }
 
     let udp_rx_buffer =
-    UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; BUFLEN]);
+    UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; RX_PACKETS], vec![0; BUFLEN * RX_PACKETS]);
     let udp_tx_buffer =
         UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; BUFLEN]);
     let udp_socket = UdpSocket::new(udp_rx_buffer, udp_tx_buffer);
@@ -74,6 +82,7 @@ pub(crate) fn std_udp_rx(
     iface: &mut Interface::<NetPhy>,
     udp_rx_waiting: &mut Vec<Option<UdpStdState>>,
     our_sockets: &Vec<Option<SocketHandle>>,
+    socket_counters: &mut HashMap<SocketHandle, SocketCounters>,
 ) {
     let connection_handle_index = (msg.body.id() >> 16) & 0xffff;
     let body = match msg.body.memory_message_mut() {
@@ -154,6 +163,7 @@ pub(crate) fn std_udp_rx(
             match socket.recv() {
                 Ok((data, endpoint)) => {
                     log::debug!("immediate udp rx");
+                    socket_counters.entry(*handle).or_default().rx_bytes += data.len() as u64;
                     udp_rx_success(body.buf.as_slice_mut(), data, endpoint);
                 }
                 Err(e) => {
@@ -187,6 +197,8 @@ pub(crate) fn std_udp_tx(
     mut msg: xous::MessageEnvelope,
     iface: &mut Interface::<NetPhy>,
     our_sockets: &Vec<Option<SocketHandle>>,
+    socket_counters: &mut HashMap<SocketHandle, SocketCounters>,
+    policy: &mut crate::policy::NetPolicy,
 ) {
     // unpack meta
     let connection_handle_index = (msg.body.id() >> 16) & 0xffff;
@@ -216,6 +228,10 @@ pub(crate) fn std_udp_tx(
             return;
         }
     };
+    if !policy.permits(msg.sender.pid(), PolicyAction::Connect, Some(address), Some(remote_port)) {
+        std_failure(msg, NetError::AccessDenied);
+        return;
+    }
     let len = u16::from_le_bytes([bytes[19], bytes[20]]);
     // attempt the tx
     log::debug!("udp tx to fd {} -> {:?}:{} {:?}", connection_handle_index, address, remote_port, &bytes[21..21 + len as usize]);
@@ -248,6 +264,7 @@ pub(crate) fn std_udp_tx(
     }
     match socket.send_slice(&bytes[21..21 + len as usize], IpEndpoint::new(address, remote_port)) {
         Ok(_) => {
+            socket_counters.entry(*handle).or_default().tx_bytes += len as u64;
             body.buf.as_slice_mut()[0] = 0;
         }
         Err(_e) => {
@@ -0,0 +1,817 @@
+//! A virtual network for hosted mode, so the net server's stack can be exercised without a WLAN chip.
+//!
+//! Frames that the stack transmits are handed to `VirtualNet`, which plays the part of every other
+//! host on a 10.0.2.0/24 LAN (the same layout as QEMU's user networking):
+//!
+//!   - it answers ARP requests for every address but ours, so all traffic goes to one peer
+//!   - a DHCP server hands out 10.0.2.15, with 10.0.2.2 as the router and 10.0.2.3 as the DNS server
+//!   - the DNS server resolves every A query to 10.0.2.2
+//!   - every unicast address answers pings, and echoes UDP datagrams and TCP streams on every port
+//!
+//! The replies are queued, and picked up by `NetPhy` the next time smoltcp polls it for frames.
+//!
+//! Only traffic that originates in the net server's own smoltcp stack reaches this network: DHCP,
+//! ARP, pings sent through `net::Ping` (`net ping` in the shell), and whatever a test drives through
+//! `NetPhy` directly. In hosted mode, libstd's `std::net` sockets are built on the host's socket calls
+//! and never reach the net server, so `TcpStream` and `UdpSocket` -- and everything built on them,
+//! such as the DNS resolver, TLS, `net tcpget` and `net udp` -- keep using the host's network. The
+//! pcap capture and the stats in hosted mode likewise only see the frames described above.
+//!
+//! Because the addresses the net server reports are on this network while the sockets are on the
+//! host's, the dns server's mDNS responder is turned off in hosted mode. Otherwise it would announce
+//! 10.0.2.15 onto the host's LAN.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+
+pub(crate) const GUEST_ADDR: [u8; 4] = [10, 0, 2, 15];
+pub(crate) const GATEWAY_ADDR: [u8; 4] = [10, 0, 2, 2];
+pub(crate) const DNS_ADDR: [u8; 4] = [10, 0, 2, 3];
+const NETMASK: [u8; 4] = [255, 255, 255, 0];
+const BROADCAST_ADDR: [u8; 4] = [255, 255, 255, 255];
+/// the MAC address of all the virtual hosts; locally administered
+const PEER_MAC: [u8; 6] = [0x52, 0x55, 0x0a, 0x00, 0x02, 0x02];
+const BROADCAST_MAC: [u8; 6] = [0xff; 6];
+const LEASE_SECS: u32 = 24 * 3600;
+const DNS_TTL_SECS: u32 = 60;
+/// Rather than keeping track of the MSS that the stack offers, the echo peer sends segments small
+/// enough for any stack to accept.
+const TCP_MSS: usize = 536;
+/// The most data that a TCP connection buffers before it has been echoed
+const TCP_WINDOW: usize = 8192;
+/// Frames waiting to be received beyond this are dropped, as a real link would
+const RX_QUEUE_LIMIT: usize = 64;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_ARP: u16 = 0x0806;
+const IP_PROTOCOL_ICMP: u8 = 1;
+const IP_PROTOCOL_TCP: u8 = 6;
+const IP_PROTOCOL_UDP: u8 = 17;
+const DHCP_SERVER_PORT: u16 = 67;
+const DHCP_CLIENT_PORT: u16 = 68;
+const DNS_PORT: u16 = 53;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+/// A TCP connection: (stack address, stack port, peer address, peer port)
+type TcpKey = ([u8; 4], u16, [u8; 4], u16);
+
+struct TcpPeer {
+    /// the next sequence number the peer sends
+    snd_nxt: u32,
+    /// the oldest sequence number the stack hasn't acknowledged
+    snd_una: u32,
+    /// the next sequence number the peer expects
+    rcv_nxt: u32,
+    /// the receive window the stack last advertised
+    window: u32,
+    /// data received and not yet echoed
+    pending: VecDeque<u8>,
+    fin_received: bool,
+    fin_sent: bool,
+}
+
+pub(crate) struct VirtualNet {
+    /// learned from the frames the stack sends
+    guest_mac: [u8; 6],
+    rx: VecDeque<Vec<u8>>,
+    tcp: HashMap<TcpKey, TcpPeer>,
+    ip_id: u16,
+}
+impl VirtualNet {
+    pub fn new() -> VirtualNet {
+        VirtualNet {
+            guest_mac: [0; 6],
+            rx: VecDeque::new(),
+            tcp: HashMap::new(),
+            ip_id: 0,
+        }
+    }
+
+    /// Returns the length of the next frame for the stack, if there is one
+    pub fn pending(&self) -> Option<usize> {
+        self.rx.front().map(|f| f.len())
+    }
+
+    /// Returns the next frame for the stack
+    pub fn recv(&mut self) -> Option<Vec<u8>> {
+        self.rx.pop_front()
+    }
+
+    /// Handles a frame sent by the stack
+    pub fn send(&mut self, frame: &[u8]) {
+        if frame.len() < 14 {
+            return;
+        }
+        let dst_mac = &frame[0..6];
+        if dst_mac != PEER_MAC && dst_mac != BROADCAST_MAC {
+            // multicast, e.g. mDNS and IGMP, has nobody to talk to here
+            return;
+        }
+        self.guest_mac.copy_from_slice(&frame[6..12]);
+        let payload = &frame[14..];
+        match u16::from_be_bytes([frame[12], frame[13]]) {
+            ETHERTYPE_ARP => self.arp(payload),
+            ETHERTYPE_IPV4 => self.ipv4(payload),
+            _ => (),
+        }
+    }
+
+    fn queue(&mut self, dst_mac: [u8; 6], ethertype: u16, payload: &[u8]) {
+        if self.rx.len() >= RX_QUEUE_LIMIT {
+            log::warn!("virtual network receive queue is full, dropping a frame");
+            return;
+        }
+        let mut frame = Vec::with_capacity(14 + payload.len());
+        frame.extend_from_slice(&dst_mac);
+        frame.extend_from_slice(&PEER_MAC);
+        frame.extend_from_slice(&ethertype.to_be_bytes());
+        frame.extend_from_slice(payload);
+        self.rx.push_back(frame);
+    }
+
+    fn arp(&mut self, packet: &[u8]) {
+        // Ethernet/IPv4 requests only
+        if packet.len() < 28 || packet[0..8] != [0, 1, 8, 0, 6, 4, 0, 1] {
+            return;
+        }
+        let sender_ip = &packet[14..18];
+        let target_ip = &packet[24..28];
+        if target_ip == GUEST_ADDR || target_ip == sender_ip || target_ip == [0, 0, 0, 0] {
+            return;
+        }
+        let mut reply = Vec::with_capacity(28);
+        reply.extend_from_slice(&[0, 1, 8, 0, 6, 4, 0, 2]);
+        reply.extend_from_slice(&PEER_MAC);
+        reply.extend_from_slice(target_ip);
+        reply.extend_from_slice(&packet[8..14]);
+        reply.extend_from_slice(sender_ip);
+        self.queue(self.guest_mac, ETHERTYPE_ARP, &reply);
+    }
+
+    fn ipv4(&mut self, packet: &[u8]) {
+        if packet.len() < 20 || packet[0] >> 4 != 4 {
+            return;
+        }
+        let header_len = (packet[0] & 0xf) as usize * 4;
+        let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+        if header_len < 20 || total_len < header_len || total_len > packet.len() {
+            return;
+        }
+        let mut src = [0u8; 4];
+        src.copy_from_slice(&packet[12..16]);
+        let mut dst = [0u8; 4];
+        dst.copy_from_slice(&packet[16..20]);
+        let body = &packet[header_len..total_len];
+        let protocol = packet[9];
+        if protocol == IP_PROTOCOL_UDP && body.len() >= 8
+        && u16::from_be_bytes([body[2], body[3]]) == DHCP_SERVER_PORT {
+            self.dhcp(&body[8..]);
+            return;
+        }
+        if !is_unicast(dst) {
+            return;
+        }
+        match protocol {
+            IP_PROTOCOL_ICMP => self.icmp(src, dst, body),
+            IP_PROTOCOL_UDP => self.udp(src, dst, body),
+            IP_PROTOCOL_TCP => self.tcp(src, dst, body),
+            _ => (),
+        }
+    }
+
+    fn send_ipv4(&mut self, src: [u8; 4], dst: [u8; 4], protocol: u8, payload: &[u8]) {
+        let mut packet = Vec::with_capacity(20 + payload.len());
+        packet.extend_from_slice(&[0x45, 0]);
+        packet.extend_from_slice(&((20 + payload.len()) as u16).to_be_bytes());
+        packet.extend_from_slice(&self.ip_id.to_be_bytes());
+        self.ip_id = self.ip_id.wrapping_add(1);
+        packet.extend_from_slice(&[0x40, 0, 64, protocol, 0, 0]); // don't fragment, TTL 64
+        packet.extend_from_slice(&src);
+        packet.extend_from_slice(&dst);
+        let sum = checksum(&packet, 0);
+        packet[10..12].copy_from_slice(&sum.to_be_bytes());
+        packet.extend_from_slice(payload);
+        let dst_mac = if dst == BROADCAST_ADDR { BROADCAST_MAC } else { self.guest_mac };
+        self.queue(dst_mac, ETHERTYPE_IPV4, &packet);
+    }
+
+    fn icmp(&mut self, src: [u8; 4], dst: [u8; 4], message: &[u8]) {
+        // echo requests only
+        if message.len() < 8 || message[0] != 8 || message[1] != 0 {
+            return;
+        }
+        let mut reply = message.to_vec();
+        reply[0] = 0;
+        reply[2..4].copy_from_slice(&[0, 0]);
+        let sum = checksum(&reply, 0);
+        reply[2..4].copy_from_slice(&sum.to_be_bytes());
+        self.send_ipv4(dst, src, IP_PROTOCOL_ICMP, &reply);
+    }
+
+    fn udp(&mut self, src: [u8; 4], dst: [u8; 4], datagram: &[u8]) {
+        if datagram.len() < 8 {
+            return;
+        }
+        let src_port = u16::from_be_bytes([datagram[0], datagram[1]]);
+        let dst_port = u16::from_be_bytes([datagram[2], datagram[3]]);
+        let len = (u16::from_be_bytes([datagram[4], datagram[5]]) as usize).min(datagram.len());
+        if len < 8 {
+            return;
+        }
+        let payload = &datagram[8..len];
+        let reply = if dst_port == DNS_PORT {
+            match dns_reply(payload) {
+                Some(reply) => reply,
+                None => return,
+            }
+        } else {
+            payload.to_vec()
+        };
+        self.send_udp(dst, dst_port, src, src_port, &reply);
+    }
+
+    fn send_udp(&mut self, src: [u8; 4], src_port: u16, dst: [u8; 4], dst_port: u16, payload: &[u8]) {
+        let mut datagram = Vec::with_capacity(8 + payload.len());
+        datagram.extend_from_slice(&src_port.to_be_bytes());
+        datagram.extend_from_slice(&dst_port.to_be_bytes());
+        datagram.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        datagram.extend_from_slice(&[0, 0]);
+        datagram.extend_from_slice(payload);
+        let sum = match transport_checksum(src, dst, IP_PROTOCOL_UDP, &datagram) {
+            // zero means "no checksum" for UDP, so a computed zero is sent as all ones
+            0 => 0xffff,
+            sum => sum,
+        };
+        datagram[6..8].copy_from_slice(&sum.to_be_bytes());
+        self.send_ipv4(src, dst, IP_PROTOCOL_UDP, &datagram);
+    }
+
+    fn dhcp(&mut self, message: &[u8]) {
+        // a BOOTREQUEST from an Ethernet client, with the DHCP magic cookie
+        if message.len() < 240 || message[0] != 1 || message[1] != 1 || message[2] != 6
+        || message[236..240] != [99, 130, 83, 99] {
+            return;
+        }
+        let reply_type = match dhcp_option(&message[240..], 53) {
+            Some([1]) => 2, // DISCOVER -> OFFER
+            Some([3]) => 5, // REQUEST -> ACK
+            _ => return,
+        };
+        let mut reply = vec![0u8; 240];
+        reply[0..4].copy_from_slice(&[2, 1, 6, 0]);
+        reply[4..8].copy_from_slice(&message[4..8]); // transaction ID
+        reply[10..12].copy_from_slice(&message[10..12]); // flags
+        reply[16..20].copy_from_slice(&GUEST_ADDR);
+        reply[20..24].copy_from_slice(&GATEWAY_ADDR);
+        reply[28..44].copy_from_slice(&message[28..44]); // client hardware address
+        reply[236..240].copy_from_slice(&[99, 130, 83, 99]);
+        reply.extend_from_slice(&[53, 1, reply_type]);
+        reply.extend_from_slice(&[54, 4]);
+        reply.extend_from_slice(&GATEWAY_ADDR);
+        reply.extend_from_slice(&[51, 4]);
+        reply.extend_from_slice(&LEASE_SECS.to_be_bytes());
+        reply.extend_from_slice(&[1, 4]);
+        reply.extend_from_slice(&NETMASK);
+        reply.extend_from_slice(&[3, 4]);
+        reply.extend_from_slice(&GATEWAY_ADDR);
+        reply.extend_from_slice(&[6, 4]);
+        reply.extend_from_slice(&DNS_ADDR);
+        reply.push(255);
+        // BOOTP messages are at least 300 bytes long
+        reply.resize(reply.len().max(300), 0);
+        self.send_udp(GATEWAY_ADDR, DHCP_SERVER_PORT, BROADCAST_ADDR, DHCP_CLIENT_PORT, &reply);
+    }
+
+    fn tcp(&mut self, src: [u8; 4], dst: [u8; 4], segment: &[u8]) {
+        if segment.len() < 20 {
+            return;
+        }
+        let src_port = u16::from_be_bytes([segment[0], segment[1]]);
+        let dst_port = u16::from_be_bytes([segment[2], segment[3]]);
+        let seq = u32::from_be_bytes([segment[4], segment[5], segment[6], segment[7]]);
+        let ack = u32::from_be_bytes([segment[8], segment[9], segment[10], segment[11]]);
+        let data_offset = (segment[12] >> 4) as usize * 4;
+        let flags = segment[13];
+        let window = u16::from_be_bytes([segment[14], segment[15]]) as u32;
+        if data_offset < 20 || data_offset > segment.len() {
+            return;
+        }
+        let payload = &segment[data_offset..];
+        let key = (src, src_port, dst, dst_port);
+
+        if flags & TCP_RST != 0 {
+            self.tcp.remove(&key);
+            return;
+        }
+        if flags & TCP_SYN != 0 && flags & TCP_ACK == 0 {
+            // a new connection, which replaces any stale one on the same ports
+            let isn = 0x1000_0000u32 ^ ((src_port as u32) << 16 | dst_port as u32);
+            let peer = TcpPeer {
+                snd_nxt: isn.wrapping_add(1),
+                snd_una: isn,
+                rcv_nxt: seq.wrapping_add(1),
+                window,
+                pending: VecDeque::new(),
+                fin_received: false,
+                fin_sent: false,
+            };
+            let rcv_nxt = peer.rcv_nxt;
+            self.tcp.insert(key, peer);
+            self.send_tcp(key, isn, rcv_nxt, TCP_SYN | TCP_ACK, TCP_WINDOW as u16, &[]);
+            return;
+        }
+        let peer = match self.tcp.get_mut(&key) {
+            Some(peer) => peer,
+            None => {
+                // nobody is listening on this connection
+                if flags & TCP_ACK != 0 {
+                    self.send_tcp(key, ack, 0, TCP_RST, 0, &[]);
+                } else {
+                    let seg_len = payload.len() as u32 + (flags & TCP_FIN != 0) as u32;
+                    self.send_tcp(key, 0, seq.wrapping_add(seg_len), TCP_RST | TCP_ACK, 0, &[]);
+                }
+                return;
+            }
+        };
+
+        if flags & TCP_ACK != 0 {
+            if seq_before(peer.snd_una, ack) && !seq_before(peer.snd_nxt, ack) {
+                peer.snd_una = ack;
+            }
+            peer.window = window;
+        }
+        let mut must_ack = false;
+        if !payload.is_empty() || flags & TCP_FIN != 0 {
+            must_ack = true;
+            // only in-order data is taken; anything else is dropped, and the stack will send it again
+            if seq == peer.rcv_nxt && !peer.fin_received {
+                let room = TCP_WINDOW - peer.pending.len();
+                let accepted = payload.len().min(room);
+                peer.pending.extend(payload[..accepted].iter());
+                peer.rcv_nxt = peer.rcv_nxt.wrapping_add(accepted as u32);
+                if flags & TCP_FIN != 0 && accepted == payload.len() {
+                    peer.rcv_nxt = peer.rcv_nxt.wrapping_add(1);
+                    peer.fin_received = true;
+                }
+            }
+        }
+        if peer.fin_sent && peer.fin_received && peer.snd_una == peer.snd_nxt {
+            // our FIN was acknowledged, so the connection is done
+            let rcv_nxt = peer.rcv_nxt;
+            self.tcp.remove(&key);
+            if must_ack {
+                self.send_tcp(key, ack, rcv_nxt, TCP_ACK, 0, &[]);
+            }
+            return;
+        }
+
+        // echo as much as the stack's receive window allows
+        let mut segments = Vec::new();
+        loop {
+            let in_flight = peer.snd_nxt.wrapping_sub(peer.snd_una);
+            let allowed = (peer.window.saturating_sub(in_flight) as usize).min(TCP_MSS).min(peer.pending.len());
+            if allowed == 0 {
+                break;
+            }
+            let data: Vec<u8> = peer.pending.drain(..allowed).collect();
+            segments.push((peer.snd_nxt, TCP_ACK | TCP_PSH, data));
+            peer.snd_nxt = peer.snd_nxt.wrapping_add(allowed as u32);
+        }
+        if peer.fin_received && !peer.fin_sent && peer.pending.is_empty() {
+            segments.push((peer.snd_nxt, TCP_ACK | TCP_FIN, Vec::new()));
+            peer.snd_nxt = peer.snd_nxt.wrapping_add(1);
+            peer.fin_sent = true;
+        }
+        if segments.is_empty() && must_ack {
+            segments.push((peer.snd_nxt, TCP_ACK, Vec::new()));
+        }
+        let rcv_nxt = peer.rcv_nxt;
+        let window = (TCP_WINDOW - peer.pending.len()) as u16;
+        for (seq, flags, data) in segments {
+            self.send_tcp(key, seq, rcv_nxt, flags, window, &data);
+        }
+    }
+
+    fn send_tcp(&mut self, key: TcpKey, seq: u32, ack: u32, flags: u8, window: u16, payload: &[u8]) {
+        let (guest, guest_port, peer, peer_port) = key;
+        let mut segment = Vec::with_capacity(20 + payload.len());
+        segment.extend_from_slice(&peer_port.to_be_bytes());
+        segment.extend_from_slice(&guest_port.to_be_bytes());
+        segment.extend_from_slice(&seq.to_be_bytes());
+        segment.extend_from_slice(&ack.to_be_bytes());
+        segment.extend_from_slice(&[5 << 4, flags]);
+        segment.extend_from_slice(&window.to_be_bytes());
+        segment.extend_from_slice(&[0, 0, 0, 0]);
+        segment.extend_from_slice(payload);
+        let sum = transport_checksum(peer, guest, IP_PROTOCOL_TCP, &segment);
+        segment[16..18].copy_from_slice(&sum.to_be_bytes());
+        self.send_ipv4(peer, guest, IP_PROTOCOL_TCP, &segment);
+    }
+}
+
+/// Connects `NetPhy` to a `VirtualNet`, in place of the COM link to the WLAN chip
+pub(crate) struct VirtualLink {
+    net: RefCell<VirtualNet>,
+}
+impl VirtualLink {
+    pub fn new() -> VirtualLink {
+        log::info!("using the virtual network, with this device at {:?}", std::net::Ipv4Addr::from(GUEST_ADDR));
+        VirtualLink { net: RefCell::new(VirtualNet::new()) }
+    }
+    /// Returns the length of the next frame waiting to be fetched
+    pub fn pending_rx(&self) -> Option<u16> {
+        self.net.borrow().pending().map(|len| len as u16)
+    }
+    pub fn wlan_fetch_packet(&self, pkt: &mut [u8]) -> Result<(), xous::Error> {
+        match self.net.borrow_mut().recv() {
+            Some(frame) if frame.len() == pkt.len() => {
+                pkt.copy_from_slice(&frame);
+                Ok(())
+            }
+            _ => Err(xous::Error::InvalidLength),
+        }
+    }
+    pub fn wlan_send_packet(&self, pkt: &[u8]) -> Result<(), xous::Error> {
+        self.net.borrow_mut().send(pkt);
+        Ok(())
+    }
+}
+
+fn is_unicast(addr: [u8; 4]) -> bool {
+    addr != BROADCAST_ADDR && addr[0] < 224 && addr[3] != 255
+}
+
+/// Returns the value of DHCP option `code`
+fn dhcp_option(mut options: &[u8], code: u8) -> Option<&[u8]> {
+    loop {
+        match *options.first()? {
+            0 => options = &options[1..],
+            255 => return None,
+            c => {
+                let len = *options.get(1)? as usize;
+                let value = options.get(2..2 + len)?;
+                if c == code {
+                    return Some(value);
+                }
+                options = &options[2 + len..];
+            }
+        }
+    }
+}
+
+/// Answers a DNS query: every A record is the gateway, and every other record doesn't exist
+fn dns_reply(query: &[u8]) -> Option<Vec<u8>> {
+    // a standard query, with exactly one question
+    if query.len() < 12 || query[2] & 0xf8 != 0 || query[4..6] != [0, 1] {
+        return None;
+    }
+    let mut index = 12;
+    loop {
+        let label_len = *query.get(index)? as usize;
+        if label_len == 0 {
+            break;
+        }
+        if label_len & 0xc0 != 0 {
+            return None;
+        }
+        index += 1 + label_len;
+    }
+    let question = query.get(12..index + 5)?;
+    let qtype = u16::from_be_bytes([question[question.len() - 4], question[question.len() - 3]]);
+    let answers = (qtype == 1) as u8;
+
+    let mut reply = Vec::with_capacity(12 + question.len() + 16);
+    reply.extend_from_slice(&query[0..2]);
+    // a response, recursion desired and available, no error
+    reply.extend_from_slice(&[0x81, 0x80, 0, 1, 0, answers, 0, 0, 0, 0]);
+    reply.extend_from_slice(question);
+    if answers != 0 {
+        // a pointer to the name in the question
+        reply.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1]);
+        reply.extend_from_slice(&DNS_TTL_SECS.to_be_bytes());
+        reply.extend_from_slice(&[0, 4]);
+        reply.extend_from_slice(&GATEWAY_ADDR);
+    }
+    Some(reply)
+}
+
+/// The internet checksum of RFC 1071, starting from `initial`
+fn checksum(data: &[u8], initial: u32) -> u16 {
+    let mut sum = initial;
+    for chunk in data.chunks(2) {
+        let word = if chunk.len() == 2 {
+            u16::from_be_bytes([chunk[0], chunk[1]])
+        } else {
+            u16::from_be_bytes([chunk[0], 0])
+        };
+        sum += word as u32;
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// The checksum of a TCP or UDP packet, which covers a pseudo-header with the IP addresses
+fn transport_checksum(src: [u8; 4], dst: [u8; 4], protocol: u8, packet: &[u8]) -> u16 {
+    let pseudo = u16::from_be_bytes([src[0], src[1]]) as u32
+        + u16::from_be_bytes([src[2], src[3]]) as u32
+        + u16::from_be_bytes([dst[0], dst[1]]) as u32
+        + u16::from_be_bytes([dst[2], dst[3]]) as u32
+        + protocol as u32
+        + packet.len() as u32;
+    checksum(packet, pseudo)
+}
+
+/// Compares sequence numbers modulo 2^32, as in RFC 793
+fn seq_before(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUEST_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 1];
+    const GUEST_PORT: u16 = 49153;
+
+    fn ethernet(dst: [u8; 6], ethertype: u16, payload: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&dst);
+        frame.extend_from_slice(&GUEST_MAC);
+        frame.extend_from_slice(&ethertype.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn ipv4(src: [u8; 4], dst: [u8; 4], protocol: u8, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x45, 0];
+        packet.extend_from_slice(&((20 + payload.len()) as u16).to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 0, 64, protocol, 0, 0]);
+        packet.extend_from_slice(&src);
+        packet.extend_from_slice(&dst);
+        let sum = checksum(&packet, 0);
+        packet[10..12].copy_from_slice(&sum.to_be_bytes());
+        packet.extend_from_slice(payload);
+        let dst_mac = if dst == BROADCAST_ADDR { BROADCAST_MAC } else { PEER_MAC };
+        ethernet(dst_mac, ETHERTYPE_IPV4, &packet)
+    }
+
+    fn udp(src: [u8; 4], dst: [u8; 4], src_port: u16, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+        let mut datagram = Vec::new();
+        datagram.extend_from_slice(&src_port.to_be_bytes());
+        datagram.extend_from_slice(&dst_port.to_be_bytes());
+        datagram.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        datagram.extend_from_slice(&[0, 0]);
+        datagram.extend_from_slice(payload);
+        ipv4(src, dst, IP_PROTOCOL_UDP, &datagram)
+    }
+
+    fn tcp(seq: u32, ack: u32, flags: u8, window: u16, payload: &[u8]) -> Vec<u8> {
+        let mut segment = Vec::new();
+        segment.extend_from_slice(&GUEST_PORT.to_be_bytes());
+        segment.extend_from_slice(&7u16.to_be_bytes());
+        segment.extend_from_slice(&seq.to_be_bytes());
+        segment.extend_from_slice(&ack.to_be_bytes());
+        segment.extend_from_slice(&[5 << 4, flags]);
+        segment.extend_from_slice(&window.to_be_bytes());
+        segment.extend_from_slice(&[0, 0, 0, 0]);
+        segment.extend_from_slice(payload);
+        ipv4(GUEST_ADDR, GATEWAY_ADDR, IP_PROTOCOL_TCP, &segment)
+    }
+
+    /// Checks the headers of a frame for the stack, and returns its IP payload
+    fn ip_payload(frame: &[u8], protocol: u8) -> Vec<u8> {
+        assert_eq!(&frame[6..12], &PEER_MAC);
+        assert_eq!(&frame[12..14], &ETHERTYPE_IPV4.to_be_bytes());
+        let ip = &frame[14..];
+        assert_eq!(checksum(&ip[..20], 0), 0, "IP header checksum");
+        assert_eq!(ip[9], protocol);
+        let mut src = [0u8; 4];
+        src.copy_from_slice(&ip[12..16]);
+        let mut dst = [0u8; 4];
+        dst.copy_from_slice(&ip[16..20]);
+        let payload = &ip[20..u16::from_be_bytes([ip[2], ip[3]]) as usize];
+        if protocol != IP_PROTOCOL_ICMP {
+            assert_eq!(transport_checksum(src, dst, protocol, payload), 0, "transport checksum");
+        }
+        payload.to_vec()
+    }
+
+    /// Returns (seq, ack, flags, payload) of a TCP segment for the stack
+    fn tcp_fields(frame: &[u8]) -> (u32, u32, u8, Vec<u8>) {
+        let segment = ip_payload(frame, IP_PROTOCOL_TCP);
+        assert_eq!(&segment[0..4], &[0, 7, 0xc0, 0x01]);
+        let seq = u32::from_be_bytes([segment[4], segment[5], segment[6], segment[7]]);
+        let ack = u32::from_be_bytes([segment[8], segment[9], segment[10], segment[11]]);
+        (seq, ack, segment[13], segment[20..].to_vec())
+    }
+
+    #[test]
+    fn arp() {
+        let mut net = VirtualNet::new();
+        let mut request = vec![0, 1, 8, 0, 6, 4, 0, 1];
+        request.extend_from_slice(&GUEST_MAC);
+        request.extend_from_slice(&GUEST_ADDR);
+        request.extend_from_slice(&[0; 6]);
+        request.extend_from_slice(&GATEWAY_ADDR);
+        net.send(&ethernet(BROADCAST_MAC, ETHERTYPE_ARP, &request));
+        let reply = net.recv().unwrap();
+        assert_eq!(&reply[0..6], &GUEST_MAC);
+        assert_eq!(&reply[14..22], &[0, 1, 8, 0, 6, 4, 0, 2]);
+        assert_eq!(&reply[22..28], &PEER_MAC);
+        assert_eq!(&reply[28..32], &GATEWAY_ADDR);
+        assert_eq!(&reply[38..42], &GUEST_ADDR);
+
+        // nobody else has our address
+        request[24..28].copy_from_slice(&GUEST_ADDR);
+        net.send(&ethernet(BROADCAST_MAC, ETHERTYPE_ARP, &request));
+        assert!(net.recv().is_none());
+    }
+
+    #[test]
+    fn dhcp() {
+        let mut net = VirtualNet::new();
+        let mut discover = vec![0u8; 240];
+        discover[0..3].copy_from_slice(&[1, 1, 6]);
+        discover[4..8].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        discover[28..34].copy_from_slice(&GUEST_MAC);
+        discover[236..240].copy_from_slice(&[99, 130, 83, 99]);
+        discover.extend_from_slice(&[53, 1, 1, 255]);
+        net.send(&udp([0; 4], BROADCAST_ADDR, DHCP_CLIENT_PORT, DHCP_SERVER_PORT, &discover));
+        let offer = net.recv().unwrap();
+        assert_eq!(&offer[0..6], &BROADCAST_MAC);
+        let offer = ip_payload(&offer, IP_PROTOCOL_UDP);
+        assert_eq!(&offer[0..4], &[0, 67, 0, 68]);
+        let message = &offer[8..];
+        assert!(message.len() >= 300);
+        assert_eq!(&message[4..8], &[0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(&message[16..20], &GUEST_ADDR);
+        assert_eq!(&message[28..34], &GUEST_MAC);
+        assert_eq!(dhcp_option(&message[240..], 53), Some(&[2u8][..]));
+        assert_eq!(dhcp_option(&message[240..], 1), Some(&NETMASK[..]));
+        assert_eq!(dhcp_option(&message[240..], 3), Some(&GATEWAY_ADDR[..]));
+        assert_eq!(dhcp_option(&message[240..], 6), Some(&DNS_ADDR[..]));
+
+        let mut request = discover.clone();
+        request[242] = 3;
+        net.send(&udp([0; 4], BROADCAST_ADDR, DHCP_CLIENT_PORT, DHCP_SERVER_PORT, &request));
+        let ack = ip_payload(&net.recv().unwrap(), IP_PROTOCOL_UDP);
+        assert_eq!(dhcp_option(&ack[8 + 240..], 53), Some(&[5u8][..]));
+    }
+
+    #[test]
+    fn dns() {
+        let mut net = VirtualNet::new();
+        let mut query = vec![0x12, 0x34, 0x01, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        query.extend_from_slice(b"\x09betrusted\x02io\x00\x00\x01\x00\x01");
+        net.send(&udp(GUEST_ADDR, DNS_ADDR, GUEST_PORT, DNS_PORT, &query));
+        let reply = ip_payload(&net.recv().unwrap(), IP_PROTOCOL_UDP);
+        assert_eq!(&reply[0..4], &[0, 53, 0xc0, 0x01]);
+        let message = &reply[8..];
+        assert_eq!(&message[0..8], &[0x12, 0x34, 0x81, 0x80, 0, 1, 0, 1]);
+        assert_eq!(&message[12..query.len()], &query[12..]);
+        assert_eq!(&message[message.len() - 4..], &GATEWAY_ADDR);
+
+        // AAAA records don't exist
+        let len = query.len();
+        query[len - 3] = 28;
+        net.send(&udp(GUEST_ADDR, DNS_ADDR, GUEST_PORT, DNS_PORT, &query));
+        let reply = ip_payload(&net.recv().unwrap(), IP_PROTOCOL_UDP);
+        assert_eq!(&reply[8 + 4..8 + 8], &[0, 1, 0, 0]);
+        assert_eq!(reply.len(), 8 + query.len());
+    }
+
+    #[test]
+    fn echo() {
+        let mut net = VirtualNet::new();
+        net.send(&udp(GUEST_ADDR, [192, 0, 2, 1], GUEST_PORT, 7, b"hello"));
+        let reply = net.recv().unwrap();
+        assert_eq!(&reply[14 + 12..14 + 20], &[192, 0, 2, 1, 10, 0, 2, 15]);
+        let reply = ip_payload(&reply, IP_PROTOCOL_UDP);
+        assert_eq!(&reply[0..4], &[0, 7, 0xc0, 0x01]);
+        assert_eq!(&reply[8..], b"hello");
+
+        let mut ping = vec![8, 0, 0, 0, 0x02, 0x2b, 0, 1];
+        ping.extend_from_slice(b"payload");
+        let sum = checksum(&ping, 0);
+        ping[2..4].copy_from_slice(&sum.to_be_bytes());
+        net.send(&ipv4(GUEST_ADDR, GATEWAY_ADDR, IP_PROTOCOL_ICMP, &ping));
+        let pong = ip_payload(&net.recv().unwrap(), IP_PROTOCOL_ICMP);
+        assert_eq!(pong[0], 0);
+        assert_eq!(checksum(&pong, 0), 0);
+        assert_eq!(&pong[4..], &ping[4..]);
+
+        // broadcasts aren't echoed
+        net.send(&udp(GUEST_ADDR, BROADCAST_ADDR, GUEST_PORT, 7, b"hello"));
+        assert!(net.recv().is_none());
+    }
+
+    #[test]
+    fn tcp_echo() {
+        let mut net = VirtualNet::new();
+        let isn = 1000;
+        net.send(&tcp(isn, 0, TCP_SYN, 4096, &[]));
+        let (peer_isn, ack, flags, _) = tcp_fields(&net.recv().unwrap());
+        assert_eq!((ack, flags), (isn + 1, TCP_SYN | TCP_ACK));
+        net.send(&tcp(isn + 1, peer_isn + 1, TCP_ACK, 4096, &[]));
+        assert!(net.recv().is_none());
+
+        // more data than fits in one segment
+        let data: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        net.send(&tcp(isn + 1, peer_isn + 1, TCP_ACK | TCP_PSH, 4096, &data));
+        let mut echoed = Vec::new();
+        while let Some(frame) = net.recv() {
+            let (seq, ack, _, payload) = tcp_fields(&frame);
+            assert_eq!(seq, peer_isn + 1 + echoed.len() as u32);
+            assert_eq!(ack, isn + 1 + 1000);
+            assert!(payload.len() <= TCP_MSS);
+            echoed.extend_from_slice(&payload);
+        }
+        assert_eq!(echoed, data);
+
+        // out of order data is dropped, and the expected sequence number is acknowledged
+        net.send(&tcp(isn + 2001, peer_isn + 1001, TCP_ACK, 4096, b"late"));
+        let (_, ack, flags, payload) = tcp_fields(&net.recv().unwrap());
+        assert_eq!((ack, flags, payload.len()), (isn + 1001, TCP_ACK, 0));
+
+        // the peer closes after the stack does
+        net.send(&tcp(isn + 1001, peer_isn + 1001, TCP_ACK | TCP_FIN, 4096, &[]));
+        let (seq, ack, flags, _) = tcp_fields(&net.recv().unwrap());
+        assert_eq!((seq, ack, flags), (peer_isn + 1001, isn + 1002, TCP_ACK | TCP_FIN));
+        net.send(&tcp(isn + 1002, peer_isn + 1002, TCP_ACK, 4096, &[]));
+        assert!(net.recv().is_none());
+        assert!(net.tcp.is_empty());
+
+        // the connection is gone, so anything more is reset
+        net.send(&tcp(isn + 1002, peer_isn + 1002, TCP_ACK, 4096, b"more"));
+        let (seq, _, flags, _) = tcp_fields(&net.recv().unwrap());
+        assert_eq!((seq, flags), (peer_isn + 1002, TCP_RST));
+    }
+
+    /// Runs a smoltcp interface on `NetPhy` over the virtual network, the way the hosted net server
+    /// does: it leases an address with DHCP, then sends a datagram to be echoed.
+    #[test]
+    fn netphy_end_to_end() {
+        use crate::device::NetPhy;
+        use smoltcp::iface::{InterfaceBuilder, NeighborCache, Routes};
+        use smoltcp::socket::{Dhcpv4Event, Dhcpv4Socket, UdpPacketMetadata, UdpSocket, UdpSocketBuffer};
+        use smoltcp::time::Instant;
+        use smoltcp::wire::{EthernetAddress, IpCidr, IpEndpoint, Ipv4Address};
+        use std::collections::BTreeMap;
+
+        let mut iface = InterfaceBuilder::new(NetPhy::with_link(VirtualLink::new()), vec![])
+            .ip_addrs(vec![IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0)])
+            .routes(Routes::new(BTreeMap::new()))
+            .hardware_addr(EthernetAddress::from_bytes(&GUEST_MAC).into())
+            .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+            .finalize();
+        let dhcp_handle = iface.add_socket(Dhcpv4Socket::new());
+        let mut now = 0;
+
+        let mut config = None;
+        while config.is_none() && now < 10_000 {
+            iface.poll(Instant::from_millis(now)).ok();
+            if let Some(Dhcpv4Event::Configured(lease)) = iface.get_socket::<Dhcpv4Socket>(dhcp_handle).poll() {
+                config = Some(lease);
+            }
+            now += 100;
+        }
+        let config = config.expect("no DHCP lease from the virtual network");
+        assert_eq!(config.address.address(), Ipv4Address::from_bytes(&GUEST_ADDR));
+        assert_eq!(config.router, Some(Ipv4Address::from_bytes(&GATEWAY_ADDR)));
+        assert_eq!(config.dns_servers[0], Some(Ipv4Address::from_bytes(&DNS_ADDR)));
+        iface.update_ip_addrs(|addrs| addrs[0] = IpCidr::Ipv4(config.address));
+        iface.routes_mut().add_default_ipv4_route(config.router.unwrap()).unwrap();
+
+        let udp_handle = iface.add_socket(UdpSocket::new(
+            UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 4], vec![0; 1024]),
+            UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 4], vec![0; 1024]),
+        ));
+        // off the local subnet, so the datagram goes through the router
+        let peer = IpEndpoint::new(Ipv4Address::new(192, 0, 2, 1).into(), 7);
+        let socket = iface.get_socket::<UdpSocket>(udp_handle);
+        socket.bind(GUEST_PORT).unwrap();
+        socket.send_slice(b"hello", peer).unwrap();
+
+        let mut echoed = None;
+        let deadline = now + 10_000;
+        while echoed.is_none() && now < deadline {
+            iface.poll(Instant::from_millis(now)).ok();
+            if let Ok((data, from)) = iface.get_socket::<UdpSocket>(udp_handle).recv() {
+                echoed = Some((data.to_vec(), from));
+            }
+            now += 100;
+        }
+        assert_eq!(echoed, Some((b"hello".to_vec(), peer)));
+
+        let stats = iface.device().stats().borrow().interface();
+        assert!(stats.rx_packets > 0 && stats.tx_packets > 0);
+        assert_eq!(stats.tx_dropped, 0);
+    }
+}
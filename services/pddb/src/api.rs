@@ -105,6 +105,8 @@ pub(crate) enum Opcode {
     GetKeyNameAtIndex,
     DictCountInBasis,
     GetDictNameAtIndex,
+    /// bulk version of the above: key names plus their attributes, a page at a time
+    ListKeyAttributes,
 
     /// primary method for accessing the database
     KeyRequest,
@@ -117,6 +119,16 @@ pub(crate) enum Opcode {
     /// drops any connection state associated with a given key
     KeyDrop,
 
+    /// register/unregister for change notifications on a dictionary
+    DictSubscribe,
+    DictUnsubscribe,
+
+    /// atomically apply a set of staged writes and deletes
+    TxnCommit,
+
+    /// consistency check (and optional repair) of the unlocked bases
+    Fsck,
+
     /// Menu opcodes
     MenuListBasis,
 
@@ -125,6 +137,8 @@ pub(crate) enum Opcode {
 
     /// Suspend/resume callback
     SuspendResume,
+    /// Ticktimer-driven check of the `TimeOutSecs` retention policies
+    BasisTimeoutPoll,
     /// quit the server
     Quit,
     /// Write debug dump (only available in hosted mode)
@@ -164,14 +178,15 @@ pub enum PddbRequestCode {
 pub enum BasisRetentionPolicy {
     Persist,
     ClearAfterSleeps(u32),
-    //TimeOutSecs(u32),
+    /// Locks the basis once this many seconds have elapsed without a key access
+    TimeOutSecs(u32),
 }
 impl BasisRetentionPolicy {
     pub fn derive_init_state(&self) -> u32 {
         match self {
             BasisRetentionPolicy::Persist => 0,
             BasisRetentionPolicy::ClearAfterSleeps(sleeps) => *sleeps,
-            //BasisRetentionPolicy::TimeOutSecs(secs) => *secs,
+            BasisRetentionPolicy::TimeOutSecs(secs) => *secs,
         }
     }
 }
@@ -202,11 +217,57 @@ pub struct PddbKeyRequest {
     pub token: Option<ApiToken>,
     pub create_dict: bool,
     pub create_key: bool,
+    /// only meaningful for deletions: destroy the data on disk right away, instead of on the next sync
+    pub paranoid: bool,
     pub alloc_hint: Option<u64>, // this is a usize but for IPC we must have defined memory sizes, so we pick the big option.
     pub cb_sid: Option<[u32; 4]>,
     pub result: PddbRequestCode,
 }
 
+/// A request to be notified of changes to a dictionary
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct PddbSubscribeRequest {
+    pub basis_specified: bool,
+    pub basis: xous_ipc::String::<BASIS_NAME_LEN>,
+    pub dict: xous_ipc::String::<DICT_NAME_LEN>,
+    pub cb_sid: [u32; 4],
+    pub token: Option<ApiToken>,
+    pub code: PddbRequestCode,
+}
+/// The type of change that triggered a dictionary change notification
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub enum DictChangeKind {
+    KeyCreated,
+    /// sent once a key handle that was written to is flushed or dropped, not on every write
+    KeyUpdated,
+    KeyDeleted,
+    DictDeleted,
+    /// a basis was unlocked, which may change the keys visible in the dictionary
+    BasisMounted,
+    /// a basis was locked or deleted, which may change the keys visible in the dictionary
+    BasisUnmounted,
+}
+/// Notification sent from the PDDB server to a subscriber's callback server
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct PddbDictChange {
+    pub token: ApiToken,
+    pub kind: DictChangeKind,
+    pub dict: xous_ipc::String::<DICT_NAME_LEN>,
+    /// empty if the change is not specific to a key
+    pub key: xous_ipc::String::<KEY_NAME_LEN>,
+    /// empty if the basis could not be determined
+    pub basis: xous_ipc::String::<BASIS_NAME_LEN>,
+}
+/// A change to a subscribed dictionary, as delivered to the subscriber's callback
+#[derive(Clone, Debug)]
+pub struct DictChange {
+    pub kind: DictChangeKind,
+    pub dict: String,
+    pub key: Option<String>,
+    pub basis: Option<String>,
+}
+
 /// Return codes for Read/Write API calls to the main server
 #[repr(u8)]
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
@@ -218,7 +279,20 @@ pub(crate) enum PddbRetcode {
     UnexpectedEof = 4,
     InternalError = 5,
     DiskFull = 6,
+    /// a transaction is committed, but will only be fully applied the next time its basis is mounted
+    TxnPending = 7,
 }
+/// Layout of the buffer for a `TxnCommit` request. It's a raw byte layout instead of rkyv, because the
+/// staged data can be large and we don't want to incur extra copies of it:
+///   - `u32` return code (`PddbRetcode`), filled in by the server
+///   - `u32` length of the encoded operations
+///   - `[u8; BASIS_NAME_LEN]` basis name, zero-padded
+///   - the operations, encoded as described in `txn.rs`
+pub(crate) const TXN_HEADER_LEN: usize = 8 + BASIS_NAME_LEN;
+/// Upper bound on the encoded size of a transaction. The whole transaction is held in RAM by the
+/// server while it is committed, so this can't be too large.
+pub const TXN_MAX_LEN: usize = 256 * 1024;
+
 /// PddbBuf is a C-representation of a page of memory that's used
 /// to shuttle data for streaming channels. It must be exactly one
 /// page in size, with some overhead specific to the PDDB book-keeping
@@ -329,6 +403,78 @@ impl PddbKeyAttrIpc {
     }
 }
 
+/// number of key records returned per `ListKeyAttributes` call. Sized so that a `PddbKeyAttrList` fits in one page.
+pub(crate) const KEY_ATTR_LIST_PAGE: usize = 16;
+#[derive(Copy, Clone, Default)]
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+/// one record of a `PddbKeyAttrList`; the dict is implicit from the request
+pub struct PddbKeyAttrEntry {
+    pub name: xous_ipc::String::<KEY_NAME_LEN>,
+    pub basis: xous_ipc::String::<BASIS_NAME_LEN>,
+    pub len: u64,
+    pub reserved: u64,
+    pub age: u64,
+    pub flags: u32,
+    /// descriptor index; 0 indicates the key disappeared between the list snapshot and the attribute lookup
+    pub index: u32,
+}
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+/// A page of key names with attributes. The first request (`index` = 0) snapshots the key list on
+/// the server side; the client then requests pages with increasing `index` until `total` is reached.
+pub struct PddbKeyAttrList {
+    pub basis_specified: bool,
+    pub basis: xous_ipc::String::<BASIS_NAME_LEN>,
+    pub dict: xous_ipc::String::<DICT_NAME_LEN>,
+    /// index of the first entry in this page
+    pub index: u32,
+    /// total number of keys in the snapshot
+    pub total: u32,
+    /// number of valid records in `entries`
+    pub count: u32,
+    pub entries: [PddbKeyAttrEntry; KEY_ATTR_LIST_PAGE],
+    pub token: [u32; 4],
+    pub code: PddbRequestCode,
+}
+
+/// Results of a consistency check. Counts are summed across all the bases that were checked.
+#[derive(Copy, Clone, Default, Debug, Eq, PartialEq)]
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct FsckReport {
+    /// number of bases checked
+    pub bases: u32,
+    /// number of virtual pages mapped by the checked bases
+    pub pages: u32,
+    /// page table entries that decrypt under a basis key, but were superseded by another entry for the same virtual page
+    pub stale_ptes: u32,
+    /// mapped pages that no basis root, dictionary or key refers to
+    pub orphaned: u32,
+    /// pages that a dictionary or key refers to, but which have no mapping. Only data pages are repairable.
+    pub missing: u32,
+    /// virtual pages that share a physical page with another virtual page
+    pub cross_linked: u32,
+    /// FSCB entries that claim a page is free when it's holding data for a checked basis
+    pub fscb_conflicts: u32,
+    /// FSCB entries that point outside of the data region
+    pub fscb_out_of_range: u32,
+    /// number of the above problems that were repaired
+    pub repaired: u32,
+}
+impl FsckReport {
+    /// total number of problems found
+    pub fn problems(&self) -> u32 {
+        self.stale_ptes + self.orphaned + self.missing + self.cross_linked + self.fscb_conflicts + self.fscb_out_of_range
+    }
+}
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct PddbFsckRequest {
+    /// if not specified, every unlocked basis is checked
+    pub basis_specified: bool,
+    pub basis: xous_ipc::String::<BASIS_NAME_LEN>,
+    pub repair: bool,
+    pub report: FsckReport,
+    pub code: PddbRequestCode,
+}
+
 /// Debugging commands, available only in hosted mode
 #[cfg(not(any(target_os = "none", target_os = "xous")))]
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
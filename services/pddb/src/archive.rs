@@ -0,0 +1,373 @@
+//! Portable, encrypted archive format for backing up and restoring the contents of a Basis.
+//!
+//! An archive consists of a small plaintext header, followed by a sequence of encrypted chunks.
+//!
+//! The header is `ARCHIVE_HEADER_LEN` bytes long, and laid out as follows (all integers little-endian):
+//!   - `[u8; 8]` magic number, `PDDBARC\0`
+//!   - `u32` archive format version
+//!   - `u32` bcrypt cost used to derive the archive key
+//!   - `[u8; 16]` salt for bcrypt & HKDF
+//!   - `[u8; 7]` nonce prefix
+//!   - `[u8; 9]` reserved, must be zero
+//!
+//! The archive key is derived by running the password through bcrypt with the header's salt and cost,
+//! and then expanding the first 23 bytes of the result with HKDF-SHA256 (salt = header salt,
+//! info = `pddb archive key`). Only 23 bytes of bcrypt output are used because that is all that the
+//! standard bcrypt encoding carries, which keeps the format compatible with off-the-shelf bcrypt
+//! implementations in host tooling.
+//!
+//! Each chunk is a `u32` length, whose MSB flags the final chunk, followed by that many bytes of
+//! AES-256-GCM-SIV ciphertext + tag. A chunk carries at most `ARCHIVE_CHUNK_LEN` bytes of plaintext.
+//! The nonce for a chunk is the nonce prefix || chunk counter (`u32`, big-endian) || final flag (`u8`),
+//! which makes re-ordering, truncation or splicing of chunks detectable (the "STREAM" construction).
+//! The entire header is used as the AAD for every chunk, so it is authenticated as well.
+//!
+//! The plaintext is a stream of records, each starting with a one-byte tag:
+//!   - `ARCHIVE_REC_DICT`: `u8` name length, name; subsequent keys belong to this dictionary
+//!   - `ARCHIVE_REC_KEY`: `u8` name length, name, `u64` reserved, `u32` age, `u64` data length, data
+//!   - `ARCHIVE_REC_END`: end of archive; must be the last record in the final chunk
+//!
+//! Key flags are not archived. They only track the state of a key in the cache (`valid`, `unresolved`),
+//! and are set up afresh when a key is written back on import.
+
+use aes_gcm_siv::{AesGcmSiv, Key, Nonce};
+use aes_gcm_siv::aead::{Aead, NewAead, Payload};
+use aes::Aes256;
+use std::convert::TryInto;
+use std::io::{Read, Write, Result, Error, ErrorKind};
+
+use crate::bcrypt::*;
+
+pub const ARCHIVE_MAGIC: [u8; 8] = *b"PDDBARC\0";
+pub const ARCHIVE_VERSION: u32 = 1;
+pub const ARCHIVE_HEADER_LEN: usize = 48;
+/// maximum amount of plaintext carried by a single chunk
+pub const ARCHIVE_CHUNK_LEN: usize = 64 * 1024;
+/// bcrypt cost used when creating archives. Archives are expected to leave the device, so this is
+/// set a bit higher than the cost used to unlock a Basis on the device.
+pub const ARCHIVE_BCRYPT_COST: u32 = 10;
+pub const ARCHIVE_SALT_LEN: usize = 16;
+pub const ARCHIVE_NONCE_PREFIX_LEN: usize = 7;
+
+const ARCHIVE_REC_END: u8 = 0x00;
+const ARCHIVE_REC_DICT: u8 = 0x01;
+const ARCHIVE_REC_KEY: u8 = 0x02;
+const ARCHIVE_FINAL_FLAG: u32 = 0x8000_0000;
+const ARCHIVE_TAG_LEN: usize = 16;
+
+/// A record recovered from an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveRecord {
+    /// All keys that follow, up to the next `Dict` record, belong to this dictionary.
+    Dict(String),
+    /// A key header. The key's data must be retrieved with `ArchiveReader::read_key_data()` before
+    /// calling `next_record()` again; any data not read is skipped.
+    Key {
+        name: String,
+        reserved: usize,
+        age: u32,
+        len: usize,
+    },
+    /// The end of the archive.
+    End,
+}
+
+fn archive_header(cost: u32, salt: &[u8; ARCHIVE_SALT_LEN], nonce_prefix: &[u8; ARCHIVE_NONCE_PREFIX_LEN]) -> [u8; ARCHIVE_HEADER_LEN] {
+    let mut header = [0u8; ARCHIVE_HEADER_LEN];
+    header[..8].copy_from_slice(&ARCHIVE_MAGIC);
+    header[8..12].copy_from_slice(&ARCHIVE_VERSION.to_le_bytes());
+    header[12..16].copy_from_slice(&cost.to_le_bytes());
+    header[16..32].copy_from_slice(salt);
+    header[32..39].copy_from_slice(nonce_prefix);
+    header
+}
+
+fn archive_cipher(cost: u32, salt: &[u8; ARCHIVE_SALT_LEN], password: &str) -> AesGcmSiv::<Aes256> {
+    let mut hashed_password: [u8; 24] = [0; 24];
+    bcrypt(cost, salt, password, &mut hashed_password);
+    let hk = hkdf::Hkdf::<sha2::Sha256>::new(Some(salt), &hashed_password[..23]);
+    let mut okm = [0u8; 32];
+    hk.expand(b"pddb archive key", &mut okm).expect("invalid length specified for HKDF");
+    let cipher = AesGcmSiv::<Aes256>::new(Key::from_slice(&okm));
+
+    let hp_ptr = hashed_password.as_mut_ptr();
+    for i in 0..hashed_password.len() {
+        unsafe{hp_ptr.add(i).write_volatile(core::mem::zeroed());}
+    }
+    let okm_ptr = okm.as_mut_ptr();
+    for i in 0..okm.len() {
+        unsafe{okm_ptr.add(i).write_volatile(core::mem::zeroed());}
+    }
+    core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+    cipher
+}
+
+fn archive_nonce(nonce_prefix: &[u8; ARCHIVE_NONCE_PREFIX_LEN], counter: u32, last: bool) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[..7].copy_from_slice(nonce_prefix);
+    nonce[7..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = if last { 1 } else { 0 };
+    nonce
+}
+
+/// Serializes dictionaries and keys into an encrypted archive.
+pub struct ArchiveWriter<W: Write> {
+    out: W,
+    cipher: AesGcmSiv::<Aes256>,
+    header: [u8; ARCHIVE_HEADER_LEN],
+    nonce_prefix: [u8; ARCHIVE_NONCE_PREFIX_LEN],
+    counter: u32,
+    /// plaintext staged for the next chunk
+    buf: Vec::<u8>,
+    /// data bytes still expected for the current key
+    key_remaining: usize,
+}
+impl<W: Write> ArchiveWriter<W> {
+    /// Starts a new archive. `salt` and `nonce_prefix` must come from a good source of randomness, and
+    /// must not be re-used across archives.
+    pub fn new(mut out: W, password: &str, salt: [u8; ARCHIVE_SALT_LEN], nonce_prefix: [u8; ARCHIVE_NONCE_PREFIX_LEN]) -> Result<Self> {
+        let header = archive_header(ARCHIVE_BCRYPT_COST, &salt, &nonce_prefix);
+        out.write_all(&header)?;
+        Ok(ArchiveWriter {
+            out,
+            cipher: archive_cipher(ARCHIVE_BCRYPT_COST, &salt, password),
+            header,
+            nonce_prefix,
+            counter: 0,
+            buf: Vec::with_capacity(ARCHIVE_CHUNK_LEN + 1),
+            key_remaining: 0,
+        })
+    }
+    fn emit_chunk(&mut self, len: usize, last: bool) -> Result<()> {
+        let nonce = archive_nonce(&self.nonce_prefix, self.counter, last);
+        let ciphertext = self.cipher.encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                aad: &self.header,
+                msg: &self.buf[..len],
+            }
+        ).or(Err(Error::new(ErrorKind::Other, "archive chunk encryption failed")))?;
+        let mut ct_len = ciphertext.len() as u32;
+        if last {
+            ct_len |= ARCHIVE_FINAL_FLAG;
+        }
+        self.out.write_all(&ct_len.to_le_bytes())?;
+        self.out.write_all(&ciphertext)?;
+        self.buf.drain(..len);
+        self.counter = self.counter.checked_add(1)
+            .ok_or(Error::new(ErrorKind::InvalidInput, "archive is too large"))?;
+        Ok(())
+    }
+    fn push(&mut self, data: &[u8]) -> Result<()> {
+        self.buf.extend_from_slice(data);
+        // always hold back at least one byte, so that there is something to put in the final chunk
+        while self.buf.len() > ARCHIVE_CHUNK_LEN {
+            self.emit_chunk(ARCHIVE_CHUNK_LEN, false)?;
+        }
+        Ok(())
+    }
+    fn push_name(&mut self, name: &str, max_len: usize) -> Result<()> {
+        if name.len() == 0 || name.len() > max_len - 1 {
+            return Err(Error::new(ErrorKind::InvalidInput, "name is empty or too long"));
+        }
+        self.push(&[name.len() as u8])?;
+        self.push(name.as_bytes())
+    }
+    /// Starts a new dictionary. Keys added after this call belong to it.
+    pub fn dict(&mut self, name: &str) -> Result<()> {
+        if self.key_remaining != 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "previous key's data was not completely written"));
+        }
+        self.push(&[ARCHIVE_REC_DICT])?;
+        self.push_name(name, crate::api::DICT_NAME_LEN)
+    }
+    /// Starts a new key in the current dictionary. Exactly `len` bytes of data must follow via `key_data()`.
+    pub fn key(&mut self, name: &str, reserved: usize, age: u32, len: usize) -> Result<()> {
+        if self.key_remaining != 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "previous key's data was not completely written"));
+        }
+        self.push(&[ARCHIVE_REC_KEY])?;
+        self.push_name(name, crate::api::KEY_NAME_LEN)?;
+        self.push(&(reserved as u64).to_le_bytes())?;
+        self.push(&age.to_le_bytes())?;
+        self.push(&(len as u64).to_le_bytes())?;
+        self.key_remaining = len;
+        Ok(())
+    }
+    /// Appends data to the current key. May be called several times to stream a large key in.
+    pub fn key_data(&mut self, data: &[u8]) -> Result<()> {
+        if data.len() > self.key_remaining {
+            return Err(Error::new(ErrorKind::InvalidInput, "key data exceeds the declared key length"));
+        }
+        self.key_remaining -= data.len();
+        self.push(data)
+    }
+    /// Closes out the archive and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        if self.key_remaining != 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "last key's data was not completely written"));
+        }
+        self.push(&[ARCHIVE_REC_END])?;
+        let len = self.buf.len();
+        self.emit_chunk(len, true)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Recovers dictionaries and keys from an encrypted archive.
+pub struct ArchiveReader<R: Read> {
+    input: R,
+    cipher: AesGcmSiv::<Aes256>,
+    header: [u8; ARCHIVE_HEADER_LEN],
+    nonce_prefix: [u8; ARCHIVE_NONCE_PREFIX_LEN],
+    counter: u32,
+    /// plaintext of the current chunk
+    plaintext: Vec::<u8>,
+    pos: usize,
+    last_seen: bool,
+    end_seen: bool,
+    /// data bytes not yet consumed from the current key
+    key_remaining: usize,
+}
+impl<R: Read> ArchiveReader<R> {
+    /// Opens an archive. The first chunk is decrypted immediately, so a wrong password is reported here.
+    pub fn new(mut input: R, password: &str) -> Result<Self> {
+        let mut header = [0u8; ARCHIVE_HEADER_LEN];
+        input.read_exact(&mut header)?;
+        if header[..8] != ARCHIVE_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a PDDB archive"));
+        }
+        let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if version != ARCHIVE_VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, "unsupported PDDB archive version"));
+        }
+        let cost = u32::from_le_bytes(header[12..16].try_into().unwrap());
+        if cost < 4 || cost > 31 {
+            return Err(Error::new(ErrorKind::InvalidData, "archive bcrypt cost is out of range"));
+        }
+        let salt: [u8; ARCHIVE_SALT_LEN] = header[16..32].try_into().unwrap();
+        let nonce_prefix: [u8; ARCHIVE_NONCE_PREFIX_LEN] = header[32..39].try_into().unwrap();
+        let mut reader = ArchiveReader {
+            input,
+            cipher: archive_cipher(cost, &salt, password),
+            header,
+            nonce_prefix,
+            counter: 0,
+            plaintext: Vec::new(),
+            pos: 0,
+            last_seen: false,
+            end_seen: false,
+            key_remaining: 0,
+        };
+        reader.next_chunk()?;
+        Ok(reader)
+    }
+    fn next_chunk(&mut self) -> Result<()> {
+        if self.last_seen {
+            return Err(Error::new(ErrorKind::InvalidData, "archive records run past the final chunk"));
+        }
+        let mut len_bytes = [0u8; 4];
+        self.input.read_exact(&mut len_bytes).or(Err(Error::new(ErrorKind::UnexpectedEof, "archive is truncated")))?;
+        let raw_len = u32::from_le_bytes(len_bytes);
+        let last = raw_len & ARCHIVE_FINAL_FLAG != 0;
+        let ct_len = (raw_len & !ARCHIVE_FINAL_FLAG) as usize;
+        if ct_len < ARCHIVE_TAG_LEN || ct_len > ARCHIVE_CHUNK_LEN + ARCHIVE_TAG_LEN {
+            return Err(Error::new(ErrorKind::InvalidData, "archive chunk has an invalid length"));
+        }
+        let mut ciphertext = vec![0u8; ct_len];
+        self.input.read_exact(&mut ciphertext).or(Err(Error::new(ErrorKind::UnexpectedEof, "archive is truncated")))?;
+        let nonce = archive_nonce(&self.nonce_prefix, self.counter, last);
+        self.plaintext = self.cipher.decrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                aad: &self.header,
+                msg: &ciphertext,
+            }
+        ).or(Err(Error::new(ErrorKind::InvalidData, "archive authentication failed (wrong password, or corrupted archive)")))?;
+        self.pos = 0;
+        self.last_seen = last;
+        self.counter = self.counter.checked_add(1)
+            .ok_or(Error::new(ErrorKind::InvalidData, "archive has too many chunks"))?;
+        Ok(())
+    }
+    /// Fills `data` entirely from the plaintext stream, pulling in chunks as needed.
+    fn pull(&mut self, data: &mut [u8]) -> Result<()> {
+        let mut copied = 0;
+        while copied < data.len() {
+            if self.pos == self.plaintext.len() {
+                self.next_chunk()?;
+            }
+            let avail = core::cmp::min(self.plaintext.len() - self.pos, data.len() - copied);
+            data[copied..copied + avail].copy_from_slice(&self.plaintext[self.pos..self.pos + avail]);
+            self.pos += avail;
+            copied += avail;
+        }
+        Ok(())
+    }
+    fn pull_u8(&mut self) -> Result<u8> {
+        let mut b = [0u8; 1];
+        self.pull(&mut b)?;
+        Ok(b[0])
+    }
+    fn pull_name(&mut self, max_len: usize) -> Result<String> {
+        let len = self.pull_u8()? as usize;
+        if len == 0 || len > max_len - 1 {
+            return Err(Error::new(ErrorKind::InvalidData, "archive contains an invalid name length"));
+        }
+        let mut name = vec![0u8; len];
+        self.pull(&mut name)?;
+        String::from_utf8(name).or(Err(Error::new(ErrorKind::InvalidData, "archive contains a name that is not valid utf-8")))
+    }
+    /// Returns the next record in the archive. Any unread data of the previous key is skipped.
+    pub fn next_record(&mut self) -> Result<ArchiveRecord> {
+        if self.end_seen {
+            return Ok(ArchiveRecord::End);
+        }
+        let mut discard = [0u8; 1024];
+        while self.key_remaining > 0 {
+            let len = core::cmp::min(discard.len(), self.key_remaining);
+            self.pull(&mut discard[..len])?;
+            self.key_remaining -= len;
+        }
+        match self.pull_u8()? {
+            ARCHIVE_REC_DICT => Ok(ArchiveRecord::Dict(self.pull_name(crate::api::DICT_NAME_LEN)?)),
+            ARCHIVE_REC_KEY => {
+                let name = self.pull_name(crate::api::KEY_NAME_LEN)?;
+                let mut reserved = [0u8; 8];
+                self.pull(&mut reserved)?;
+                let mut age = [0u8; 4];
+                self.pull(&mut age)?;
+                let mut len = [0u8; 8];
+                self.pull(&mut len)?;
+                let reserved = u64::from_le_bytes(reserved) as usize;
+                let len = u64::from_le_bytes(len) as usize;
+                self.key_remaining = len;
+                Ok(ArchiveRecord::Key {
+                    name,
+                    reserved,
+                    age: u32::from_le_bytes(age),
+                    len,
+                })
+            }
+            ARCHIVE_REC_END => {
+                // the end record has to be the very last thing in the archive, otherwise a truncated
+                // chunk stream could be passed off as a complete archive.
+                if !self.last_seen || self.pos != self.plaintext.len() {
+                    return Err(Error::new(ErrorKind::InvalidData, "archive end record is misplaced"));
+                }
+                self.end_seen = true;
+                Ok(ArchiveRecord::End)
+            }
+            _ => Err(Error::new(ErrorKind::InvalidData, "archive contains an unknown record type")),
+        }
+    }
+    /// Reads data belonging to the most recent key record into `data`. Returns the number of bytes read,
+    /// which is 0 once all of the key's data has been consumed.
+    pub fn read_key_data(&mut self, data: &mut [u8]) -> Result<usize> {
+        let len = core::cmp::min(data.len(), self.key_remaining);
+        self.pull(&mut data[..len])?;
+        self.key_remaining -= len;
+        Ok(len)
+    }
+}
@@ -10,8 +10,10 @@ mod fastspace;
 pub use fastspace::*;
 mod types;
 pub use types::*;
-mod bcrypt;
-pub use bcrypt::*;
+mod txnlog;
+pub use txnlog::*;
+mod fsck;
+pub use fsck::*;
 
 // local to the backend
 mod murmur3;
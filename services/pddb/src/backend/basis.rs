@@ -185,6 +185,10 @@ pub(crate) const KEY_MAXCOUNT: usize = 131_071; // 2^17 - 1
 /// because the usize type isn't big enough. Recompiling for a 64-bit target, however, should give
 /// you access to the 32GiB file size limit.
 pub(crate) const LARGE_FILE_MAX_SIZE: u64 = 0x0000_0008_0000_0000;
+/// Maximum number of decrypted vpages held in the page cache of a large key. Only one large key per
+/// dictionary holds a cache at any given time, so this bounds the RAM consumed by streaming access
+/// to large keys to about 16kiB per dictionary, regardless of the size of the key.
+pub(crate) const LARGE_CACHE_PAGES: usize = 4;
 
 /// The chosen "stride" of a dict/key entry. Drives a lot of key parameters in the database's characteristics.
 /// This is chosen such that 32 of these entries fit evenly into a VPAGE.
@@ -314,6 +318,7 @@ impl BasisCache {
                 small_pool: Vec::<KeySmallPool>::new(),
                 small_pool_free: BinaryHeap::<KeySmallPoolOrd>::new(),
                 aad: my_aad,
+                large_cache_owner: None,
             };
             log::debug!("adding dictionary {}", name);
             basis.dicts.insert(String::from(name), dict_cache);
@@ -412,6 +417,7 @@ impl BasisCache {
     ) -> Result<usize> {
         if let Some(basis_index) = self.select_basis(basis_name) {
             let basis = &mut self.cache[basis_index];
+            basis.last_access = hw.timestamp_now();
             if !basis.ensure_dict_in_cache(hw, dict) {
                 return Err(Error::new(ErrorKind::NotFound, "dictionary not found"));
             }
@@ -439,74 +445,8 @@ impl BasisCache {
                             panic!("Key allocated to small area but its cache data was not of the small type");
                         }
                     } else {
-                        if offset.unwrap_or(0) as u64 > kcache.len {
-                            return Err(Error::new(ErrorKind::UnexpectedEof, "offest requested is beyond the key length"));
-                        }
-                        if data.len() == 0 { // mostly because i don't want to have to think about this case in the later logic.
-                            return Ok(0)
-                        }
-                        // large pool fetch
-                        let mut cur_offset = offset.unwrap_or(0) as u64;
-                        let mut blocks_read = 0;
-                        loop {
-                            let start_vpage_addr = ((kcache.start + cur_offset) / VPAGE_SIZE as u64) * VPAGE_SIZE as u64;
-                            // handle the case of terminating the last block read nicely:
-                            // we can either end because we hit the end of the key data (so the data readback buffer is too large)
-                            let key_endstop = if (kcache.len - cur_offset) < VPAGE_SIZE as u64 {
-                                (kcache.len - cur_offset) as usize + size_of::<JournalType>()
-                            } else {
-                                VPAGE_SIZE + size_of::<JournalType>()
-                            };
-                            // or we can hit the end because we ran out of datareadback buffer
-                            let data_endstop = if (data.len() as u64 - cur_offset) < VPAGE_SIZE as u64 {
-                                (data.len() as u64 - cur_offset) as usize + size_of::<JournalType>()
-                            } else {
-                                VPAGE_SIZE + size_of::<JournalType>()
-                            };
-                            // of the two possible end criteria, pick the nearest one
-                            let endstop = if key_endstop < data_endstop {
-                                key_endstop
-                            } else {
-                                data_endstop
-                            };
-                            log::debug!("reading offset {}->{}/{}:{}", key, cur_offset, endstop, kcache.len);
-                            let mut read_offset = (cur_offset % VPAGE_SIZE as u64) as usize;
-                            if let Some(pp) = basis.v2p_map.get(&VirtAddr::new(start_vpage_addr).unwrap()) {
-                                assert!(pp.valid(), "v2p returned an invalid page");
-                                let pt_data = hw.data_decrypt_page(&basis.cipher, &basis.aad, pp).expect("Decryption auth error");
-                                if blocks_read != 0 {
-                                    assert!(read_offset == 0, "algorithm error in handling offset data");
-                                }
-                                for (&src, dst) in
-                                pt_data[
-                                    size_of::<JournalType>() // always this fixed offset per block
-                                    + read_offset // this should be 0 after the first block
-                                    ..endstop
-                                ].iter().zip(data[cur_offset as usize..].iter_mut()) {
-                                    *dst = src;
-                                    cur_offset += 1;
-                                    read_offset += 1;
-                                }
-                                blocks_read += 1;
-                            } else {
-                                log::warn!("Not enough bytes available to read for key {}:{} ({}/{})", dict, key, cur_offset, data.len());
-                                return Ok(cur_offset as usize)
-                            }
-                            // cur_offset won't hit (start + len) if we're hitting an endstop, so the loop won't exit.
-                            // if we hit an endstop, we should exit the read loop.
-                            if endstop != VPAGE_SIZE + size_of::<JournalType>() {
-                                break;
-                            }
-                            // break if we've hit or exceeded our endstop
-                            if read_offset >= endstop {
-                                break;
-                            }
-                            // also exit if we're "just nice", e.g. the size of the data happened to be exactly a multiple of the length of our block size
-                            if cur_offset == kcache.len {
-                                break;
-                            }
-                        }
-                        return Ok(cur_offset as usize)
+                        // large pool fetch, via the dictionary's bounded page cache
+                        return dict_entry.large_key_read(hw, &basis.v2p_map, &basis.cipher, key, data, offset.unwrap_or(0));
                     }
 
                 } else {
@@ -520,11 +460,18 @@ impl BasisCache {
         }
     }
 
+    /// Removes a key from a dictionary. In `paranoid` mode, the key's data and descriptor are destroyed on disk
+    /// before the call returns, instead of lingering until the next time the small pool is synced.
     pub(crate) fn key_remove(&mut self,
         hw: &mut PddbOs, dict: &str, key: &str, basis_name: Option<&str>, paranoid: bool
     ) -> Result<()> {
+        // a paranoid erase re-packs the surviving small keys into a fresh page before the old one is destroyed
+        if paranoid && !hw.ensure_fast_space_alloc(2, &self.cache) {
+            return Err(Error::new(ErrorKind::OutOfMemory, "No free space to re-pack small pool"));
+        }
         if let Some(basis_index) = self.select_basis(basis_name) {
             let basis = &mut self.cache[basis_index];
+            basis.last_access = hw.timestamp_now();
             if !basis.ensure_dict_in_cache(hw, dict) {
                 return Err(Error::new(ErrorKind::NotFound, "dictionary not found"));
             }
@@ -533,17 +480,28 @@ impl BasisCache {
                 basis.clean = false;
                 if dict_entry.ensure_key_entry(hw, &mut basis.v2p_map, &basis.cipher, key) {
                     if !paranoid {
-                        dict_entry.key_remove(hw, &mut basis.v2p_map, &basis.cipher, key, false);
+                        dict_entry.key_remove(hw, &mut basis.v2p_map, &basis.cipher, key);
                     } else {
-                        // this implementation is still in progress
-                        dict_entry.key_erase(key);
-
+                        let stale = dict_entry.key_erase(hw, &mut basis.v2p_map, &basis.cipher, key);
+                        if stale.is_some() {
+                            // re-pack the surviving keys of the small pool block into a fresh page
+                            if !dict_entry.sync_small_pool(hw, &mut basis.v2p_map, &basis.cipher) {
+                                // put the old block back, so the surviving keys are still reachable on disk
+                                let (pool_vaddr, pp) = stale.unwrap();
+                                basis.v2p_map.insert(pool_vaddr, pp);
+                                return Err(Error::new(ErrorKind::OutOfMemory, "Ran out of memory syncing small pool"));
+                            }
+                        }
                         // encrypt and write the dict entry to disk
                         basis.dict_sync(hw, dict)?;
                         // sync the root basis structure as well, while we're at it...
                         basis.basis_sync(hw);
                         // finally, sync the page tables.
                         basis.pt_sync(hw);
+                        // the re-packed data is now committed, so it's safe to destroy the old block
+                        if let Some((_, mut pp)) = stale {
+                            hw.page_erase_and_free(&mut pp);
+                        }
                     }
                     return Ok(())
                 } else {
@@ -649,16 +607,19 @@ impl BasisCache {
         if let Some(basis_index) = self.select_basis(basis_name) {
             let dict_found = (&mut self.cache[basis_index]).ensure_dict_in_cache(hw, dict);
             if !dict_found { // now that we're clear of the deep search, mutate the basis if we are sure it's not there
-                self.dict_add(hw, dict, basis_name).expect("couldn't add dictionary");
+                self.dict_add(hw, dict, basis_name)?;
             }
             // at this point, the dictionary should definitely be in cache
             // pre-flight & allocatefree space requirements
             if let Some(dict_entry) = self.cache[basis_index].dicts.get(dict) {
-                hw.ensure_fast_space_alloc(dict_entry.alloc_estimate_small(), &self.cache);
-                // large pools don't have caching implemented, so we don't have to check for free space for them
+                if !hw.ensure_fast_space_alloc(dict_entry.alloc_estimate_small(), &self.cache) {
+                    return Err(Error::new(ErrorKind::OutOfMemory, "No free space to sync dict"));
+                }
+                // large pool pages are allocated when the key is reserved, so the page cache needs no extra free space
             }
             // refetch the basis here to avoid the re-borrow problem, now that all the potential dict cache mutations are done
             let basis = &mut self.cache[basis_index];
+            basis.last_access = hw.timestamp_now();
 
             // bumping this every key update affects performance *a lot* -- don't think this is worth it.
             // the bases should only "age" when dicts or keys are modified, not when any data in it is updated for any reason.
@@ -679,9 +640,8 @@ impl BasisCache {
                 if !dict_entry.sync_small_pool(hw, &mut basis.v2p_map, &basis.cipher) {
                     return Err(Error::new(ErrorKind::OutOfMemory, "Ran out of memory syncing small pool"));
                 }
-                // we don't have large pool caches yet, but this is a placeholder to remember to do "something" at this point,
-                // once we do have them.
-                dict_entry.sync_large_pool();
+                // write back any dirty pages left in the large key page cache
+                dict_entry.sync_large_pool(hw, &basis.v2p_map, &basis.cipher);
 
                 // encrypt and write the dict entry to disk
                 basis.dict_sync(hw, dict)?;
@@ -699,6 +659,8 @@ impl BasisCache {
         }
     }
 
+    /// Looks up the attributes of a key. This doesn't count as an access for the `TimeOutSecs` retention
+    /// policy, as the server also calls it internally, e.g. to check on keys that have been handed out.
     pub(crate) fn key_attributes(&mut self, hw: &mut PddbOs, dict: &str, key: &str, basis_name: Option<&str>) -> Result<KeyAttributes> {
         if basis_name.is_none() {
             for basis in self.cache.iter_mut().rev() {
@@ -939,7 +901,9 @@ impl BasisCache {
         Ok(())
     }
 
-    pub(crate) fn suspend(&mut self, hw: &mut PddbOs) {
+    /// Syncs everything and applies the sleep-related retention policies. Returns the names of the
+    /// bases that were locked as a result.
+    pub(crate) fn suspend(&mut self, hw: &mut PddbOs) -> Vec<String> {
         self.sync(hw, None).expect("couldn't sync on suspend");
         let mut lock_list = Vec::<String>::new();
         for basis in self.cache.iter_mut() {
@@ -951,18 +915,40 @@ impl BasisCache {
                         lock_list.push(basis.name.clone());
                     }
                 }
-                /*
-                BasisRetentionPolicy::TimeOutSecs(secs) => {
-                    if secs < basis.policy_state {
+                BasisRetentionPolicy::TimeOutSecs(_) => {
+                    // catch any basis whose timeout expired before the poller had a chance to run
+                    if basis.idle_expired(hw.timestamp_now()) {
                         lock_list.push(basis.name.clone());
                     }
-                }*/
+                }
             }
         }
-        for basis in lock_list {
-            log::info!("unmounting basis on sleep: {}", &basis);
-            self.basis_unmount(hw, &basis).ok();
+        for basis in lock_list.iter() {
+            log::info!("unmounting basis on sleep: {}", basis);
+            self.basis_unmount(hw, basis).ok();
+        }
+        lock_list
+    }
+
+    /// Returns the names of any basis whose `TimeOutSecs` retention policy has expired. The caller
+    /// is responsible for locking them, so that the usual disconnect notifications can go out first.
+    pub(crate) fn basis_timed_out(&self, hw: &PddbOs) -> Vec<String> {
+        let now = hw.timestamp_now();
+        let mut expired = Vec::<String>::new();
+        for basis in self.cache.iter() {
+            if basis.idle_expired(now) {
+                expired.push(basis.name.clone());
+            }
         }
+        expired
+    }
+    /// True if any mounted basis is subject to a `TimeOutSecs` retention policy.
+    pub(crate) fn has_timeout_policy(&self) -> bool {
+        self.cache.iter().any(|b| matches!(b.policy, BasisRetentionPolicy::TimeOutSecs(_)))
+    }
+    /// Checks the consistency of `basis_name`, or of every unlocked basis if `None`. See `fsck.rs`.
+    pub(crate) fn fsck(&mut self, hw: &mut PddbOs, basis_name: Option<&str>, repair: bool) -> Result<FsckReport> {
+        fsck_bases(hw, &mut self.cache, basis_name, repair)
     }
 }
 
@@ -1002,6 +988,8 @@ pub(crate) struct BasisCacheEntry {
     pub policy: BasisRetentionPolicy,
     // rention state
     pub policy_state: u32,
+    /// time of the last key access, in ms. Drives the `TimeOutSecs` retention policy.
+    pub last_access: u64,
 }
 impl BasisCacheEntry {
     /// given a pointer to the hardware, name of the basis, and its cryptographic key, try to derive
@@ -1054,6 +1042,7 @@ impl BasisCacheEntry {
                     large_alloc_ptr: None,
                     policy,
                     policy_state: policy.derive_init_state(),
+                    last_access: hw.timestamp_now(),
                 };
                 if !lazy {
                     bcache.populate_caches(hw);
@@ -1070,6 +1059,15 @@ impl BasisCacheEntry {
             None
         }
     }
+    /// Checks if the basis has sat idle for longer than its `TimeOutSecs` retention policy allows.
+    /// Always false for other policies.
+    pub(crate) fn idle_expired(&self, now: u64) -> bool {
+        if let BasisRetentionPolicy::TimeOutSecs(secs) = self.policy {
+            now.saturating_sub(self.last_access) >= secs as u64 * 1000
+        } else {
+            false
+        }
+    }
     /// called during the initial basis scan to track where the large allocation pointer end should be.
     /// basically try to find the maximal extent of already allocated data, and start allocating from there.
     pub(crate) fn large_pool_update(&mut self, maybe_end: u64) {
@@ -1148,12 +1146,11 @@ impl BasisCacheEntry {
         }
     }
 
-    /// If `paranoid` is true, it recurses through each key and replaces its data with random junk.
-    /// Otherwise, it does a "shallow" delete and just removes the directory entry, which is much
-    /// more performant. Note that the intended "fast" way to secure-erase data is to store sensitive
-    /// data in its own Basis, and then remove the Basis itself. This is much faster than picking
-    /// through compounded data and re-writing partias sectors, and because of this, initially,
-    /// the `paranoid` erase is `unimplemented`.
+    /// Deleting a dictionary always overwrites its key descriptors, small pool blocks and large key data
+    /// with random junk, because the dictionary slot and its pages get re-used. If `paranoid` is true, the
+    /// plaintext copies of the small keys in the cache are also zeroed before the cache entry is dropped.
+    /// Note that the intended "fast" way to secure-erase data is to store sensitive data in its own Basis,
+    /// and then remove the Basis itself.
     pub(crate) fn dict_delete(&mut self, hw: &mut PddbOs, name: &str, paranoid: bool) -> Result<()> {
         if self.ensure_dict_in_cache(hw, name) {
             let dcache = self.dicts.get_mut(name).expect("entry was ensured, but somehow missing");
@@ -1170,8 +1167,16 @@ impl BasisCacheEntry {
             }
             for key in key_list {
                 log::debug!("removing {}:{}", name, key);
-                // this will wipe any large pools if paranoid is set
-                dcache.key_remove(hw, &mut self.v2p_map, &self.cipher, &key, paranoid);
+                if paranoid {
+                    // the on-disk copy of the small pool is wiped wholesale below, but don't leave plaintext lying around in RAM either
+                    if let Some(KeyCacheData::Small(cache_data)) = dcache.keys.get_mut(&key).and_then(|k| k.data.as_mut()) {
+                        for b in cache_data.data.iter_mut() {
+                            *b = 0;
+                        }
+                    }
+                }
+                // this will wipe any large pools
+                dcache.key_remove(hw, &mut self.v2p_map, &self.cipher, &key);
             }
             // wipe & de-allocate any small pages
             for index in 0..dcache.small_pool.len() {
@@ -59,6 +59,9 @@ pub(crate) struct DictCacheEntry {
     pub(crate) small_pool_free: BinaryHeap<KeySmallPoolOrd>,
     /// copy of our AAD, for convenience
     pub(crate) aad: Vec::<u8>,
+    /// name of the large key that currently holds a page cache, if any. Only one large key per dictionary
+    /// is allowed to hold cached pages, to keep the memory footprint bounded.
+    pub(crate) large_cache_owner: Option<String>,
 }
 impl DictCacheEntry {
     pub fn new(dict: Dictionary, index: usize, aad: &Vec<u8>) -> DictCacheEntry {
@@ -80,6 +83,7 @@ impl DictCacheEntry {
             small_pool: Vec::<KeySmallPool>::new(),
             small_pool_free: BinaryHeap::<KeySmallPoolOrd>::new(),
             aad: my_aad,
+            large_cache_owner: None,
         }
     }
     /// Populates cache entries, reporting the maximum extent of large alloc data seen so far.
@@ -379,7 +383,7 @@ impl DictCacheEntry {
                     for (&src, dst) in data.iter().zip(update_data[offset..].iter_mut()) { *dst = src };
                     log::debug!("update/extend: removing {}", name);
                     // now remove the old key entirely
-                    self.key_remove(hw, v2p_map, cipher, name, false);
+                    self.key_remove(hw, v2p_map, cipher, name);
                     if update_data.len() > 4 { // just make sure that this log call doesn't fail on an index violation...
                         log::debug!("update/extend: re-adding {} with data len {}: {:x?}...", name, update_data.len(), &update_data[..4]);
                     }
@@ -441,111 +445,61 @@ impl DictCacheEntry {
                 // note: there is no need to update small_pool_free because the reserved size did not change.
             } else {
                 // it's a large key
-                if let Some(_kcd) = &kcache.data {
-                    unimplemented!("caching is not yet implemented for large data sets");
+                kcache.age = kcache.age.saturating_add(1);
+                kcache.clean = false;
+                let start = kcache.start;
+                // make sure we are the key that holds the dictionary's page cache
+                if self.large_cache_owner.as_deref() != Some(name) {
+                    self.large_cache_release(hw, v2p_map, cipher);
+                    self.large_cache_owner = Some(name.to_string());
+                }
+                let kcache = self.keys.get_mut(name).expect("Entry was assured, but then not there!");
+                if kcache.data.is_none() {
+                    kcache.data = Some(KeyCacheData::Large(KeyLargeData::new()));
+                }
+                let cache = if let Some(KeyCacheData::Large(cache)) = kcache.data.as_mut() {
+                    cache
                 } else {
-                    kcache.age = kcache.age.saturating_add(1);
-                    kcache.clean = false;
-                    /* // this was for debugging a patching bug -- OK to remove
-                    if data.len() == 4 {
-                        use std::convert::TryInto;
-                        log::info!("patching checksum: {:x} at offset {}", u32::from_le_bytes(data.try_into().unwrap()), offset);
-                    }*/
-                    // 1. handle unaligned start offsets
-                    let mut written: usize = 0;
-                    if ((kcache.start + offset as u64 + written as u64) % VPAGE_SIZE as u64) != 0 {
-                        let start_vpage_addr = ((kcache.start + offset as u64) / VPAGE_SIZE as u64) * VPAGE_SIZE as u64;
-                        let pp = v2p_map.get(&VirtAddr::new(start_vpage_addr).unwrap()).expect("large key data allocation missing");
-                        assert!(pp.valid(), "v2p returned an invalid page");
-                        let mut pt_data = match hw.data_decrypt_page(&cipher, &self.aad, pp) {
-                            Some(data) => data,
-                            None => {
-                                // this case is triggered by the following circumstance:
-                                //  - we reserved data that includes this current page
-                                //  - up until now, we've only written data into the previous page (so this page is not initialized -- it's garbage)
-                                //  - we just issued an update that causes the data to touch this page for the first time
-                                // in response to this, we allocate a fresh page of 0's.
-                                log::debug!("Reserved and uninitialized page encountered updating large block: {} {:x}..{}->{}; update @{}..{}",
-                                    name, kcache.start, kcache.len, kcache.reserved, offset, data.len());
-                                let mut d = vec![0u8; VPAGE_SIZE + size_of::<JournalType>()];
-                                for (&src, dst) in (hw.trng_u32() % JOURNAL_RAND_RANGE).to_le_bytes().iter().zip(d[..size_of::<JournalType>()].iter_mut()) {
-                                    *dst = src;
-                                }
-                                d
-                            }
-                        };
-                        if offset > 0 {
-                            log::trace!("patching offset {}, total length {}, data length {}", offset % VPAGE_SIZE, kcache.len, data.len());
-                        }
-                        for (&src, dst) in data[written..].iter().zip(pt_data[size_of::<JournalType>() + (offset % VPAGE_SIZE)..].iter_mut()) {
-                            *dst = src;
-                            written += 1;
-                        }
-                        if written < data.len() {
-                            assert!((kcache.start + offset as u64 + written as u64) % VPAGE_SIZE as u64 == 0, "alignment algorithm failed");
-                        }
-                        hw.data_encrypt_and_patch_page(cipher, &self.aad, &mut pt_data, &pp);
-                    }
-                    // 2. do the rest
-                    while written < data.len() {
-                        let vpage_addr = ((kcache.start + written as u64 + offset as u64) / VPAGE_SIZE as u64) * VPAGE_SIZE as u64;
-                        let pp = v2p_map.get(&VirtAddr::new(vpage_addr).unwrap()).expect("large key data allocation missing");
-                        assert!(pp.valid(), "v2p returned an invalid page");
-                        if data.len() - written >= VPAGE_SIZE {
-                            // overwrite whole pages without decryption
-                            let mut block = [0u8; VPAGE_SIZE + size_of::<JournalType>()];
-                            for (&src, dst) in (hw.trng_u32() % JOURNAL_RAND_RANGE).to_le_bytes().iter().zip(block[..size_of::<JournalType>()].iter_mut()) {
-                                *dst = src;
-                            }
-                            for (&src, dst) in data[written..].iter().zip(block[size_of::<JournalType>()..].iter_mut()) {
-                                *dst = src;
-                                written += 1;
-                            }
-                            hw.data_encrypt_and_patch_page(cipher, &self.aad, &mut block, pp);
-                        } else {
-                            // handle partial trailing pages
-                            if let Some(pt_data) = hw.data_decrypt_page(&cipher, &self.aad, pp).as_mut() {
-                                for (&src, dst) in data[written..].iter().zip(pt_data[size_of::<JournalType>()..].iter_mut()) {
-                                    *dst = src;
-                                    written += 1;
-                                }
-                                hw.data_encrypt_and_patch_page(cipher, &self.aad, pt_data, pp);
-                            } else {
-                                // page didn't exist, initialize it with 0's and merge the tail end.
-                                let mut pt_data = [0u8; VPAGE_SIZE + size_of::<JournalType>()];
-                                for (&src, dst) in (hw.trng_u32() % JOURNAL_RAND_RANGE).to_le_bytes().iter().zip(pt_data[..size_of::<JournalType>()].iter_mut()) {
-                                    *dst = src;
-                                }
-                                for (&src, dst) in data[written..].iter().zip(pt_data[size_of::<JournalType>()..].iter_mut()) {
-                                    *dst = src;
-                                    written += 1;
-                                }
-                                hw.data_encrypt_and_patch_page(cipher, &self.aad, &mut pt_data, pp);
-                            }
-                        }
+                    panic!("Key allocated to large area but its cache data was not of the large type");
+                };
+                // 1. patch the data into the page cache, one vpage at a time. Pages that are entirely overwritten
+                // are not decrypted first; dirty pages are written back on eviction or by `sync_large_pool()`.
+                let mut written: usize = 0;
+                while written < data.len() {
+                    let abs_addr = start + offset as u64 + written as u64;
+                    let vpage_addr = VirtAddr::new((abs_addr / VPAGE_SIZE as u64) * VPAGE_SIZE as u64).unwrap();
+                    let page_offset = (abs_addr % VPAGE_SIZE as u64) as usize;
+                    let overwrite = page_offset == 0 && (data.len() - written) >= VPAGE_SIZE;
+                    let page = large_cache_fetch(hw, v2p_map, cipher, &self.aad, cache, vpage_addr, overwrite);
+                    for (&src, dst) in data[written..].iter().zip(page.data[size_of::<JournalType>() + page_offset..].iter_mut()) {
+                        *dst = src;
+                        written += 1;
                     }
-                    log::trace!("data written: {}, data requested to write: {}", written, data.len());
-                    assert!(written == data.len(), "algorithm problem -- didn't write all the data we thought we would");
-                    // 3. truncate or extend
-                    // check if we grew the length; extend the length by exactly enough if so.
-                    if kcache.len < (data.len() + offset) as u64 {
-                        kcache.len = (data.len() + offset) as u64;
-                    } else if truncate {
-                        // discard all whole pages after written+offset, and reset the reserved field to the smaller size.
-                        let vpage_end_offset = PageAlignedVa::from((written + offset) as u64);
-                        if (vpage_end_offset.as_u64() - kcache.start) > kcache.reserved {
-                            for vpage in (vpage_end_offset.as_u64()..kcache.start + kcache.reserved).step_by(VPAGE_SIZE) {
-                                if let Some(pp) = v2p_map.get_mut(&VirtAddr::new(vpage).unwrap()) {
-                                    assert!(pp.valid(), "v2p returned an invalid page");
-                                    log::trace!("fast_space_free key_update {} before", pp.journal());
-                                    hw.fast_space_free(pp);
-                                    assert!(pp.valid() == false, "pp is still marked as valid!");
-                                }
+                    page.clean = false;
+                    cache.clean = false;
+                }
+                log::trace!("data written: {}, data requested to write: {}", written, data.len());
+                assert!(written == data.len(), "algorithm problem -- didn't write all the data we thought we would");
+                // 2. truncate or extend
+                // check if we grew the length; extend the length by exactly enough if so.
+                if kcache.len < (data.len() + offset) as u64 {
+                    kcache.len = (data.len() + offset) as u64;
+                } else if truncate {
+                    kcache.len = (data.len() + offset) as u64;
+                    // discard all whole pages after written+offset, and reset the reserved field to the smaller size.
+                    let vpage_end = PageAlignedVa::from(start + (written + offset) as u64).as_u64();
+                    if vpage_end < start + kcache.reserved {
+                        // any cached data past the new end is garbage now; drop it without writing it back
+                        cache.pages.retain(|p| p.vaddr.get() < vpage_end);
+                        for vpage in (vpage_end..start + kcache.reserved).step_by(VPAGE_SIZE) {
+                            if let Some(pp) = v2p_map.get_mut(&VirtAddr::new(vpage).unwrap()) {
+                                assert!(pp.valid(), "v2p returned an invalid page");
+                                log::trace!("fast_space_free key_update {} before", pp.journal());
+                                hw.fast_space_free(pp);
+                                assert!(pp.valid() == false, "pp is still marked as valid!");
                             }
-                            kcache.reserved = vpage_end_offset.as_u64() - kcache.start;
-                            kcache.clean = false;
-                            kcache.len = (data.len() + offset) as u64;
                         }
+                        kcache.reserved = vpage_end - start;
                     }
                 }
             }
@@ -648,7 +602,7 @@ impl DictCacheEntry {
                     age: 0,
                     descriptor_index,
                     clean: false,
-                    data: None, // no caching implemented yet for large keys
+                    data: None, // the page cache is created on the first access to the key's data
                 };
                 self.keys.insert(name.to_string(), kcache);
                 self.key_count += 1;
@@ -679,14 +633,12 @@ impl DictCacheEntry {
         }
     }
     /// Used to remove a key from the dictionary. If you call it with a non-existent key,
-    /// the routine has no effect, and does not report an error. Small keys are not immediately
-    /// overwritten in paranoid mode, but large keys are.
+    /// the routine has no effect, and does not report an error. Large keys are always overwritten
+    /// with noise as they are removed, because of the pool-reuse problem; small keys are only
+    /// dropped from their pool, and are overwritten the next time the pool is synced. Use `key_erase()`
+    /// if the small key data has to be destroyed immediately.
     pub fn key_remove(&mut self, hw: &mut PddbOs, v2p_map: &mut HashMap::<VirtAddr, PhysPage>, cipher: &AesGcmSiv::<Aes256>,
-        name_str: &str, paranoid: bool) {
-        if paranoid {
-            // large records are paranoid-erased, by default, because of the pool-reuse problem.
-            unimplemented!("Paranoid erase for small records not yet implemented. Calling sync after an update, however, effectively does a paranoid erase.");
-        }
+        name_str: &str) {
         // this call will check the disk to see if there's key data that's not in cache.
         if self.ensure_key_entry(hw, v2p_map, cipher, name_str) {
             let name = String::from(name_str);
@@ -717,6 +669,11 @@ impl DictCacheEntry {
 
                 } else {
                     // handle the large pool case
+                    // any cached plaintext is discarded without being written back, the pages are about to be erased.
+                    kcache.data = None;
+                    if self.large_cache_owner.as_deref() == Some(name_str) {
+                        self.large_cache_owner = None;
+                    }
                     // mark the entry as invalid and dirty; virtual space is one huge memory leak...
                     // ...but we remove the virtual pages from the page pool, effectively reclaiming the physical space.
                     for vpage in kcache.large_pool_vpages() {
@@ -757,12 +714,56 @@ impl DictCacheEntry {
         }
         // if there's no key....we're done!
     }
-    /// used to remove a key from the dictionary, syncing 0's to the disk in the key's place
-    /// sort of less relevant now that the large keys have a paranoid mode; probably this routine should actually
-    /// be a higher-level function that catches the paranoid request and does an "update" of 0's to the key
-    /// then does a disk sync and then calls remove
-    pub fn key_erase(&mut self, _name: &str) {
-        unimplemented!();
+    /// Paranoid version of `key_remove()`. The key's descriptor is scrubbed from disk right away, and any
+    /// plaintext of the key held in the cache is zeroed out.
+    ///
+    /// Large keys are noise-erased by `key_remove()` already. For small keys, the physical page that backs
+    /// the key's small pool block is detached from the `v2p_map` and returned to the caller, along with its
+    /// virtual address. The caller is expected to re-pack the surviving keys of the block into a fresh page
+    /// with `sync_small_pool()`, commit the page tables, and only then destroy the stale page with
+    /// `PddbOs::page_erase_and_free()`. Doing it in this order means a power loss part way through the
+    /// erase can't take the other keys in the block along with it.
+    pub(crate) fn key_erase(&mut self, hw: &mut PddbOs, v2p_map: &mut HashMap::<VirtAddr, PhysPage>, cipher: &AesGcmSiv::<Aes256>,
+        name_str: &str) -> Option<(VirtAddr, PhysPage)> {
+        if !self.ensure_key_entry(hw, v2p_map, cipher, name_str) {
+            log::debug!("key_erase() key does not exist: {}", name_str);
+            return None;
+        }
+        let (small_index, descriptor_index) = match self.keys.get_mut(name_str) {
+            Some(kcache) if kcache.flags.valid() => {
+                if let Some(KeyCacheData::Small(cache_data)) = kcache.data.as_mut() {
+                    for b in cache_data.data.iter_mut() {
+                        *b = 0;
+                    }
+                }
+                kcache.data = None;
+                (small_storage_index_from_key(kcache, self.index), kcache.descriptor_index.get() as usize)
+            }
+            _ => return None,
+        };
+        self.key_remove(hw, v2p_map, cipher, name_str);
+
+        // scrub the descriptor, so the key's name and location can't be recovered either. Re-encrypting the
+        // page in place overwrites the old ciphertext.
+        let dk_vaddr = VirtAddr::new(dict_indices_to_vaddr(self.index, descriptor_index)).unwrap();
+        if let Some(pp) = v2p_map.get(&dk_vaddr) {
+            assert!(pp.valid(), "v2p returned an invalid page");
+            if let Some(mut page) = hw.data_decrypt_page(cipher, &self.aad, pp) {
+                let start = size_of::<JournalType>() + (descriptor_index % DK_PER_VPAGE) * DK_STRIDE;
+                for b in page[start..start + DK_STRIDE].iter_mut() {
+                    *b = 0;
+                }
+                hw.data_encrypt_and_patch_page(cipher, &self.aad, &mut page, pp);
+            }
+        }
+
+        if let Some(index) = small_index {
+            let pool_vaddr = VirtAddr::new(small_storage_base_vaddr_from_indices(self.index, index)).unwrap();
+            // the pool was marked dirty by key_remove(), so the next sync_small_pool() will allocate it a new page
+            v2p_map.remove(&pool_vaddr).map(|pp| (pool_vaddr, pp))
+        } else {
+            None
+        }
     }
     /// estimates the amount of space needed to sync the dict cache. Pass this to ensure_fast_space_alloc() before calling a sync.
     /// estimate can be inaccurate under pathological allocation conditions.
@@ -857,8 +858,79 @@ impl DictCacheEntry {
         true
     }
 
-    /// No data cache to flush yet...large pool caches not implemented!
-    pub(crate) fn sync_large_pool(&self) {
+    /// Writes back any dirty pages held in the large key page cache. Unlike the small pool, the large pool
+    /// does not relocate data on sync, so this does not dirty any key descriptors; however, a `pt_sync` is
+    /// still required if the write-back caused fresh physical pages to be committed.
+    pub(crate) fn sync_large_pool(&mut self, hw: &mut PddbOs, v2p_map: &HashMap::<VirtAddr, PhysPage>, cipher: &AesGcmSiv::<Aes256>) {
+        if let Some(owner) = self.large_cache_owner.as_ref() {
+            if let Some(kcache) = self.keys.get_mut(owner) {
+                if let Some(KeyCacheData::Large(cache)) = kcache.data.as_mut() {
+                    if !cache.clean {
+                        for page in cache.pages.iter_mut() {
+                            large_cache_writeback(hw, v2p_map, cipher, &self.aad, page);
+                        }
+                        cache.clean = true;
+                    }
+                }
+            }
+        }
+    }
+    /// Flushes and discards the page cache of the current large cache owner, so that another key can take its place.
+    fn large_cache_release(&mut self, hw: &mut PddbOs, v2p_map: &HashMap::<VirtAddr, PhysPage>, cipher: &AesGcmSiv::<Aes256>) {
+        self.sync_large_pool(hw, v2p_map, cipher);
+        if let Some(owner) = self.large_cache_owner.take() {
+            if let Some(kcache) = self.keys.get_mut(&owner) {
+                kcache.data = None;
+            }
+        }
+    }
+    /// Reads data out of a large key, starting at `offset`, through the key's page cache. The number of bytes
+    /// read is returned; it will be less than the length of `data` if the read runs past the end of the key.
+    pub(crate) fn large_key_read(&mut self, hw: &mut PddbOs, v2p_map: &HashMap::<VirtAddr, PhysPage>, cipher: &AesGcmSiv::<Aes256>,
+        name: &str, data: &mut [u8], offset: usize) -> Result<usize> {
+        let (start, len) = if let Some(kcache) = self.keys.get(name) {
+            (kcache.start, kcache.len)
+        } else {
+            return Err(Error::new(ErrorKind::NotFound, "key not found"));
+        };
+        if offset as u64 > len {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "offest requested is beyond the key length"));
+        }
+        let readlen = if (len - offset as u64) < data.len() as u64 {
+            (len - offset as u64) as usize
+        } else {
+            data.len()
+        };
+        if self.large_cache_owner.as_deref() != Some(name) {
+            self.large_cache_release(hw, v2p_map, cipher);
+            self.large_cache_owner = Some(name.to_string());
+        }
+        let kcache = self.keys.get_mut(name).expect("Entry was checked, but then not there!");
+        if kcache.data.is_none() {
+            kcache.data = Some(KeyCacheData::Large(KeyLargeData::new()));
+        }
+        let cache = if let Some(KeyCacheData::Large(cache)) = kcache.data.as_mut() {
+            cache
+        } else {
+            panic!("Key allocated to large area but its cache data was not of the large type");
+        };
+        let mut bytes_read = 0;
+        while bytes_read < readlen {
+            let abs_addr = start + offset as u64 + bytes_read as u64;
+            let vpage_addr = VirtAddr::new((abs_addr / VPAGE_SIZE as u64) * VPAGE_SIZE as u64).unwrap();
+            let page_offset = (abs_addr % VPAGE_SIZE as u64) as usize;
+            if !v2p_map.contains_key(&vpage_addr) {
+                log::warn!("Not enough bytes available to read for key {} ({}/{})", name, offset + bytes_read, len);
+                break;
+            }
+            let page = large_cache_fetch(hw, v2p_map, cipher, &self.aad, cache, vpage_addr, false);
+            for (&src, dst) in page.data[size_of::<JournalType>() + page_offset..].iter().zip(data[bytes_read..readlen].iter_mut()) {
+                *dst = src;
+                bytes_read += 1;
+            }
+        }
+        log::debug!("read {}: {} bytes @ {}/{}", name, bytes_read, offset, len);
+        Ok(bytes_read)
     }
 
     /// Finds the next available slot to store the key metadata (not the data itself). It also
@@ -1201,6 +1273,70 @@ impl PlaintextCache {
     }
 }
 
+/// Returns the cached plaintext of a large key's vpage, pulling it into the page cache if it isn't there
+/// already. If the cache is full, the least recently used page is evicted, and written back to disk if dirty.
+/// `overwrite` indicates that the caller is about to replace the entire contents of the page, in which case
+/// we skip the expensive decryption step.
+pub(crate) fn large_cache_fetch<'a>(hw: &mut PddbOs, v2p_map: &HashMap::<VirtAddr, PhysPage>, cipher: &AesGcmSiv::<Aes256>, aad: &[u8],
+    cache: &'a mut KeyLargeData, vaddr: VirtAddr, overwrite: bool) -> &'a mut KeyLargePage {
+    if let Some(index) = cache.pages.iter().position(|p| p.vaddr == vaddr) {
+        // bump the page to the most recently used position
+        let page = cache.pages.remove(index).unwrap();
+        cache.pages.push_back(page);
+    } else {
+        if cache.pages.len() >= LARGE_CACHE_PAGES {
+            let mut evicted = cache.pages.pop_front().unwrap();
+            large_cache_writeback(hw, v2p_map, cipher, aad, &mut evicted);
+        }
+        let pp = v2p_map.get(&vaddr).expect("large key data allocation missing");
+        assert!(pp.valid(), "v2p returned an invalid page");
+        let existing = if overwrite {
+            None
+        } else {
+            hw.data_decrypt_page(cipher, aad, pp)
+        };
+        let data = match existing {
+            Some(data) => data,
+            None => {
+                // this case is triggered when we are overwriting the whole page, or by the following circumstance:
+                //  - we reserved data that includes this current page
+                //  - up until now, we've only written data into the previous page (so this page is not initialized -- it's garbage)
+                //  - we just issued an access that touches this page for the first time
+                // in response to this, we allocate a fresh page of 0's.
+                if !overwrite {
+                    log::debug!("Reserved and uninitialized page encountered in large block at {:x?}", vaddr);
+                }
+                let mut d = vec![0u8; VPAGE_SIZE + size_of::<JournalType>()];
+                for (&src, dst) in (hw.trng_u32() % JOURNAL_RAND_RANGE).to_le_bytes().iter().zip(d[..size_of::<JournalType>()].iter_mut()) {
+                    *dst = src;
+                }
+                d
+            }
+        };
+        cache.pages.push_back(KeyLargePage {
+            // a freshly initialized page is not on disk yet, but if nobody writes to it, there's no reason to commit it either.
+            clean: true,
+            vaddr,
+            data,
+        });
+    }
+    cache.pages.back_mut().unwrap()
+}
+
+/// Encrypts and commits a cached large key page to disk, if it is dirty.
+pub(crate) fn large_cache_writeback(hw: &mut PddbOs, v2p_map: &HashMap::<VirtAddr, PhysPage>, cipher: &AesGcmSiv::<Aes256>, aad: &[u8],
+    page: &mut KeyLargePage) {
+    if !page.clean {
+        if let Some(pp) = v2p_map.get(&page.vaddr) {
+            assert!(pp.valid(), "v2p returned an invalid page");
+            hw.data_encrypt_and_patch_page(cipher, aad, &mut page.data, pp);
+        } else {
+            log::warn!("Dirty large key page at {:x?} has no backing page; discarding", page.vaddr);
+        }
+        page.clean = true;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,267 @@
+/// Online consistency checking of unlocked bases.
+///
+/// The checker works from three views of the same disk, and reports places where they disagree:
+///
+/// 1. The raw page table: every PTE that decrypts under a basis' page table key.
+/// 2. The v2p map of the basis, which is the page table after journal conflicts are resolved at mount time.
+/// 3. The descriptors: the basis root, the dictionaries, and the keys within them, which tell us which
+///    virtual pages are supposed to be in use.
+///
+/// In addition, the FSCB is cross-checked against every page that is mapped by an unlocked basis. Pages
+/// belonging to locked bases can't be told apart from free space, so the checker can only vouch for the
+/// bases that are currently unlocked; this is the same limitation that the free space sweep has.
+///
+/// Problems are repaired on request, without guessing which copy of the data is correct:
+/// - stale PTEs are erased, and orphaned pages are scrubbed and returned to free space;
+/// - FSCB entries that point at in-use or non-existent pages are withdrawn;
+/// - a page that is mapped more than once is copied, so that every claimant gets its own page with the
+///   shared contents. The last claimant keeps the original page;
+/// - a missing page in the small or large pool is replaced with a zero-filled page, so the keys that refer
+///   to it can be read again. The data that was on the missing page is lost either way.
+///
+/// Missing basis roots and descriptors are only reported, as are stale PTEs, orphans and cross-linked pages
+/// whose pages don't authenticate under the basis key (these are most likely checksum collisions with
+/// another basis' page table entries, see `pt_scan_key()`).
+
+use crate::api::*;
+use super::*;
+
+use std::collections::{HashMap, HashSet};
+use std::io::{Result, Error, ErrorKind};
+use std::mem::size_of;
+
+/// Classifies every virtual address that a basis refers to.
+struct BasisRefs {
+    /// dictionary index -> number of entries in its small pool
+    dicts: HashMap::<u32, usize>,
+    /// (start, end) of the reservation of every key in the large pool
+    large: Vec::<(u64, u64)>,
+    /// pages that must be mapped for the basis to be readable
+    required: HashSet::<VirtAddr>,
+}
+impl BasisRefs {
+    fn new(basis: &BasisCacheEntry) -> Self {
+        let mut refs = BasisRefs {
+            dicts: HashMap::new(),
+            large: Vec::new(),
+            required: HashSet::new(),
+        };
+        refs.required.insert(VirtAddr::new(VPAGE_SIZE as u64).unwrap());
+        for dict in basis.dicts.values() {
+            if !dict.flags.valid() {
+                continue;
+            }
+            let dict_vaddr = dict.index.get() as u64 * DICT_VSIZE;
+            refs.dicts.insert(dict.index.get(), dict.small_pool.len());
+            refs.required.insert(VirtAddr::new(dict_vaddr).unwrap());
+            for key in dict.keys.values() {
+                if !key.flags.valid() {
+                    continue;
+                }
+                refs.required.insert(VirtAddr::new(
+                    dict_vaddr + key.descriptor_vpage_num() as u64 * VPAGE_SIZE as u64
+                ).unwrap());
+                if key.start < SMALL_POOL_END {
+                    if let Some(pool_index) = small_storage_index_from_key(key, dict.index) {
+                        refs.required.insert(VirtAddr::new(small_storage_base_vaddr_from_indices(dict.index, pool_index)).unwrap());
+                    }
+                } else {
+                    refs.large.push((key.start, key.start + key.reserved));
+                    for vbase in (key.start..key.start + key.len).step_by(VPAGE_SIZE) {
+                        refs.required.insert(VirtAddr::new((vbase / VPAGE_SIZE as u64) * VPAGE_SIZE as u64).unwrap());
+                    }
+                }
+            }
+        }
+        refs
+    }
+    /// true if some descriptor in the basis accounts for `vaddr`
+    fn owns(&self, vaddr: VirtAddr) -> bool {
+        let va = vaddr.get();
+        if va == VPAGE_SIZE as u64 {
+            true
+        } else if va >= DICT_VSIZE && va < SMALL_POOL_START {
+            // anywhere in the descriptor region of a live dictionary
+            self.dicts.contains_key(&((va / DICT_VSIZE) as u32))
+        } else if va >= SMALL_POOL_START && va < SMALL_POOL_END {
+            let dict_index = ((va - SMALL_POOL_START) / SMALL_POOL_STRIDE) as u32 + 1;
+            let pool_index = ((va - SMALL_POOL_START) % SMALL_POOL_STRIDE) as usize / SMALL_CAPACITY;
+            self.dicts.get(&dict_index).map_or(false, |&pool_len| pool_index < pool_len)
+        } else if va >= LARGE_POOL_START {
+            self.large.iter().any(|&(start, end)| va >= start && va < end)
+        } else {
+            false
+        }
+    }
+}
+
+/// Checks `basis_name`, or every basis in `cache` if `None`, for consistency, and repairs what it can if
+/// `repair` is set. The FSCB is checked regardless of which basis is selected.
+pub(crate) fn fsck_bases(hw: &mut PddbOs, cache: &mut [BasisCacheEntry], basis_name: Option<&str>, repair: bool) -> Result<FsckReport> {
+    let mut report = FsckReport::default();
+    // bring the disk up to date with the caches, and pull every dictionary and key into the cache
+    for basis in cache.iter_mut() {
+        if basis_name.map_or(true, |name| name == basis.name) {
+            basis.sync(hw)?;
+            basis.populate_caches(hw);
+        }
+    }
+    if let Some(name) = basis_name {
+        if !cache.iter().any(|b| b.name == name) {
+            return Err(Error::new(ErrorKind::NotFound, "Requested basis not found, or PDDB not mounted."));
+        }
+    }
+
+    // every physical page mapped by an unlocked basis, with the number of times it's mapped
+    let mut phys_use = HashMap::<PhysAddr, u32>::new();
+    for basis in cache.iter() {
+        for pp in basis.v2p_map.values() {
+            *phys_use.entry(pp.page_number()).or_insert(0) += 1;
+        }
+    }
+
+    for basis in cache.iter_mut() {
+        if !basis_name.map_or(true, |name| name == basis.name) {
+            continue;
+        }
+        report.bases += 1;
+        report.pages += basis.v2p_map.len() as u32;
+        let refs = BasisRefs::new(basis);
+
+        // descriptors vs v2p map
+        let mut missing = Vec::<VirtAddr>::new();
+        for &vaddr in refs.required.iter() {
+            if !basis.v2p_map.contains_key(&vaddr) {
+                log::warn!("fsck {}: {:x} is referenced but not mapped", basis.name, vaddr.get());
+                report.missing += 1;
+                missing.push(vaddr);
+            }
+        }
+        let mut orphans = Vec::<VirtAddr>::new();
+        let mut cross_links = Vec::<VirtAddr>::new();
+        for (&vaddr, pp) in basis.v2p_map.iter() {
+            if phys_use.get(&pp.page_number()).copied().unwrap_or(0) > 1 {
+                log::warn!("fsck {}: {:x} maps to physical page {:x}, which is also mapped elsewhere", basis.name, vaddr.get(), pp.page_number());
+                report.cross_linked += 1;
+                cross_links.push(vaddr);
+            } else if !refs.owns(vaddr) {
+                log::warn!("fsck {}: {:x} is mapped to physical page {:x}, but nothing refers to it", basis.name, vaddr.get(), pp.page_number());
+                report.orphaned += 1;
+                orphans.push(vaddr);
+            }
+        }
+
+        // raw page table vs v2p map
+        let mut stale = Vec::<PhysAddr>::new();
+        for (vaddr, page_number) in hw.pt_scan_entries(&basis.cipher_ecb) {
+            if basis.v2p_map.get(&vaddr).map(|pp| pp.page_number()) != Some(page_number) {
+                log::warn!("fsck {}: stale PTE {:x}->{:x}", basis.name, vaddr.get(), page_number);
+                report.stale_ptes += 1;
+                // an entry for a page that is live in some basis can't be ours to erase
+                if !phys_use.contains_key(&page_number) {
+                    stale.push(page_number);
+                }
+            }
+        }
+
+        if repair {
+            // A PTE can pass its checksum by chance under the wrong key, in which case both the entry and the
+            // page belong to some other, possibly locked, basis. Only touch pages that authenticate as ours.
+            for page_number in stale {
+                let mut pp = PhysPage(0);
+                pp.set_page_number(page_number);
+                if hw.data_decrypt_page(&basis.cipher, &basis.aad, &pp).is_some() {
+                    hw.pt_erase(page_number);
+                    report.repaired += 1;
+                }
+            }
+            for vaddr in orphans {
+                if !hw.fast_space_ensure_next_log() {
+                    log::warn!("fsck {}: out of FSCB log space, leaving remaining orphans in place", basis.name);
+                    break;
+                }
+                let authentic = basis.v2p_map.get(&vaddr)
+                    .map_or(false, |pp| hw.data_decrypt_page(&basis.cipher, &basis.aad, pp).is_some());
+                if authentic {
+                    let mut pp = basis.v2p_map.remove(&vaddr).unwrap();
+                    hw.page_erase_and_free(&mut pp);
+                    report.repaired += 1;
+                }
+            }
+            for vaddr in cross_links {
+                let shared = basis.v2p_map[&vaddr];
+                let mut data = match hw.data_decrypt_page(&basis.cipher, &basis.aad, &shared) {
+                    Some(data) => data,
+                    None => continue,
+                };
+                let claims = phys_use.get_mut(&shared.page_number()).unwrap();
+                if *claims > 1 {
+                    let mut copy = match hw.try_fast_space_alloc() {
+                        Some(pp) => pp,
+                        None => {
+                            log::warn!("fsck {}: out of free space, leaving remaining cross-linked pages in place", basis.name);
+                            break;
+                        }
+                    };
+                    copy.set_valid(true);
+                    copy.set_clean(true);
+                    hw.data_encrypt_and_patch_page(&basis.cipher, &basis.aad, &mut data, &copy);
+                    hw.pt_patch_mapping(vaddr, copy.page_number(), &basis.cipher_ecb);
+                    basis.v2p_map.insert(vaddr, copy);
+                    *claims -= 1;
+                } else {
+                    // the last claimant keeps the original page, so make sure the page table points it there
+                    hw.pt_patch_mapping(vaddr, shared.page_number(), &basis.cipher_ecb);
+                }
+                report.repaired += 1;
+            }
+            for vaddr in missing {
+                // descriptors can't be made up, but a hole in the data can be
+                if vaddr.get() < SMALL_POOL_START {
+                    continue;
+                }
+                let mut blank = match hw.try_fast_space_alloc() {
+                    Some(pp) => pp,
+                    None => {
+                        log::warn!("fsck {}: out of free space, leaving remaining missing pages unmapped", basis.name);
+                        break;
+                    }
+                };
+                blank.set_valid(true);
+                blank.set_clean(true);
+                let mut data = vec![0u8; VPAGE_SIZE + size_of::<JournalType>()];
+                for (&src, dst) in (hw.trng_u32() % JOURNAL_RAND_RANGE).to_le_bytes().iter().zip(data[..size_of::<JournalType>()].iter_mut()) {
+                    *dst = src;
+                }
+                hw.data_encrypt_and_patch_page(&basis.cipher, &basis.aad, &mut data, &blank);
+                hw.pt_patch_mapping(vaddr, blank.page_number(), &basis.cipher_ecb);
+                basis.v2p_map.insert(vaddr, blank);
+                report.repaired += 1;
+            }
+        }
+    }
+
+    // FSCB vs every mapped page
+    let data_pages = hw.data_pages();
+    for pp in hw.fast_space_entries() {
+        let problem = if pp.page_number() as u64 >= data_pages as u64 {
+            log::warn!("fsck: FSCB entry for page {:x} is out of range", pp.page_number());
+            report.fscb_out_of_range += 1;
+            true
+        } else if (pp.space_state() == SpaceState::Free || pp.space_state() == SpaceState::Dirty)
+        && phys_use.contains_key(&pp.page_number()) {
+            log::warn!("fsck: FSCB lists page {:x} as {:?}, but it is in use", pp.page_number(), pp.space_state());
+            report.fscb_conflicts += 1;
+            true
+        } else {
+            false
+        };
+        if problem && repair {
+            if hw.fast_space_withdraw(&pp) {
+                report.repaired += 1;
+            }
+        }
+    }
+    log::info!("fsck: {:?}", report);
+    Ok(report)
+}
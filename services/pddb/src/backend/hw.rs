@@ -130,6 +130,9 @@ pub(crate) struct PddbOs {
     dna: u64,
     /// reference to a TrngPool object that's shared among all the hardware functions
     entropy: Rc<RefCell<TrngPool>>,
+    /// number of space reservations that succeed before they start failing, used to inject write errors in testing
+    #[cfg(not(any(target_os = "none", target_os = "xous")))]
+    fail_alloc_after: Option<usize>,
 }
 
 impl PddbOs {
@@ -197,6 +200,7 @@ impl PddbOs {
                 fspace_log_len: 0,
                 dna: llio.soc_dna().unwrap(),
                 entropy: trngpool,
+                fail_alloc_after: None,
             }
         };
         ret
@@ -242,6 +246,13 @@ impl PddbOs {
         self.cipher_ecb = None;
         self.fspace_log_next_addr = None;
         self.pddb_mr.reset();
+        self.fail_alloc_after = None;
+    }
+    #[allow(dead_code)]
+    #[cfg(not(any(target_os = "none", target_os = "xous")))]
+    /// makes every space reservation after the next `count` fail as if the disk were full, or stops doing so if `None`
+    pub(crate) fn test_fail_alloc_after(&mut self, count: Option<usize>) {
+        self.fail_alloc_after = count;
     }
     pub(crate) fn is_efuse_secured(&self) -> bool {
         self.rootkeys.is_efuse_secured().expect("couldn't query efuse security state") == Some(true)
@@ -432,9 +443,54 @@ impl PddbOs {
     /// into the page table, overwriting the impostor entry in the paget able. On the next mount, this turns into the "trivial conflict"
     /// case, where one page will validate and the other will not.
     pub(crate) fn pt_scan_key(&self, key: &[u8; AES_KEYSIZE], basis_name: &str) -> Option<HashMap::<VirtAddr, PhysPage>> {
-        let cipher = Aes256::new(&GenericArray::from_slice(key));
-        let pt = self.pt_as_slice();
         let mut map = HashMap::<VirtAddr, PhysPage>::new();
+        for (vaddr, page_number) in self.pt_scan_entries(&Aes256::new(&GenericArray::from_slice(key))) {
+            let mut pp = PhysPage(0);
+            pp.set_page_number(page_number);
+            // the state is clean because this entry is, by definition, synchronized with the disk
+            pp.set_clean(true);
+            pp.set_valid(true);
+            pp.set_space_state(SpaceState::Used);
+            // handle conflicting journal versions here
+            if let Some(prev_page) = map.get(&vaddr) {
+                let cipher = AesGcmSiv::<Aes256>::new(Key::from_slice(key));
+                let aad = self.data_aad(basis_name);
+                let prev_data = self.data_decrypt_page(&cipher, &aad, prev_page);
+                let new_data = self.data_decrypt_page(&cipher, &aad, &pp);
+                if let Some(new_d) = new_data {
+                    if let Some(prev_d) = prev_data {
+                        let prev_j = JournalType::from_le_bytes(prev_d[..size_of::<JournalType>()].try_into().unwrap());
+                        let new_j = JournalType::from_le_bytes(new_d[..size_of::<JournalType>()].try_into().unwrap());
+                        if new_j > prev_j {
+                            map.insert(vaddr, pp);
+                        } else if new_j == prev_j {
+                            log::error!("Found duplicate blocks with same journal age, picking arbitrary block and moving on...");
+                        }
+                    } else {
+                        self.resolve_pp_journal(&mut pp);
+                        // prev data was bogus anyways, replace with the new entry
+                        map.insert(vaddr, pp);
+                    }
+                } else {
+                    // new data is bogus, ignore it
+                }
+            } else {
+                self.resolve_pp_journal(&mut pp);
+                map.insert(vaddr, pp);
+            }
+        }
+        if map.len() > 0 {
+            Some(map)
+        } else {
+            None
+        }
+    }
+    /// Returns every page table entry that decrypts and checksums correctly under `cipher`, as
+    /// (virtual address, physical page number) pairs in the order they appear in the page table.
+    /// No conflict resolution is done, so a virtual address may show up more than once.
+    pub(crate) fn pt_scan_entries(&self, cipher: &Aes256) -> Vec::<(VirtAddr, PhysAddr)> {
+        let pt = self.pt_as_slice();
+        let mut entries = Vec::<(VirtAddr, PhysAddr)>::new();
         let blank = [0xffu8; aes::BLOCK_SIZE];
         for (page_index, pt_page) in pt.chunks(PAGE_SIZE).enumerate() {
             let clean_page = if pt_page[..aes::BLOCK_SIZE] == blank {
@@ -452,47 +508,11 @@ impl PddbOs {
                 let mut block = Block::clone_from_slice(candidate);
                 cipher.decrypt_block(&mut block);
                 if let Some(pte) = Pte::try_from_slice(block.as_slice()) {
-                    let mut pp = PhysPage(0);
-                    pp.set_page_number(((page_index * PAGE_SIZE / aes::BLOCK_SIZE) + index) as PhysAddr);
-                    // the state is clean because this entry is, by definition, synchronized with the disk
-                    pp.set_clean(true);
-                    pp.set_valid(true);
-                    pp.set_space_state(SpaceState::Used);
-                    // handle conflicting journal versions here
-                    if let Some(prev_page) = map.get(&pte.vaddr()) {
-                        let cipher = AesGcmSiv::<Aes256>::new(Key::from_slice(key));
-                        let aad = self.data_aad(basis_name);
-                        let prev_data = self.data_decrypt_page(&cipher, &aad, prev_page);
-                        let new_data = self.data_decrypt_page(&cipher, &aad, &pp);
-                        if let Some(new_d) = new_data {
-                            if let Some(prev_d) = prev_data {
-                                let prev_j = JournalType::from_le_bytes(prev_d[..size_of::<JournalType>()].try_into().unwrap());
-                                let new_j = JournalType::from_le_bytes(new_d[..size_of::<JournalType>()].try_into().unwrap());
-                                if new_j > prev_j {
-                                    map.insert(pte.vaddr(), pp);
-                                } else if new_j == prev_j {
-                                    log::error!("Found duplicate blocks with same journal age, picking arbitrary block and moving on...");
-                                }
-                            } else {
-                                self.resolve_pp_journal(&mut pp);
-                                // prev data was bogus anyways, replace with the new entry
-                                map.insert(pte.vaddr(), pp);
-                            }
-                        } else {
-                            // new data is bogus, ignore it
-                        }
-                    } else {
-                        self.resolve_pp_journal(&mut pp);
-                        map.insert(pte.vaddr(), pp);
-                    }
+                    entries.push((pte.vaddr(), ((page_index * PAGE_SIZE / aes::BLOCK_SIZE) + index) as PhysAddr));
                 }
             }
         }
-        if map.len() > 0 {
-            Some(map)
-        } else {
-            None
-        }
+        entries
     }
     /// Pages drawn from disk might already have come from the FSCB. We need to make the journal number
     /// of these consistent with those in the FSCB so later on when they are retired we don't have journal conflicts.
@@ -1055,23 +1075,7 @@ impl PddbOs {
                 ppc.set_journal(ppc.journal() + 1); // this is guaranteed not to overflow because of a check in the "if" clause above
 
                 // commit the usage to the journal
-                self.syskey_ensure();
-                let cipher = self.cipher_ecb.as_ref().expect("Inconsistent internal state - syskey_ensure() failed");
-                let mut update = SpaceUpdate::new(self.entropy.borrow_mut().get_u64(), ppc);
-                let mut block = Block::from_mut_slice(update.deref_mut());
-                log::trace!("block: {:x?}", block);
-                cipher.encrypt_block(&mut block);
-                let log_addr = self.fspace_log_next_addr.take().unwrap() as PhysAddr;
-                log::trace!("patch: {:x?}", block);
-                self.patch_fscb(&block, log_addr);
-                self.fspace_log_len += 1;
-                let next_addr = log_addr + aes::BLOCK_SIZE as PhysAddr;
-                if (next_addr & (PAGE_SIZE as PhysAddr - 1)) != 0 {
-                    self.fspace_log_next_addr = Some(next_addr as PhysAddr);
-                } else {
-                    // fspace_log_next_addr is already None because we used "take()". We'll find a free spot for the
-                    // next journal entry the next time around.
-                }
+                self.fast_space_log(ppc);
                 maybe_alloc = Some(ppc);
             }
             if maybe_alloc.is_none() {
@@ -1086,6 +1090,18 @@ impl PddbOs {
             maybe_alloc
         }
     }
+    /// Overwrites a physical page and its page table entry with noise, and returns the page to FastSpace.
+    /// Only use this on pages that have already been detached from their basis' v2p map: `pt_sync()`
+    /// will not know about the page, so its page table entry has to be scrubbed here.
+    pub(crate) fn page_erase_and_free(&mut self, pp: &mut PhysPage) {
+        let mut noise = [0u8; PAGE_SIZE];
+        self.trng_slice(&mut noise);
+        self.patch_data(&noise, pp.page_number() * PAGE_SIZE as u32);
+        self.pt_erase(pp.page_number());
+        log::trace!("fast_space_free page_erase_and_free {} before", pp.journal());
+        self.fast_space_free(pp);
+        assert!(pp.valid() == false, "pp is still marked as valid!");
+    }
     pub fn fast_space_free(&mut self, pp: &mut PhysPage) {
         self.fast_space_ensure_next_log();
         if !self.fspace_cache.remove(&pp) {
@@ -1100,9 +1116,16 @@ impl PddbOs {
         self.fspace_cache.insert(pp.clone());
 
         // commit the free'd block to the journal
+        self.fast_space_log(pp.clone());
+        // mark the page as invalid, so that it will be deleted on the next PT sync
+        pp.set_valid(false);
+    }
+    /// Appends a record of the new state of `pp` to the FSCB log. The caller must have ensured that
+    /// `fspace_log_next_addr` is valid.
+    fn fast_space_log(&mut self, pp: PhysPage) {
         self.syskey_ensure();
         let cipher = self.cipher_ecb.as_ref().expect("Inconsistent internal state - syskey_ensure() failed");
-        let mut update = SpaceUpdate::new(self.entropy.borrow_mut().get_u64(), pp.clone());
+        let mut update = SpaceUpdate::new(self.entropy.borrow_mut().get_u64(), pp);
         let mut block = Block::from_mut_slice(update.deref_mut());
         log::trace!("block: {:x?}", block);
         cipher.encrypt_block(&mut block);
@@ -1117,13 +1140,48 @@ impl PddbOs {
             // fspace_log_next_addr is already None because we used "take()". We'll find a free spot for the
             // next journal entry the next time around.
         }
-        // mark the page as invalid, so that it will be deleted on the next PT sync
-        pp.set_valid(false);
+    }
+    /// Returns a copy of every entry in the fspace cache. Used by the consistency checker.
+    pub(crate) fn fast_space_entries(&self) -> Vec::<PhysPage> {
+        self.fspace_cache.iter().cloned().collect()
+    }
+    /// Withdraws a page from the fspace cache by journaling it as `Used`. This is how the consistency
+    /// checker retires FSCB entries that claim a page is free when it is actually holding data.
+    /// Returns false if the page could not be withdrawn.
+    pub(crate) fn fast_space_withdraw(&mut self, pp: &PhysPage) -> bool {
+        if !self.fast_space_ensure_next_log() {
+            return false;
+        }
+        let mut entry = match self.fspace_cache.get(pp) {
+            Some(entry) => entry.clone(),
+            None => return false,
+        };
+        if entry.journal() >= PHYS_PAGE_JOURNAL_MAX {
+            return false;
+        }
+        self.fspace_cache.remove(&entry);
+        entry.set_space_state(SpaceState::Used);
+        entry.set_journal(entry.journal() + 1);
+        self.fast_space_log(entry);
+        true
+    }
+    /// The number of physical pages in the data region of the PDDB.
+    pub(crate) fn data_pages(&self) -> u32 {
+        ((PDDB_A_LEN - self.data_phys_base.as_usize()) / PAGE_SIZE) as u32
     }
     /// This is a "look before you leap" function that will potentially pause all system operations
     /// and do a deep scan for space if the required amount is not available.
     pub fn ensure_fast_space_alloc(&mut self, pages: usize, cache: &Vec::<BasisCacheEntry>) -> bool {
         const BUFFER: usize = 1; // a bit of slop in the trigger point
+        #[cfg(not(any(target_os = "none", target_os = "xous")))]
+        match self.fail_alloc_after {
+            Some(0) => {
+                log::warn!("injected fast space alloc failure");
+                return false;
+            }
+            Some(count) => self.fail_alloc_after = Some(count - 1),
+            None => (),
+        }
         let has_pages = self.fast_space_has_pages(pages + BUFFER);
         log::trace!("alloc fast_space_len: {}, log_len {}, has {} pages: {}", self.fast_space_len(), self.fspace_log_len, pages + BUFFER, has_pages);
         // make sure we have fast space pages...
@@ -1806,7 +1864,7 @@ impl PddbOs {
     pub(crate) fn basis_derive_key(&self, basis_name: &str, password: &str) -> BasisKeys {
         use sha2::{FallbackStrategy, Sha512Trunc256};
         use digest::Digest;
-        use backend::bcrypt::*;
+        use crate::bcrypt::*;
 
         // 1. derive the salt from the "key" region. First step is to create the salt lookup
         // table, which is done by hashing the name and password together with SHA-512
@@ -1887,7 +1945,7 @@ impl PddbOs {
     pub(crate) fn basis_derive_key_v00_00_01_01(&self, basis_name: &str, password: &str, scd: &StaticCryptoDataV1) -> [u8; AES_KEYSIZE] {
         use sha2::{FallbackStrategy, Sha512Trunc256};
         use digest::Digest;
-        use backend::bcrypt::*;
+        use crate::bcrypt::*;
 
         // 1. derive the salt from the "key" region. First step is to create the salt lookup
         // table, which is done by hashing the name and password together with SHA-512
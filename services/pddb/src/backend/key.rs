@@ -4,6 +4,7 @@ use super::*;
 use std::num::NonZeroU32;
 use core::ops::{Deref, DerefMut};
 use std::cmp::Ordering;
+use std::collections::VecDeque;
 use std::io::{Result, Error, ErrorKind};
 
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
@@ -137,7 +138,6 @@ impl KeyCacheEntry {
 pub (crate) enum KeyCacheData {
     Small(KeySmallData),
     // the "Medium" type has a region reserved for it, but we haven't coded a handler for it.
-    #[allow(dead_code)] // Large data caching isn't implemented, so of course, we don't ever create this type
     Large(KeyLargeData),
 }
 /// Small data is optimized for low overhead, and always represent a complete copy of the data.
@@ -145,13 +145,28 @@ pub(crate) struct KeySmallData {
     pub clean: bool,
     pub(crate) data: Vec::<u8>,
 }
-/// This can hold just a portion of a large key's data. For now, we now essentially manually
-/// encode a sub-slice in parts, but, later on we could get more clever and start to cache
-/// multiple disjoint portions of a large key's data...
-#[allow(dead_code)] // large key caching is not yet implemented
+/// Large data is never held in RAM in its entirety. Instead, we keep a handful of decrypted vpages
+/// around, so that streaming reads and writes (which are chunked to the size of a `PddbBuf` and thus
+/// straddle vpage boundaries) don't have to decrypt and re-encrypt the same page over and over again.
+/// The pages are kept in least-recently-used order: the front is the next candidate for eviction.
 pub(crate) struct KeyLargeData {
     pub clean: bool,
-    pub(crate) start: u64,
+    pub(crate) pages: VecDeque::<KeyLargePage>,
+}
+impl KeyLargeData {
+    pub(crate) fn new() -> KeyLargeData {
+        KeyLargeData {
+            clean: true,
+            pages: VecDeque::<KeyLargePage>::with_capacity(LARGE_CACHE_PAGES),
+        }
+    }
+}
+/// A single cached vpage of a large key.
+pub(crate) struct KeyLargePage {
+    pub clean: bool,
+    /// virtual address of the vpage this data belongs to
+    pub(crate) vaddr: VirtAddr,
+    /// plaintext of the vpage, including the journal number prefix
     pub(crate) data: Vec::<u8>,
 }
 
@@ -0,0 +1,194 @@
+/// Multi-key transactions.
+///
+/// Individual page writes are already atomic: every data page carries a journal revision, and when
+/// a power loss leaves two physical copies of a virtual page, the mount process keeps the newer one.
+/// A transaction extends this to a group of keys by way of a redo log:
+///
+/// 1. The encoded operations are written to a reserved key in the target basis, followed by a digest of
+///    the entire record. Once this key is fully on disk, the transaction is committed.
+/// 2. The operations are applied, in order, to the basis. Every operation is idempotent -- writes replace
+///    the whole key, and deleting a key that's already gone is not an error.
+/// 3. The reserved dictionary holding the log is removed.
+///
+/// If power is lost during (1), the digest does not check out on the next mount and the transaction is
+/// discarded. If power is lost during (2) or (3), the log is replayed from the top on the next mount.
+///
+/// An error (e.g. running out of space) is handled the same way: a failure in (1) discards the log, and
+/// the transaction never happened. A failure in (2) or (3) leaves the log in place to be replayed on the
+/// next mount, or before the next transaction in the same basis, and the commit reports `TxnStatus::Pending`.
+
+use crate::api::*;
+use crate::txn::*;
+use super::*;
+
+use std::convert::TryInto;
+use std::io::{Result, Error, ErrorKind};
+
+/// Reserved dictionary for the transaction log. Transactions may not touch it.
+pub(crate) const TXN_LOG_DICT: &'static str = ".pddb.txnlog";
+pub(crate) const TXN_LOG_KEY: &'static str = "log";
+
+/// Returns whether `dict` is reserved for the PDDB's own use. Clients can't open, list, subscribe to or
+/// delete reserved dictionaries, and they're left out of dictionary listings.
+pub(crate) fn is_reserved_dict(dict: &str) -> bool {
+    dict == TXN_LOG_DICT
+}
+const TXN_LOG_MAGIC: [u8; 4] = *b"PDTX";
+const TXN_LOG_VERSION: u32 = 1;
+/// magic + version + length of the encoded ops
+const TXN_LOG_HEADER_LEN: usize = 12;
+const TXN_LOG_DIGEST_LEN: usize = 32;
+
+/// Result of a transaction that made it past the commit point.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum TxnStatus {
+    /// All the operations have been applied and the log is retired.
+    Applied,
+    /// The log is on disk, but applying it failed part-way. The operations will be completed by
+    /// `txn_recover` the next time the basis is mounted; until then, the affected keys may hold
+    /// either their old or their new values.
+    Pending,
+}
+
+fn txn_log_digest(data: &[u8]) -> [u8; TXN_LOG_DIGEST_LEN] {
+    use sha2::{FallbackStrategy, Sha512Trunc256};
+    use digest::Digest;
+    let mut hasher = Sha512Trunc256::new_with_strategy(FallbackStrategy::SoftwareOnly);
+    hasher.update(data);
+    let mut digest = [0u8; TXN_LOG_DIGEST_LEN];
+    digest.copy_from_slice(hasher.finalize().as_slice());
+    digest
+}
+
+impl BasisCache {
+    /// Checks that a set of operations is something we're willing to commit.
+    pub(crate) fn txn_validate(&self, basis_name: &str, ops: &[TxnOp]) -> Result<()> {
+        if !self.basis_list().iter().any(|name| name == basis_name) {
+            return Err(Error::new(ErrorKind::NotFound, "Requested basis not found, or PDDB not mounted."));
+        }
+        for op in ops.iter() {
+            if is_reserved_dict(op.dict()) {
+                return Err(Error::new(ErrorKind::PermissionDenied, "transactions may not modify the transaction log"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Step 1: durably records the operations of a transaction in `basis_name`. This is the commit point.
+    pub(crate) fn txn_log_write(&mut self, hw: &mut PddbOs, basis_name: &str, ops: &[TxnOp]) -> Result<()> {
+        let mut log = Vec::<u8>::new();
+        log.extend_from_slice(&TXN_LOG_MAGIC);
+        log.extend_from_slice(&TXN_LOG_VERSION.to_le_bytes());
+        log.extend_from_slice(&[0u8; 4]); // patched with the length once the ops are encoded
+        txn_encode(ops, &mut log)?;
+        let ops_len = (log.len() - TXN_LOG_HEADER_LEN) as u32;
+        log[8..12].copy_from_slice(&ops_len.to_le_bytes());
+        let digest = txn_log_digest(&log);
+        log.extend_from_slice(&digest);
+        // key_update syncs the data, dictionary and page table before returning
+        self.key_update(hw, TXN_LOG_DICT, TXN_LOG_KEY, &log, None, None, Some(basis_name), true)
+    }
+
+    /// Step 2: applies the operations of a transaction to `basis_name`.
+    pub(crate) fn txn_apply(&mut self, hw: &mut PddbOs, basis_name: &str, ops: &[TxnOp]) -> Result<()> {
+        for op in ops.iter() {
+            match op {
+                TxnOp::Write{dict, key, data} => {
+                    self.key_update(hw, dict, key, data, None, None, Some(basis_name), true)?;
+                }
+                TxnOp::Delete{dict, key} => {
+                    match self.key_remove(hw, dict, key, Some(basis_name), false) {
+                        Ok(_) => (),
+                        Err(e) if e.kind() == ErrorKind::NotFound => (),
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        }
+        self.sync(hw, Some(basis_name))
+    }
+
+    /// Step 3: retires the transaction log of `basis_name`.
+    pub(crate) fn txn_log_clear(&mut self, hw: &mut PddbOs, basis_name: &str) -> Result<()> {
+        if self.dict_attributes(hw, TXN_LOG_DICT, Some(basis_name)).is_err() {
+            return Ok(());
+        }
+        self.dict_remove(hw, TXN_LOG_DICT, Some(basis_name), false)?;
+        self.sync(hw, Some(basis_name))
+    }
+
+    /// Reads back the transaction log of `basis_name`. Returns `None` if there is no log, and an error
+    /// if there is a log but it is incomplete or damaged.
+    fn txn_log_read(&mut self, hw: &mut PddbOs, basis_name: &str) -> Result<Option<Vec::<TxnOp>>> {
+        let attr = match self.key_attributes(hw, TXN_LOG_DICT, TXN_LOG_KEY, Some(basis_name)) {
+            Ok(attr) => attr,
+            Err(_) => return Ok(None),
+        };
+        if attr.len < TXN_LOG_HEADER_LEN + TXN_LOG_DIGEST_LEN {
+            return Err(Error::new(ErrorKind::InvalidData, "transaction log is truncated"));
+        }
+        let mut log = vec![0u8; attr.len];
+        let readlen = self.key_read(hw, TXN_LOG_DICT, TXN_LOG_KEY, &mut log, None, Some(basis_name))?;
+        if readlen != attr.len {
+            return Err(Error::new(ErrorKind::InvalidData, "transaction log is truncated"));
+        }
+        let (body, digest) = log.split_at(log.len() - TXN_LOG_DIGEST_LEN);
+        if txn_log_digest(body)[..] != digest[..] {
+            return Err(Error::new(ErrorKind::InvalidData, "transaction log digest mismatch"));
+        }
+        if body[..4] != TXN_LOG_MAGIC || u32::from_le_bytes(body[4..8].try_into().unwrap()) != TXN_LOG_VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, "transaction log has an unknown format"));
+        }
+        let ops_len = u32::from_le_bytes(body[8..12].try_into().unwrap()) as usize;
+        if ops_len != body.len() - TXN_LOG_HEADER_LEN {
+            return Err(Error::new(ErrorKind::InvalidData, "transaction log length mismatch"));
+        }
+        txn_decode(&body[TXN_LOG_HEADER_LEN..]).map(|ops| Some(ops))
+    }
+
+    /// Finishes or discards any transaction that was interrupted in `basis_name`. Must be called every time
+    /// a basis is mounted, before it is used. Returns the number of operations that were replayed.
+    pub(crate) fn txn_recover(&mut self, hw: &mut PddbOs, basis_name: &str) -> Result<usize> {
+        let replayed = match self.txn_log_read(hw, basis_name) {
+            Ok(None) => {
+                // a torn commit can also leave just the dictionary behind
+                return self.txn_log_clear(hw, basis_name).map(|_| 0);
+            }
+            Ok(Some(ops)) => {
+                log::info!("replaying interrupted transaction of {} ops in basis {}", ops.len(), basis_name);
+                self.txn_apply(hw, basis_name, &ops)?;
+                ops.len()
+            }
+            Err(e) => {
+                log::warn!("discarding incomplete transaction in basis {}: {:?}", basis_name, e);
+                0
+            }
+        };
+        self.txn_log_clear(hw, basis_name)?;
+        Ok(replayed)
+    }
+
+    /// Atomically applies `ops` to `basis_name`. If an error is returned, none of the operations took effect.
+    pub(crate) fn txn_commit(&mut self, hw: &mut PddbOs, basis_name: &str, ops: &[TxnOp]) -> Result<TxnStatus> {
+        self.txn_validate(basis_name, ops)?;
+        // don't let a new log clobber one that still needs replaying
+        self.txn_recover(hw, basis_name)?;
+        if let Err(e) = self.txn_log_write(hw, basis_name, ops) {
+            // a partial log would be discarded on the next mount anyway, but don't leave it lying around
+            if let Err(clear_err) = self.txn_log_clear(hw, basis_name) {
+                log::warn!("couldn't discard failed transaction log in basis {}: {:?}", basis_name, clear_err);
+            }
+            return Err(e);
+        }
+        // past the commit point, the transaction can only be completed, not undone
+        if let Err(e) = self.txn_apply(hw, basis_name, ops) {
+            log::error!("transaction in basis {} is committed but could not be applied: {:?}", basis_name, e);
+            return Ok(TxnStatus::Pending);
+        }
+        if let Err(e) = self.txn_log_clear(hw, basis_name) {
+            log::error!("transaction in basis {} is applied, but its log could not be retired: {:?}", basis_name, e);
+            return Ok(TxnStatus::Pending);
+        }
+        Ok(TxnStatus::Applied)
+    }
+}
@@ -104,11 +104,12 @@ impl<'a> Read for PddbKey<'a> {
                 let pbuf = PddbBuf::from_slice_mut(self.buf.as_mut());
                 match pbuf.retcode {
                     PddbRetcode::Ok => {
-                        for (&src, dst) in pbuf.data.iter().zip(buf.iter_mut()) {
+                        assert!(pbuf.len <= readlen, "More data returned than we requested");
+                        for (&src, dst) in pbuf.data[..pbuf.len as usize].iter().zip(buf.iter_mut()) {
                             *dst = src;
                         }
-                        assert!(pbuf.len <= readlen, "More data returned than we requested");
-                        self.pos += buf.len() as u64;
+                        // advance by what was actually read: large keys are streamed in chunks smaller than `buf`
+                        self.pos += pbuf.len as u64;
                         Ok(pbuf.len as usize)
                     }
                     PddbRetcode::BasisLost => Err(Error::new(ErrorKind::BrokenPipe, "Basis lost")),
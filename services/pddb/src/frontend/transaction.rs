@@ -0,0 +1,98 @@
+use crate::*;
+use crate::txn::*;
+use xous_ipc::Buffer;
+
+use num_traits::*;
+use std::io::{Result, Error, ErrorKind};
+
+/// A set of writes and deletes that are applied to a single basis all-or-nothing, even across
+/// a power loss. Operations are staged locally, and nothing is sent to the PDDB until `commit()`
+/// is called; dropping the transaction without committing it discards the staged operations.
+///
+/// Operations are applied in the order they were staged. Reads made while a transaction is open
+/// do not see its staged operations.
+pub struct PddbTransaction<'a> {
+    pub(crate) pddb: &'a Pddb,
+    pub(crate) basis: String,
+    pub(crate) ops: Vec::<TxnOp>,
+    /// running total of the encoded size of `ops`
+    pub(crate) len: usize,
+}
+impl<'a> PddbTransaction<'a> {
+    fn stage(&mut self, op: TxnOp) -> Result<()> {
+        let mut encoded = Vec::<u8>::new();
+        txn_encode(core::slice::from_ref(&op), &mut encoded)?;
+        if self.len + encoded.len() > TXN_MAX_LEN {
+            return Err(Error::new(ErrorKind::OutOfMemory, "transaction is too large"));
+        }
+        self.len += encoded.len();
+        self.ops.push(op);
+        Ok(())
+    }
+    /// Stages a replacement of the entire contents of `dict_name:key_name` with `data`. The key, and
+    /// its dictionary, are created if they do not exist.
+    pub fn write(&mut self, dict_name: &str, key_name: &str, data: &[u8]) -> Result<()> {
+        self.stage(TxnOp::Write {
+            dict: String::from(dict_name),
+            key: String::from(key_name),
+            data: data.to_vec(),
+        })
+    }
+    /// Stages the removal of `dict_name:key_name`. It is not an error if the key does not exist.
+    pub fn delete(&mut self, dict_name: &str, key_name: &str) -> Result<()> {
+        self.stage(TxnOp::Delete {
+            dict: String::from(dict_name),
+            key: String::from(key_name),
+        })
+    }
+    /// Discards all the staged operations.
+    pub fn abort(self) {
+        // staged data may be sensitive, so scrub it on the way out
+        for op in self.ops.into_iter() {
+            if let TxnOp::Write{mut data, ..} = op {
+                for b in data.iter_mut() {
+                    unsafe{(b as *mut u8).write_volatile(0)};
+                }
+            }
+        }
+        core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+    }
+    /// Applies all the staged operations atomically.
+    ///
+    /// If an error of kind `Interrupted` is returned, the transaction was committed, but the PDDB failed
+    /// (e.g. ran out of space) while applying it. It will be completed the next time the basis is mounted,
+    /// and until then the keys it touches may hold either their old or their new values. Keys written
+    /// in the meantime may be overwritten when the transaction is completed, so callers should stop using
+    /// the basis and remount it. For any other error, none of the operations took effect.
+    pub fn commit(self) -> Result<()> {
+        if self.ops.len() == 0 {
+            return Ok(());
+        }
+        let mut buf = Buffer::new(TXN_HEADER_LEN + self.len);
+        {
+            let raw = buf.as_mut();
+            let mut encoded = Vec::<u8>::with_capacity(self.len);
+            txn_encode(&self.ops, &mut encoded)?;
+            raw[..4].copy_from_slice(&(PddbRetcode::Uninit as u32).to_le_bytes());
+            raw[4..8].copy_from_slice(&(encoded.len() as u32).to_le_bytes());
+            for (&src, dst) in self.basis.as_bytes().iter().zip(raw[8..TXN_HEADER_LEN].iter_mut()) {
+                *dst = src;
+            }
+            raw[TXN_HEADER_LEN..TXN_HEADER_LEN + encoded.len()].copy_from_slice(&encoded);
+        }
+        buf.lend_mut(self.pddb.conn, Opcode::TxnCommit.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let retcode = buf.as_ref()[0];
+        // the buffer holds a copy of the staged data
+        buf.volatile_clear();
+        self.abort();
+        match FromPrimitive::from_u8(retcode) {
+            Some(PddbRetcode::Ok) => Ok(()),
+            Some(PddbRetcode::BasisLost) => Err(Error::new(ErrorKind::NotFound, "Basis not found, or PDDB not mounted")),
+            Some(PddbRetcode::AccessDenied) => Err(Error::new(ErrorKind::PermissionDenied, "Transaction touches a reserved dictionary")),
+            Some(PddbRetcode::DiskFull) => Err(Error::new(ErrorKind::OutOfMemory, "Out of disk space")),
+            Some(PddbRetcode::TxnPending) => Err(Error::new(ErrorKind::Interrupted, "Transaction committed, but completes on the next mount")),
+            _ => Err(Error::new(ErrorKind::Other, "Transaction failed for unspecified reasons")),
+        }
+    }
+}
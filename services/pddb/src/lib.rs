@@ -4,13 +4,18 @@ pub mod api;
 pub use api::*;
 pub mod frontend;
 pub use frontend::*;
+mod bcrypt;
+pub mod archive;
+pub use archive::*;
+pub mod txn;
 
 use num_traits::*;
-use std::io::{Result, Error, ErrorKind};
+use std::io::{Result, Error, ErrorKind, Read, Write};
 use xous::{CID, SID, msg_scalar_unpack, send_message, Message};
 use xous_ipc::Buffer;
 
 use std::collections::HashMap;
+use std::num::NonZeroU32;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::thread::JoinHandle;
@@ -23,6 +28,8 @@ use std::cell::RefCell;
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
 pub enum CbOp {
     Change,
+    /// memory message carrying a `PddbDictChange`
+    DictChange,
     Quit
 }
 
@@ -87,6 +94,10 @@ pub struct Pddb {
     /// in the case of a basis change. Basis changes are thought to be rare; so, big changes
     /// like this are probably OK.
     keys: Arc<Mutex<HashMap<ApiToken, Box<dyn Fn() + 'static + Send> >>>,
+    /// Handlers for dictionary change subscriptions. These run on the callback thread, so the same
+    /// restrictions apply as for the `keys` closures. They are reference counted so the callback thread
+    /// can call them without holding the lock, which leaves the handlers free to (un)subscribe.
+    subscriptions: Arc<Mutex<HashMap<ApiToken, Arc<dyn Fn(DictChange) + 'static + Send + Sync> >>>,
     trng: trng::Trng,
 }
 impl Pddb {
@@ -100,6 +111,7 @@ impl Pddb {
             cb: RefCell::new(None),
             cb_handle: RefCell::new(None),
             keys,
+            subscriptions: Arc::new(Mutex::new(HashMap::new())),
             trng: trng::Trng::new(&xns).unwrap(),
         }
     }
@@ -108,6 +120,7 @@ impl Pddb {
             let sid = xous::create_server().unwrap();
             let handle = thread::spawn({
                 let keys = Arc::clone(&self.keys);
+                let subscriptions = Arc::clone(&self.subscriptions);
                 let sid = sid.clone();
                 move || {
                     loop {
@@ -121,6 +134,21 @@ impl Pddb {
                                     log::warn!("Key changed but no callback was hooked to receive it");
                                 }
                             }),
+                            Some(CbOp::DictChange) => {
+                                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                                let change = buffer.to_original::<PddbDictChange, _>().unwrap();
+                                let handler = subscriptions.lock().unwrap().get(&change.token).cloned();
+                                if let Some(cb) = handler {
+                                    cb(DictChange {
+                                        kind: change.kind,
+                                        dict: String::from(change.dict.as_str().unwrap_or("")),
+                                        key: if change.key.len() > 0 {Some(String::from(change.key.as_str().unwrap_or("")))} else {None},
+                                        basis: if change.basis.len() > 0 {Some(String::from(change.basis.as_str().unwrap_or("")))} else {None},
+                                    });
+                                } else {
+                                    log::warn!("Dictionary changed but no subscription was hooked to receive it");
+                                }
+                            }
                             Some(CbOp::Quit) => { // blocking scalar
                                 xous::return_scalar(msg.sender, 0).unwrap();
                                 break;
@@ -227,6 +255,9 @@ impl Pddb {
             }
         }
     }
+    /// Unlocks a basis, prompting the user for its password. `policy` defaults to `Persist` if `None`;
+    /// a `TimeOutSecs` policy will cause the basis to lock itself after the given number of seconds
+    /// have elapsed without any access to its keys.
     pub fn unlock_basis(&self, basis_name: &str, policy: Option<BasisRetentionPolicy>) -> Result<()> {
         if basis_name.len() > BASIS_NAME_LEN - 1 {
             return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
@@ -336,6 +367,7 @@ impl Pddb {
             key: xous_ipc::String::<KEY_NAME_LEN>::from_str(key_name),
             create_dict,
             create_key,
+            paranoid: false,
             token: None,
             result: PddbRequestCode::Uninit,
             cb_sid,
@@ -382,8 +414,87 @@ impl Pddb {
         }
     }
 
+    /// Registers `change_cb` to be called whenever a key in `dict_name` is created, updated or deleted, when the
+    /// dictionary itself is deleted, and when a basis is mounted or unmounted. If `basis_name` is specified, only
+    /// changes within that basis are reported. The dictionary does not need to exist yet.
+    ///
+    /// The callback runs on a helper thread, and is meant to do little more than send a message to the
+    /// subscribing server (for example, to trigger a re-read of the dictionary). Returns a token that can be
+    /// handed to `unsubscribe_dict()`.
+    pub fn subscribe_dict(&self, dict_name: &str, basis_name: Option<&str>, change_cb: impl Fn(DictChange) + 'static + Send + Sync) -> Result<ApiToken> {
+        if dict_name.len() > (DICT_NAME_LEN - 1) {
+            return Err(Error::new(ErrorKind::InvalidInput, "dictionary name too long"));
+        }
+        let bname = if let Some(bname) = basis_name {
+            if bname.len() > BASIS_NAME_LEN - 1 {
+                return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+            }
+            xous_ipc::String::<BASIS_NAME_LEN>::from_str(bname)
+        } else {
+            xous_ipc::String::<BASIS_NAME_LEN>::new()
+        };
+        self.ensure_async_responder();
+        let cb_sid = self.cb.borrow().as_ref().expect("async responder was ensured, but missing").to_array();
+
+        // hold the lock across the request, so a notification can't race ahead of the handler registration
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        let request = PddbSubscribeRequest {
+            basis_specified: basis_name.is_some(),
+            basis: bname,
+            dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(dict_name),
+            cb_sid,
+            token: None,
+            code: PddbRequestCode::Uninit,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, Opcode::DictSubscribe.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let response = buf.to_original::<PddbSubscribeRequest, _>().unwrap();
+        match response.code {
+            PddbRequestCode::NoErr => {
+                if let Some(token) = response.token {
+                    subscriptions.insert(token, Arc::new(change_cb));
+                    Ok(token)
+                } else {
+                    Err(Error::new(ErrorKind::Other, "Internal error"))
+                }
+            }
+            PddbRequestCode::NoFreeSpace => Err(Error::new(ErrorKind::OutOfMemory, "Too many subscriptions")),
+            PddbRequestCode::AccessDenied => Err(Error::new(ErrorKind::PermissionDenied, "Dictionary is reserved")),
+            _ => Err(Error::new(ErrorKind::Other, "Internal error")),
+        }
+    }
+    /// Cancels a subscription made with `subscribe_dict()`.
+    pub fn unsubscribe_dict(&self, token: ApiToken) -> Result<()> {
+        self.subscriptions.lock().unwrap().remove(&token);
+        let response = send_message(
+            self.conn,
+            Message::new_blocking_scalar(Opcode::DictUnsubscribe.to_usize().unwrap(),
+            token[0] as usize, token[1] as usize, token[2] as usize, 0)
+        ).or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        if let xous::Result::Scalar1(found) = response {
+            if found != 0 {
+                Ok(())
+            } else {
+                Err(Error::new(ErrorKind::NotFound, "subscription not found"))
+            }
+        } else {
+            Err(Error::new(ErrorKind::Other, "Xous internal error"))
+        }
+    }
+
     /// deletes a key within the dictionary
     pub fn delete_key(&self, dict_name: &str, key_name: &str, basis_name: Option<&str>) -> Result<()> {
+        self.delete_key_inner(dict_name, key_name, basis_name, false)
+    }
+    /// deletes a key within the dictionary, and securely erases it. A regular `delete_key` leaves the old
+    /// ciphertext of small keys on disk until their storage block is next re-written; this call overwrites
+    /// it with noise before returning, at the cost of re-writing the block that contained the key.
+    pub fn delete_key_paranoid(&self, dict_name: &str, key_name: &str, basis_name: Option<&str>) -> Result<()> {
+        self.delete_key_inner(dict_name, key_name, basis_name, true)
+    }
+    fn delete_key_inner(&self, dict_name: &str, key_name: &str, basis_name: Option<&str>, paranoid: bool) -> Result<()> {
         if key_name.len() > (KEY_NAME_LEN - 1) {
             return Err(Error::new(ErrorKind::InvalidInput, "key name too long"));
         }
@@ -414,6 +525,7 @@ impl Pddb {
             key: xous_ipc::String::<KEY_NAME_LEN>::from_str(key_name),
             create_dict: false,
             create_key: false,
+            paranoid,
             token: None,
             result: PddbRequestCode::Uninit,
             cb_sid,
@@ -428,11 +540,20 @@ impl Pddb {
         match response.result {
             PddbRequestCode::NoErr => Ok(()),
             PddbRequestCode::NotFound => Err(Error::new(ErrorKind::NotFound, "Dictionary or key was not found")),
+            PddbRequestCode::NoFreeSpace => Err(Error::new(ErrorKind::OutOfMemory, "No more space on disk")),
+            PddbRequestCode::AccessDenied => Err(Error::new(ErrorKind::PermissionDenied, "Dictionary is reserved")),
             _ => Err(Error::new(ErrorKind::Other, "Internal error"))
         }
     }
-    /// deletes the entire dictionary
+    /// deletes the entire dictionary. The dictionary's storage on disk is always overwritten with noise.
     pub fn delete_dict(&self, dict_name: &str, basis_name: Option<&str>) -> Result<()> {
+        self.delete_dict_inner(dict_name, basis_name, false)
+    }
+    /// deletes the entire dictionary, and also scrubs the plaintext of its keys out of the PDDB server's cache.
+    pub fn delete_dict_paranoid(&self, dict_name: &str, basis_name: Option<&str>) -> Result<()> {
+        self.delete_dict_inner(dict_name, basis_name, true)
+    }
+    fn delete_dict_inner(&self, dict_name: &str, basis_name: Option<&str>, paranoid: bool) -> Result<()> {
         if dict_name.len() > (DICT_NAME_LEN - 1) {
             return Err(Error::new(ErrorKind::InvalidInput, "dictionary name too long"));
         }
@@ -460,6 +581,7 @@ impl Pddb {
             key: xous_ipc::String::<KEY_NAME_LEN>::new(),
             create_dict: false,
             create_key: false,
+            paranoid,
             token: None,
             result: PddbRequestCode::Uninit,
             cb_sid,
@@ -474,10 +596,57 @@ impl Pddb {
         match response.result {
             PddbRequestCode::NoErr => Ok(()),
             PddbRequestCode::NotFound => Err(Error::new(ErrorKind::NotFound, "Dictionary or key was not found")),
+            PddbRequestCode::AccessDenied => Err(Error::new(ErrorKind::PermissionDenied, "Dictionary is reserved")),
             _ => Err(Error::new(ErrorKind::Other, "Internal error"))
         }
     }
 
+    /// Starts a transaction on `basis_name`. Writes and deletes staged in the transaction, across
+    /// any number of dictionaries in that basis, take effect all together when it is committed, or not at all.
+    /// A basis must be named explicitly, because a transaction can't span multiple bases.
+    pub fn begin_transaction(&self, basis_name: &str) -> Result<PddbTransaction> {
+        if basis_name.len() == 0 || basis_name.len() > BASIS_NAME_LEN - 1 {
+            return Err(Error::new(ErrorKind::InvalidInput, "basis name empty or too long"));
+        }
+        Ok(PddbTransaction {
+            pddb: self,
+            basis: String::from(basis_name),
+            ops: Vec::new(),
+            len: 0,
+        })
+    }
+
+    /// Checks the on-disk structures of `basis_name`, or of every unlocked basis if `None`, for consistency.
+    /// If `repair` is set, problems that can be fixed safely are fixed. Note that data in locked bases is
+    /// indistinguishable from free space, so the free space records can only be checked against unlocked bases.
+    pub fn fsck(&self, basis_name: Option<&str>, repair: bool) -> Result<FsckReport> {
+        let bname = if let Some(bname) = basis_name {
+            if bname.len() > BASIS_NAME_LEN - 1 {
+                return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+            }
+            xous_ipc::String::<BASIS_NAME_LEN>::from_str(bname)
+        } else {
+            xous_ipc::String::<BASIS_NAME_LEN>::new()
+        };
+        let request = PddbFsckRequest {
+            basis_specified: basis_name.is_some(),
+            basis: bname,
+            repair,
+            report: FsckReport::default(),
+            code: PddbRequestCode::Uninit,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, Opcode::Fsck.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let response = buf.to_original::<PddbFsckRequest, _>().unwrap();
+        match response.code {
+            PddbRequestCode::NoErr => Ok(response.report),
+            PddbRequestCode::NotFound => Err(Error::new(ErrorKind::NotFound, "Basis not found")),
+            _ => Err(Error::new(ErrorKind::Other, "Internal error")),
+        }
+    }
+
     pub fn sync(&self) -> Result<()> {
         let response = send_message(
             self.conn,
@@ -557,6 +726,71 @@ impl Pddb {
     }
 
 
+    /// Lists the keys in a dictionary together with their attributes. This is equivalent to calling
+    /// `list_keys()` followed by `attributes()` on every key, but it transfers a page of keys per IPC call
+    /// instead of making two or more calls per key. Keys that are deleted while the listing is in
+    /// progress are omitted from the result.
+    pub fn list_key_attributes(&self, dict_name: &str, basis_name: Option<&str>) -> Result<Vec::<(String, KeyAttributes)>> {
+        if dict_name.len() > (DICT_NAME_LEN - 1) {
+            return Err(Error::new(ErrorKind::InvalidInput, "dictionary name too long"));
+        }
+        let bname = if let Some(bname) = basis_name {
+            if bname.len() > BASIS_NAME_LEN - 1 {
+                return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+            }
+            xous_ipc::String::<BASIS_NAME_LEN>::from_str(bname)
+        } else {
+            xous_ipc::String::<BASIS_NAME_LEN>::new()
+        };
+        let token = [self.trng.get_u32().unwrap(), self.trng.get_u32().unwrap(), self.trng.get_u32().unwrap(), self.trng.get_u32().unwrap()];
+        let mut key_list = Vec::<(String, KeyAttributes)>::new();
+        let mut index = 0;
+        loop {
+            let request = PddbKeyAttrList {
+                basis_specified: basis_name.is_some(),
+                basis: bname,
+                dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(dict_name),
+                index,
+                total: 0,
+                count: 0,
+                entries: [PddbKeyAttrEntry::default(); KEY_ATTR_LIST_PAGE],
+                token,
+                code: PddbRequestCode::Uninit,
+            };
+            let mut buf = Buffer::into_buf(request)
+                .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+            buf.lend_mut(self.conn, Opcode::ListKeyAttributes.to_u32().unwrap())
+                .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+            let response = buf.to_original::<PddbKeyAttrList, _>().unwrap();
+            match response.code {
+                PddbRequestCode::NoErr => (),
+                PddbRequestCode::NotFound => return Err(Error::new(ErrorKind::NotFound, "dictionary not found")),
+                PddbRequestCode::AccessDenied => return Err(Error::new(ErrorKind::WouldBlock, "another key listing is in progress")),
+                _ => return Err(Error::new(ErrorKind::Other, "Internal error")),
+            }
+            for entry in response.entries[..response.count as usize].iter() {
+                if let Some(desc_index) = NonZeroU32::new(entry.index) {
+                    key_list.push((
+                        String::from(entry.name.as_str().expect("utf-8 parse error in key name")),
+                        KeyAttributes {
+                            len: entry.len as usize,
+                            reserved: entry.reserved as usize,
+                            age: entry.age as usize,
+                            dict: String::from(dict_name),
+                            basis: String::from(entry.basis.as_str().expect("utf-8 parse error in basis name")),
+                            flags: KeyFlags(entry.flags),
+                            index: desc_index,
+                        }
+                    ));
+                }
+            }
+            index += response.count;
+            if response.count == 0 || index >= response.total {
+                break;
+            }
+        }
+        Ok(key_list)
+    }
     pub fn list_dict(&self, basis_name: Option<&str>) -> Result<Vec::<String>> {
         let bname = if let Some(bname) = basis_name {
             if bname.len() > BASIS_NAME_LEN - 1 {
@@ -613,6 +847,76 @@ impl Pddb {
         }
         Ok(dict_list)
     }
+    /// Writes every dictionary and key in `basis_name` to `out` as an encrypted archive, sealed with `password`.
+    /// See `archive.rs` for a description of the format. Key data is streamed, so very large keys do not
+    /// need to fit in memory. Key flags are not carried in the archive.
+    pub fn export_basis(&self, basis_name: &str, password: &str, out: &mut impl Write) -> Result<()> {
+        let mut salt = [0u8; ARCHIVE_SALT_LEN];
+        for chunk in salt.chunks_mut(8) {
+            chunk.copy_from_slice(&self.trng.get_u64().or(Err(Error::new(ErrorKind::Other, "TRNG error")))?.to_le_bytes());
+        }
+        let mut nonce_prefix = [0u8; ARCHIVE_NONCE_PREFIX_LEN];
+        let nonce_rand = self.trng.get_u64().or(Err(Error::new(ErrorKind::Other, "TRNG error")))?.to_le_bytes();
+        nonce_prefix.copy_from_slice(&nonce_rand[..ARCHIVE_NONCE_PREFIX_LEN]);
+
+        let mut archive = ArchiveWriter::new(out, password, salt, nonce_prefix)?;
+        let mut buf = [0u8; 4096];
+        for dict in self.list_dict(Some(basis_name))? {
+            archive.dict(&dict)?;
+            for key in self.list_keys(&dict, Some(basis_name))? {
+                let mut pkey = self.get(&dict, &key, Some(basis_name), false, false, None, None::<fn()>)?;
+                let attr = pkey.attributes()?;
+                archive.key(&key, attr.reserved, attr.age as u32, attr.len)?;
+                let mut remaining = attr.len;
+                while remaining > 0 {
+                    let readlen = pkey.read(&mut buf)?;
+                    if readlen == 0 {
+                        return Err(Error::new(ErrorKind::UnexpectedEof, "key shrank while it was being exported"));
+                    }
+                    let readlen = core::cmp::min(readlen, remaining);
+                    archive.key_data(&buf[..readlen])?;
+                    remaining -= readlen;
+                }
+            }
+        }
+        archive.finish()?;
+        Ok(())
+    }
+    /// Restores the contents of an archive created by `export_basis()` into `basis_name`. Dictionaries and
+    /// keys are created as needed; keys that already exist are overwritten. The archive is authenticated
+    /// chunk-by-chunk as it is read, so a corrupted archive may be partially applied before an error is returned.
+    pub fn import_basis(&self, basis_name: &str, password: &str, input: &mut impl Read) -> Result<()> {
+        let mut archive = ArchiveReader::new(input, password)?;
+        let mut buf = [0u8; 4096];
+        let mut dict: Option<String> = None;
+        loop {
+            match archive.next_record()? {
+                ArchiveRecord::Dict(name) => dict = Some(name),
+                ArchiveRecord::Key{name, reserved, age: _, len} => {
+                    let dict_name = dict.as_ref()
+                        .ok_or(Error::new(ErrorKind::InvalidData, "archive has a key outside of a dictionary"))?;
+                    let mut pkey = self.get(dict_name, &name, Some(basis_name), true, true,
+                        Some(core::cmp::max(reserved, len)), None::<fn()>)?;
+                    if pkey.attributes()?.len > len {
+                        // remove stale data past the end of the archived copy
+                        drop(pkey);
+                        self.delete_key(dict_name, &name, Some(basis_name))?;
+                        pkey = self.get(dict_name, &name, Some(basis_name), true, true,
+                            Some(core::cmp::max(reserved, len)), None::<fn()>)?;
+                    }
+                    loop {
+                        let readlen = archive.read_key_data(&mut buf)?;
+                        if readlen == 0 {
+                            break;
+                        }
+                        pkey.write_all(&buf[..readlen])?;
+                    }
+                }
+                ArchiveRecord::End => break,
+            }
+        }
+        self.sync()
+    }
     /// Public function to query efuse security state. Replicated here to avoid exposing RootKeys full API to the world.
     pub fn is_efuse_secured(&self) -> bool {
         let response = send_message(self.conn,
@@ -657,6 +961,11 @@ impl Pddb {
 
 impl Drop for Pddb {
     fn drop(&mut self) {
+        // cancel subscriptions while the server can still be reached, so it stops notifying a dead callback server
+        let tokens: Vec::<ApiToken> = self.subscriptions.lock().unwrap().keys().copied().collect();
+        for token in tokens {
+            self.unsubscribe_dict(token).ok();
+        }
         if let Some(cb_sid) = self.cb.take() {
             let handle = self.cb_handle.take().unwrap(); // we guarantee this is always set when cb is set
             let cid = xous::connect(cb_sid).unwrap();
@@ -372,7 +372,11 @@ mod ux;
 use ux::*;
 mod menu;
 use menu::*;
+mod bcrypt;
+mod txn;
 
+#[cfg(not(any(target_os = "none", target_os = "xous")))]
+mod archive;
 #[cfg(not(any(target_os = "none", target_os = "xous")))]
 mod tests;
 #[cfg(not(any(target_os = "none", target_os = "xous")))]
@@ -412,6 +416,26 @@ struct TokenRecord {
     pub basis: Option<String>,
     pub alloc_hint: Option<usize>,
     pub conn: Option<xous::CID>, // callback connection, if one was specified
+    /// set when the key was written through this token, and a `KeyUpdated` notification is owed
+    pub dirty: bool,
+}
+
+/// A dictionary change-notification subscription
+#[derive(Debug)]
+struct Subscription {
+    pub dict: String,
+    pub basis: Option<String>,
+    pub conn: xous::CID,
+}
+/// Limit on the number of subscriptions, so a misbehaving client can't exhaust our outgoing connections.
+const MAX_SUBSCRIPTIONS: usize = 32;
+
+/// Interval at which the `TimeOutSecs` retention policies are checked, while any such basis is mounted.
+const BASIS_TIMEOUT_POLL_MS: usize = 1000;
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+enum TimeoutPumpOp {
+    Start,
+    Quit,
 }
 
 fn main() -> ! {
@@ -437,6 +461,8 @@ fn main() -> ! {
     let mut basis_cache = BasisCache::new();
     // storage for the token lookup: given an ApiToken, return a dict/key/basis set. Basis can be None or specified.
     let mut token_dict = HashMap::<ApiToken, TokenRecord>::new();
+    // storage for dictionary change subscriptions
+    let mut subscriptions = HashMap::<ApiToken, Subscription>::new();
 
     // mount poller thread
     let is_mounted = Arc::new(AtomicBool::new(false));
@@ -508,6 +534,43 @@ fn main() -> ! {
             ).expect("couldn't send mount request");
         }
     });
+    // basis timeout pump. It's idle until a basis with a `TimeOutSecs` policy is unlocked, and it goes back
+    // to idle once the main loop reports that no such basis remain, so we aren't waking up the CPU for nothing.
+    let timeout_sid = xous::create_server().expect("couldn't create basis timeout pump server");
+    let timeout_cid = xous::connect(timeout_sid).expect("couldn't connect to basis timeout pump");
+    let _ = thread::spawn({
+        let my_cid = my_cid.clone();
+        move || {
+            let tt = ticktimer_server::Ticktimer::new().unwrap();
+            loop {
+                let msg = xous::receive_message(timeout_sid).unwrap();
+                match FromPrimitive::from_usize(msg.body.id()) {
+                    Some(TimeoutPumpOp::Start) => {
+                        loop {
+                            tt.sleep_ms(BASIS_TIMEOUT_POLL_MS).unwrap();
+                            match send_message(my_cid,
+                                Message::new_blocking_scalar(Opcode::BasisTimeoutPoll.to_usize().unwrap(), 0, 0, 0, 0)
+                            ) {
+                                Ok(xous::Result::Scalar1(active)) => {
+                                    if active == 0 {
+                                        break;
+                                    }
+                                }
+                                _ => {
+                                    log::error!("basis timeout poll failed, stopping the pump");
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    Some(TimeoutPumpOp::Quit) => break,
+                    None => log::warn!("got unrecognized message: {:?}", msg),
+                }
+            }
+            xous::destroy_server(timeout_sid).ok();
+        }
+    });
+    let mut timeout_pump_running = false;
     // main server loop
     let mut key_list = Vec::<String>::new(); // storage for key lists
     let mut key_token: Option<[u32; 4]> = None;
@@ -528,9 +591,28 @@ fn main() -> ! {
         let mut msg = xous::receive_message(pddb_sid).unwrap();
         match FromPrimitive::from_usize(msg.body.id()) {
             Some(Opcode::SuspendResume) => xous::msg_scalar_unpack!(msg, token, _, _, _, {
-                basis_cache.suspend(&mut pddb_os);
+                for basis in basis_cache.suspend(&mut pddb_os) {
+                    notify_dict_change(&mut subscriptions, &token_dict, DictChangeKind::BasisUnmounted, None, None, Some(&basis));
+                }
                 susres.suspend_until_resume(token).expect("couldn't execute suspend/resume");
             }),
+            Some(Opcode::BasisTimeoutPoll) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                for basis in basis_cache.basis_timed_out(&pddb_os) {
+                    log::info!("locking basis on inactivity timeout: {}", &basis);
+                    // same cleanup path as an explicit CloseBasis request
+                    notify_of_disconnect(&mut pddb_os, &token_dict, &mut basis_cache);
+                    match basis_cache.basis_unmount(&mut pddb_os, &basis) {
+                        Ok(_) => notify_dict_change(&mut subscriptions, &token_dict, DictChangeKind::BasisUnmounted, None, None, Some(&basis)),
+                        Err(e) => log::error!("couldn't lock basis {} on timeout: {:?}", &basis, e),
+                    }
+                }
+                if basis_cache.has_timeout_policy() {
+                    xous::return_scalar(msg.sender, 1).unwrap();
+                } else {
+                    timeout_pump_running = false;
+                    xous::return_scalar(msg.sender, 0).unwrap();
+                }
+            }),
             Some(Opcode::IsEfuseSecured) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
                 if pddb_os.is_efuse_secured() {
                     xous::return_scalar(msg.sender, 1).unwrap();
@@ -657,6 +739,18 @@ fn main() -> ! {
                                     mgmt.policy.unwrap_or(BasisRetentionPolicy::Persist)
                                 ) {
                                     basis_cache.basis_add(basis);
+                                    let name = mgmt.name.as_str().expect("name is not valid utf-8");
+                                    if let Err(e) = basis_cache.txn_recover(&mut pddb_os, name) {
+                                        log::error!("couldn't recover interrupted transaction in basis {}: {:?}", name, e);
+                                    }
+                                    notify_dict_change(&mut subscriptions, &token_dict, DictChangeKind::BasisMounted,
+                                        None, None, Some(mgmt.name.as_str().expect("name is not valid utf-8")));
+                                    if basis_cache.has_timeout_policy() && !timeout_pump_running {
+                                        send_message(timeout_cid,
+                                            Message::new_scalar(TimeoutPumpOp::Start.to_usize().unwrap(), 0, 0, 0, 0)
+                                        ).expect("couldn't start basis timeout pump");
+                                        timeout_pump_running = true;
+                                    }
                                     finished = true;
                                     mgmt.code = PddbRequestCode::NoErr;
                                 } else {
@@ -697,7 +791,11 @@ fn main() -> ! {
                 match mgmt.code {
                     PddbRequestCode::Close => {
                         match basis_cache.basis_unmount(&mut pddb_os, mgmt.name.as_str().expect("name is not valid utf-8")) {
-                            Ok(_) => mgmt.code = PddbRequestCode::NoErr,
+                            Ok(_) => {
+                                notify_dict_change(&mut subscriptions, &token_dict, DictChangeKind::BasisUnmounted,
+                                    None, None, Some(mgmt.name.as_str().expect("name is not valid utf-8")));
+                                mgmt.code = PddbRequestCode::NoErr
+                            }
                             Err(e) => match e.kind() {
                                 ErrorKind::NotFound => mgmt.code = PddbRequestCode::NotFound,
                                 _ => mgmt.code = PddbRequestCode::InternalError,
@@ -717,7 +815,11 @@ fn main() -> ! {
                 match mgmt.code {
                     PddbRequestCode::Delete => {
                         match basis_cache.basis_delete(&mut pddb_os, mgmt.name.as_str().expect("name is not valid utf-8")) {
-                            Ok(_) => mgmt.code = PddbRequestCode::NoErr,
+                            Ok(_) => {
+                                notify_dict_change(&mut subscriptions, &token_dict, DictChangeKind::BasisUnmounted,
+                                    None, None, Some(mgmt.name.as_str().expect("name is not valid utf-8")));
+                                mgmt.code = PddbRequestCode::NoErr
+                            }
                             Err(e) => match e.kind() {
                                 ErrorKind::NotFound => mgmt.code = PddbRequestCode::NotFound,
                                 _ => mgmt.code = PddbRequestCode::InternalError,
@@ -740,6 +842,10 @@ fn main() -> ! {
                 };
                 let dict = req.dict.as_str().expect("dict utf-8 decode error");
                 let key = req.key.as_str().expect("key utf-8 decode error");
+                if is_reserved_dict(dict) {
+                    req.result = PddbRequestCode::AccessDenied;
+                    buffer.replace(req).unwrap(); continue
+                }
                 if basis_cache.dict_attributes(&mut pddb_os, dict, bname).is_err() {
                     if req.create_dict {
                         match basis_cache.dict_add(&mut pddb_os, dict, bname) {
@@ -768,7 +874,11 @@ fn main() -> ! {
                         match basis_cache.key_update(&mut pddb_os,
                             dict, key, &empty, None, alloc_hint, bname, true
                         ) {
-                            Ok(_) => {},
+                            Ok(_) => {
+                                let created_in = basis_cache.key_attributes(&mut pddb_os, dict, key, bname).ok().map(|a| a.basis);
+                                notify_dict_change(&mut subscriptions, &token_dict, DictChangeKind::KeyCreated,
+                                    Some(dict), Some(key), created_in.as_deref());
+                            },
                             Err(e) => {
                                 log::error!("Couldn't allocate key: {:?}", e);
                                 match e.kind() {
@@ -794,6 +904,7 @@ fn main() -> ! {
                     basis: if let Some(name) = bname {Some(String::from(name))} else {None},
                     conn: cid,
                     alloc_hint,
+                    dirty: false,
                 };
                 token_dict.insert(token, token_record);
                 req.token = Some(token);
@@ -803,21 +914,15 @@ fn main() -> ! {
             Some(Opcode::KeyDrop) => msg_blocking_scalar_unpack!(msg, t0, t1, t2, _, {
                 let token: ApiToken = [t0 as u32, t1 as u32, t2 as u32];
                 if let Some(rec) = token_dict.remove(&token) {
+                    if rec.dirty {
+                        notify_dict_change(&mut subscriptions, &token_dict, DictChangeKind::KeyUpdated,
+                            Some(&rec.dict), Some(&rec.key), rec.basis.as_deref());
+                    }
                     // now check if we can safely disconnect and recycle our connection number.
                     // This is important because we can only have 32 outgoing connections...
                     if let Some(conn_to_remove) = rec.conn {
-                        let mut still_needs_cid = false;
-                        for r in token_dict.values() {
-                            // check through the remaining dictionary values to see if they have a connection that is the same as our number
-                            if let Some(existing_conn) = r.conn {
-                                if existing_conn == conn_to_remove {
-                                    still_needs_cid = true;
-                                    break;
-                                }
-                            }
-                        }
                         // if nobody else had my connection number, disconnect it.
-                        if !still_needs_cid {
+                        if !cid_in_use(&token_dict, &subscriptions, conn_to_remove) {
                             unsafe{xous::disconnect(conn_to_remove).expect("couldn't disconnect from callback server")};
                         }
                     } else {
@@ -826,6 +931,41 @@ fn main() -> ! {
                 }
                 xous::return_scalar(msg.sender, 1).expect("couldn't ack KeyDrop");
             }),
+            Some(Opcode::DictSubscribe) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbSubscribeRequest, _>().unwrap();
+                if is_reserved_dict(req.dict.as_str().unwrap_or("")) {
+                    req.code = PddbRequestCode::AccessDenied;
+                    buffer.replace(req).unwrap();
+                    continue;
+                }
+                if subscriptions.len() >= MAX_SUBSCRIPTIONS {
+                    req.code = PddbRequestCode::NoFreeSpace;
+                    buffer.replace(req).unwrap();
+                    continue;
+                }
+                let token: ApiToken = [pddb_os.trng_u32(), pddb_os.trng_u32(), pddb_os.trng_u32()];
+                let conn = xous::connect(xous::SID::from_array(req.cb_sid)).expect("couldn't connect for callback");
+                subscriptions.insert(token, Subscription {
+                    dict: String::from(req.dict.as_str().expect("dict utf-8 decode error")),
+                    basis: if req.basis_specified {Some(String::from(req.basis.as_str().expect("basis utf-8 decode error")))} else {None},
+                    conn,
+                });
+                req.token = Some(token);
+                req.code = PddbRequestCode::NoErr;
+                buffer.replace(req).unwrap();
+            }
+            Some(Opcode::DictUnsubscribe) => msg_blocking_scalar_unpack!(msg, t0, t1, t2, _, {
+                let token: ApiToken = [t0 as u32, t1 as u32, t2 as u32];
+                if let Some(sub) = subscriptions.remove(&token) {
+                    if !cid_in_use(&token_dict, &subscriptions, sub.conn) {
+                        unsafe{xous::disconnect(sub.conn).expect("couldn't disconnect from callback server")};
+                    }
+                    xous::return_scalar(msg.sender, 1).expect("couldn't ack DictUnsubscribe");
+                } else {
+                    xous::return_scalar(msg.sender, 0).expect("couldn't ack DictUnsubscribe");
+                }
+            }),
             Some(Opcode::DeleteKey) => {
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
                 let mut req: PddbKeyRequest = buffer.to_original::<PddbKeyRequest, _>().unwrap();
@@ -836,8 +976,14 @@ fn main() -> ! {
                 };
                 let dict = req.dict.as_str().expect("dict utf-8 decode error");
                 let key = req.key.as_str().expect("key utf-8 decode error");
-                match basis_cache.key_remove(&mut pddb_os, dict, key, bname, false) {
+                if is_reserved_dict(dict) {
+                    req.result = PddbRequestCode::AccessDenied;
+                    buffer.replace(req).unwrap();
+                    continue;
+                }
+                match basis_cache.key_remove(&mut pddb_os, dict, key, bname, req.paranoid) {
                     Ok(_) => {
+                        notify_dict_change(&mut subscriptions, &token_dict, DictChangeKind::KeyDeleted, Some(dict), Some(key), bname);
                         let mut evict_list = Vec::<ApiToken>::new();
                         // check to see if we need to eliminate any ApiTokens as a result of this.
                         for (token, rec) in token_dict.iter() {
@@ -870,6 +1016,7 @@ fn main() -> ! {
                     Err(e) => {
                         match e.kind() {
                             std::io::ErrorKind::NotFound => req.result = PddbRequestCode::NotFound,
+                            std::io::ErrorKind::OutOfMemory => req.result = PddbRequestCode::NoFreeSpace,
                             _ => req.result = PddbRequestCode::InternalError,
                         }
                     }
@@ -885,9 +1032,15 @@ fn main() -> ! {
                     None
                 };
                 let dict = req.dict.as_str().expect("dict utf-8 decode error");
+                if is_reserved_dict(dict) {
+                    req.result = PddbRequestCode::AccessDenied;
+                    buffer.replace(req).unwrap();
+                    continue;
+                }
                 log::debug!("attempting to remove dict {} basis {:?}", dict, bname);
-                match basis_cache.dict_remove(&mut pddb_os, dict, bname, false) {
+                match basis_cache.dict_remove(&mut pddb_os, dict, bname, req.paranoid) {
                     Ok(_) => {
+                        notify_dict_change(&mut subscriptions, &token_dict, DictChangeKind::DictDeleted, Some(dict), None, bname);
                         let mut evict_list = Vec::<ApiToken>::new();
                         // check to see if we need to eliminate any ApiTokens as a result of this.
                         for (token, rec) in token_dict.iter() {
@@ -926,6 +1079,94 @@ fn main() -> ! {
                 }
                 buffer.replace(req).unwrap();
             }
+            Some(Opcode::TxnCommit) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let raw = buffer.as_mut();
+                if raw.len() < TXN_HEADER_LEN {
+                    log::error!("TxnCommit buffer is too short");
+                    continue;
+                }
+                let ops_len = u32::from_le_bytes([raw[4], raw[5], raw[6], raw[7]]) as usize;
+                let name_len = raw[8..TXN_HEADER_LEN].iter().position(|&c| c == 0).unwrap_or(BASIS_NAME_LEN);
+                let retcode = if ops_len > TXN_MAX_LEN || TXN_HEADER_LEN + ops_len > raw.len() {
+                    PddbRetcode::InternalError
+                } else {
+                    match (std::str::from_utf8(&raw[8..8 + name_len]), txn::txn_decode(&raw[TXN_HEADER_LEN..TXN_HEADER_LEN + ops_len])) {
+                        (Ok(bname), Ok(ops)) => {
+                            // note which keys exist beforehand, so subscribers can be told if a write created or updated a key
+                            let existed: Vec::<bool> = ops.iter().map(|op|
+                                basis_cache.key_attributes(&mut pddb_os, op.dict(), op.key(), Some(bname)).is_ok()
+                            ).collect();
+                            match basis_cache.txn_commit(&mut pddb_os, bname, &ops) {
+                                Ok(TxnStatus::Pending) => {
+                                    // the keys are in flux until the log is replayed, so hold off on notifications
+                                    log::warn!("transaction in basis {} will complete on the next mount", bname);
+                                    PddbRetcode::TxnPending
+                                }
+                                Ok(TxnStatus::Applied) => {
+                                    for (op, &existed) in ops.iter().zip(existed.iter()) {
+                                        match op {
+                                            txn::TxnOp::Write{dict, key, ..} => {
+                                                let kind = if existed { DictChangeKind::KeyUpdated } else { DictChangeKind::KeyCreated };
+                                                notify_dict_change(&mut subscriptions, &token_dict, kind, Some(dict), Some(key), Some(bname));
+                                            }
+                                            txn::TxnOp::Delete{dict, key} => {
+                                                if !existed {
+                                                    continue;
+                                                }
+                                                notify_dict_change(&mut subscriptions, &token_dict, DictChangeKind::KeyDeleted, Some(dict), Some(key), Some(bname));
+                                                // tokens for the deleted key, in this basis or in the union of bases, are no longer valid
+                                                token_dict.retain(|_, rec|
+                                                    !(&rec.dict == dict && &rec.key == key && rec.basis.as_ref().map_or(true, |b| b == bname))
+                                                );
+                                            }
+                                        }
+                                    }
+                                    PddbRetcode::Ok
+                                }
+                                Err(e) => {
+                                    log::warn!("transaction in basis {} failed: {:?}", bname, e);
+                                    match e.kind() {
+                                        std::io::ErrorKind::NotFound => PddbRetcode::BasisLost,
+                                        std::io::ErrorKind::PermissionDenied => PddbRetcode::AccessDenied,
+                                        std::io::ErrorKind::OutOfMemory => PddbRetcode::DiskFull,
+                                        _ => PddbRetcode::InternalError,
+                                    }
+                                }
+                            }
+                        }
+                        _ => {
+                            log::error!("TxnCommit request is malformed");
+                            PddbRetcode::InternalError
+                        }
+                    }
+                };
+                buffer.as_mut()[..4].copy_from_slice(&(retcode as u32).to_le_bytes());
+            }
+            Some(Opcode::Fsck) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbFsckRequest, _>().unwrap();
+                let bname = if req.basis_specified {
+                    Some(req.basis.as_str().unwrap())
+                } else {
+                    None
+                };
+                log::info!("fsck of basis {:?}, repair: {}", bname, req.repair);
+                match basis_cache.fsck(&mut pddb_os, bname, req.repair) {
+                    Ok(report) => {
+                        req.report = report;
+                        req.code = PddbRequestCode::NoErr;
+                    }
+                    Err(e) => {
+                        log::error!("fsck failed: {:?}", e);
+                        match e.kind() {
+                            std::io::ErrorKind::NotFound => req.code = PddbRequestCode::NotFound,
+                            _ => req.code = PddbRequestCode::InternalError,
+                        }
+                    }
+                }
+                buffer.replace(req).unwrap();
+            }
             Some(Opcode::KeyAttributes) => {
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
                 let mut req = buffer.to_original::<PddbKeyAttrIpc, _>().unwrap();
@@ -955,6 +1196,12 @@ fn main() -> ! {
                     buffer.replace(req).unwrap();
                     continue;
                 }
+                // reserved dictionaries are hidden, as in the dictionary listing
+                if is_reserved_dict(req.dict.as_str().unwrap_or("")) {
+                    req.code = PddbRequestCode::NotFound;
+                    buffer.replace(req).unwrap();
+                    continue;
+                }
                 key_token = Some(req.token);
                 key_list.clear();
                 let bname = if req.basis_specified {
@@ -1034,7 +1281,8 @@ fn main() -> ! {
                 } else {
                     None
                 };
-                let list = basis_cache.dict_list(&mut pddb_os, bname);
+                let list: Vec<String> = basis_cache.dict_list(&mut pddb_os, bname)
+                    .into_iter().filter(|dict| !is_reserved_dict(dict)).collect();
                 if list.len() > 0 {
                     req.index = list.len() as u32;
                     for dict in list {
@@ -1071,6 +1319,88 @@ fn main() -> ! {
                 }
                 buffer.replace(req).unwrap();
             }
+            Some(Opcode::ListKeyAttributes) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbKeyAttrList, _>().unwrap();
+                // owned copies, because the page entries in `req` are filled in while these are in use
+                let basis = if req.basis_specified {
+                    Some(String::from(req.basis.as_str().unwrap()))
+                } else {
+                    None
+                };
+                let bname = basis.as_deref();
+                let dict = String::from(req.dict.as_str().expect("dict utf-8 decode error"));
+                if is_reserved_dict(&dict) {
+                    req.code = PddbRequestCode::NotFound;
+                    buffer.replace(req).unwrap();
+                    continue;
+                }
+                // the first page snapshots the key list; this shares state with KeyCountInDict/GetKeyNameAtIndex,
+                // so only one key listing of either type can be in progress at a time.
+                if req.index == 0 {
+                    if key_token.is_some() {
+                        log::debug!("key list already in progress");
+                        req.code = PddbRequestCode::AccessDenied;
+                        buffer.replace(req).unwrap();
+                        continue;
+                    }
+                    key_list.clear();
+                    match basis_cache.key_list(&mut pddb_os, &dict, bname) {
+                        Ok(list) => {
+                            for key in list {
+                                key_list.push(key);
+                            }
+                        }
+                        Err(e) => {
+                            match e.kind() {
+                                std::io::ErrorKind::NotFound => req.code = PddbRequestCode::NotFound,
+                                _ => req.code = PddbRequestCode::InternalError,
+                            }
+                            buffer.replace(req).unwrap();
+                            continue;
+                        }
+                    }
+                    if key_list.len() > 0 {
+                        key_token = Some(req.token);
+                    }
+                } else if key_token != Some(req.token) {
+                    req.code = PddbRequestCode::AccessDenied;
+                    buffer.replace(req).unwrap();
+                    continue;
+                } else if req.index >= key_list.len() as u32 {
+                    req.code = PddbRequestCode::InternalError;
+                    buffer.replace(req).unwrap();
+                    continue;
+                }
+                let start = req.index as usize;
+                let end = if start + KEY_ATTR_LIST_PAGE < key_list.len() {
+                    start + KEY_ATTR_LIST_PAGE
+                } else {
+                    key_list.len()
+                };
+                for (key, entry) in key_list[start..end].iter().zip(req.entries.iter_mut()) {
+                    *entry = PddbKeyAttrEntry::default();
+                    entry.name = xous_ipc::String::<KEY_NAME_LEN>::from_str(key);
+                    // a key can vanish between the snapshot and the lookup; that's signalled with an index of 0
+                    if let Ok(attr) = basis_cache.key_attributes(&mut pddb_os, &dict, key, bname) {
+                        entry.basis = xous_ipc::String::<BASIS_NAME_LEN>::from_str(&attr.basis);
+                        entry.len = attr.len as u64;
+                        entry.reserved = attr.reserved as u64;
+                        entry.age = attr.age as u64;
+                        entry.flags = attr.flags.0;
+                        entry.index = attr.index.get();
+                    }
+                }
+                req.total = key_list.len() as u32;
+                req.count = (end - start) as u32;
+                req.code = PddbRequestCode::NoErr;
+                if end == key_list.len() {
+                    log::debug!("last key attribute page, resetting state");
+                    key_token = None;
+                    key_list.clear();
+                }
+                buffer.replace(req).unwrap();
+            }
             Some(Opcode::ReadKey) => {
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
                 let pbuf = PddbBuf::from_slice_mut(buffer.as_mut()); // direct translation, no serialization necessary for performance
@@ -1100,7 +1430,7 @@ fn main() -> ! {
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
                 let pbuf = PddbBuf::from_slice_mut(buffer.as_mut()); // direct translation, no serialization necessary for performance
                 let token = pbuf.token;
-                if let Some(rec) = token_dict.get(&token) {
+                if let Some(rec) = token_dict.get_mut(&token) {
                     match basis_cache.key_update(&mut pddb_os,
                         &rec.dict, &rec.key,
                         &pbuf.data[..pbuf.len as usize], Some(pbuf.position as usize),
@@ -1108,6 +1438,7 @@ fn main() -> ! {
                         false
                     ) {
                         Ok(_) => {
+                            rec.dirty = true;
                             pbuf.retcode = PddbRetcode::Ok;
                         }
                         Err(e) => match e.kind() {
@@ -1126,6 +1457,17 @@ fn main() -> ! {
                 basis_cache.sync(&mut pddb_os, None).expect("couldn't sync basis");
             }
             Some(Opcode::WriteKeyFlush) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                let mut updated = Vec::<(String, String, Option<String>)>::new();
+                for rec in token_dict.values_mut() {
+                    if rec.dirty {
+                        rec.dirty = false;
+                        updated.push((rec.dict.clone(), rec.key.clone(), rec.basis.clone()));
+                    }
+                }
+                for (dict, key, basis) in updated {
+                    notify_dict_change(&mut subscriptions, &token_dict, DictChangeKind::KeyUpdated,
+                        Some(&dict), Some(&key), basis.as_deref());
+                }
                 match basis_cache.sync(&mut pddb_os, None) {
                     Ok(_) => xous::return_scalar(msg.sender, PddbRetcode::Ok.to_usize().unwrap()).unwrap(),
                     Err(e) => match e.kind() {
@@ -1158,6 +1500,9 @@ fn main() -> ! {
                         if let Some(sys_basis) = pddb_os.pddb_mount() {
                             log::info!("remount successful");
                             basis_cache.basis_add(sys_basis);
+                            if let Err(e) = basis_cache.txn_recover(&mut pddb_os, PDDB_DEFAULT_SYSTEM_BASIS) {
+                                log::error!("couldn't recover interrupted transaction in the system basis: {:?}", e);
+                            }
                         } else {
                             log::info!("remount failed");
                         }
@@ -1170,6 +1515,11 @@ fn main() -> ! {
                     pw_cid,
                     Message::new_blocking_scalar(PwManagerOpcode::Quit.to_usize().unwrap(), 0, 0, 0, 0)
                 ).unwrap();
+                // non-blocking, as the pump may itself be blocked waiting on a poll response from us
+                send_message(
+                    timeout_cid,
+                    Message::new_scalar(TimeoutPumpOp::Quit.to_usize().unwrap(), 0, 0, 0, 0)
+                ).unwrap();
                 xous::return_scalar(msg.sender, 0).unwrap();
                 break
             }
@@ -1237,6 +1587,9 @@ fn try_mount_or_format(modals: &modals::Modals, pddb_os: &mut PddbOs, basis_cach
         if let Some(sys_basis) = pddb_os.pddb_mount() {
             log::info!("PDDB mount operation finished successfully");
             basis_cache.basis_add(sys_basis);
+            if let Err(e) = basis_cache.txn_recover(pddb_os, PDDB_DEFAULT_SYSTEM_BASIS) {
+                log::error!("couldn't recover interrupted transaction in the system basis: {:?}", e);
+            }
             return true
         }
     }
@@ -1430,6 +1783,54 @@ pub(crate) fn hw_testcase(pddb_os: &mut PddbOs) {
     pddb_os.dbg_dump(Some("manual".to_string()), None);
 }
 
+/// Checks if a callback connection is still referenced by any key token or subscription.
+fn cid_in_use(token_dict: &HashMap::<ApiToken, TokenRecord>, subscriptions: &HashMap::<ApiToken, Subscription>, cid: xous::CID) -> bool {
+    token_dict.values().any(|r| r.conn == Some(cid)) || subscriptions.values().any(|s| s.conn == cid)
+}
+
+/// Sends a change notification to every subscription that matches the dictionary and basis of the change.
+/// A `None` dict means the change applies to every dictionary (basis mount/unmount). A `None` basis means
+/// the change applies to the union of bases. Subscribers that can no longer be reached are dropped.
+fn notify_dict_change(subscriptions: &mut HashMap::<ApiToken, Subscription>, token_dict: &HashMap::<ApiToken, TokenRecord>,
+    kind: DictChangeKind, dict: Option<&str>, key: Option<&str>, basis: Option<&str>
+) {
+    let mut dead = Vec::<ApiToken>::new();
+    for (token, sub) in subscriptions.iter() {
+        if let Some(d) = dict {
+            if d != sub.dict {
+                continue;
+            }
+        }
+        if let (Some(b), Some(sub_b)) = (basis, &sub.basis) {
+            if b != sub_b {
+                continue;
+            }
+        }
+        let change = PddbDictChange {
+            token: *token,
+            kind,
+            dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(&sub.dict),
+            key: xous_ipc::String::<KEY_NAME_LEN>::from_str(key.unwrap_or("")),
+            basis: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis.unwrap_or("")),
+        };
+        let buf = Buffer::into_buf(change).expect("couldn't serialize dict change");
+        match buf.send(sub.conn, pddb::CbOp::DictChange.to_u32().unwrap()) {
+            Ok(_) => log::debug!("{:?} notification for {}:{:?} sent", kind, &sub.dict, key),
+            Err(e) => {
+                log::warn!("Dict change notification for {} failed, dropping subscription: {:?}", &sub.dict, e);
+                dead.push(*token);
+            }
+        }
+    }
+    for token in dead {
+        if let Some(sub) = subscriptions.remove(&token) {
+            if !cid_in_use(token_dict, subscriptions, sub.conn) {
+                unsafe{xous::disconnect(sub.conn).ok()};
+            }
+        }
+    }
+}
+
 fn notify_of_disconnect(pddb_os: &mut PddbOs, token_dict: &HashMap::<ApiToken, TokenRecord>, basis_cache: &mut BasisCache) {
     // 1. search to see if any of the active tokens are are in our token_dict
     // 2. notify them of the disconnect, if there is a callback set.
@@ -2,8 +2,10 @@ use rand_chacha::ChaCha8Rng;
 use rand_chacha::rand_core::RngCore;
 use rand_chacha::rand_core::SeedableRng;
 use crate::*;
+use crate::txn::*;
 use core::sync::atomic::{AtomicU64, Ordering};
 use std::collections::{BTreeSet, HashSet};
+use std::mem::size_of;
 use std::io::Result;
 
 const UPPER_BOUND: usize = 9000;
@@ -220,6 +222,434 @@ pub(crate) fn patch_test(hw: &mut PddbOs, basis_cache: &mut BasisCache,
     Ok(())
 }
 
+/// streams a large key in and out in `PddbBuf`-sized chunks, checking unaligned reads and patches
+/// along the way. The key and its dictionary are removed at the end, so the check scripts never see them.
+pub(crate) fn large_stream_test(hw: &mut PddbOs, basis_cache: &mut BasisCache, maybe_len: Option<usize>) -> Result<()> {
+    const DICT: &'static str = "largestream";
+    const KEY: &'static str = "stream";
+    const CHUNK: usize = 4072; // size of the data field in a PddbBuf
+    let len = maybe_len.unwrap_or(256 * 1024);
+
+    let mut model = Vec::<u8>::new();
+    for i in 0..len {
+        model.push((i + i / VPAGE_SIZE) as u8);
+    }
+    // 1. stream the data in
+    for (index, chunk) in model.chunks(CHUNK).enumerate() {
+        basis_cache.key_update(hw, DICT, KEY, chunk, Some(index * CHUNK), None, None, false)?;
+    }
+    // 2. stream the data back out
+    let mut readback = [0u8; CHUNK];
+    let mut offset = 0;
+    while offset < len {
+        let readlen = basis_cache.key_read(hw, DICT, KEY, &mut readback, Some(offset), None)?;
+        assert!(readlen > 0, "large key read returned no data before the end of the key");
+        assert!(readback[..readlen] == model[offset..offset + readlen], "large key data mismatch at offset {}", offset);
+        offset += readlen;
+    }
+    assert!(basis_cache.key_read(hw, DICT, KEY, &mut readback, Some(len), None)? == 0, "read at end of key returned data");
+    assert!(basis_cache.key_read(hw, DICT, KEY, &mut readback, Some(len + 1), None).is_err(), "read past end of key did not fail");
+    // 3. patch a region that straddles several vpages, starting at an unaligned offset
+    let patch_offset = len / 3 + 7;
+    let patch = [0xA5u8; 3 * VPAGE_SIZE];
+    basis_cache.key_update(hw, DICT, KEY, &patch, Some(patch_offset), None, None, false)?;
+    for (&src, dst) in patch.iter().zip(model[patch_offset..].iter_mut()) {
+        *dst = src;
+    }
+    // 4. spot-check unaligned reads around the patch boundaries
+    for &check_offset in [patch_offset - 13, patch_offset + patch.len() - 13, len / 2 + 13, len - 100].iter() {
+        let readlen = basis_cache.key_read(hw, DICT, KEY, &mut readback[..1000], Some(check_offset), None)?;
+        let expected = if len - check_offset < 1000 { len - check_offset } else { 1000 };
+        assert!(readlen == expected, "short read at offset {}: {}/{}", check_offset, readlen, expected);
+        assert!(readback[..readlen] == model[check_offset..check_offset + readlen], "large key data mismatch at offset {}", check_offset);
+    }
+    basis_cache.key_remove(hw, DICT, KEY, None, false)?;
+    basis_cache.dict_remove(hw, DICT, None, false)?;
+    Ok(())
+}
+
+/// paranoid-erases some small keys out of a shared small pool block, and confirms the survivors are
+/// re-packed intact. Finishes with a paranoid delete of the whole dictionary.
+pub(crate) fn paranoid_erase_test(hw: &mut PddbOs, basis_cache: &mut BasisCache) -> Result<()> {
+    const DICT: &'static str = "paranoid";
+    const NUM_KEYS: usize = 8;
+    let mut keys = Vec::<(String, Vec::<u8>)>::new();
+    for keynum in 0..NUM_KEYS {
+        let name = format!("secret{}", keynum);
+        let mut data = Vec::<u8>::new();
+        for i in 0..(64 + keynum * 17) {
+            data.push((keynum * 31 + i) as u8);
+        }
+        basis_cache.key_update(hw, DICT, &name, &data, None, None, None, true)?;
+        keys.push((name, data));
+    }
+    // erase every other key; each erase re-packs the block the keys share
+    for (name, _) in keys.iter().step_by(2) {
+        basis_cache.key_remove(hw, DICT, name, None, true)?;
+    }
+    let key_list = basis_cache.key_list(hw, DICT, None)?;
+    for (index, (name, data)) in keys.iter().enumerate() {
+        if index % 2 == 0 {
+            assert!(!key_list.contains(name), "erased key {} is still listed", name);
+            let mut readback = [0u8; 256];
+            assert!(basis_cache.key_read(hw, DICT, name, &mut readback, None, None).is_err(), "erased key {} is still readable", name);
+        } else {
+            assert!(key_list.contains(name), "surviving key {} went missing", name);
+            let mut readback = [0u8; 256];
+            let readlen = basis_cache.key_read(hw, DICT, name, &mut readback, None, None)?;
+            assert!(readback[..readlen] == data[..], "surviving key {} was corrupted by the re-pack", name);
+        }
+    }
+    basis_cache.dict_remove(hw, DICT, None, true)?;
+    Ok(())
+}
+
+/// commits transactions against the system basis, injecting a "power loss" at each step of the commit by
+/// throwing away the in-RAM basis cache and remounting from disk, and checks that every key either has
+/// all of its old values or all of its new values afterwards. Also injects out-of-space errors into the
+/// commit, and checks that they are reported on the correct side of the commit point.
+pub(crate) fn transaction_test(hw: &mut PddbOs, basis_cache: &mut BasisCache) -> Result<()> {
+    const DICT_A: &'static str = "txntest";
+    const DICT_B: &'static str = "txntest2";
+    fn crash(hw: &mut PddbOs, basis_cache: &mut BasisCache) {
+        *basis_cache = BasisCache::new();
+        basis_cache.basis_add(hw.pddb_mount().expect("couldn't remount system basis"));
+    }
+    fn check(hw: &mut PddbOs, basis_cache: &mut BasisCache, expected: &[(&str, &str, Option<&[u8]>)]) {
+        for &(dict, key, value) in expected.iter() {
+            let mut readback = [0u8; 256];
+            match (basis_cache.key_read(hw, dict, key, &mut readback, None, None), value) {
+                (Ok(readlen), Some(data)) => assert!(&readback[..readlen] == data, "{}:{} has the wrong contents", dict, key),
+                (Err(_), None) => (),
+                (result, _) => panic!("{}:{} has the wrong state: {:?}", dict, key, result),
+            }
+        }
+        assert!(basis_cache.dict_attributes(hw, TXN_LOG_DICT, None).is_err(), "transaction log was left behind");
+    }
+    let old_a = "old value of a".as_bytes();
+    let old_b = "old value of b".as_bytes();
+    let new_a = "a new, longer value of a".as_bytes();
+    let new_b = "new b".as_bytes();
+    let ops = vec![
+        TxnOp::Write{dict: DICT_A.to_string(), key: "a".to_string(), data: new_a.to_vec()},
+        TxnOp::Write{dict: DICT_B.to_string(), key: "b".to_string(), data: new_b.to_vec()},
+        TxnOp::Delete{dict: DICT_A.to_string(), key: "c".to_string()},
+    ];
+    let old_state = [(DICT_A, "a", Some(old_a)), (DICT_B, "b", Some(old_b)), (DICT_A, "c", Some(old_a))];
+    let new_state = [(DICT_A, "a", Some(new_a)), (DICT_B, "b", Some(new_b)), (DICT_A, "c", None)];
+    let reset = |hw: &mut PddbOs, basis_cache: &mut BasisCache| -> Result<()> {
+        basis_cache.key_update(hw, DICT_A, "a", old_a, None, None, None, true)?;
+        basis_cache.key_update(hw, DICT_B, "b", old_b, None, None, None, true)?;
+        basis_cache.key_update(hw, DICT_A, "c", old_a, None, None, None, true)?;
+        basis_cache.sync(hw, None)
+    };
+
+    // 1. power loss right after the commit point
+    reset(hw, basis_cache)?;
+    basis_cache.txn_log_write(hw, PDDB_DEFAULT_SYSTEM_BASIS, &ops)?;
+    crash(hw, basis_cache);
+    assert!(basis_cache.txn_recover(hw, PDDB_DEFAULT_SYSTEM_BASIS)? == ops.len());
+    check(hw, basis_cache, &new_state);
+
+    // 2. power loss part-way through applying the operations
+    reset(hw, basis_cache)?;
+    basis_cache.txn_log_write(hw, PDDB_DEFAULT_SYSTEM_BASIS, &ops)?;
+    basis_cache.txn_apply(hw, PDDB_DEFAULT_SYSTEM_BASIS, &ops[..1])?;
+    crash(hw, basis_cache);
+    basis_cache.txn_recover(hw, PDDB_DEFAULT_SYSTEM_BASIS)?;
+    check(hw, basis_cache, &new_state);
+
+    // 3. power loss after the operations are applied, but before the log is retired
+    reset(hw, basis_cache)?;
+    basis_cache.txn_log_write(hw, PDDB_DEFAULT_SYSTEM_BASIS, &ops)?;
+    basis_cache.txn_apply(hw, PDDB_DEFAULT_SYSTEM_BASIS, &ops)?;
+    crash(hw, basis_cache);
+    basis_cache.txn_recover(hw, PDDB_DEFAULT_SYSTEM_BASIS)?;
+    check(hw, basis_cache, &new_state);
+
+    // 4. power loss while the log itself is being written: the transaction must be discarded
+    reset(hw, basis_cache)?;
+    basis_cache.txn_log_write(hw, PDDB_DEFAULT_SYSTEM_BASIS, &ops)?;
+    let attr = basis_cache.key_attributes(hw, TXN_LOG_DICT, TXN_LOG_KEY, None)?;
+    let mut log = vec![0u8; attr.len];
+    basis_cache.key_read(hw, TXN_LOG_DICT, TXN_LOG_KEY, &mut log, None, None)?;
+    basis_cache.key_update(hw, TXN_LOG_DICT, TXN_LOG_KEY, &log[..log.len() / 2], None, None, None, true)?;
+    crash(hw, basis_cache);
+    assert!(basis_cache.txn_recover(hw, PDDB_DEFAULT_SYSTEM_BASIS)? == 0);
+    check(hw, basis_cache, &old_state);
+
+    // 5. an uninterrupted commit
+    reset(hw, basis_cache)?;
+    basis_cache.txn_commit(hw, PDDB_DEFAULT_SYSTEM_BASIS, &ops)?;
+    check(hw, basis_cache, &new_state);
+    crash(hw, basis_cache);
+    check(hw, basis_cache, &new_state);
+
+    // 6. the disk "fills up" at every possible point of a commit: a failure before the commit point must leave
+    // the old values, and a failure after it must be reported as pending and finish on the next mount
+    let mut seen_failed = false;
+    let mut seen_pending = false;
+    let mut allocs = 0;
+    loop {
+        assert!(allocs < 256, "commit never succeeded with failure injection");
+        reset(hw, basis_cache)?;
+        hw.test_fail_alloc_after(Some(allocs));
+        let result = basis_cache.txn_commit(hw, PDDB_DEFAULT_SYSTEM_BASIS, &ops);
+        hw.test_fail_alloc_after(None);
+        match result {
+            Err(_) => {
+                seen_failed = true;
+                check(hw, basis_cache, &old_state);
+                crash(hw, basis_cache);
+                assert!(basis_cache.txn_recover(hw, PDDB_DEFAULT_SYSTEM_BASIS)? == 0, "a failed commit was replayed");
+                check(hw, basis_cache, &old_state);
+            }
+            Ok(TxnStatus::Pending) => {
+                seen_pending = true;
+                crash(hw, basis_cache);
+                assert!(basis_cache.txn_recover(hw, PDDB_DEFAULT_SYSTEM_BASIS)? == ops.len(), "a pending commit was not replayed");
+                check(hw, basis_cache, &new_state);
+            }
+            Ok(TxnStatus::Applied) => {
+                check(hw, basis_cache, &new_state);
+                break;
+            }
+        }
+        allocs += 1;
+    }
+    assert!(seen_failed && seen_pending, "failure injection missed one side of the commit point");
+
+    // 7. the log itself is off-limits
+    let bad_ops = vec![TxnOp::Delete{dict: TXN_LOG_DICT.to_string(), key: TXN_LOG_KEY.to_string()}];
+    assert!(basis_cache.txn_commit(hw, PDDB_DEFAULT_SYSTEM_BASIS, &bad_ops).is_err());
+
+    basis_cache.dict_remove(hw, DICT_A, None, false)?;
+    basis_cache.dict_remove(hw, DICT_B, None, false)?;
+    Ok(())
+}
+
+/// runs the consistency checker over a PDDB that has been through the other tests, and checks that it
+/// finds nothing to complain about, and that a repair pass on a healthy PDDB doesn't touch anything.
+/// Then damages the system basis in various ways, and checks that each kind of damage is found, that
+/// a repair pass fixes it, and that the PDDB checks out clean afterwards, also when remounted from disk.
+pub(crate) fn fsck_test(hw: &mut PddbOs, basis_cache: &mut BasisCache) -> Result<()> {
+    const DICT: &'static str = "fscktest";
+    let report = basis_cache.fsck(hw, None, false)?;
+    assert!(report.bases == basis_cache.basis_count() as u32, "fsck skipped a basis");
+    assert!(report.problems() == 0, "fsck found problems in a healthy PDDB: {:?}", report);
+    let repair = basis_cache.fsck(hw, None, true)?;
+    assert!(repair.repaired == 0, "fsck repaired a healthy PDDB: {:?}", repair);
+    assert!(repair.pages == report.pages, "fsck repair changed the page count");
+    assert!(basis_cache.fsck(hw, Some("no such basis"), false).is_err());
+
+    fn remount(hw: &mut PddbOs) -> BasisCacheEntry {
+        let mut basis = hw.pddb_mount().expect("couldn't remount system basis");
+        basis.populate_caches(hw);
+        basis
+    }
+    fn key_vaddr(basis: &BasisCacheEntry, key: &str, page: u64) -> VirtAddr {
+        let start = basis.dicts.get(DICT).expect("test dictionary is missing").keys.get(key).expect("test key is missing").start;
+        VirtAddr::new(start + page * VPAGE_SIZE as u64).unwrap()
+    }
+    fn check_repair(hw: &mut PddbOs, basis_cache: &mut BasisCache, basis: BasisCacheEntry, what: &str, found: fn(&FsckReport) -> u32) -> Result<()> {
+        *basis_cache = BasisCache::new();
+        basis_cache.basis_add(basis);
+        let report = basis_cache.fsck(hw, None, false)?;
+        assert!(found(&report) > 0, "fsck missed {}: {:?}", what, report);
+        let repair = basis_cache.fsck(hw, None, true)?;
+        assert!(repair.repaired >= found(&report), "fsck didn't repair {}: {:?}", what, repair);
+        let after = basis_cache.fsck(hw, None, false)?;
+        assert!(after.problems() == 0, "fsck left problems behind after repairing {}: {:?}", what, after);
+        basis_cache.sync(hw, None)?;
+        *basis_cache = BasisCache::new();
+        basis_cache.basis_add(remount(hw));
+        let remounted = basis_cache.fsck(hw, None, false)?;
+        assert!(remounted.problems() == 0, "repair of {} did not stick: {:?}", what, remounted);
+        Ok(())
+    }
+    fn check_key(hw: &mut PddbOs, basis_cache: &mut BasisCache, key: &str, data: &[u8]) {
+        let mut readback = vec![0u8; data.len()];
+        let readlen = basis_cache.key_read(hw, DICT, key, &mut readback, None, None).expect("couldn't read back test key");
+        assert!(readlen == data.len() && readback == data, "{} was corrupted", key);
+    }
+
+    // keys that are big enough to land in the large pool, and span more than one page
+    let data_a: Vec::<u8> = (0..VPAGE_SIZE * 2).map(|i| i as u8).collect();
+    let data_b: Vec::<u8> = (0..VPAGE_SIZE * 2).map(|i| (i as u8).wrapping_mul(3)).collect();
+    basis_cache.key_update(hw, DICT, "a", &data_a, None, None, None, true)?;
+    basis_cache.key_update(hw, DICT, "b", &data_b, None, None, None, true)?;
+    basis_cache.sync(hw, None)?;
+
+    // 1. an orphan: a valid page table entry and page that nothing refers to
+    let basis = remount(hw);
+    let mut orphan = hw.try_fast_space_alloc().expect("couldn't allocate a page");
+    orphan.set_valid(true);
+    let mut page = vec![0u8; VPAGE_SIZE + size_of::<JournalType>()];
+    hw.data_encrypt_and_patch_page(&basis.cipher, &basis.aad, &mut page, &orphan);
+    let orphan_vaddr = VirtAddr::new((LARGE_POOL_START / VPAGE_SIZE as u64 - 1) * VPAGE_SIZE as u64).unwrap();
+    hw.pt_patch_mapping(orphan_vaddr, orphan.page_number(), &basis.cipher_ecb);
+    let basis = remount(hw);
+    assert!(basis.v2p_map.contains_key(&orphan_vaddr), "orphan was not mapped on mount");
+    check_repair(hw, basis_cache, basis, "an orphaned page", |r| r.orphaned)?;
+    check_key(hw, basis_cache, "a", &data_a);
+
+    // 2. a page claimed by two keys: "b" loses its first page to "a"
+    let mut basis = remount(hw);
+    let shared = basis.v2p_map[&key_vaddr(&basis, "a", 0)];
+    let b_vaddr = key_vaddr(&basis, "b", 0);
+    basis.v2p_map.insert(b_vaddr, shared);
+    check_repair(hw, basis_cache, basis, "a cross-linked page", |r| r.cross_linked)?;
+    check_key(hw, basis_cache, "a", &data_a);
+    let mut expected_b = data_b.clone();
+    expected_b[..VPAGE_SIZE].copy_from_slice(&data_a[..VPAGE_SIZE]);
+    check_key(hw, basis_cache, "b", &expected_b);
+    basis_cache.key_update(hw, DICT, "b", &data_b, None, None, None, true)?;
+    basis_cache.sync(hw, None)?;
+
+    // 3. the FSCB lists a live page as free
+    let basis = remount(hw);
+    let mut live = basis.v2p_map[&key_vaddr(&basis, "a", 1)];
+    hw.fast_space_free(&mut live);
+    check_repair(hw, basis_cache, basis, "a free space entry for a live page", |r| r.fscb_conflicts)?;
+    check_key(hw, basis_cache, "a", &data_a);
+
+    // 4. a key whose second page has gone missing: it reads back as zeroes after the repair
+    let basis = remount(hw);
+    hw.pt_erase(basis.v2p_map[&key_vaddr(&basis, "b", 1)].page_number());
+    let basis = remount(hw);
+    assert!(!basis.v2p_map.contains_key(&key_vaddr(&basis, "b", 1)), "erased page is still mapped");
+    check_repair(hw, basis_cache, basis, "a missing page", |r| r.missing)?;
+    let mut expected_b = data_b.clone();
+    for b in expected_b[VPAGE_SIZE..].iter_mut() {
+        *b = 0;
+    }
+    check_key(hw, basis_cache, "b", &expected_b);
+    check_key(hw, basis_cache, "a", &data_a);
+
+    basis_cache.dict_remove(hw, DICT, None, false)?;
+    basis_cache.sync(hw, None)?;
+    Ok(())
+}
+
+/// exports `src_basis` to an in-memory archive, checks that a wrong password, a flipped bit and a truncated
+/// archive are all rejected, and then restores the archive into `dst_basis` and compares the two bases.
+/// This exercises the archive format against the basis cache directly; `Pddb::export_basis()` and
+/// `Pddb::import_basis()` are covered by `pddb test` in shellchat, as they need a running server.
+pub(crate) fn archive_roundtrip_test(hw: &mut PddbOs, basis_cache: &mut BasisCache, src_basis: &str, dst_basis: &str) -> Result<()> {
+    use crate::archive::*;
+    const ARCHIVE_PW: &'static str = "archive password";
+    // 1. export
+    let mut archive = ArchiveWriter::new(Vec::<u8>::new(), ARCHIVE_PW, [0x5A; ARCHIVE_SALT_LEN], [0xC3; ARCHIVE_NONCE_PREFIX_LEN])?;
+    let mut exported = 0;
+    for dict in basis_cache.dict_list(hw, Some(src_basis)).iter() {
+        archive.dict(dict)?;
+        for key in basis_cache.key_list(hw, dict, Some(src_basis))?.iter() {
+            let attr = basis_cache.key_attributes(hw, dict, key, Some(src_basis))?;
+            let mut data = vec![0u8; attr.len];
+            let readlen = basis_cache.key_read(hw, dict, key, &mut data, None, Some(src_basis))?;
+            assert!(readlen == attr.len, "short read on export of {}:{}", dict, key);
+            archive.key(key, attr.reserved, attr.age as u32, attr.len)?;
+            // split the data to exercise streaming of key data
+            let (first, second) = data.split_at(attr.len / 2);
+            archive.key_data(first)?;
+            archive.key_data(second)?;
+            exported += 1;
+        }
+    }
+    let image = archive.finish()?;
+    log::info!("exported {} keys into a {}-byte archive", exported, image.len());
+
+    // 2. corrupted & mis-keyed archives must not get past authentication
+    assert!(ArchiveReader::new(&image[..], "wrong password").is_err(), "archive opened with the wrong password");
+    let drain = |mut reader: ArchiveReader<&[u8]>| -> Result<()> {
+        let mut buf = [0u8; 1024];
+        loop {
+            match reader.next_record()? {
+                ArchiveRecord::End => return Ok(()),
+                ArchiveRecord::Key{..} => while reader.read_key_data(&mut buf)? != 0 {},
+                _ => (),
+            }
+        }
+    };
+    let mut corrupted = image.clone();
+    let flip = corrupted.len() - 20;
+    corrupted[flip] ^= 0x10;
+    assert!(ArchiveReader::new(&corrupted[..], ARCHIVE_PW).and_then(drain).is_err(), "corrupted archive was accepted");
+    let truncated = &image[..image.len() - 1];
+    assert!(ArchiveReader::new(truncated, ARCHIVE_PW).and_then(drain).is_err(), "truncated archive was accepted");
+
+    // 3. import
+    let mut reader = ArchiveReader::new(&image[..], ARCHIVE_PW)?;
+    let mut dict: Option<String> = None;
+    let mut imported = 0;
+    loop {
+        match reader.next_record()? {
+            ArchiveRecord::Dict(name) => {
+                if !basis_cache.dict_list(hw, Some(dst_basis)).contains(&name) {
+                    basis_cache.dict_add(hw, &name, Some(dst_basis))?;
+                }
+                dict = Some(name);
+            }
+            ArchiveRecord::Key{name, reserved, age: _, len} => {
+                let mut data = vec![0u8; len];
+                assert!(reader.read_key_data(&mut data)? == len, "short read of key data from archive");
+                basis_cache.key_update(hw, dict.as_ref().unwrap(), &name, &data, None, Some(reserved), Some(dst_basis), true)?;
+                imported += 1;
+            }
+            ArchiveRecord::End => break,
+        }
+    }
+    assert!(imported == exported, "imported {} keys, but exported {}", imported, exported);
+    basis_cache.sync(hw, Some(dst_basis))?;
+
+    // 4. compare
+    let src_dicts = basis_cache.dict_list(hw, Some(src_basis));
+    assert!(src_dicts == basis_cache.dict_list(hw, Some(dst_basis)), "restored dictionary list does not match");
+    for dict in src_dicts.iter() {
+        let src_keys = basis_cache.key_list(hw, dict, Some(src_basis))?;
+        assert!(src_keys == basis_cache.key_list(hw, dict, Some(dst_basis))?, "restored key list for {} does not match", dict);
+        for key in src_keys.iter() {
+            let src_attr = basis_cache.key_attributes(hw, dict, key, Some(src_basis))?;
+            let dst_attr = basis_cache.key_attributes(hw, dict, key, Some(dst_basis))?;
+            assert!(src_attr.len == dst_attr.len, "restored length of {}:{} does not match", dict, key);
+            let mut src_data = vec![0u8; src_attr.len];
+            let mut dst_data = vec![0u8; dst_attr.len];
+            basis_cache.key_read(hw, dict, key, &mut src_data, None, Some(src_basis))?;
+            basis_cache.key_read(hw, dict, key, &mut dst_data, None, Some(dst_basis))?;
+            assert!(src_data == dst_data, "restored data of {}:{} does not match", dict, key);
+        }
+    }
+    Ok(())
+}
+
+/// unlocks `basis` with a one-second `TimeOutSecs` policy, and checks that internal lookups such as
+/// `key_attributes` don't hold it open: it has to time out and get unmounted once client I/O stops.
+pub(crate) fn timeout_test(hw: &mut PddbOs, basis_cache: &mut BasisCache, basis: &str, password: &str) -> Result<()> {
+    const DICT: &'static str = "timeout";
+    const KEY: &'static str = "idle";
+    let tt = ticktimer_server::Ticktimer::new().unwrap();
+    let unlocked = basis_cache.basis_unlock(hw, basis, password, BasisRetentionPolicy::TimeOutSecs(1))
+        .expect("couldn't unlock timeout test basis");
+    basis_cache.basis_add(unlocked);
+    basis_cache.key_update(hw, DICT, KEY, "some data".as_bytes(), None, None, Some(basis), true)?;
+    assert!(!basis_cache.basis_timed_out(hw).contains(&basis.to_string()), "basis timed out right after a write");
+
+    // keep poking at the basis the way the server does internally, for longer than the timeout
+    for _ in 0..8 {
+        tt.sleep_ms(200).unwrap();
+        basis_cache.key_attributes(hw, DICT, KEY, Some(basis))?;
+        basis_cache.dict_attributes(hw, DICT, Some(basis))?;
+    }
+    // same as the `BasisTimeoutPoll` handler
+    let expired = basis_cache.basis_timed_out(hw);
+    assert!(expired.contains(&basis.to_string()), "basis with no client I/O did not time out");
+    for name in expired.iter() {
+        basis_cache.basis_unmount(hw, name)?;
+    }
+    assert!(!basis_cache.basis_list().contains(&basis.to_string()), "timed out basis is still mounted");
+    Ok(())
+}
+
 pub(crate) fn delete_pattern(hw: &mut PddbOs, basis_cache: &mut BasisCache,
     maybe_num_dicts: Option<usize>, maybe_num_keys: Option<usize>, maybe_key_sizes: Option<(usize, usize)>,
     maybe_extra_reserved: Option<usize>,
@@ -395,6 +825,8 @@ pub(crate) fn ci_tests(pddb_os: &mut PddbOs) -> Result<()> {
     {
         const EXTRA_BASIS: &'static str = "Basis2";
         const EXTRA_BASIS_PW: &'static str = "some password blah blah";
+        const ARCHIVE_BASIS: &'static str = "Basis3";
+        const ARCHIVE_BASIS_PW: &'static str = "restored from an archive";
 
         log::set_max_level(log::LevelFilter::Info);
         log::info!("Seed for this run: {}", xous::TESTING_RNG_SEED.load(core::sync::atomic::Ordering::SeqCst));
@@ -438,6 +870,18 @@ pub(crate) fn ci_tests(pddb_os: &mut PddbOs) -> Result<()> {
         delete_pattern(pddb_os, &mut basis_cache, None, None, None, None)?;
         pddb_os.dbg_dump(Some("patterne".to_string()), None);
 
+        log::info!("Doing large key streaming test");
+        large_stream_test(pddb_os, &mut basis_cache, None)?;
+
+        log::info!("Doing paranoid erase test");
+        paranoid_erase_test(pddb_os, &mut basis_cache)?;
+
+        log::info!("Doing transaction crash-recovery test");
+        transaction_test(pddb_os, &mut basis_cache)?;
+
+        log::info!("Doing fsck test");
+        fsck_test(pddb_os, &mut basis_cache)?;
+
         // extended tests.
         // allocation space curtailed to force resource exhaustion faster.
         // note to self: FSCB_PAGES revert to 16 (hw.rs), FASTSPACE_PAGES revert to 2 (fastspace.rs)
@@ -546,6 +990,19 @@ pub(crate) fn ci_tests(pddb_os: &mut PddbOs) -> Result<()> {
         assert!(merge2_list.difference(&merge_list).count() == 0, "merged list is different from the original list after remount");
         list_all(pddb_os, &mut basis_cache);
 
+        log::info!("Doing archive round-trip test");
+        basis_cache.basis_create(pddb_os,
+            ARCHIVE_BASIS, ARCHIVE_BASIS_PW).expect("couldn't build archive test basis");
+        if let Some(basis3) = basis_cache.basis_unlock(pddb_os,
+            ARCHIVE_BASIS, ARCHIVE_BASIS_PW, BasisRetentionPolicy::Persist) {
+            basis_cache.basis_add(basis3);
+        }
+        archive_roundtrip_test(pddb_os, &mut basis_cache, EXTRA_BASIS, ARCHIVE_BASIS)?;
+        basis_cache.basis_unmount(pddb_os, ARCHIVE_BASIS).unwrap();
+
+        log::info!("Doing basis timeout test");
+        timeout_test(pddb_os, &mut basis_cache, ARCHIVE_BASIS, ARCHIVE_BASIS_PW)?;
+
         log::info!("CI done");
 
         /*
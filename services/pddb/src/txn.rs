@@ -0,0 +1,114 @@
+//! Encoding of the operations staged in a PDDB transaction. The same encoding is used to ship a
+//! transaction from the client to the server, and (wrapped in a header and digest) as the body of
+//! the transaction log that the server keeps on disk while a commit is in progress.
+//!
+//! Each operation is a one-byte tag, followed by:
+//!   - `TXN_OP_WRITE`: `u8` dict name length, dict name, `u8` key name length, key name, `u32` data length, data
+//!   - `TXN_OP_DELETE`: `u8` dict name length, dict name, `u8` key name length, key name
+
+use std::convert::TryInto;
+use std::io::{Result, Error, ErrorKind};
+
+use crate::api::{DICT_NAME_LEN, KEY_NAME_LEN};
+
+const TXN_OP_WRITE: u8 = 0x01;
+const TXN_OP_DELETE: u8 = 0x02;
+
+/// One staged operation of a transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxnOp {
+    /// replace the entire contents of a key, creating the key (and its dictionary) if necessary
+    Write {
+        dict: String,
+        key: String,
+        data: Vec::<u8>,
+    },
+    /// remove a key; removing a key that does not exist is not an error
+    Delete {
+        dict: String,
+        key: String,
+    },
+}
+impl TxnOp {
+    pub fn dict(&self) -> &str {
+        match self {
+            TxnOp::Write{dict, ..} => dict,
+            TxnOp::Delete{dict, ..} => dict,
+        }
+    }
+    pub fn key(&self) -> &str {
+        match self {
+            TxnOp::Write{key, ..} => key,
+            TxnOp::Delete{key, ..} => key,
+        }
+    }
+}
+
+fn encode_name(out: &mut Vec::<u8>, name: &str, max_len: usize) -> Result<()> {
+    if name.len() == 0 || name.len() > max_len - 1 {
+        return Err(Error::new(ErrorKind::InvalidInput, "name is empty or too long"));
+    }
+    out.push(name.len() as u8);
+    out.extend_from_slice(name.as_bytes());
+    Ok(())
+}
+
+/// Appends the encoding of `ops` to `out`.
+pub fn txn_encode(ops: &[TxnOp], out: &mut Vec::<u8>) -> Result<()> {
+    for op in ops.iter() {
+        match op {
+            TxnOp::Write{dict, key, data} => {
+                if data.len() > u32::MAX as usize {
+                    return Err(Error::new(ErrorKind::InvalidInput, "key data too large for a transaction"));
+                }
+                out.push(TXN_OP_WRITE);
+                encode_name(out, dict, DICT_NAME_LEN)?;
+                encode_name(out, key, KEY_NAME_LEN)?;
+                out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                out.extend_from_slice(data);
+            }
+            TxnOp::Delete{dict, key} => {
+                out.push(TXN_OP_DELETE);
+                encode_name(out, dict, DICT_NAME_LEN)?;
+                encode_name(out, key, KEY_NAME_LEN)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Recovers a list of operations from their encoding. Fails if the encoding is malformed in any way.
+pub fn txn_decode(mut data: &[u8]) -> Result<Vec::<TxnOp>> {
+    fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+        if data.len() < len {
+            return Err(Error::new(ErrorKind::InvalidData, "transaction record is truncated"));
+        }
+        let (head, tail) = data.split_at(len);
+        *data = tail;
+        Ok(head)
+    }
+    fn take_name(data: &mut &[u8], max_len: usize) -> Result<String> {
+        let len = take(data, 1)?[0] as usize;
+        if len == 0 || len > max_len - 1 {
+            return Err(Error::new(ErrorKind::InvalidData, "transaction record has an invalid name length"));
+        }
+        String::from_utf8(take(data, len)?.to_vec())
+            .or(Err(Error::new(ErrorKind::InvalidData, "transaction record has a name that is not valid utf-8")))
+    }
+    let mut ops = Vec::<TxnOp>::new();
+    while data.len() > 0 {
+        let tag = take(&mut data, 1)?[0];
+        let dict = take_name(&mut data, DICT_NAME_LEN)?;
+        let key = take_name(&mut data, KEY_NAME_LEN)?;
+        match tag {
+            TXN_OP_WRITE => {
+                let len = u32::from_le_bytes(take(&mut data, 4)?.try_into().unwrap()) as usize;
+                let value = take(&mut data, len)?.to_vec();
+                ops.push(TxnOp::Write{dict, key, data: value});
+            }
+            TXN_OP_DELETE => ops.push(TxnOp::Delete{dict, key}),
+            _ => return Err(Error::new(ErrorKind::InvalidData, "transaction record has an unknown operation")),
+        }
+    }
+    Ok(ops)
+}
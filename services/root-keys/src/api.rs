@@ -9,6 +9,8 @@ pub(crate) enum Opcode {
     KeysInitialized,
     /// check that the digital signature on the gateware
     CheckGatewareSignature,
+    /// check the digital signature on an app image, lent as a mutable memory message
+    CheckAppSignature,
     /// check if the efuse has been locked down
     IsEfuseSecured,
     /// quick check to see if the JTAG can read its IDCODE
@@ -95,6 +97,22 @@ pub enum RootkeyResult {
     FlashError,
 }
 
+/// The outcome of checking the signature on an app image. The discriminant is passed back
+/// in the `offset` field of the lent image, so it must not be zero.
+#[derive(Debug, num_derive::FromPrimitive, num_derive::ToPrimitive, PartialEq, Eq, Copy, Clone)]
+pub enum AppSignature {
+    /// signed with this device's self-signing key
+    SelfSign = 1,
+    /// signed with the third-party key
+    ThirdParty = 2,
+    /// signed with the well-known developer key
+    Developer = 3,
+    /// not signed with any of the keys, or not a signed image at all
+    Invalid = 4,
+    /// the keys have not been initialized, so there is nothing to check against
+    Uninit = 5,
+}
+
 /// AES operation definitions
 pub use cipher::{BlockCipher, consts::U16};
 use zeroize::Zeroize;
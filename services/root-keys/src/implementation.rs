@@ -2157,6 +2157,46 @@ impl<'a> RootKeys {
         sigtype
     }
 
+    /// Checks the signature on an app image. App images are made by `sign-image`, so they have the same
+    /// layout as a signed kernel: a `SignatureInFlash` record padded out to `SIGBLOCK_SIZE`, followed
+    /// by the signed data. Like the gateware, an app may be signed by any of the known keys.
+    pub fn check_app_signature(&mut self, image: &[u8]) -> SignatureResult {
+        if image.len() < SIGBLOCK_SIZE as usize {
+            return SignatureResult::Invalid;
+        }
+        let mut sig_region: [u8; core::mem::size_of::<SignatureInFlash>()] = [0; core::mem::size_of::<SignatureInFlash>()];
+        sig_region.copy_from_slice(&image[..core::mem::size_of::<SignatureInFlash>()]);
+        let sig_rec: &SignatureInFlash = unsafe{(sig_region.as_ptr() as *const SignatureInFlash).as_ref().unwrap()};
+        let signed_len = sig_rec.signed_len as usize;
+        if sig_rec.version != SIG_VERSION || signed_len > image.len() - SIGBLOCK_SIZE as usize {
+            log::warn!("app image has a malformed signature record");
+            return SignatureResult::Invalid;
+        }
+        let sig = match Signature::from_bytes(&sig_rec.signature) {
+            Ok(sig) => sig,
+            Err(_) => return SignatureResult::Invalid,
+        };
+        let signed = &image[SIGBLOCK_SIZE as usize..SIGBLOCK_SIZE as usize + signed_len];
+
+        for (sigtype, key_loc) in [
+            (SignatureResult::SelfSignOk, KeyRomLocs::SELFSIGN_PUBKEY),
+            (SignatureResult::ThirdPartyOk, KeyRomLocs::THIRDPARTY_PUBKEY),
+            (SignatureResult::DevKeyOk, KeyRomLocs::DEVELOPER_PUBKEY),
+        ] {
+            let pubkey_bytes = self.read_key_256(key_loc);
+            // skip uninitialized key slots
+            if pubkey_bytes.iter().all(|&b| b == 0) {
+                continue;
+            }
+            if let Ok(pubkey) = PublicKey::from_bytes(&pubkey_bytes) {
+                if pubkey.verify_strict(signed, &sig).is_ok() {
+                    return sigtype;
+                }
+            }
+        }
+        SignatureResult::Invalid
+    }
+
     pub fn fetch_gw_metadata(&self, region_enum: GatewareRegion) -> MetadataInFlash {
         let region = match region_enum {
             GatewareRegion::Boot => self.gateware(),
@@ -103,6 +103,39 @@ impl RootKeys {
         }
     }
 
+    /// this will check the signature on an app image made with `sign-image --app-image`. `image` is the
+    /// whole signed image, starting with the signature block. It is checked against the self-signing,
+    /// third-party and developer keys in turn, and the first key that matches is reported.
+    /// returns None if no keys have been initialized
+    pub fn check_app_signature(&self, image: &[u8]) -> Result<Option<AppSignature>, xous::Error> {
+        // the image is copied into pages of its own, so it can be lent to the server
+        let mut region = xous::map_memory(
+            None,
+            None,
+            (image.len() + 0xFFF) & !0xFFF,
+            xous::MemoryFlags::R | xous::MemoryFlags::W,
+        )?;
+        region.as_slice_mut::<u8>()[..image.len()].copy_from_slice(image);
+        let response = send_message(self.conn,
+            Message::MutableBorrow(xous::MemoryMessage {
+                id: Opcode::CheckAppSignature.to_usize().unwrap(),
+                buf: region,
+                offset: None,
+                valid: xous::MemorySize::new(image.len()),
+            })
+        );
+        xous::unmap_memory(region)?;
+        if let xous::Result::MemoryReturned(offset, _valid) = response? {
+            match offset.and_then(|o| FromPrimitive::from_usize(o.get())) {
+                Some(AppSignature::Uninit) => Ok(None),
+                Some(result) => Ok(Some(result)),
+                None => Err(xous::Error::InternalError),
+            }
+        } else {
+            Err(xous::Error::InternalError)
+        }
+    }
+
     pub fn is_efuse_secured(&self) -> Result<Option<bool>, xous::Error> {
         let response = send_message(self.conn,
             Message::new_blocking_scalar(Opcode::IsEfuseSecured.to_usize().unwrap(), 0, 0, 0, 0)
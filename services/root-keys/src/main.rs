@@ -192,6 +192,14 @@ mod implementation {
             log::info!("done");
             SignatureResult::DevKeyOk
         }
+        pub fn check_app_signature(&mut self, image: &[u8]) -> SignatureResult {
+            log::info!("faking app signature check...");
+            if image.len() > 0x1000 {
+                SignatureResult::DevKeyOk
+            } else {
+                SignatureResult::Invalid
+            }
+        }
         pub fn is_pcache_update_password_valid(&self) -> bool {
             false
         }
@@ -313,8 +321,9 @@ fn main() -> ! {
           1. Shellchat for test initiation
           2. Main menu -> trigger initialization
           3. PDDB
+          4. App loader, to check the signatures on apps
     */
-    let keys_sid = xns.register_name(api::SERVER_NAME_KEYS, Some(3)).expect("can't register server");
+    let keys_sid = xns.register_name(api::SERVER_NAME_KEYS, Some(4)).expect("can't register server");
 
     let mut keys = RootKeys::new();
     log::info!("Boot FPGA key source: {:?}", keys.fpga_key_source());
@@ -1079,6 +1088,22 @@ fn main() -> ! {
                     xous::return_scalar(msg.sender, 2).expect("couldn't send return value");
                 }
             }),
+            Some(Opcode::CheckAppSignature) => {
+                let mem = msg.body.memory_message_mut().unwrap();
+                let len = mem.valid.map(|v| v.get()).unwrap_or(0).min(mem.buf.len());
+                let result = if keys.is_initialized() {
+                    match keys.check_app_signature(&mem.buf.as_slice::<u8>()[..len]) {
+                        SignatureResult::SelfSignOk => AppSignature::SelfSign,
+                        SignatureResult::ThirdPartyOk => AppSignature::ThirdParty,
+                        SignatureResult::DevKeyOk => AppSignature::Developer,
+                        SignatureResult::Invalid => AppSignature::Invalid,
+                    }
+                } else {
+                    AppSignature::Uninit
+                };
+                // the result goes back to the caller when the image is returned
+                mem.offset = xous::MemoryAddress::new(result.to_usize().unwrap());
+            }
             Some(Opcode::TestUx) => msg_blocking_scalar_unpack!(msg, _arg, _, _, _, {
                 // dummy test for now
                 xous::return_scalar(msg.sender, 1234).unwrap();
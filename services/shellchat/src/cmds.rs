@@ -87,6 +87,9 @@ mod rtc_cmd;  use rtc_cmd::*;
 mod vibe;     use vibe::*;
 mod ssid;     use ssid::*;
 mod ver;      use ver::*;
+mod names;    use names::*;
+mod ps;       use ps::*;
+mod ipctrace; use ipctrace::*;
 //mod audio;    use audio::*; // this command is currently contra-indicated with PDDB, as the test audio currently overlaps the PDDB space. We'll fix this eventually, but for now, let's switch to PDDB mode.
 mod backlight; use backlight::*;
 mod accel;    use accel::*;
@@ -234,6 +237,9 @@ impl CmdEnv {
         let mut backlight_cmd = Backlight{};
         let mut accel_cmd = Accel{};
         let mut console_cmd = Console{};
+        let mut names_cmd = Names{};
+        let mut ps_cmd = Ps{};
+        let mut ipctrace_cmd = IpcTrace{};
         let commands: &mut [& mut dyn ShellCmdApi] = &mut [
             ///// 4. add your command to this array, so that it can be looked up and dispatched
             &mut echo_cmd,
@@ -251,6 +257,9 @@ impl CmdEnv {
             &mut self.ecup_cmd,
             &mut self.trng_cmd,
             &mut console_cmd,
+            &mut names_cmd,
+            &mut ps_cmd,
+            &mut ipctrace_cmd,
             // &mut self.memtest_cmd,
             &mut self.keys_cmd,
             &mut self.wlan_cmd,
@@ -339,6 +348,26 @@ impl CmdEnv {
     }
 }
 
+/// Room kept at the end of a reply for the `...more:` hint of `write_paged()`
+pub const MORE_HINT_ROOM: usize = 32;
+
+/// Appends `lines` to a reply, one per line, for as long as they fit. If some don't, the reply ends with
+/// `...more: <command>` instead, where `more` returns the command that resumes the listing at the key of
+/// the first line that was left out. The command must fit in `MORE_HINT_ROOM`, along with the prefix.
+pub fn write_paged<K, S>(ret: &mut String::<1024>, lines: impl IntoIterator<Item = (K, S)>, more: impl Fn(K) -> std::string::String)
+where
+    S: AsRef<str>,
+{
+    for (key, line) in lines {
+        let line = line.as_ref();
+        if ret.len() + line.len() + 1 + MORE_HINT_ROOM > 1024 {
+            write!(ret, "...more: {}", more(key)).unwrap();
+            return;
+        }
+        write!(ret, "{}\n", line).unwrap();
+    }
+}
+
 /// extract the first token, as delimited by spaces
 /// modifies the incoming line by removing the token and returning the remainder
 /// returns the found token
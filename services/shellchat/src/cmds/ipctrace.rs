@@ -0,0 +1,86 @@
+use crate::{ShellCmdApi, CommonEnv, write_paged, MORE_HINT_ROOM};
+use xous_ipc::String;
+
+#[derive(Debug)]
+pub struct IpcTrace {
+}
+
+/// Formats one event as a line of the `ipctrace` output, e.g. `812 1234ms bsend 5:2>7 op3 0123abcd`.
+/// The kernel only reports the first word of the SID, which is enough to tell servers apart.
+fn summary(seq: usize, event: &xous::IpcTraceEvent) -> std::string::String {
+    format!("{} {}ms {} {}:{}>{} op{} {:08x}",
+        seq,
+        event.timestamp_ms,
+        event.kind,
+        event.pid,
+        event.tid,
+        event.peer.map(|p| p.get()).unwrap_or(0),
+        event.opcode,
+        event.sid_prefix,
+    )
+}
+
+impl<'a> ShellCmdApi<'a> for IpcTrace {
+    cmd_api!(ipctrace); // inserts boilerplate for command API
+
+    fn process(&mut self, args: String::<1024>, _env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
+        use core::fmt::Write;
+        let mut ret = String::<1024>::new();
+        let helpstring = "ipctrace [start]\nlist: seq time kind pid:tid>peer opcode sid";
+
+        let (oldest, next) = match xous::ipc_trace_range() {
+            Ok(range) => range,
+            Err(xous::Error::UnhandledSyscall) => {
+                write!(ret, "Kernel was built without the ipc-trace feature").unwrap();
+                return Ok(Some(ret));
+            }
+            Err(e) => {
+                write!(ret, "Couldn't read the IPC trace: {:?}", e).unwrap();
+                return Ok(Some(ret));
+            }
+        };
+        // take a snapshot first, as logging is IPC too and would add to the trace as we go
+        let mut events = Vec::new();
+        for seq in oldest..next {
+            if let Ok(Some(event)) = xous::ipc_trace_event(seq) {
+                events.push((seq, event));
+            }
+        }
+
+        // every event is logged, as the full trace won't fit in the reply
+        for (seq, event) in events.iter() {
+            log::info!("{:6} {}", seq, event);
+        }
+        write!(ret, "events {} to {}\n", oldest, next).unwrap();
+
+        // by default, show the most recent events, as that's where a stuck chain of calls ends up
+        let lines: Vec<std::string::String> = events.iter().map(|(seq, event)| summary(*seq, event)).collect();
+        let start = match args.as_str().unwrap() {
+            "" => {
+                let mut room = 1024 - MORE_HINT_ROOM - ret.len();
+                let mut start = next;
+                for ((seq, _), line) in events.iter().zip(lines.iter()).rev() {
+                    if line.len() + 1 > room {
+                        break;
+                    }
+                    room -= line.len() + 1;
+                    start = *seq;
+                }
+                start
+            }
+            s => match s.parse::<usize>() {
+                Ok(start) => start,
+                Err(_) => {
+                    write!(ret, "{}", helpstring).unwrap();
+                    return Ok(Some(ret));
+                }
+            },
+        };
+        write_paged(
+            &mut ret,
+            events.iter().zip(lines.iter()).filter(|((seq, _), _)| *seq >= start).map(|((seq, _), line)| (*seq, line)),
+            |seq| format!("ipctrace {}", seq),
+        );
+        Ok(Some(ret))
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{ShellCmdApi, CommonEnv, write_paged};
+use xous_ipc::String;
+use xous_names::api::NameInfo;
+
+#[derive(Debug)]
+pub struct Names {
+}
+
+/// Formats one name as a line of the `names list` output, e.g. `gam p4 12/12 +1 !`, where `+n`
+/// counts the authenticated connections and `!` marks a name registered after the trusted init.
+fn summary(info: &NameInfo) -> std::string::String {
+    let mut line = format!("{}", info.name);
+    if let Some(pid) = info.pid {
+        line.push_str(&format!(" p{}", pid));
+    }
+    match info.max_conns {
+        Some(max) => line.push_str(&format!(" {}/{}", info.current_conns, max)),
+        None => line.push_str(&format!(" {}/-", info.current_conns)),
+    }
+    if info.has_key {
+        line.push_str(&format!(" +{}", info.auth_conns));
+    }
+    if !info.trusted {
+        line.push_str(" !");
+    }
+    line
+}
+
+impl<'a> ShellCmdApi<'a> for Names {
+    cmd_api!(names); // inserts boilerplate for command API
+
+    fn process(&mut self, args: String::<1024>, env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
+        use core::fmt::Write;
+        let mut ret = String::<1024>::new();
+        let helpstring = "names [list [start]] [untrusted] [info name]\nlist: name pPID conns/max [+authenticated] [! = registered after trusted init]";
+
+        let mut tokens = args.as_str().unwrap().split(' ');
+
+        let names = match env.xns.list_names() {
+            Ok(names) => names,
+            Err(e) => {
+                write!(ret, "couldn't list names: {:?}", e).unwrap();
+                return Ok(Some(ret));
+            }
+        };
+        let sub_cmd = tokens.next().unwrap_or("");
+        match sub_cmd {
+            "" | "list" | "untrusted" => {
+                let start = match tokens.next().map(|s| s.parse::<usize>()) {
+                    Some(Ok(start)) => start,
+                    Some(Err(_)) => {
+                        write!(ret, "{}", helpstring).unwrap();
+                        return Ok(Some(ret));
+                    }
+                    None => 0,
+                };
+                // every name is logged, as the full table doesn't fit in the reply
+                for info in names.iter() {
+                    log::info!("{}", summary(info));
+                }
+                let listed: Vec<&NameInfo> = names.iter().filter(|info| sub_cmd != "untrusted" || !info.trusted).collect();
+                let untrusted = names.iter().filter(|info| !info.trusted).count();
+                write!(ret, "{} names, {} registered after trusted init\n", names.len(), untrusted).unwrap();
+                write_paged(
+                    &mut ret,
+                    listed.iter().enumerate().skip(start).map(|(i, info)| (i, summary(info))),
+                    |i| format!("names {} {}", if sub_cmd.is_empty() { "list" } else { sub_cmd }, i),
+                );
+            }
+            "info" => {
+                // names may contain spaces, so the name is the rest of the line
+                let name = tokens.collect::<Vec<&str>>().join(" ");
+                if !name.is_empty() {
+                    match names.iter().find(|info| info.name.to_str() == name) {
+                        Some(info) => {
+                            write!(ret, "{}\n", info.name).unwrap();
+                            match info.pid {
+                                Some(pid) => {
+                                    let process = xous::PID::new(pid).and_then(|pid| xous::process_name(pid).ok());
+                                    match process {
+                                        Some(process) => write!(ret, "registered by PID {} ({})\n", pid, process.to_str()).unwrap(),
+                                        None => write!(ret, "registered by PID {}\n", pid).unwrap(),
+                                    }
+                                }
+                                None => write!(ret, "registering PID unknown\n").unwrap(),
+                            }
+                            match info.max_conns {
+                                Some(max) => write!(ret, "connections: {} of {}\n", info.current_conns, max).unwrap(),
+                                None => write!(ret, "connections: {}, unlimited\n", info.current_conns).unwrap(),
+                            }
+                            if info.has_key {
+                                write!(ret, "authenticated connections: {}\n", info.auth_conns).unwrap();
+                            } else {
+                                write!(ret, "no authenticated connections allowed\n").unwrap();
+                            }
+                            if info.trusted {
+                                write!(ret, "registered during trusted init").unwrap();
+                            } else {
+                                write!(ret, "registered after trusted init").unwrap();
+                            }
+                        }
+                        None => write!(ret, "{} is not registered", name).unwrap(),
+                    }
+                } else {
+                    write!(ret, "{}", helpstring).unwrap();
+                }
+            }
+            _ => {
+                write!(ret, "{}", helpstring).unwrap();
+            }
+        }
+        Ok(Some(ret))
+    }
+}
@@ -1,7 +1,6 @@
 use crate::{ShellCmdApi, CommonEnv};
 use com::api::NET_MTU;
 use xous_ipc::String;
-#[cfg(any(target_os = "none", target_os = "xous"))]
 use net::XousServerId;
 use net::NetPingCallback;
 use xous::MessageEnvelope;
@@ -15,11 +14,13 @@ use std::thread;
 use std::sync::mpsc;
 use dns::Dns; // necessary to work around https://github.com/rust-lang/rust/issues/94182
 
+/// PDDB dictionary that `net pcap save pddb` writes captures into
+const PCAP_DICT: &str = "net.pcap";
+
 pub struct NetCmd {
     callback_id: Option<u32>,
     callback_conn: u32,
     dns: Dns,
-    #[cfg(any(target_os = "none", target_os = "xous"))]
     ping: Option<net::Ping>,
 }
 impl NetCmd {
@@ -28,7 +29,6 @@ impl NetCmd {
             callback_id: None,
             callback_conn: xns.request_connection_blocking(crate::SERVER_NAME_SHELLCHAT).unwrap(),
             dns: dns::Dns::new(&xns).unwrap(),
-            #[cfg(any(target_os = "none", target_os = "xous"))]
             ping: None,
         }
     }
@@ -53,10 +53,10 @@ impl<'a> ShellCmdApi<'a> for NetCmd {
         use core::fmt::Write;
         let mut ret = String::<1024>::new();
         #[cfg(any(target_os = "none", target_os = "xous"))]
-        let helpstring = "net [udp [rx socket] [tx dest socket]] [ping [host] [count]] [tcpget host/path]";
-        // no ping in hosted mode -- why would you need it? we're using the host's network connection.
+        let helpstring = "net [udp [rx socket] [tx dest socket]] [ping [host] [count]] [tcpget host/path] [mdns [name host] [browse _svc._tcp]]\n[pcap [start filter] [stop] [clear] [save log|pddb key]] [stats]";
+        // in hosted mode, ping goes out on the net server's virtual network, where every address (e.g. 10.0.2.2) answers, and mDNS is off
         #[cfg(not(any(target_os = "none", target_os = "xous")))]
-        let helpstring = "net [udp [port]] [count]] [tcpget host/path]";
+        let helpstring = "net [udp [port]] [count]] [ping [host] [count]] [tcpget host/path] [tls host] [httptest]\n[pcap [start filter] [stop] [clear] [save log|pddb key|file path]] [stats]";
 
         let mut tokens = args.as_str().unwrap().split(' ');
 
@@ -70,52 +70,26 @@ impl<'a> ShellCmdApi<'a> for NetCmd {
                     }.ok();
                 }
                 "tcpget" => {
-                    // note: to keep shellchat lightweight, we do a very minimal parsing of the URL. We assume it always has
-                    // a form such as:
-                    // bunniefoo.com./bunnie/test.txt
-                    // It will break on everything else. The `url` crate is nice but "large" for a demo.
-                    // There is no https support, obvs.
+                    // fetches a URL such as bunniefoo.com/bunnie/test.txt. The scheme defaults to http://, but https:// works too.
                     if let Some(url) = tokens.next() {
-                        match url.split_once('/') {
-                            Some((host, path)) => {
-                                match TcpStream::connect((host, 80)) {
-                                    Ok(mut stream) => {
-                                        log::trace!("stream open, setting timeouts");
-                                        stream.set_read_timeout(Some(Duration::from_millis(10_000))).unwrap();
-                                        stream.set_write_timeout(Some(Duration::from_millis(10_000))).unwrap();
-                                        log::debug!("read timeout: {:?}", stream.read_timeout().unwrap().unwrap().as_millis());
-                                        log::debug!("write timeout: {:?}", stream.write_timeout().unwrap().unwrap().as_millis());
-                                        log::info!("my socket: {:?}", stream.local_addr());
-                                        log::info!("peer addr: {:?}", stream.peer_addr());
-                                        log::info!("sending GET request");
-                                        match write!(stream, "GET /{} HTTP/1.1\r\n", path) {
-                                            Ok(_) => log::trace!("sent GET"),
-                                            Err(e) => {
-                                                log::error!("GET err {:?}", e);
-                                                write!(ret, "Error sending GET: {:?}", e).unwrap();
-                                            }
-                                        }
-                                        write!(stream, "Host: {}\r\nAccept: */*\r\nUser-Agent: Precursor/0.9.6\r\n", host).expect("stream error");
-                                        write!(stream, "Connection: close\r\n").expect("stream error");
-                                        write!(stream, "\r\n").expect("stream error");
-                                        log::info!("fetching response....");
-                                        let mut buf = [0u8; 512];
-                                        match stream.read(&mut buf) {
-                                            Ok(len) => {
-                                                log::trace!("raw response ({}): {:?}", len, &buf[..len]);
-                                                write!(ret, "{}", std::string::String::from_utf8_lossy(&buf[..len.min(buf.len())])).ok(); // let it run off the end
-                                                log::info!("{}NET.TCPGET,{},{}",
-                                                    xous::BOOKEND_START,
-                                                    std::string::String::from_utf8_lossy(&buf[..len.min(buf.len())]),
-                                                    xous::BOOKEND_END);
-                                            }
-                                            Err(e) => write!(ret, "Didn't get response from host: {:?}", e).unwrap(),
-                                        }
+                        match xous_http::Client::new().timeout_ms(10_000).get(url) {
+                            Ok(response) => {
+                                write!(ret, "{} {}\n", response.status(), response.reason()).unwrap();
+                                // only the start of the body fits in the reply
+                                let mut body = Vec::new();
+                                match response.take(512).read_to_end(&mut body) {
+                                    Ok(_) => {
+                                        let text = std::string::String::from_utf8_lossy(&body);
+                                        write!(ret, "{}", text).ok(); // let it run off the end
+                                        log::info!("{}NET.TCPGET,{},{}",
+                                            xous::BOOKEND_START,
+                                            text,
+                                            xous::BOOKEND_END);
                                     }
-                                    Err(e) => write!(ret, "Couldn't connect to {}:80: {:?}", host, e).unwrap(),
+                                    Err(e) => write!(ret, "Error reading body: {:?}", e).unwrap(),
                                 }
                             }
-                            _ => write!(ret, "Usage: tcpget bunniefoo.com/bunnie/test.txt").unwrap(),
+                            Err(e) => write!(ret, "Couldn't get {}: {:?}", url, e).unwrap(),
                         }
                     } else {
                         write!(ret, "Usage: tcpget bunniefoo.com/bunnie/test.txt").unwrap();
@@ -150,6 +124,10 @@ impl<'a> ShellCmdApi<'a> for NetCmd {
                             log::info!("demo server shutting down.");
                         }
                     });
+                    // make the server discoverable by browsing for web servers on the local network
+                    if let Err(e) = self.dns.mdns_register("Precursor demo", "_http._tcp", 80, &["path=/"]) {
+                        log::warn!("couldn't publish the demo server with mDNS: {:?}", e);
+                    }
                     write!(ret, "TCP listener started on port 80").unwrap();
                     log::info!("{}NET.SERVER,{}", xous::BOOKEND_START, xous::BOOKEND_END);
                 }
@@ -283,10 +261,245 @@ impl<'a> ShellCmdApi<'a> for NetCmd {
                         }
                     }
                 }
+                "stats" => {
+                    match env.netmgr.interface_stats() {
+                        Ok(s) => {
+                            write!(ret, "rx {} pkts {} bytes, {} dropped, {} bad csum\n",
+                                s.rx_packets, s.rx_bytes, s.rx_dropped, s.rx_checksum_errors).unwrap();
+                            write!(ret, "tx {} pkts {} bytes, {} dropped, {} retx",
+                                s.tx_packets, s.tx_bytes, s.tx_dropped, s.tcp_retransmits).unwrap();
+                        }
+                        Err(e) => write!(ret, "Couldn't get interface stats: {:?}", e).unwrap(),
+                    }
+                    match env.netmgr.socket_stats() {
+                        Ok((sockets, total)) => {
+                            for socket in sockets.iter() {
+                                write!(ret, "\n{:?} {} ", socket.kind, socket.state).ok();
+                                if let Some(local) = socket.local {
+                                    write!(ret, "{}", local).ok();
+                                }
+                                if let Some(remote) = socket.remote {
+                                    write!(ret, "->{}", remote).ok();
+                                }
+                                write!(ret, " pid{} rx{} tx{}",
+                                    socket.pid.map_or(0, |p| p.get()), socket.rx_bytes, socket.tx_bytes).ok();
+                                if socket.retransmits != 0 {
+                                    write!(ret, " retx{}", socket.retransmits).ok();
+                                }
+                            }
+                            if total > sockets.len() {
+                                write!(ret, "\n...and {} more", total - sockets.len()).ok();
+                            }
+                        }
+                        Err(e) => write!(ret, "\nCouldn't get socket stats: {:?}", e).unwrap(),
+                    }
+                }
+                "pcap" => {
+                    fn show(ret: &mut String::<1024>, status: Result<net::PcapStatus, xous::Error>) {
+                        match status {
+                            Ok(s) => write!(ret, "Capture {}: {} frames, {} bytes, {} dropped",
+                                if s.running { "running" } else { "stopped" }, s.frames, s.bytes, s.dropped).unwrap(),
+                            Err(e) => write!(ret, "Capture error: {:?}", e).unwrap(),
+                        }
+                    }
+                    match tokens.next() {
+                        Some("start") => {
+                            // the filter is everything after `start`, e.g. `net pcap start udp port 53`
+                            let filter = tokens.collect::<Vec<&str>>().join(" ");
+                            match env.netmgr.pcap_start(&filter) {
+                                Err(xous::Error::InvalidString) => write!(ret, "Invalid filter: {}", filter).unwrap(),
+                                status => show(&mut ret, status),
+                            }
+                        }
+                        Some("stop") => show(&mut ret, env.netmgr.pcap_stop()),
+                        Some("clear") => show(&mut ret, env.netmgr.pcap_clear()),
+                        Some("save") => {
+                            let pcap = match env.netmgr.pcap_fetch() {
+                                Ok(pcap) => pcap,
+                                Err(e) => {
+                                    write!(ret, "Couldn't fetch the capture: {:?}", e).unwrap();
+                                    return Ok(Some(ret));
+                                }
+                            };
+                            match (tokens.next(), tokens.next()) {
+                                (Some("log"), _) => {
+                                    // hex encoded, so the file can be reassembled from the debug console with e.g. `xxd -r -p`
+                                    for chunk in pcap.chunks(256) {
+                                        let mut line = std::string::String::with_capacity(chunk.len() * 2);
+                                        for b in chunk {
+                                            write!(line, "{:02x}", b).unwrap();
+                                        }
+                                        log::info!("{}NET.PCAP,{},{}", xous::BOOKEND_START, line, xous::BOOKEND_END);
+                                    }
+                                    write!(ret, "Wrote {} bytes of pcap to the debug log", pcap.len()).unwrap();
+                                }
+                                (Some("pddb"), Some(key)) => {
+                                    let pddb = pddb::Pddb::new();
+                                    // replace any previous capture, which could be longer than this one
+                                    pddb.delete_key(PCAP_DICT, key, None).ok();
+                                    match pddb.get(PCAP_DICT, key, None, true, true, Some(pcap.len()), None::<fn()>) {
+                                        Ok(mut entry) => {
+                                            match entry.write_all(&pcap).and_then(|_| entry.flush()) {
+                                                Ok(_) => write!(ret, "Saved {} bytes to {}:{}", pcap.len(), PCAP_DICT, key).unwrap(),
+                                                Err(e) => write!(ret, "PDDB error storing capture: {:?}", e).unwrap(),
+                                            }
+                                        }
+                                        Err(e) => write!(ret, "PDDB error creating key: {:?}", e).unwrap(),
+                                    }
+                                }
+                                #[cfg(not(any(target_os = "none", target_os = "xous")))]
+                                (Some("file"), Some(path)) => {
+                                    match std::fs::write(path, &pcap) {
+                                        Ok(_) => write!(ret, "Saved {} bytes to {}", pcap.len(), path).unwrap(),
+                                        Err(e) => write!(ret, "Couldn't write {}: {:?}", path, e).unwrap(),
+                                    }
+                                }
+                                _ => write!(ret, "Usage: net pcap save log|pddb key").unwrap(),
+                            }
+                        }
+                        _ => show(&mut ret, env.netmgr.pcap_status()),
+                    }
+                }
+                "mdns" => {
+                    match tokens.next() {
+                        Some("name") => {
+                            if let Some(name) = tokens.next() {
+                                match self.dns.mdns_set_hostname(name) {
+                                    Ok(_) => write!(ret, "Now answering to {}.local", name).unwrap(),
+                                    Err(e) => write!(ret, "Couldn't set host name: {:?}", e).unwrap(),
+                                }
+                            } else {
+                                write!(ret, "Usage: net mdns name precursor").unwrap();
+                            }
+                        }
+                        Some("browse") => {
+                            let service = tokens.next().unwrap_or("_http._tcp");
+                            match self.dns.mdns_browse(service) {
+                                Ok(instances) => {
+                                    write!(ret, "{} instances of {}", instances.len(), service).unwrap();
+                                    for instance in instances.iter() {
+                                        write!(ret, "\n{} @ {}:{}", instance.instance, instance.host, instance.port).ok();
+                                        if let Some(addr) = instance.addrs.first() {
+                                            write!(ret, " ({})", addr).ok();
+                                        }
+                                    }
+                                }
+                                Err(e) => write!(ret, "Browse error: {:?}", e).unwrap(),
+                            }
+                        }
+                        _ => {
+                            match self.dns.mdns_hostname() {
+                                Ok(name) => write!(ret, "Answering to {}.local", name).unwrap(),
+                                Err(e) => write!(ret, "mDNS error: {:?}", e).unwrap(),
+                            }
+                        }
+                    }
+                }
+                // the stand-in server listens on the loopback interface, which only the host has
+                #[cfg(not(any(target_os = "none", target_os = "xous")))]
+                "httptest" => {
+                    match http_save_test(&pddb::Pddb::new()) {
+                        Ok(_) => write!(ret, "http save test passed").unwrap(),
+                        Err(e) => write!(ret, "http save test failed: {}", e).unwrap(),
+                    }
+                }
+                // `tls` can't be built for the device until `ring` supports riscv32
+                #[cfg(not(any(target_os = "none", target_os = "xous")))]
                 "tls" => {
-
+                    // host names are parsed as host[:port], with a default port of 443
+                    fn host_port(arg: &str) -> (&str, u16) {
+                        match arg.rsplit_once(':').and_then(|(h, p)| Some((h, p.parse::<u16>().ok()?))) {
+                            Some(hp) => hp,
+                            None => (arg, 443),
+                        }
+                    }
+                    let tls = tls::Tls::new();
+                    match tokens.next() {
+                        Some("pin") => {
+                            // pinning is a two-step process: the first call shows the fingerprint of the certificate, and
+                            // the pin is only stored once the user confirms it by passing the same fingerprint back in.
+                            if let Some(arg) = tokens.next() {
+                                let (host, port) = host_port(arg);
+                                let confirmed = tokens.next().map(|fp| tls::fingerprint_from_hex(fp));
+                                match tls.probe(host, port) {
+                                    Ok(chain) => {
+                                        if let Some(cert) = chain.first() {
+                                            let fingerprint = tls::fingerprint(&cert.0);
+                                            match confirmed {
+                                                None => write!(ret, "{} presented a certificate with SHA-256 fingerprint\n{}\nIf this is the certificate you expect, run `net tls pin {} <fingerprint>` to trust it",
+                                                    host, tls::fingerprint_hex(&fingerprint), arg).unwrap(),
+                                                Some(Some(fp)) if fp == fingerprint => match tls.pin(host, cert) {
+                                                    Ok(_) => write!(ret, "Pinned {} to {}", host, tls::fingerprint_hex(&fingerprint)).unwrap(),
+                                                    Err(e) => write!(ret, "Couldn't save pin: {:?}", e).unwrap(),
+                                                },
+                                                Some(_) => write!(ret, "Fingerprint doesn't match the certificate {} presented, which is\n{}\nNothing was pinned",
+                                                    host, tls::fingerprint_hex(&fingerprint)).unwrap(),
+                                            }
+                                        } else {
+                                            write!(ret, "{} didn't present a certificate", host).unwrap();
+                                        }
+                                    }
+                                    Err(e) => write!(ret, "Couldn't reach {}:{}: {:?}", host, port, e).unwrap(),
+                                }
+                            } else {
+                                write!(ret, "Usage: net tls pin host[:port] [fingerprint]").unwrap();
+                            }
+                        }
+                        Some("unpin") => {
+                            if let Some(host) = tokens.next() {
+                                match tls.unpin(host) {
+                                    Ok(count) => write!(ret, "Removed {} pins for {}", count, host).unwrap(),
+                                    Err(e) => write!(ret, "Couldn't remove pins: {:?}", e).unwrap(),
+                                }
+                            } else {
+                                write!(ret, "Usage: net tls unpin host").unwrap();
+                            }
+                        }
+                        Some("mozilla") => {
+                            match tls.trust_mozilla_roots() {
+                                Ok(count) => write!(ret, "Trusted {} roots from the Mozilla root program", count).unwrap(),
+                                Err(e) => write!(ret, "Couldn't save roots: {:?}", e).unwrap(),
+                            }
+                        }
+                        Some("clear") => {
+                            match tls.clear_roots() {
+                                Ok(_) => write!(ret, "All roots of trust removed").unwrap(),
+                                Err(e) => write!(ret, "Couldn't remove roots: {:?}", e).unwrap(),
+                            }
+                        }
+                        Some(arg) => {
+                            let (host, port) = host_port(arg);
+                            match tls.connect(host, port) {
+                                Ok(mut stream) => {
+                                    write!(ret, "Connected to {}:{}\n{:?} {:?}\n",
+                                        host, port,
+                                        stream.conn.protocol_version(),
+                                        stream.conn.negotiated_cipher_suite().map(|cs| cs.suite()),
+                                    ).unwrap();
+                                    // show the status line of a minimal request, to prove data flows both ways
+                                    let mut buf = [0u8; 256];
+                                    match write!(stream, "HEAD / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", host)
+                                        .and_then(|_| stream.flush())
+                                        .and_then(|_| stream.read(&mut buf)) {
+                                        Ok(len) => {
+                                            let response = std::string::String::from_utf8_lossy(&buf[..len]);
+                                            write!(ret, "{}", response.lines().next().unwrap_or("")).unwrap();
+                                        }
+                                        Err(e) => write!(ret, "Error exchanging data: {:?}", e).unwrap(),
+                                    }
+                                }
+                                Err(e) => {
+                                    if let Some(reason) = tls::cert_error(&e) {
+                                        write!(ret, "{} is not trusted: {:?}\nUse `net tls pin {}` to trust it anyway", host, reason, arg).unwrap();
+                                    } else {
+                                        write!(ret, "Couldn't connect to {}:{}: {:?}", host, port, e).unwrap();
+                                    }
+                                }
+                            }
+                        }
+                        None => write!(ret, "Usage: net tls [host[:port]] [pin host[:port] [fingerprint]] [unpin host] [mozilla] [clear]").unwrap(),
+                    }
                 }
-                #[cfg(any(target_os = "none", target_os = "xous"))]
                 "ping" => {
                     if let Some(name) = tokens.next() {
                         match self.dns.lookup(name) {
@@ -423,6 +636,64 @@ enum Responses {
     Buzz,
 }
 
+/// Downloads a body from a stand-in server with `xous_http`, and saves it over an existing, longer key:
+/// the key must end up holding exactly the new body.
+#[cfg(not(any(target_os = "none", target_os = "xous")))]
+fn http_save_test(pddb: &pddb::Pddb) -> Result<(), std::string::String> {
+    const DICT: &'static str = "httpsavetest";
+    const BODY: &'static str = "a short body";
+    let listener = TcpListener::bind("127.0.0.1:0").map_err(|e| format!("couldn't bind the stand-in server: {:?}", e))?;
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        // one request for each save below
+        for _ in 0..2 {
+            let (mut stream, _) = match listener.accept() {
+                Ok(conn) => conn,
+                Err(_) => return,
+            };
+            let mut request = [0u8; 1024];
+            stream.read(&mut request).ok();
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", BODY.len(), BODY).ok();
+        }
+    });
+    let url = format!("http://127.0.0.1:{}/", port);
+    let client = xous_http::Client::new().timeout_ms(10_000);
+    let save = |key: &str| -> Result<u64, std::string::String> {
+        client.get(&url)
+            .and_then(|mut response| response.save_to_pddb(pddb, DICT, key, None))
+            .map_err(|e| format!("couldn't save the body to {}: {:?}", key, e))
+    };
+    let read_key = |key: &str| -> Result<Vec<u8>, std::string::String> {
+        let mut data = Vec::new();
+        pddb.get(DICT, key, None, false, false, None, None::<fn()>)
+            .and_then(|mut handle| handle.read_to_end(&mut data))
+            .map_err(|e| format!("couldn't read back {}: {:?}", key, e))?;
+        Ok(data)
+    };
+
+    let result = (|| -> Result<(), std::string::String> {
+        // a key that doesn't exist yet
+        let len = save("fresh")?;
+        if len != BODY.len() as u64 || read_key("fresh")? != BODY.as_bytes() {
+            return Err(format!("saved {} bytes, expected {}", len, BODY.len()));
+        }
+        // a key that already holds something longer
+        pddb.get(DICT, "existing", None, true, true, None, None::<fn()>)
+            .and_then(|mut handle| handle.write_all(&[0xAA; 100]))
+            .and_then(|_| pddb.sync())
+            .map_err(|e| format!("couldn't set up the existing key: {:?}", e))?;
+        save("existing")?;
+        if read_key("existing")? != BODY.as_bytes() {
+            return Err(std::string::String::from("old contents of the key survived the save"));
+        }
+        Ok(())
+    })();
+
+    pddb.delete_dict(DICT, None).ok();
+    pddb.sync().ok();
+    result
+}
+
 fn handle_connection(mut stream: TcpStream, boot_instant: Instant) {
     // the result is implementation dependent, on Xous hardware, this is effectively the same as ticktimer.elapsed_ms()
     let elapsed_time = Instant::now().duration_since(boot_instant);
@@ -21,9 +21,9 @@ impl<'a> ShellCmdApi<'a> for PddbCmd {
         use core::fmt::Write;
         let mut ret = String::<1024>::new();
         #[cfg(not(feature="pddbtest"))]
-        let helpstring = "pddb [basislist] [basiscreate] [basisunlock] [basislock] [basisdelete] [default]\n[dictlist] [keylist] [query] [dictdelete] [keydelete]";
+        let helpstring = "pddb [basislist] [basiscreate] [basisunlock] [basislock] [basisdelete] [default]\n[dictlist] [keylist] [query] [dictdelete] [keydelete] [fsck]";
         #[cfg(feature="pddbtest")]
-        let helpstring = "pddb [basislist] [basiscreate] [basisunlock] [basislock] [basisdelete] [default]\n[dictlist] [keylist] [query] [dictdelete] [keydelete]\n[test]";
+        let helpstring = "pddb [basislist] [basiscreate] [basisunlock] [basislock] [basisdelete] [default]\n[dictlist] [keylist] [query] [dictdelete] [keydelete] [fsck]\n[test]";
 
         let mut tokens = args.as_str().unwrap().split(' ');
         if let Some(sub_cmd) = tokens.next() {
@@ -61,12 +61,24 @@ impl<'a> ShellCmdApi<'a> for PddbCmd {
                 }
                 "basisunlock" => {
                     if let Some(bname) = tokens.next() {
-                        match self.pddb.unlock_basis(bname, None) {
+                        // an optional second argument locks the basis after that many seconds of inactivity
+                        let policy = if let Some(secs) = tokens.next() {
+                            match secs.parse::<u32>() {
+                                Ok(s) => Some(pddb::BasisRetentionPolicy::TimeOutSecs(s)),
+                                Err(_) => {
+                                    write!(ret, "usage: pddb basisunlock [basis name] [timeout secs]").unwrap();
+                                    return Ok(Some(ret));
+                                }
+                            }
+                        } else {
+                            None
+                        };
+                        match self.pddb.unlock_basis(bname, policy) {
                             Ok(_) => write!(ret, "basis {} unlocked successfully", bname).unwrap(),
                             Err(e) => write!(ret, "basis {} could not be unlocked: {:?}", bname, e).unwrap(),
                         }
                     } else {
-                        write!(ret, "usage: pddb basisunlock [basis name]").unwrap()
+                        write!(ret, "usage: pddb basisunlock [basis name] [timeout secs]").unwrap()
                     }
                 }
                 "basislock" => {
@@ -214,6 +226,32 @@ impl<'a> ShellCmdApi<'a> for PddbCmd {
                         Err(_) => write!(ret, "Error encountered listing dictionaries").ok().unwrap_or(()),
                     }
                 }
+                "fsck" => {
+                    // usage: pddb fsck [repair] [basis name]
+                    let mut repair = false;
+                    let mut bname = tokens.next();
+                    if bname == Some("repair") {
+                        repair = true;
+                        bname = tokens.next();
+                    }
+                    match self.pddb.fsck(bname, repair) {
+                        Ok(report) => {
+                            write!(ret, "Checked {} basis, {} pages: {} problems",
+                                report.bases, report.pages, report.problems()).unwrap();
+                            if report.problems() != 0 {
+                                write!(ret, "\nstale PTE {}, orphaned {}, missing {}, cross-linked {}\nFSCB conflicts {}, FSCB out of range {}",
+                                    report.stale_ptes, report.orphaned, report.missing, report.cross_linked,
+                                    report.fscb_conflicts, report.fscb_out_of_range).unwrap();
+                                if repair {
+                                    write!(ret, "\n{} repaired", report.repaired).unwrap();
+                                } else {
+                                    write!(ret, "\nuse `pddb fsck repair` to fix").unwrap();
+                                }
+                            }
+                        }
+                        Err(e) => write!(ret, "fsck failed: {:?}", e).unwrap(),
+                    }
+                }
                 // note that this feature only works in hosted mode
                 #[cfg(feature="pddbtest")]
                 "test" => {
@@ -328,6 +366,15 @@ impl<'a> ShellCmdApi<'a> for PddbCmd {
                     self.pddb.dbg_dump("std_test3").unwrap();
                     write!(ret, "dumped std_test3\n").unwrap();
 
+                    archive_test(&self.pddb);
+                    write!(ret, "archive test passed\n").unwrap();
+
+                    key_attr_list_test(&self.pddb);
+                    write!(ret, "key attribute list test passed\n").unwrap();
+
+                    subscription_test(&self.pddb);
+                    write!(ret, "subscription test passed\n").unwrap();
+
                 }
                 _ => {
                     write!(ret, "{}", helpstring).unwrap();
@@ -340,3 +387,149 @@ impl<'a> ShellCmdApi<'a> for PddbCmd {
         Ok(Some(ret))
     }
 }
+
+/// Exports the system basis with `export_basis()`, messes with some of its keys, and checks that
+/// `import_basis()` puts them back the way they were.
+#[cfg(feature="pddbtest")]
+fn archive_test(pddb: &pddb::Pddb) {
+    const DICT: &'static str = "archivetest";
+    const PW: &'static str = "archive test password";
+    let basis = pddb::PDDB_DEFAULT_SYSTEM_BASIS;
+    let read_key = |key: &str| -> Option<Vec<u8>> {
+        let mut handle = pddb.get(DICT, key, Some(basis), false, false, None, None::<fn()>).ok()?;
+        let mut data = Vec::new();
+        handle.read_to_end(&mut data).ok()?;
+        Some(data)
+    };
+    let write_key = |key: &str, data: &[u8]| {
+        let mut handle = pddb.get(DICT, key, Some(basis), true, true, Some(data.len()), None::<fn()>)
+            .expect("couldn't create archive test key");
+        handle.write_all(data).expect("couldn't write archive test key");
+    };
+    let mut originals = Vec::new();
+    for i in 0..4 {
+        let name = format!("key{}", i);
+        // one of the keys goes in the large pool
+        let data: Vec<u8> = (0..(100 + i * 3000)).map(|b| (b + i) as u8).collect();
+        write_key(&name, &data);
+        originals.push((name, data));
+    }
+    pddb.sync().unwrap();
+
+    let mut image = Vec::<u8>::new();
+    pddb.export_basis(basis, PW, &mut image).expect("couldn't export basis");
+    log::info!("exported {} into a {}-byte archive", basis, image.len());
+
+    // delete one key, lengthen one, and overwrite another
+    pddb.delete_key(DICT, &originals[0].0, Some(basis)).unwrap();
+    let mut longer = originals[1].1.clone();
+    longer.extend_from_slice(&[0xAA; 500]);
+    write_key(&originals[1].0, &longer);
+    write_key(&originals[2].0, &[0x55; 100]);
+    pddb.sync().unwrap();
+
+    assert!(pddb.import_basis(basis, "wrong password", &mut &image[..]).is_err(), "archive imported with the wrong password");
+    let mut corrupted = image.clone();
+    let flip = corrupted.len() - 20;
+    corrupted[flip] ^= 0x10;
+    assert!(pddb.import_basis(basis, PW, &mut &corrupted[..]).is_err(), "corrupted archive was imported");
+
+    pddb.import_basis(basis, PW, &mut &image[..]).expect("couldn't import basis");
+    for (name, data) in originals.iter() {
+        assert!(read_key(name).as_ref() == Some(data), "{}:{} was not restored", DICT, name);
+    }
+    pddb.delete_dict(DICT, Some(basis)).unwrap();
+    pddb.sync().unwrap();
+}
+
+/// Lists a dictionary that spans several pages of `list_key_attributes()`, and checks the result against
+/// `list_keys()` and the attributes of each key.
+#[cfg(feature="pddbtest")]
+fn key_attr_list_test(pddb: &pddb::Pddb) {
+    const DICT: &'static str = "attrlisttest";
+    // a bit more than two pages' worth, so the last page is a partial one
+    const NUM_KEYS: usize = 37;
+    for i in 0..NUM_KEYS {
+        let mut handle = pddb.get(DICT, &format!("key{}", i), None, true, true, Some(64), None::<fn()>)
+            .expect("couldn't create key attribute test key");
+        handle.write_all(&vec![i as u8; i * 7]).unwrap();
+    }
+    pddb.sync().unwrap();
+
+    let listing = pddb.list_key_attributes(DICT, None).expect("couldn't list key attributes");
+    assert!(listing.len() == NUM_KEYS, "listed {} keys, expected {}", listing.len(), NUM_KEYS);
+    let mut names: Vec<&str> = listing.iter().map(|(name, _)| name.as_str()).collect();
+    names.sort();
+    names.dedup();
+    assert!(names.len() == NUM_KEYS, "paging repeated some keys");
+    let mut expected = pddb.list_keys(DICT, None).unwrap();
+    expected.sort();
+    assert!(names == expected, "listed keys don't match list_keys()");
+
+    for (name, attr) in listing.iter() {
+        let i: usize = name.trim_start_matches("key").parse().unwrap();
+        assert!(attr.len == i * 7, "{} has length {}, expected {}", name, attr.len, i * 7);
+        assert!(attr.flags.valid() && !attr.flags.unresolved(), "{} has the wrong flags: {:?}", name, attr.flags);
+        assert!(attr.dict == DICT, "{} is listed in dictionary {}", name, attr.dict);
+        let single = pddb.get(DICT, name, None, false, false, None, None::<fn()>).unwrap().attributes().unwrap();
+        assert!(attr.reserved == single.reserved && attr.index == single.index && attr.basis == single.basis,
+            "{} attributes don't match those of the key itself", name);
+    }
+    pddb.delete_dict(DICT, None).unwrap();
+    pddb.sync().unwrap();
+}
+
+/// Subscribes to a dictionary, and checks that creating, updating and deleting a key in it are reported,
+/// and that nothing more is reported once the subscription is cancelled.
+#[cfg(feature="pddbtest")]
+fn subscription_test(pddb: &pddb::Pddb) {
+    use std::sync::{Arc, Mutex};
+    use pddb::DictChangeKind;
+    const DICT: &'static str = "subscriptiontest";
+    let tt = ticktimer_server::Ticktimer::new().unwrap();
+    let changes = Arc::new(Mutex::new(Vec::<pddb::DictChange>::new()));
+    let token = pddb.subscribe_dict(DICT, None, {
+        let changes = changes.clone();
+        move |change| changes.lock().unwrap().push(change)
+    }).expect("couldn't subscribe");
+    // notifications are delivered asynchronously, so give them a moment to arrive
+    let wait_for = |kind: DictChangeKind| {
+        for _ in 0..20 {
+            let found = {
+                let mut changes = changes.lock().unwrap();
+                changes.iter().position(|c| c.kind == kind).map(|index| changes.remove(index))
+            };
+            if let Some(change) = found {
+                assert!(change.dict == DICT && change.key.as_deref() == Some("key"), "wrong change reported: {:?}", change);
+                return;
+            }
+            tt.sleep_ms(100).unwrap();
+        }
+        panic!("{:?} was not reported", kind);
+    };
+
+    {
+        let mut key = pddb.get(DICT, "key", None, true, true, Some(64), None::<fn()>).unwrap();
+        key.write_all(&[1, 2, 3]).unwrap();
+    }
+    wait_for(DictChangeKind::KeyCreated);
+    wait_for(DictChangeKind::KeyUpdated);
+    {
+        let mut key = pddb.get(DICT, "key", None, false, false, None, None::<fn()>).unwrap();
+        key.write_all(&[4, 5, 6]).unwrap();
+    }
+    wait_for(DictChangeKind::KeyUpdated);
+    pddb.delete_key(DICT, "key", None).unwrap();
+    wait_for(DictChangeKind::KeyDeleted);
+
+    pddb.unsubscribe_dict(token).expect("couldn't unsubscribe");
+    changes.lock().unwrap().clear();
+    {
+        let mut key = pddb.get(DICT, "key", None, true, true, Some(64), None::<fn()>).unwrap();
+        key.write_all(&[7, 8, 9]).unwrap();
+    }
+    pddb.delete_dict(DICT, None).unwrap();
+    pddb.sync().unwrap();
+    tt.sleep_ms(500).unwrap();
+    assert!(changes.lock().unwrap().is_empty(), "changes were reported after unsubscribing");
+}
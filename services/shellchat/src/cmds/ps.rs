@@ -0,0 +1,88 @@
+use crate::{ShellCmdApi, CommonEnv, write_paged};
+use xous_ipc::String;
+
+#[derive(Debug)]
+pub struct Ps {
+}
+
+/// Formats one process as a line of the `ps` output, e.g. `5 gam 412k 3t 1r 2s 14c 0q`. RAM
+/// isn't tracked in hosted mode, and is shown as `-k` there.
+fn summary(pid: xous::PID, stats: &xous::ProcessStats) -> std::string::String {
+    let name = xous::process_name(pid);
+    format!("{} {} {}k {}t {}r {}s {}c {}q",
+        pid,
+        name.as_ref().map(|n| n.to_str()).unwrap_or("-"),
+        stats.mapped_pages.map(|pages| (pages * 4).to_string()).unwrap_or("-".to_string()),
+        stats.threads,
+        stats.ready_threads,
+        stats.servers,
+        stats.connections,
+        stats.queued_messages,
+    )
+}
+
+impl<'a> ShellCmdApi<'a> for Ps {
+    cmd_api!(ps); // inserts boilerplate for command API
+
+    fn process(&mut self, args: String::<1024>, _env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
+        use core::fmt::Write;
+        let mut ret = String::<1024>::new();
+        let helpstring = "ps [start] [info pid]\nlist: pid name RAM threads ready servers conns queued";
+
+        // there's no call to list the processes, so ask about every possible PID
+        let mut processes = Vec::new();
+        for pid in 1..=u8::MAX {
+            let pid = xous::PID::new(pid).unwrap();
+            if let Ok(stats) = xous::process_stats(pid) {
+                processes.push((pid, stats));
+            }
+        }
+
+        let mut tokens = args.as_str().unwrap().split(' ');
+        match tokens.next().unwrap_or("") {
+            "info" => {
+                let pid = tokens.next().and_then(|s| s.parse::<u8>().ok()).and_then(xous::PID::new);
+                match pid.and_then(|pid| processes.iter().find(|(p, _)| *p == pid)) {
+                    Some((pid, stats)) => {
+                        match xous::process_name(*pid) {
+                            Ok(name) => write!(ret, "PID {} ({})\n", pid, name.to_str()).unwrap(),
+                            Err(_) => write!(ret, "PID {}\n", pid).unwrap(),
+                        }
+                        match stats.mapped_pages {
+                            Some(pages) => write!(ret, "RAM: {} pages, {}k heap\n", pages, stats.heap_size / 1024).unwrap(),
+                            None => write!(ret, "RAM: not tracked, {}k heap\n", stats.heap_size / 1024).unwrap(),
+                        }
+                        write!(ret, "threads: {}, {} ready, {} blocked\n", stats.threads, stats.ready_threads, stats.blocked_threads).unwrap();
+                        write!(ret, "servers: {}, {} messages queued\n", stats.servers, stats.queued_messages).unwrap();
+                        write!(ret, "connections: {}", stats.connections).unwrap();
+                    }
+                    None => write!(ret, "{}", helpstring).unwrap(),
+                }
+            }
+            start => {
+                let start = match start {
+                    "" => 0,
+                    s => match s.parse::<usize>() {
+                        Ok(start) => start,
+                        Err(_) => {
+                            write!(ret, "{}", helpstring).unwrap();
+                            return Ok(Some(ret));
+                        }
+                    },
+                };
+                // every process is logged, as the full table may not fit in the reply
+                for (pid, stats) in processes.iter() {
+                    log::info!("{}", summary(*pid, stats));
+                }
+                let total_pages: usize = processes.iter().filter_map(|(_, stats)| stats.mapped_pages).sum();
+                write!(ret, "{} processes, {}k RAM\n", processes.len(), total_pages * 4).unwrap();
+                write_paged(
+                    &mut ret,
+                    processes.iter().enumerate().skip(start).map(|(i, (pid, stats))| (i, summary(*pid, stats))),
+                    |i| format!("ps {}", i),
+                );
+            }
+        }
+        Ok(Some(ret))
+    }
+}
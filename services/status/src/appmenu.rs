@@ -4,7 +4,7 @@ use num_traits::*;
 
 use crate::{StatusOpcode, app_autogen};
 
-pub fn create_app_menu(status_conn: xous::CID) {
+pub fn create_app_menu(status_conn: xous::CID, app_mgr: xous::SID) -> MenuMatic {
     let mut menu_items = Vec::<MenuItem>::new();
 
     menu_items.push(MenuItem {
@@ -18,12 +18,38 @@ pub fn create_app_menu(status_conn: xous::CID) {
     // insert the application menu items
     app_autogen::app_menu_items(&mut menu_items, status_conn);
 
-    menu_items.push(MenuItem {
+    menu_items.push(close_item());
+    menu_matic(menu_items, gam::APP_MENU_NAME, Some(app_mgr)).expect("couldn't create MenuMatic manager")
+}
+
+fn close_item() -> MenuItem {
+    MenuItem {
         name: xous_ipc::String::from_str(t!("mainmenu.closemenu", xous::LANG)),
         action_conn: None,
         action_opcode: 0,
         action_payload: MenuPayload::Scalar([0, 0, 0, 0]),
         close_on_select: true,
-    });
-    menu_matic(menu_items, gam::APP_MENU_NAME, None);
+    }
+}
+
+/// Adds menu items for apps that were stored in the PDDB since the last call. `stored_apps` holds the names
+/// of the apps already in the menu, and the index of a name in it is the payload of its menu item.
+pub fn add_stored_apps(menu: &MenuMatic, status_conn: xous::CID, stored_apps: &mut Vec<String>, names: Vec<String>) {
+    let new_apps: Vec<String> = names.into_iter().filter(|name| !stored_apps.contains(name)).collect();
+    if new_apps.is_empty() {
+        return;
+    }
+    // keep the close item at the bottom of the menu
+    menu.delete_item(t!("mainmenu.closemenu", xous::LANG));
+    for name in new_apps {
+        menu.add_item(MenuItem {
+            name: xous_ipc::String::from_str(&name),
+            action_conn: Some(status_conn),
+            action_opcode: StatusOpcode::LaunchStoredApp.to_u32().unwrap(),
+            action_payload: MenuPayload::Scalar([stored_apps.len() as u32, 0, 0, 0]),
+            close_on_select: true,
+        });
+        stored_apps.push(name);
+    }
+    menu.add_item(close_item());
 }
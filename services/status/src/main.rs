@@ -9,6 +9,7 @@ mod kbdmenu;
 use kbdmenu::*;
 mod app_autogen;
 mod time;
+mod netpolicy;
 
 use com::api::*;
 use core::fmt::Write;
@@ -52,6 +53,8 @@ pub(crate) enum StatusOpcode {
     SwitchToShellchat,
     /// Switch to an app
     SwitchToApp,
+    /// Start an app stored in the PDDB
+    LaunchStoredApp,
 
     /// Set the keyboard map
     SetKeyboard,
@@ -83,6 +86,18 @@ fn battstats_cb(stats: BattStats) {
     }
 }
 
+/// Formats the traffic rate of `bytes` moved over `ms` milliseconds, compactly enough for the status bar
+fn format_rate(bytes: u64, ms: u64) -> String {
+    let rate = bytes * 1000 / ms.max(1);
+    if rate < 1000 {
+        format!("{}B", rate)
+    } else if rate < 1_000_000 {
+        format!("{:.1}k", rate as f32 / 1000.0)
+    } else {
+        format!("{:.1}M", rate as f32 / 1_000_000.0)
+    }
+}
+
 pub fn pump_thread(conn: usize, pump_run: Arc<AtomicBool>) {
     let ticktimer = ticktimer_server::Ticktimer::new().unwrap();
     loop {
@@ -306,6 +321,8 @@ fn main() -> ! {
         secnotes_interval = 4;
     }
     let mut battstats_phase = true;
+    // (time, rx bytes, tx bytes) of the interface when the SSID was last shown, to compute the traffic rate
+    let mut last_traffic: Option<(u64, u64, u64)> = None;
     let mut secnotes_force_redraw = false;
 
     // the EC gets reset by the Net crate on boot to ensure that the state machines are synced up
@@ -324,6 +341,10 @@ fn main() -> ! {
     let time_sid = xous::create_server().unwrap();
     let time_cid = xous::connect(time_sid).unwrap();
     time::start_time_ux(time_sid);
+    // and one for the network permissions editor
+    let net_policy_sid = xous::create_server().unwrap();
+    let net_policy_cid = xous::connect(net_policy_sid).unwrap();
+    netpolicy::start_net_policy_ux(net_policy_sid);
     // this is used by the main loop to get the localtime to show on the status bar
     let mut localtime = llio::LocalTime::new();
     // used to hide time when the PDDB is not mounted
@@ -333,8 +354,14 @@ fn main() -> ! {
     let modals = modals::Modals::new(&xns).unwrap();
 
     log::debug!("starting main menu thread");
-    create_main_menu(keys.clone(), xous::connect(status_sid).unwrap(), &com, time_cid);
-    create_app_menu(xous::connect(status_sid).unwrap());
+    create_main_menu(keys.clone(), xous::connect(status_sid).unwrap(), &com, time_cid, net_policy_cid);
+    let app_mgr = xous::create_server().unwrap();
+    let app_menumatic = create_app_menu(xous::connect(status_sid).unwrap(), app_mgr);
+    // apps stored in the PDDB, in the order they were added to the app menu
+    let mut stored_apps = Vec::<String>::new();
+    let stored_apps_conn = xous::connect(status_sid).unwrap();
+    // the loader takes one connection, so it's made once, the first time the app menu is raised
+    let mut loader: Option<app_loader::AppLoader> = None;
     let kbd_mgr = xous::create_server().unwrap();
     let kbd_menumatic = create_kbd_menu(xous::connect(status_sid).unwrap(), kbd_mgr);
     let kbd = keyboard::Keyboard::new(&xns).unwrap();
@@ -396,6 +423,18 @@ fn main() -> ! {
                                 ssid.name.as_str().unwrap_or("UTF-8 Erorr"),
                                 ssid.rssi,
                             ).unwrap();
+                            if let Ok(traffic) = netmgr.interface_stats() {
+                                let now = ticktimer.elapsed_ms();
+                                if let Some((then, rx, tx)) = last_traffic {
+                                    write!(
+                                        &mut battstats_tv,
+                                        " \u{2b07}{}\u{2b06}{}",
+                                        format_rate(traffic.rx_bytes.saturating_sub(rx), now - then),
+                                        format_rate(traffic.tx_bytes.saturating_sub(tx), now - then),
+                                    ).unwrap();
+                                }
+                                last_traffic = Some((now, traffic.rx_bytes, traffic.tx_bytes));
+                            }
                         } else {
                             write!(
                                 &mut battstats_tv,
@@ -599,6 +638,17 @@ fn main() -> ! {
             },
             Some(StatusOpcode::SubmenuApp) => {
                 ticktimer.sleep_ms(100).ok(); // yield for a moment to allow the previous menu to close
+                // pick up any apps that were stored in the PDDB since the menu was last raised. The loader is
+                // optional, so the menu just has the built-in apps if it isn't in the image.
+                if loader.is_none() {
+                    loader = app_loader::AppLoader::new(&xns).ok();
+                }
+                if let Some(loader) = loader.as_ref() {
+                    match loader.list_apps() {
+                        Ok(names) => add_stored_apps(&app_menumatic, stored_apps_conn, &mut stored_apps, names),
+                        Err(e) => log::warn!("couldn't list stored apps: {:?}", e),
+                    }
+                }
                 gam.raise_menu(gam::APP_MENU_NAME).expect("couldn't raise App submenu");
             },
             Some(StatusOpcode::SubmenuKbd) => {
@@ -637,6 +687,20 @@ fn main() -> ! {
                     Message::new_scalar(StatusOpcode::Pump.to_usize().unwrap(), 0, 0, 0, 0),
                 ).expect("couldn't trigger status update");
             }),
+            Some(StatusOpcode::LaunchStoredApp) => msg_scalar_unpack!(msg, index, _, _, _, {
+                let app_name = stored_apps.get(index).expect("stored app index not found").clone();
+                // the item is only in the menu if the loader was reached
+                match loader.as_ref().unwrap().launch(&app_name) {
+                    Ok(pid) => log::info!("started {} as PID {}", app_name, pid),
+                    Err(e) => {
+                        log::warn!("couldn't start {}: {:?}", app_name, e);
+                        modals.show_notification(
+                            &format!("{}{} ({:?})", t!("appmenu.launch_fail", xous::LANG), app_name, e),
+                            None
+                        ).expect("couldn't show notification");
+                    }
+                }
+            }),
             Some(StatusOpcode::TrySuspend) => {
                 if ((llio.adc_vbus().unwrap() as f64) * 0.005033) > 1.5 {
                     modals.show_notification(t!("mainmenu.cant_sleep", xous::LANG), None).expect("couldn't notify that power is plugged in");
@@ -8,7 +8,7 @@ use num_traits::*;
 use crate::StatusOpcode;
 
 #[allow(unused_variables)] // quiets a warning about unused com that is emitted in tts config. Would be nice to make this more targeted...
-pub fn create_main_menu(keys: Arc<Mutex<RootKeys>>, status_conn: xous::CID, com: &com::Com, time_ux_conn: xous::CID) {
+pub fn create_main_menu(keys: Arc<Mutex<RootKeys>>, status_conn: xous::CID, com: &com::Com, time_ux_conn: xous::CID, net_policy_conn: xous::CID) {
     let key_conn = keys.lock().unwrap().conn();
 
     let mut menuitems = Vec::<MenuItem>::new();
@@ -113,6 +113,13 @@ pub fn create_main_menu(keys: Arc<Mutex<RootKeys>>, status_conn: xous::CID, com:
         action_payload: MenuPayload::Scalar([0, 0, 0, 0]),
         close_on_select: true,
     });
+    menuitems.push(MenuItem {
+        name: String::from_str(t!("mainmenu.net_policy", xous::LANG)),
+        action_conn: Some(net_policy_conn),
+        action_opcode: crate::netpolicy::NetPolicyUxOp::Edit.to_u32().unwrap(),
+        action_payload: MenuPayload::Scalar([0, 0, 0, 0]),
+        close_on_select: true,
+    });
     menuitems.push(MenuItem {
         name: String::from_str(t!("mainmenu.battery_disconnect", xous::LANG)),
         action_conn: Some(status_conn),
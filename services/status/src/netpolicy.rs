@@ -0,0 +1,121 @@
+//! Editor for the net server's per-app socket policies, raised from the main menu.
+//!
+//! The editor offers a few presets, plus free-form rules for anything else. The rules are
+//! validated by the net server, which also stores them in the PDDB.
+use std::thread;
+use locales::t;
+use num_traits::*;
+
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+pub(crate) enum NetPolicyUxOp {
+    Edit,
+    Quit,
+}
+
+const PRESET_ALLOW_ALL: &str = "allow connect any; allow listen; allow ping";
+const PRESET_DENY_ALL: &str = "deny connect any; deny listen; deny ping";
+const PRESET_OUTBOUND_ONLY: &str = "deny listen";
+
+pub fn start_net_policy_ux(sid: xous::SID) {
+    thread::spawn({
+        move || {
+            let xns = xous_names::XousNames::new().unwrap();
+            let modals = modals::Modals::new(&xns).unwrap();
+            let netmgr = net::NetManager::new();
+            let pddb_poller = pddb::PddbMountPoller::new();
+
+            loop {
+                let msg = xous::receive_message(sid).unwrap();
+                match FromPrimitive::from_usize(msg.body.id()) {
+                    Some(NetPolicyUxOp::Edit) => xous::msg_scalar_unpack!(msg, _, _, _, _, {
+                        if !pddb_poller.is_mounted_nonblocking() {
+                            modals.show_notification(t!("stats.please_mount", xous::LANG), None).expect("couldn't show notification");
+                            continue;
+                        }
+                        // pick the app to edit; the default policy is listed first
+                        let all_apps = t!("netpolicy.all_apps", xous::LANG);
+                        modals.add_list_item(all_apps).expect("couldn't build radio item list");
+                        let apps = netmgr.policy_apps().unwrap_or_default();
+                        for app in apps.iter().filter(|app| app.as_str() != net::DEFAULT_POLICY_APP) {
+                            modals.add_list_item(app).expect("couldn't build radio item list");
+                        }
+                        let app = match modals.get_radiobutton(t!("netpolicy.pick_app", xous::LANG)) {
+                            Ok(app) if app == all_apps => net::DEFAULT_POLICY_APP.to_string(),
+                            Ok(app) => app,
+                            _ => {
+                                log::error!("get_radiobutton failed");
+                                continue;
+                            }
+                        };
+
+                        // pick what to do with it
+                        let current = netmgr.policy_get(&app).unwrap_or_default();
+                        let choices = [
+                            t!("netpolicy.allow_all", xous::LANG),
+                            t!("netpolicy.deny_all", xous::LANG),
+                            t!("netpolicy.outbound_only", xous::LANG),
+                            t!("netpolicy.custom", xous::LANG),
+                            t!("netpolicy.remove", xous::LANG),
+                        ];
+                        for choice in choices.iter() {
+                            modals.add_list_item(choice).expect("couldn't build radio item list");
+                        }
+                        let prompt = if current.is_empty() {
+                            format!("{}\n{}", app, t!("netpolicy.no_policy", xous::LANG))
+                        } else {
+                            format!("{}\n{}", app, current.replace('\n', "; "))
+                        };
+                        let choice = match modals.get_radiobutton(&prompt) {
+                            Ok(choice) => choice,
+                            _ => {
+                                log::error!("get_radiobutton failed");
+                                continue;
+                            }
+                        };
+                        let rules = if choice == choices[0] {
+                            PRESET_ALLOW_ALL.to_string()
+                        } else if choice == choices[1] {
+                            PRESET_DENY_ALL.to_string()
+                        } else if choice == choices[2] {
+                            PRESET_OUTBOUND_ONLY.to_string()
+                        } else if choice == choices[3] {
+                            let placeholder = if current.is_empty() { None } else { Some(current.replace('\n', "; ")) };
+                            let entered = modals.alert_builder(t!("netpolicy.enter_rules", xous::LANG))
+                                .field(placeholder, None)
+                                .build()
+                                .expect("couldn't get rules")
+                                .first()
+                                .as_str()
+                                .to_string();
+                            if entered.trim().is_empty() {
+                                // nothing typed over the placeholder: leave the policy as it was
+                                continue;
+                            }
+                            entered
+                        } else {
+                            String::new()
+                        };
+                        match netmgr.policy_set(&app, &rules) {
+                            Ok(()) => (),
+                            Err(xous::Error::InvalidString) => {
+                                modals.show_notification(t!("netpolicy.invalid_rules", xous::LANG), None).expect("couldn't show notification");
+                            }
+                            Err(e) => {
+                                log::error!("couldn't set socket policy for {}: {:?}", app, e);
+                                modals.show_notification(t!("netpolicy.set_failed", xous::LANG), None).expect("couldn't show notification");
+                            }
+                        }
+                    }),
+                    Some(NetPolicyUxOp::Quit) => {
+                        xous::return_scalar(msg.sender, 0).unwrap();
+                        break;
+                    }
+                    None => {
+                        log::warn!("unhandled opcode: {:?}", msg);
+                    }
+                }
+            }
+            xous::destroy_server(sid).ok();
+        }
+    });
+}
@@ -3,7 +3,7 @@ MEMORY
   RAM : ORIGIN = 0x40000000, LENGTH = 16M
   FLASH : ORIGIN = 0x20501000, LENGTH = 188k
   FONTS : ORIGIN = 0x20530000, LENGTH = 4352k
-  MEMLCD: ORIGIN = 0xB0000000, LENGTH = 32k
+  MEMLCD : ORIGIN = 0xB0000000, LENGTH = 32k
 }
 /*
 Fonts go from 0x2053_0000 to 0x2097_0000
@@ -7,6 +7,7 @@ enum StartupCommand {
     WriteArgs = 2,
     WriteEnvironment = 3,
     PingResponse = 4,
+    WriteText = 5,
     FinishStartup = 255,
 }
 
@@ -17,6 +18,7 @@ impl From<xous::MessageId> for StartupCommand {
             2 => StartupCommand::WriteArgs,
             3 => StartupCommand::WriteEnvironment,
             4 => StartupCommand::PingResponse,
+            5 => StartupCommand::WriteText,
             255 => StartupCommand::FinishStartup,
             _ => StartupCommand::Unhandled,
         }
@@ -37,6 +39,7 @@ pub extern "C" fn init(server1: u32, server2: u32, server3: u32, server4: u32) -
         {
             match envelope.id().into() {
                 StartupCommand::WriteMemory => write_memory(envelope.body.memory_message()),
+                StartupCommand::WriteText => write_text(envelope.body.memory_message()),
                 StartupCommand::FinishStartup => finish_startup(server, envelope),
                 StartupCommand::PingResponse => ping_response(envelope),
 
@@ -78,6 +81,34 @@ fn write_memory(memory: Option<&xous::MemoryMessage>) {
     }
 }
 
+/// Like `write_memory()`, but for code. The pages have to be mapped writable to copy the code in,
+/// and the write permission is dropped afterwards, as flags can be removed but never added.
+fn write_text(memory: Option<&xous::MemoryMessage>) {
+    let memory = match memory {
+        Some(s) => s,
+        None => return,
+    };
+
+    let mut target_memory = xous::map_memory(
+        None,
+        memory.offset,
+        memory.buf.len(),
+        xous::MemoryFlags::R | xous::MemoryFlags::W | xous::MemoryFlags::X,
+    )
+    .unwrap();
+
+    for (src, dest) in memory
+        .buf
+        .as_slice::<usize>()
+        .iter()
+        .zip(target_memory.as_slice_mut())
+    {
+        *dest = *src;
+    }
+
+    xous::update_memory_flags(target_memory, xous::MemoryFlags::R | xous::MemoryFlags::X).unwrap();
+}
+
 fn finish_startup(server: xous::SID, envelope: xous::MessageEnvelope) -> ! {
     let entrypoint = envelope.body.scalar_message().unwrap().arg1;
     drop(envelope);
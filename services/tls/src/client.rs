@@ -0,0 +1,72 @@
+use crate::trust::*;
+
+use rustls::{Certificate, ClientConfig, ClientConnection, ServerName, StreamOwned};
+
+use std::convert::TryFrom;
+use std::io::{Error, ErrorKind, Result};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long to wait on the network, both during the handshake and afterwards
+const TLS_TIMEOUT_MS: u64 = 10_000;
+
+/// A TLS connection to a server. It implements `Read` and `Write` for the plaintext.
+pub type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+/// Connects to `host` and runs the handshake, so that certificate problems are reported here
+/// rather than on the first read or write.
+pub fn connect_with(config: Arc<ClientConfig>, host: &str, port: u16) -> Result<TlsStream> {
+    let sock = TcpStream::connect((host, port))?;
+    handshake(config, sock, host)
+}
+
+/// Runs the client handshake for `host` over an already connected socket. A socket without timeouts
+/// gets the default of `TLS_TIMEOUT_MS`.
+pub fn handshake(config: Arc<ClientConfig>, sock: TcpStream, host: &str) -> Result<TlsStream> {
+    let name = ServerName::try_from(host)
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "invalid host name"))?;
+    if sock.read_timeout()?.is_none() {
+        sock.set_read_timeout(Some(Duration::from_millis(TLS_TIMEOUT_MS)))?;
+    }
+    if sock.write_timeout()?.is_none() {
+        sock.set_write_timeout(Some(Duration::from_millis(TLS_TIMEOUT_MS)))?;
+    }
+    let conn = ClientConnection::new(config, name)
+        .map_err(|e| Error::new(ErrorKind::Other, e))?;
+    let mut stream = StreamOwned::new(conn, sock);
+    while stream.conn.is_handshaking() {
+        // errors in the handshake, including certificate validation, come back as `InvalidData`
+        // with the `rustls::Error` inside
+        stream.conn.complete_io(&mut stream.sock)?;
+    }
+    Ok(stream)
+}
+
+/// Retrieves the certificate chain presented by `host`, without validating it. The end-entity
+/// certificate comes first.
+pub fn probe_with(sock: TcpStream, host: &str) -> Result<Vec<Certificate>> {
+    let config = Arc::new(
+        ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(ProbeVerifier {}))
+            .with_no_client_auth()
+    );
+    let mut stream = handshake(config, sock, host)?;
+    let chain = stream.conn.peer_certificates().map(|c| c.to_vec()).unwrap_or_default();
+    stream.conn.send_close_notify();
+    stream.conn.complete_io(&mut stream.sock).ok();
+    Ok(chain)
+}
+
+/// If `e` is a certificate validation failure, returns the reason.
+pub fn cert_error(e: &Error) -> Option<&rustls::Error> {
+    match e.get_ref().and_then(|inner| inner.downcast_ref::<rustls::Error>()) {
+        Some(err @ rustls::Error::InvalidCertificateData(_))
+        | Some(err @ rustls::Error::InvalidCertificateEncoding)
+        | Some(err @ rustls::Error::InvalidCertificateSignature)
+        | Some(err @ rustls::Error::InvalidCertificateSignatureType)
+        | Some(err @ rustls::Error::UnsupportedNameType) => Some(err),
+        _ => None,
+    }
+}
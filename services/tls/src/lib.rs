@@ -0,0 +1,162 @@
+//! A TLS client for Xous programs, built on `rustls` over the `std::net::TcpStream` provided by the
+//! net server.
+//!
+//! The trust store lives in the PDDB, so it follows the user's bases: roots of trust are kept in the
+//! `tls.trusted` dictionary, and certificates pinned to a host are kept in `tls.pinned`. Certificate
+//! validity periods are checked against the wall clock kept by the time server in `status`, so
+//! connections are refused until the time has been set.
+
+mod trust;
+pub use trust::*;
+mod client;
+pub use client::*;
+#[cfg(test)]
+mod tests;
+
+use rustls::Certificate;
+
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::TcpStream;
+
+/// Dictionary of trust anchors. The key is a fingerprint of the anchor, and the value is the anchor
+/// as encoded by `encode_anchor()`.
+pub const TLS_TRUSTED_DICT: &str = "tls.trusted";
+/// Dictionary of pinned certificates. The key is `<host>:<fingerprint>`, and the value is the DER
+/// certificate, which is kept so it can be inspected later.
+pub const TLS_PINNED_DICT: &str = "tls.pinned";
+
+pub struct Tls {
+    pddb: pddb::Pddb,
+}
+impl Tls {
+    pub fn new() -> Self {
+        Tls {
+            pddb: pddb::Pddb::new(),
+        }
+    }
+
+    fn list_keys(&self, dict: &str) -> Result<Vec<String>> {
+        match self.pddb.list_keys(dict, None) {
+            Ok(keys) => Ok(keys),
+            // nothing has been stored yet
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write_key(&self, dict: &str, key: &str, data: &[u8]) -> Result<()> {
+        let mut entry = self.pddb.get(dict, key, None, true, true, Some(data.len()), None::<fn()>)?;
+        entry.write_all(data)?;
+        entry.flush()?;
+        self.pddb.sync()
+    }
+
+    /// Loads the trust store from the PDDB. Entries that are corrupt are skipped with a warning.
+    pub fn trust_store(&self) -> Result<TrustStore> {
+        let mut store = TrustStore::new();
+        for key in self.list_keys(TLS_TRUSTED_DICT)? {
+            let mut entry = self.pddb.get(TLS_TRUSTED_DICT, &key, None, false, false, None, None::<fn()>)?;
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            if !store.add_encoded_anchor(&data) {
+                log::warn!("skipping corrupt trust anchor {}", key);
+            }
+        }
+        for key in self.list_keys(TLS_PINNED_DICT)? {
+            match key.rsplit_once(':').and_then(|(host, fp)| Some((host, fingerprint_from_hex(fp)?))) {
+                Some((host, fp)) => store.pin(host, fp),
+                None => log::warn!("skipping malformed pin {}", key),
+            }
+        }
+        log::debug!("loaded {} roots and {} pins", store.root_count(), store.pin_count());
+        Ok(store)
+    }
+
+    /// Adds the DER certificate `der` to the roots of trust.
+    pub fn trust_root(&self, der: &[u8]) -> Result<()> {
+        let anchor = webpki::TrustAnchor::try_from_cert_der(der)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "not a valid certificate"))?;
+        self.store_anchor(&anchor)
+    }
+
+    fn store_anchor(&self, anchor: &webpki::TrustAnchor) -> Result<()> {
+        let encoded = encode_anchor(anchor);
+        let key = fingerprint_hex(&fingerprint(&encoded));
+        self.write_key(TLS_TRUSTED_DICT, &key, &encoded)
+    }
+
+    /// Adds the roots of the Mozilla root program that were bundled at build time. Returns the number of roots added.
+    #[cfg(feature = "mozilla-roots")]
+    pub fn trust_mozilla_roots(&self) -> Result<usize> {
+        for anchor in webpki_roots::TLS_SERVER_ROOTS.0.iter() {
+            self.store_anchor(anchor)?;
+        }
+        Ok(webpki_roots::TLS_SERVER_ROOTS.0.len())
+    }
+
+    /// Removes every root of trust.
+    pub fn clear_roots(&self) -> Result<()> {
+        match self.pddb.delete_dict(TLS_TRUSTED_DICT, None) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => self.pddb.sync(),
+        }
+    }
+
+    /// Trusts `cert` whenever it is presented by `host`. This is how self-signed servers are trusted.
+    pub fn pin(&self, host: &str, cert: &Certificate) -> Result<()> {
+        let key = format!("{}:{}", host.to_ascii_lowercase(), fingerprint_hex(&fingerprint(&cert.0)));
+        self.write_key(TLS_PINNED_DICT, &key, &cert.0)
+    }
+
+    /// Removes every pin for `host`. Returns the number of pins removed.
+    pub fn unpin(&self, host: &str) -> Result<usize> {
+        let mut count = 0;
+        for key in self.list_keys(TLS_PINNED_DICT)? {
+            if key.rsplit_once(':').map_or(false, |(h, _)| h.eq_ignore_ascii_case(host)) {
+                self.pddb.delete_key(TLS_PINNED_DICT, &key, None)?;
+                count += 1;
+            }
+        }
+        self.pddb.sync()?;
+        Ok(count)
+    }
+
+    /// Opens a connection to `host`:`port`, validating it against the trust store.
+    pub fn connect(&self, host: &str, port: u16) -> Result<TlsStream> {
+        check_time()?;
+        connect_with(self.trust_store()?.client_config(), host, port)
+    }
+
+    /// Like `connect()`, over a socket that is already connected to `host`. Timeouts that are set on the
+    /// socket are kept, so the caller can bound how long the handshake may take.
+    pub fn connect_over(&self, sock: TcpStream, host: &str) -> Result<TlsStream> {
+        check_time()?;
+        handshake(self.trust_store()?.client_config(), sock, host)
+    }
+
+    /// Retrieves the certificate chain presented by `host`:`port` without validating it, so that the
+    /// user can inspect it before pinning it.
+    pub fn probe(&self, host: &str, port: u16) -> Result<Vec<Certificate>> {
+        probe_with(TcpStream::connect((host, port))?, host)
+    }
+}
+
+impl Default for Tls {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Certificate validity can't be checked until the wall clock has been set.
+#[cfg(any(target_os = "none", target_os = "xous"))]
+fn check_time() -> Result<()> {
+    if llio::LocalTime::new().get_local_time_ms().is_none() {
+        return Err(Error::new(ErrorKind::Other, "the time is not set, so certificates can't be checked"));
+    }
+    Ok(())
+}
+/// Hosted mode uses the host's clock, which is always set.
+#[cfg(not(any(target_os = "none", target_os = "xous")))]
+fn check_time() -> Result<()> {
+    Ok(())
+}
@@ -0,0 +1,124 @@
+// The fixtures in `testdata/` are an ECDSA P-256 CA, and a certificate for `localhost` issued by it,
+// both valid for 100 years. They were made with:
+//   openssl ecparam -name prime256v1 -genkey -noout -out ca.key
+//   openssl req -x509 -new -key ca.key -sha256 -days 36500 -subj "/CN=Xous Test CA" \
+//     -addext "basicConstraints=critical,CA:TRUE" -addext "keyUsage=critical,keyCertSign,cRLSign" -out ca.pem
+//   openssl ecparam -name prime256v1 -genkey -noout -out leaf.key
+//   openssl req -new -key leaf.key -subj "/CN=localhost" -out leaf.csr
+//   openssl x509 -req -in leaf.csr -CA ca.pem -CAkey ca.key -CAcreateserial -days 36500 -sha256 -extfile leaf.ext -out leaf.pem
+// where leaf.ext sets CA:FALSE, digitalSignature, serverAuth and DNS:localhost; followed by conversion to DER
+// (and PKCS#8 for the key).
+use crate::*;
+
+use rustls::{Certificate, PrivateKey, ServerConfig, ServerConnection, StreamOwned};
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+
+const CA: &[u8] = include_bytes!("testdata/ca.der");
+const LEAF: &[u8] = include_bytes!("testdata/leaf.der");
+const LEAF_KEY: &[u8] = include_bytes!("testdata/leaf.key.der");
+
+/// Starts a TLS server on the loopback interface that echoes back the first read of one connection.
+fn loopback_server() -> (u16, thread::JoinHandle<()>) {
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(vec![Certificate(LEAF.to_vec()), Certificate(CA.to_vec())], PrivateKey(LEAF_KEY.to_vec()))
+        .unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = thread::spawn(move || {
+        let (sock, _) = listener.accept().unwrap();
+        let conn = ServerConnection::new(Arc::new(config)).unwrap();
+        let mut stream = StreamOwned::new(conn, sock);
+        let mut buf = [0u8; 64];
+        // this fails when the client rejects our certificate, which some of the tests expect
+        if let Ok(len) = stream.read(&mut buf) {
+            stream.write_all(&buf[..len]).ok();
+            stream.conn.send_close_notify();
+            stream.conn.complete_io(&mut stream.sock).ok();
+        }
+    });
+    (port, handle)
+}
+
+fn connect_loopback(store: TrustStore, host: &str) -> std::io::Result<TlsStream> {
+    let (port, server) = loopback_server();
+    let result = handshake(store.client_config(), TcpStream::connect(("127.0.0.1", port)).unwrap(), host);
+    let result = result.and_then(|mut stream| {
+        stream.write_all(b"hello xous")?;
+        let mut echo = Vec::new();
+        stream.read_to_end(&mut echo)?;
+        assert_eq!(echo, b"hello xous");
+        Ok(stream)
+    });
+    server.join().unwrap();
+    result
+}
+
+#[test]
+fn trusted_root() {
+    let mut store = TrustStore::new();
+    store.add_root(CA).unwrap();
+    assert_eq!(store.root_count(), 1);
+    connect_loopback(store, "localhost").unwrap();
+}
+
+#[test]
+fn untrusted_root() {
+    // no anchors at all
+    let e = connect_loopback(TrustStore::new(), "localhost").unwrap_err();
+    assert!(cert_error(&e).is_some(), "{:?}", e);
+
+    // the right anchor, but the wrong name
+    let mut store = TrustStore::new();
+    store.add_root(CA).unwrap();
+    let e = connect_loopback(store, "xous.localhost").unwrap_err();
+    assert!(cert_error(&e).is_some(), "{:?}", e);
+}
+
+#[test]
+fn pinned_cert() {
+    let mut store = TrustStore::new();
+    store.pin("LocalHost", fingerprint(LEAF));
+    connect_loopback(store, "localhost").unwrap();
+
+    // a pin only applies to the host it was made for
+    let mut store = TrustStore::new();
+    store.pin("example.com", fingerprint(LEAF));
+    assert!(connect_loopback(store, "localhost").is_err());
+
+    // pinning the CA doesn't pin the certificates it issues
+    let mut store = TrustStore::new();
+    store.pin("localhost", fingerprint(CA));
+    assert!(connect_loopback(store, "localhost").is_err());
+}
+
+#[test]
+fn probe() {
+    let (port, server) = loopback_server();
+    let chain = probe_with(TcpStream::connect(("127.0.0.1", port)).unwrap(), "localhost").unwrap();
+    server.join().unwrap();
+    assert_eq!(chain.len(), 2);
+    assert_eq!(chain[0].0, LEAF);
+    assert_eq!(chain[1].0, CA);
+}
+
+#[test]
+fn anchor_encoding() {
+    let anchor = webpki::TrustAnchor::try_from_cert_der(CA).unwrap();
+    let encoded = encode_anchor(&anchor);
+    let mut store = TrustStore::new();
+    assert!(store.add_encoded_anchor(&encoded));
+    assert!(!store.add_encoded_anchor(&encoded[..encoded.len() - 1]));
+    assert!(!store.add_encoded_anchor(&[]));
+    assert_eq!(store.root_count(), 1);
+    connect_loopback(store, "localhost").unwrap();
+
+    let fp = fingerprint(LEAF);
+    assert_eq!(fingerprint_from_hex(&fingerprint_hex(&fp)), Some(fp));
+    assert_eq!(fingerprint_from_hex("00"), None);
+}
@@ -0,0 +1,169 @@
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName};
+
+use std::convert::TryInto;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Length of a certificate fingerprint, which is the SHA-256 of its DER encoding
+pub const FINGERPRINT_LEN: usize = 32;
+
+pub fn fingerprint(der: &[u8]) -> [u8; FINGERPRINT_LEN] {
+    ring::digest::digest(&ring::digest::SHA256, der).as_ref().try_into().unwrap()
+}
+
+pub fn fingerprint_hex(fingerprint: &[u8; FINGERPRINT_LEN]) -> String {
+    fingerprint.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn fingerprint_from_hex(hex: &str) -> Option<[u8; FINGERPRINT_LEN]> {
+    if hex.len() != FINGERPRINT_LEN * 2 || !hex.is_ascii() {
+        return None;
+    }
+    let mut fp = [0u8; FINGERPRINT_LEN];
+    for (i, b) in fp.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(fp)
+}
+
+/// Serializes a trust anchor for storage. Anchors are stored rather than certificates because the
+/// Mozilla root program is only distributed to us in this form. The encoding is the subject, the
+/// subject public key info and the name constraints, each preceded by its length as a `u16` (little
+/// endian). A length of 0 for the name constraints means there are none.
+pub fn encode_anchor(anchor: &webpki::TrustAnchor) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for field in [anchor.subject, anchor.spki, anchor.name_constraints.unwrap_or(&[])].iter() {
+        encoded.extend_from_slice(&(field.len() as u16).to_le_bytes());
+        encoded.extend_from_slice(field);
+    }
+    encoded
+}
+
+/// The inverse of `encode_anchor()`. Returns `None` if the data is malformed.
+pub fn decode_anchor(data: &[u8]) -> Option<OwnedTrustAnchor> {
+    let mut fields: [&[u8]; 3] = [&[]; 3];
+    let mut index = 0;
+    for field in fields.iter_mut() {
+        let len = u16::from_le_bytes(data.get(index..index + 2)?.try_into().unwrap()) as usize;
+        *field = data.get(index + 2..index + 2 + len)?;
+        index += 2 + len;
+    }
+    if index != data.len() || fields[0].is_empty() || fields[1].is_empty() {
+        return None;
+    }
+    Some(OwnedTrustAnchor::from_subject_spki_name_constraints(
+        fields[0],
+        fields[1],
+        if fields[2].is_empty() { None } else { Some(fields[2]) },
+    ))
+}
+
+/// The set of trust anchors and pinned certificates that a connection is checked against.
+pub struct TrustStore {
+    roots: RootCertStore,
+    /// (host, fingerprint of the end-entity certificate)
+    pins: Vec<(String, [u8; FINGERPRINT_LEN])>,
+}
+impl TrustStore {
+    pub fn new() -> Self {
+        TrustStore {
+            roots: RootCertStore::empty(),
+            pins: Vec::new(),
+        }
+    }
+    /// Adds the certificate `der` as a root of trust.
+    pub fn add_root(&mut self, der: &[u8]) -> Result<(), webpki::Error> {
+        let anchor = webpki::TrustAnchor::try_from_cert_der(der)?;
+        self.add_anchor(&anchor);
+        Ok(())
+    }
+    pub fn add_anchor(&mut self, anchor: &webpki::TrustAnchor) {
+        self.roots.add_server_trust_anchors(core::iter::once(
+            OwnedTrustAnchor::from_subject_spki_name_constraints(anchor.subject, anchor.spki, anchor.name_constraints)
+        ));
+    }
+    /// Adds an anchor in the form produced by `encode_anchor()`. Returns false if it is malformed.
+    pub fn add_encoded_anchor(&mut self, data: &[u8]) -> bool {
+        match decode_anchor(data) {
+            Some(anchor) => {
+                self.roots.add_server_trust_anchors(core::iter::once(anchor));
+                true
+            }
+            None => false,
+        }
+    }
+    /// Trusts the certificate with `fingerprint` when it is presented by `host`, regardless of who issued it.
+    pub fn pin(&mut self, host: &str, fingerprint: [u8; FINGERPRINT_LEN]) {
+        self.pins.push((host.to_ascii_lowercase(), fingerprint));
+    }
+    pub fn root_count(&self) -> usize {
+        self.roots.len()
+    }
+    pub fn pin_count(&self) -> usize {
+        self.pins.len()
+    }
+    pub fn client_config(self) -> Arc<ClientConfig> {
+        let verifier = PinningVerifier {
+            webpki: WebPkiVerifier::new(self.roots, None),
+            pins: self.pins,
+        };
+        Arc::new(
+            ClientConfig::builder()
+                .with_safe_defaults()
+                .with_custom_certificate_verifier(Arc::new(verifier))
+                .with_no_client_auth()
+        )
+    }
+}
+
+impl Default for TrustStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Accepts a server if its certificate is pinned for the host, otherwise defers to the webpki
+/// path validation against the roots. Pinned certificates are accepted even if they have expired
+/// or are self-signed: pinning one is an explicit decision by the user to trust it.
+struct PinningVerifier {
+    webpki: WebPkiVerifier,
+    pins: Vec<(String, [u8; FINGERPRINT_LEN])>,
+}
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if let ServerName::DnsName(name) = server_name {
+            let fp = fingerprint(&end_entity.0);
+            if self.pins.iter().any(|(host, pin)| host.eq_ignore_ascii_case(name.as_ref()) && *pin == fp) {
+                log::debug!("{} presented a pinned certificate", name.as_ref());
+                return Ok(ServerCertVerified::assertion());
+            }
+        }
+        self.webpki.verify_server_cert(end_entity, intermediates, server_name, scts, ocsp_response, now)
+    }
+}
+
+/// Accepts any certificate. This is only used by `probe()`, which retrieves the certificates
+/// of a server so the user can decide whether to pin them; no data is exchanged over the connection.
+pub(crate) struct ProbeVerifier {}
+impl ServerCertVerifier for ProbeVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
@@ -78,6 +78,8 @@ fn main() -> ! {
     let xns = xous_names::XousNames::new().unwrap();
     let usbdev_sid = xns.register_name(api::SERVER_NAME_USB_DEVICE, None).expect("can't register server");
     log::trace!("registered with NS -- {:?}", usbdev_sid);
+    // HID reports have to keep up with the host's polling
+    xous::set_thread_priority(xous::current_tid().unwrap(), xous::ThreadPriority::High).expect("couldn't set USB thread priority");
     let llio = llio::Llio::new(&xns);
     let tt = ticktimer_server::Ticktimer::new().unwrap();
     #[cfg(any(target_os = "none", target_os = "xous"))]
@@ -0,0 +1,309 @@
+//! A minimal HTTP/1.1 client for Xous apps, on top of the `std::net::TcpStream` provided by the net
+//! server, and the `tls` crate for `https://` URLs. `tls` depends on `ring`, which can't be built for
+//! the device yet, so `https://` URLs are only supported in hosted mode.
+//!
+//! Every request is made on a fresh connection that is closed when the response is dropped. Bodies
+//! are streamed: a `Response` implements `Read`, and can be copied into a `PddbKey` without being held
+//! in memory. Each request, including the reading of its body, must complete within the timeout of the
+//! `Client`, which is measured with the ticktimer.
+
+mod url;
+pub use url::*;
+mod response;
+pub use response::*;
+#[cfg(test)]
+mod tests;
+
+use std::io::{BufReader, Error, ErrorKind, Read, Result, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+pub const DEFAULT_TIMEOUT_MS: u64 = 30_000;
+pub const DEFAULT_MAX_REDIRECTS: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+}
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+        }
+    }
+}
+
+/// The time base for request timeouts is the ticktimer. Unit tests run outside of a Xous environment,
+/// so they use the host's monotonic clock instead.
+#[cfg(not(test))]
+pub(crate) struct Clock(ticktimer_server::Ticktimer);
+#[cfg(not(test))]
+impl Clock {
+    fn new() -> Self {
+        Clock(ticktimer_server::Ticktimer::new().expect("couldn't connect to the ticktimer"))
+    }
+    fn elapsed_ms(&self) -> u64 {
+        self.0.elapsed_ms()
+    }
+}
+#[cfg(test)]
+pub(crate) struct Clock(std::time::Instant);
+#[cfg(test)]
+impl Clock {
+    fn new() -> Self {
+        Clock(std::time::Instant::now())
+    }
+    fn elapsed_ms(&self) -> u64 {
+        self.0.elapsed().as_millis() as u64
+    }
+}
+
+pub(crate) struct Deadline {
+    clock: Clock,
+    expires_ms: u64,
+}
+impl Deadline {
+    fn new(timeout_ms: u64) -> Self {
+        let clock = Clock::new();
+        let expires_ms = clock.elapsed_ms() + timeout_ms;
+        Deadline { clock, expires_ms }
+    }
+    /// The time left before the deadline, or a `TimedOut` error if it has passed.
+    fn remaining(&self) -> Result<Duration> {
+        let now = self.clock.elapsed_ms();
+        if now >= self.expires_ms {
+            Err(timed_out())
+        } else {
+            Ok(Duration::from_millis(self.expires_ms - now))
+        }
+    }
+}
+
+fn timed_out() -> Error {
+    Error::new(ErrorKind::TimedOut, "HTTP request timed out")
+}
+
+pub(crate) enum Stream {
+    Plain(TcpStream),
+    #[cfg(not(any(target_os = "none", target_os = "xous")))]
+    Tls(Box<tls::TlsStream>),
+}
+
+/// A connection whose socket timeouts are re-armed before every operation, so that no
+/// operation can run past the deadline of the request.
+pub(crate) struct Transport {
+    stream: Stream,
+    deadline: Deadline,
+}
+impl Transport {
+    fn arm(&self) -> Result<()> {
+        let remaining = self.deadline.remaining()?;
+        let socket = match &self.stream {
+            Stream::Plain(s) => s,
+            #[cfg(not(any(target_os = "none", target_os = "xous")))]
+            Stream::Tls(s) => &s.sock,
+        };
+        socket.set_read_timeout(Some(remaining))?;
+        socket.set_write_timeout(Some(remaining))
+    }
+    fn check(result: Result<usize>) -> Result<usize> {
+        match result {
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => Err(timed_out()),
+            r => r,
+        }
+    }
+}
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.arm()?;
+        Transport::check(match &mut self.stream {
+            Stream::Plain(s) => s.read(buf),
+            #[cfg(not(any(target_os = "none", target_os = "xous")))]
+            Stream::Tls(s) => s.read(buf),
+        })
+    }
+}
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.arm()?;
+        Transport::check(match &mut self.stream {
+            Stream::Plain(s) => s.write(buf),
+            #[cfg(not(any(target_os = "none", target_os = "xous")))]
+            Stream::Tls(s) => s.write(buf),
+        })
+    }
+    fn flush(&mut self) -> Result<()> {
+        match &mut self.stream {
+            Stream::Plain(s) => s.flush(),
+            #[cfg(not(any(target_os = "none", target_os = "xous")))]
+            Stream::Tls(s) => s.flush(),
+        }
+    }
+}
+
+fn connect_plain(host: &str, port: u16, deadline: &Deadline) -> Result<TcpStream> {
+    let mut last_err = Error::new(ErrorKind::NotFound, "host name did not resolve");
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, deadline.remaining()?) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_err = e,
+        }
+    }
+    Err(last_err)
+}
+
+/// Connects the socket within the deadline, and arms its timeouts so the handshake can't outlast it either.
+#[cfg(not(any(target_os = "none", target_os = "xous")))]
+fn connect_tls(host: &str, port: u16, deadline: &Deadline) -> Result<Stream> {
+    let sock = connect_plain(host, port, deadline)?;
+    let remaining = deadline.remaining()?;
+    sock.set_read_timeout(Some(remaining))?;
+    sock.set_write_timeout(Some(remaining))?;
+    let stream = tls::Tls::new().connect_over(sock, host).map_err(|e| match e.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => timed_out(),
+        _ => e,
+    })?;
+    Ok(Stream::Tls(Box::new(stream)))
+}
+#[cfg(any(target_os = "none", target_os = "xous"))]
+fn connect_tls(_host: &str, _port: u16, _deadline: &Deadline) -> Result<Stream> {
+    Err(Error::new(ErrorKind::Unsupported, "https is not supported on this target yet"))
+}
+
+/// Headers can't be allowed to contain line breaks, or they could be used to inject headers or requests.
+fn check_header_text(text: &str) -> Result<()> {
+    if text.bytes().any(|b| b == b'\r' || b == b'\n') {
+        Err(Error::new(ErrorKind::InvalidInput, "line break in a request header"))
+    } else {
+        Ok(())
+    }
+}
+
+pub struct Client {
+    timeout_ms: u64,
+    max_redirects: usize,
+    user_agent: String,
+    headers: Vec<(String, String)>,
+}
+impl Client {
+    pub fn new() -> Self {
+        Client {
+            timeout_ms: DEFAULT_TIMEOUT_MS,
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            user_agent: format!("xous-http/{}", env!("CARGO_PKG_VERSION")),
+            headers: Vec::new(),
+        }
+    }
+    /// Sets how long each request may take, from connecting until the end of the body is read.
+    pub fn timeout_ms(mut self, timeout_ms: u64) -> Self {
+        self.timeout_ms = timeout_ms;
+        self
+    }
+    /// Sets how many redirects are followed. Set it to 0 to receive redirects as responses.
+    pub fn max_redirects(mut self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = std::string::String::from(user_agent);
+        self
+    }
+    /// Adds a header to every request. These headers are not sent to other hosts that requests are redirected to.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn get(&self, url: &str) -> Result<Response> {
+        self.request(Method::Get, url, None)
+    }
+    pub fn head(&self, url: &str) -> Result<Response> {
+        self.request(Method::Head, url, None)
+    }
+    pub fn post(&self, url: &str, content_type: &str, body: &[u8]) -> Result<Response> {
+        self.request(Method::Post, url, Some((content_type, body)))
+    }
+
+    /// Makes a request, following redirects. `body` is the content type and content of the request body, if any.
+    /// Responses with error statuses are returned as responses; only failures to get a response are errors.
+    pub fn request(&self, method: Method, url: &str, body: Option<(&str, &[u8])>) -> Result<Response> {
+        let origin = Url::parse(url)?;
+        let mut url = origin.clone();
+        let mut method = method;
+        let mut body = body;
+        for _ in 0..=self.max_redirects {
+            let same_origin = url.https == origin.https && url.host == origin.host && url.port == origin.port;
+            let response = self.send(method, &url, body, same_origin)?;
+            let location = match response.status() {
+                301 | 302 | 303 | 307 | 308 => response.header("location"),
+                _ => None,
+            };
+            let next = match location {
+                Some(location) if self.max_redirects > 0 => url.join(location)?,
+                _ => return Ok(response),
+            };
+            if url.https && !next.https {
+                return Err(Error::new(ErrorKind::PermissionDenied, "refusing to follow a redirect from https to http"));
+            }
+            log::debug!("{} redirected to {}", url, next);
+            // the 303 status, and for historical reasons 301 and 302 on a POST, turn the request into a GET
+            if (response.status() == 303 && method != Method::Head)
+                || (method == Method::Post && (response.status() == 301 || response.status() == 302)) {
+                method = Method::Get;
+                body = None;
+            }
+            url = next;
+        }
+        Err(Error::new(ErrorKind::Other, "too many redirects"))
+    }
+
+    fn send(&self, method: Method, url: &Url, body: Option<(&str, &[u8])>, with_headers: bool) -> Result<Response> {
+        if url.path.bytes().any(|b| b <= b' ' || b == 0x7f) {
+            return Err(Error::new(ErrorKind::InvalidInput, "URL path contains whitespace or control characters"));
+        }
+        check_header_text(&self.user_agent)?;
+        let mut request = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}\r\nAccept: */*\r\nConnection: close\r\n",
+            method.as_str(), url.path, url.host_header(), self.user_agent
+        );
+        if with_headers {
+            for (name, value) in self.headers.iter() {
+                check_header_text(name)?;
+                check_header_text(value)?;
+                request.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        if let Some((content_type, data)) = body {
+            check_header_text(content_type)?;
+            request.push_str(&format!("Content-Type: {}\r\nContent-Length: {}\r\n", content_type, data.len()));
+        }
+        request.push_str("\r\n");
+
+        let deadline = Deadline::new(self.timeout_ms);
+        let stream = if url.https {
+            connect_tls(&url.host, url.port, &deadline)?
+        } else {
+            Stream::Plain(connect_plain(&url.host, url.port, &deadline)?)
+        };
+        let mut transport = Transport { stream, deadline };
+        transport.write_all(request.as_bytes())?;
+        if let Some((_, data)) = body {
+            transport.write_all(data)?;
+        }
+        transport.flush()?;
+        Response::read_from(url.clone(), BufReader::new(transport), method)
+    }
+}
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
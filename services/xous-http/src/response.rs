@@ -0,0 +1,257 @@
+use crate::*;
+
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result, Write};
+
+/// Upper bound on the size of the status line plus headers of a response
+const MAX_HEADER_LEN: usize = 16 * 1024;
+
+/// How the end of the body is found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Framing {
+    /// no body at all, e.g. the response to a `HEAD`
+    Empty,
+    /// `Content-Length`; the field is the number of bytes still to be read
+    Length(u64),
+    /// `Transfer-Encoding: chunked`; the field is the number of bytes left in the current chunk,
+    /// or `None` if the next chunk header hasn't been read yet
+    Chunked(Option<u64>),
+    /// the body runs until the server closes the connection
+    Close,
+    /// the body has been read completely
+    Done,
+}
+
+/// A response whose status line and headers have been read. The body is read through the `Read`
+/// implementation, which decodes chunked transfer encoding and stops at the end of the body.
+pub struct Response {
+    pub(crate) url: Url,
+    pub(crate) status: u16,
+    pub(crate) reason: String,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) reader: BufReader<Transport>,
+    pub(crate) framing: Framing,
+}
+
+/// Reads a line terminated by LF, charging its length against `budget`. The line terminator is removed.
+fn read_line<R: BufRead>(reader: &mut R, budget: &mut usize) -> Result<String> {
+    let mut line = Vec::new();
+    let len = reader.by_ref().take(*budget as u64 + 1).read_until(b'\n', &mut line)?;
+    if len == 0 {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "connection closed before the response was complete"));
+    }
+    if len > *budget || line.last() != Some(&b'\n') {
+        return Err(Error::new(ErrorKind::InvalidData, "response header is too long"));
+    }
+    *budget -= len;
+    while line.last() == Some(&b'\n') || line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line).map_err(|_| Error::new(ErrorKind::InvalidData, "response header is not UTF-8"))
+}
+
+impl Response {
+    /// Reads the status line and headers from `reader`. Informational (1xx) responses are skipped.
+    pub(crate) fn read_from(url: Url, mut reader: BufReader<Transport>, method: Method) -> Result<Response> {
+        let mut budget = MAX_HEADER_LEN;
+        loop {
+            let status_line = read_line(&mut reader, &mut budget)?;
+            let mut parts = status_line.splitn(3, ' ');
+            let version = parts.next().unwrap_or("");
+            if !version.starts_with("HTTP/1.") {
+                return Err(Error::new(ErrorKind::InvalidData, "not an HTTP/1.x response"));
+            }
+            let status: u16 = parts.next().and_then(|s| s.parse().ok())
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "malformed status line"))?;
+            let reason = std::string::String::from(parts.next().unwrap_or(""));
+
+            let mut headers = Vec::<(String, String)>::new();
+            loop {
+                let line = read_line(&mut reader, &mut budget)?;
+                if line.is_empty() {
+                    break;
+                }
+                match line.split_once(':') {
+                    Some((name, value)) => headers.push((name.trim().to_string(), value.trim().to_string())),
+                    None => return Err(Error::new(ErrorKind::InvalidData, "malformed header")),
+                }
+            }
+            if (100..200).contains(&status) {
+                log::debug!("skipping informational response {}", status);
+                continue;
+            }
+
+            let mut response = Response {
+                url,
+                status,
+                reason,
+                headers,
+                reader,
+                framing: Framing::Close,
+            };
+            response.framing = if method == Method::Head || status == 204 || status == 304 {
+                Framing::Empty
+            } else if response.header("transfer-encoding").map_or(false, |te| te.to_ascii_lowercase().ends_with("chunked")) {
+                Framing::Chunked(None)
+            } else if let Some(len) = response.header("content-length") {
+                Framing::Length(len.parse().map_err(|_| Error::new(ErrorKind::InvalidData, "malformed Content-Length"))?)
+            } else {
+                Framing::Close
+            };
+            return Ok(response);
+        }
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+    /// The URL that the response came from, after any redirects.
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+    /// Returns the value of the first header named `name`, which is matched case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+    /// The length of the body, if the server said what it is up front.
+    pub fn content_length(&self) -> Option<u64> {
+        match self.framing {
+            Framing::Length(_) => self.header("content-length").and_then(|l| l.parse().ok()),
+            Framing::Empty => Some(0),
+            _ => None,
+        }
+    }
+    /// The time at the server when the response was made, as seconds since the epoch, taken from
+    /// the `Date` header. This is useful to sanity check the local clock, e.g. before generating
+    /// time-based one time passwords.
+    pub fn date(&self) -> Option<u64> {
+        self.header("date").and_then(parse_http_date)
+    }
+    pub fn into_bytes(mut self) -> Result<Vec<u8>> {
+        let mut body = Vec::new();
+        self.read_to_end(&mut body)?;
+        Ok(body)
+    }
+    pub fn into_string(self) -> Result<String> {
+        String::from_utf8(self.into_bytes()?).map_err(|_| Error::new(ErrorKind::InvalidData, "body is not UTF-8"))
+    }
+    /// Streams the body into `out` without holding all of it in memory. Returns the length of the body.
+    pub fn copy_to(&mut self, out: &mut impl Write) -> Result<u64> {
+        std::io::copy(self, out)
+    }
+    /// Streams the body into the PDDB key `dict`:`key`, replacing any previous contents of the key. If the
+    /// length of the body is known, it is used as the allocation hint so a large body lands in the large pool.
+    /// Returns the length of the body.
+    pub fn save_to_pddb(&mut self, pddb: &pddb::Pddb, dict: &str, key: &str, basis: Option<&str>) -> Result<u64> {
+        // writes don't truncate a key, so a shorter body would leave the tail of the old contents behind
+        match pddb.delete_key(dict, key, basis) {
+            Ok(_) => (),
+            Err(e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+        let hint = self.content_length().map(|l| l as usize);
+        let mut entry = pddb.get(dict, key, basis, true, true, hint, None::<fn()>)?;
+        let len = self.copy_to(&mut entry)?;
+        entry.flush()?;
+        Ok(len)
+    }
+
+    /// Reads the size line of the next chunk, and the trailers if it is the last chunk.
+    fn next_chunk(&mut self) -> Result<u64> {
+        let mut budget = MAX_HEADER_LEN;
+        let line = read_line(&mut self.reader, &mut budget)?;
+        // chunk extensions are ignored
+        let size = line.split(';').next().unwrap().trim();
+        let size = u64::from_str_radix(size, 16)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "malformed chunk size"))?;
+        if size == 0 {
+            // trailers are discarded
+            while !read_line(&mut self.reader, &mut budget)?.is_empty() {}
+        }
+        Ok(size)
+    }
+}
+
+impl Read for Response {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            match self.framing {
+                Framing::Empty | Framing::Done => return Ok(0),
+                Framing::Length(0) => {
+                    self.framing = Framing::Done;
+                }
+                Framing::Length(remaining) => {
+                    let max = remaining.min(buf.len() as u64) as usize;
+                    let len = self.reader.read(&mut buf[..max])?;
+                    if len == 0 {
+                        return Err(Error::new(ErrorKind::UnexpectedEof, "connection closed before the end of the body"));
+                    }
+                    self.framing = Framing::Length(remaining - len as u64);
+                    return Ok(len);
+                }
+                Framing::Chunked(None) => {
+                    self.framing = match self.next_chunk()? {
+                        0 => Framing::Done,
+                        size => Framing::Chunked(Some(size)),
+                    };
+                }
+                Framing::Chunked(Some(0)) => {
+                    // each chunk is followed by a CRLF
+                    let mut budget = 2;
+                    if !read_line(&mut self.reader, &mut budget)?.is_empty() {
+                        return Err(Error::new(ErrorKind::InvalidData, "chunk is longer than its size"));
+                    }
+                    self.framing = Framing::Chunked(None);
+                }
+                Framing::Chunked(Some(remaining)) => {
+                    let max = remaining.min(buf.len() as u64) as usize;
+                    let len = self.reader.read(&mut buf[..max])?;
+                    if len == 0 {
+                        return Err(Error::new(ErrorKind::UnexpectedEof, "connection closed in the middle of a chunk"));
+                    }
+                    self.framing = Framing::Chunked(Some(remaining - len as u64));
+                    return Ok(len);
+                }
+                Framing::Close => {
+                    let len = self.reader.read(buf)?;
+                    if len == 0 {
+                        self.framing = Framing::Done;
+                    }
+                    return Ok(len);
+                }
+            }
+        }
+    }
+}
+
+/// Parses an IMF-fixdate (RFC 7231 section 7.1.1.1), such as `Sun, 06 Nov 1994 08:49:37 GMT`, into
+/// seconds since the epoch. The obsolete date formats are not supported.
+pub fn parse_http_date(date: &str) -> Option<u64> {
+    let mut fields = date.split_whitespace().skip(1);
+    let day: u64 = fields.next()?.parse().ok()?;
+    let month = fields.next()?;
+    let month = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"]
+        .iter().position(|&m| m == month)? as u64 + 1;
+    let year: u64 = fields.next()?.parse().ok()?;
+    let mut hms = fields.next()?.split(':').map(|f| f.parse::<u64>().ok());
+    let (h, m, s) = (hms.next()??, hms.next()??, hms.next()??);
+    if fields.next()? != "GMT" || year < 1970 || !(1..=31).contains(&day) || h > 23 || m > 59 || s > 60 {
+        return None;
+    }
+    // days from the epoch to the start of the month, using the civil-from-days algorithm in reverse
+    let (y, mo) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * mo + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    Some(days * 86400 + h * 3600 + m * 60 + s)
+}
@@ -0,0 +1,198 @@
+use crate::*;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+/// A stand-in server on the loopback interface. Each connection is answered according to the request
+/// path, and the request line and headers are echoed in an `X-Request` header for the tests to inspect.
+fn stand_in_server() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = stream.unwrap();
+            thread::spawn(move || serve(stream, port));
+        }
+    });
+    port
+}
+
+fn serve(mut stream: TcpStream, port: u16) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request = Vec::<String>::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end().to_string();
+        if line.is_empty() {
+            break;
+        }
+        request.push(line);
+    }
+    let content_length = request.iter()
+        .find_map(|h| h.strip_prefix("Content-Length: ")).map_or(0, |l| l.parse().unwrap());
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body).unwrap();
+    let path = request[0].split(' ').nth(1).unwrap().to_string();
+    let echo = format!("X-Request: {}\r\n", request.join("|"));
+
+    let response = match path.as_str() {
+        "/plain" => format!("HTTP/1.1 200 OK\r\n{}Content-Length: 5\r\nDate: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\nhello", echo),
+        "/chunked" => format!(
+            "HTTP/1.1 200 OK\r\n{}Transfer-Encoding: chunked\r\n\r\n4;ext=1\r\nWiki\r\n5\r\npedia\r\nE\r\n in\r\n\r\nchunks.\r\n0\r\nX-Trailer: yes\r\n\r\n",
+            echo
+        ),
+        "/close" => format!("HTTP/1.0 200 OK\r\n{}\r\nuntil the connection closes", echo),
+        "/continue" => format!("HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n{}Content-Length: 2\r\n\r\nok", echo),
+        "/redirect" => format!("HTTP/1.1 302 Found\r\n{}Location: /plain\r\nContent-Length: 0\r\n\r\n", echo),
+        "/dir/relative" => format!("HTTP/1.1 301 Moved\r\n{}Location: ../plain#frag\r\nContent-Length: 0\r\n\r\n", echo),
+        "/absolute" => format!("HTTP/1.1 307 Temporary\r\n{}Location: http://127.0.0.1:{}/chunked\r\nContent-Length: 0\r\n\r\n", echo, port),
+        "/other-host" => format!("HTTP/1.1 307 Temporary\r\n{}Location: http://localhost:{}/echo\r\nContent-Length: 0\r\n\r\n", echo, port),
+        "/see-other" => format!("HTTP/1.1 303 See Other\r\n{}Location: /echo\r\nContent-Length: 0\r\n\r\n", echo),
+        "/loop" => format!("HTTP/1.1 302 Found\r\n{}Location: /loop\r\nContent-Length: 0\r\n\r\n", echo),
+        "/echo" => format!("HTTP/1.1 200 OK\r\n{}Content-Length: {}\r\n\r\n{}", echo, body.len(), String::from_utf8(body).unwrap()),
+        "/short" => format!("HTTP/1.1 200 OK\r\n{}Content-Length: 100\r\n\r\ntoo short", echo),
+        "/slow" => {
+            thread::sleep(Duration::from_millis(1000));
+            format!("HTTP/1.1 200 OK\r\n{}Content-Length: 0\r\n\r\n", echo)
+        }
+        _ => format!("HTTP/1.1 404 Not Found\r\n{}Content-Length: 9\r\n\r\nnot found", echo),
+    };
+    stream.write_all(response.as_bytes()).ok();
+}
+
+fn url(port: u16, path: &str) -> String {
+    format!("http://127.0.0.1:{}{}", port, path)
+}
+
+#[test]
+fn content_length() {
+    let port = stand_in_server();
+    let response = Client::new().get(&url(port, "/plain")).unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.reason(), "OK");
+    assert_eq!(response.content_length(), Some(5));
+    assert_eq!(response.date(), Some(784111777));
+    let request = response.header("x-request").unwrap().to_string();
+    assert!(request.starts_with("GET /plain HTTP/1.1|"));
+    assert!(request.contains(&format!("Host: 127.0.0.1:{}", port)));
+    assert!(request.contains("Connection: close"));
+    assert_eq!(response.into_string().unwrap(), "hello");
+
+    let response = Client::new().get(&url(port, "/missing")).unwrap();
+    assert_eq!(response.status(), 404);
+    assert_eq!(response.into_string().unwrap(), "not found");
+
+    // a body that ends early is an error, rather than silently short
+    let response = Client::new().get(&url(port, "/short")).unwrap();
+    assert_eq!(response.into_bytes().unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn chunked_and_close_delimited() {
+    let port = stand_in_server();
+    let mut response = Client::new().get(&url(port, "/chunked")).unwrap();
+    assert_eq!(response.content_length(), None);
+    let mut body = Vec::new();
+    // stream through a small buffer, the way a PDDB key would be written
+    assert_eq!(response.copy_to(&mut body).unwrap(), 23);
+    assert_eq!(body, b"Wikipedia in\r\n\r\nchunks.");
+
+    let response = Client::new().get(&url(port, "/close")).unwrap();
+    assert_eq!(response.into_string().unwrap(), "until the connection closes");
+
+    let response = Client::new().get(&url(port, "/continue")).unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.into_string().unwrap(), "ok");
+
+    let response = Client::new().head(&url(port, "/plain")).unwrap();
+    assert_eq!(response.content_length(), Some(0));
+    assert_eq!(response.into_bytes().unwrap().len(), 0);
+}
+
+#[test]
+fn redirects() {
+    let port = stand_in_server();
+    let response = Client::new().get(&url(port, "/redirect")).unwrap();
+    assert_eq!(response.url().path, "/plain");
+    assert_eq!(response.into_string().unwrap(), "hello");
+
+    let response = Client::new().get(&url(port, "/dir/relative")).unwrap();
+    assert_eq!(response.url().path, "/plain");
+
+    let response = Client::new().get(&url(port, "/absolute")).unwrap();
+    assert_eq!(response.into_string().unwrap(), "Wikipedia in\r\n\r\nchunks.");
+
+    // 303 turns a POST into a GET
+    let response = Client::new().post(&url(port, "/see-other"), "text/plain", b"data").unwrap();
+    assert!(response.header("x-request").unwrap().starts_with("GET /echo"));
+    assert_eq!(response.into_string().unwrap(), "");
+
+    // custom headers stay with the origin
+    let client = Client::new().header("Authorization", "secret");
+    let response = client.get(&url(port, "/redirect")).unwrap();
+    assert!(response.header("x-request").unwrap().contains("Authorization: secret"));
+    let response = client.get(&url(port, "/other-host")).unwrap();
+    assert!(!response.header("x-request").unwrap().contains("Authorization"));
+
+    let e = Client::new().get(&url(port, "/loop")).err().unwrap();
+    assert_eq!(e.kind(), std::io::ErrorKind::Other);
+    let response = Client::new().max_redirects(0).get(&url(port, "/loop")).unwrap();
+    assert_eq!(response.status(), 302);
+}
+
+#[test]
+fn post_and_validation() {
+    let port = stand_in_server();
+    let response = Client::new().post(&url(port, "/echo"), "application/json", b"{\"a\":1}").unwrap();
+    let request = response.header("x-request").unwrap().to_string();
+    assert!(request.contains("Content-Type: application/json|Content-Length: 7"));
+    assert_eq!(response.into_string().unwrap(), "{\"a\":1}");
+
+    let client = Client::new().header("X-Evil", "a\r\nHost: evil");
+    assert_eq!(client.get(&url(port, "/plain")).err().unwrap().kind(), std::io::ErrorKind::InvalidInput);
+    let client = Client::new().user_agent("agent\r\nX-Evil: 1");
+    assert_eq!(client.get(&url(port, "/plain")).err().unwrap().kind(), std::io::ErrorKind::InvalidInput);
+    assert_eq!(Client::new().get(&url(port, "/a b")).err().unwrap().kind(), std::io::ErrorKind::InvalidInput);
+    assert_eq!(Client::new().get("ftp://example.com/").err().unwrap().kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn timeout() {
+    let port = stand_in_server();
+    let e = Client::new().timeout_ms(200).get(&url(port, "/slow")).err().unwrap();
+    assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+}
+
+#[test]
+fn urls() {
+    let u = Url::parse("HTTPS://Example.com:8443/a/b?c=d#frag").unwrap();
+    assert_eq!(u, Url { https: true, host: "example.com".into(), port: 8443, path: "/a/b?c=d".into() });
+    assert_eq!(u.host_header(), "example.com:8443");
+    assert_eq!(u.join("e").unwrap().path, "/a/e");
+    assert_eq!(u.join("../e/./f").unwrap().path, "/e/f");
+    assert_eq!(u.join("../../..").unwrap().path, "/");
+    assert_eq!(u.join("/f").unwrap().to_string(), "https://example.com:8443/f");
+    assert_eq!(u.join("//other.org/g").unwrap().to_string(), "https://other.org/g");
+
+    let u = Url::parse("bunniefoo.com").unwrap();
+    assert_eq!((u.https, u.port, u.path.as_str()), (false, 80, "/"));
+    assert_eq!(Url::parse("http://h?q").unwrap().path, "/?q");
+    let u = Url::parse("http://[::1]:8080/x").unwrap();
+    assert_eq!((u.host.as_str(), u.port), ("::1", 8080));
+    assert_eq!(u.host_header(), "[::1]:8080");
+    assert!(Url::parse("http://user@host/").is_err());
+    assert!(Url::parse("http://host:port/").is_err());
+    assert!(Url::parse("http:///path").is_err());
+}
+
+#[test]
+fn http_dates() {
+    assert_eq!(parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+    assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(784111777));
+    assert_eq!(parse_http_date("Tue, 29 Feb 2028 23:59:59 GMT"), Some(1835481599));
+    assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+    assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 PST"), None);
+}
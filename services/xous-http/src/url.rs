@@ -0,0 +1,127 @@
+use std::io::{Error, ErrorKind, Result};
+
+/// The parts of an `http://` or `https://` URL that are needed to make a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub https: bool,
+    pub host: String,
+    pub port: u16,
+    /// the path and query, always starting with `/`. The fragment is dropped.
+    pub path: String,
+}
+
+impl Url {
+    /// Parses `url`. If the scheme is missing, `http://` is assumed.
+    pub fn parse(url: &str) -> Result<Url> {
+        let invalid = || Error::new(ErrorKind::InvalidInput, "invalid URL");
+        let (https, rest) = match url.find("://") {
+            Some(i) => match url[..i].to_ascii_lowercase().as_str() {
+                "http" => (false, &url[i + 3..]),
+                "https" => (true, &url[i + 3..]),
+                _ => return Err(Error::new(ErrorKind::InvalidInput, "unsupported URL scheme")),
+            },
+            None => (false, url),
+        };
+        let rest = rest.split('#').next().unwrap();
+        let (authority, path) = match rest.find(&['/', '?'][..]) {
+            Some(i) if rest.as_bytes()[i] == b'?' => (&rest[..i], format!("/{}", &rest[i..])),
+            Some(i) => (&rest[..i], std::string::String::from(&rest[i..])),
+            None => (rest, std::string::String::from("/")),
+        };
+        // user info is not supported, and is mostly a phishing vector anyway
+        if authority.contains('@') {
+            return Err(invalid());
+        }
+        let default_port = if https { 443 } else { 80 };
+        let (host, port) = if authority.starts_with('[') {
+            // IPv6 literal
+            let end = authority.find(']').ok_or_else(invalid)?;
+            let port = match &authority[end + 1..] {
+                "" => default_port,
+                p => p.strip_prefix(':').and_then(|p| p.parse().ok()).ok_or_else(invalid)?,
+            };
+            (&authority[1..end], port)
+        } else {
+            match authority.rsplit_once(':') {
+                Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+                None => (authority, default_port),
+            }
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Url {
+            https,
+            host: host.to_ascii_lowercase(),
+            port,
+            path,
+        })
+    }
+
+    /// Resolves `location`, as found in a redirect, against this URL.
+    pub fn join(&self, location: &str) -> Result<Url> {
+        if location.contains("://") {
+            Url::parse(location)
+        } else if let Some(rest) = location.strip_prefix("//") {
+            Url::parse(&format!("{}://{}", if self.https { "https" } else { "http" }, rest))
+        } else {
+            let path = if location.starts_with('/') {
+                std::string::String::from(location)
+            } else {
+                // relative to the "directory" of the current path
+                let base = self.path.split('?').next().unwrap();
+                remove_dot_segments(&format!("{}{}", &base[..base.rfind('/').unwrap() + 1], location))
+            };
+            Ok(Url {
+                https: self.https,
+                host: self.host.clone(),
+                port: self.port,
+                path: path.split('#').next().unwrap().to_string(),
+            })
+        }
+    }
+
+    /// The value of the `Host` header for this URL.
+    pub fn host_header(&self) -> String {
+        let host = if self.host.contains(':') { format!("[{}]", self.host) } else { self.host.clone() };
+        if self.port == if self.https { 443 } else { 80 } {
+            host
+        } else {
+            format!("{}:{}", host, self.port)
+        }
+    }
+}
+
+/// Resolves `.` and `..` segments in the path part of `path` (RFC 3986 section 5.2.4).
+fn remove_dot_segments(path: &str) -> String {
+    let (path, query) = match path.find('?') {
+        Some(i) => (&path[..i], &path[i..]),
+        None => (path, ""),
+    };
+    let mut out = Vec::<&str>::new();
+    let segments: Vec<&str> = path.split('/').skip(1).collect();
+    for (i, segment) in segments.iter().enumerate() {
+        let last = i == segments.len() - 1;
+        match *segment {
+            "." => {
+                if last {
+                    out.push("");
+                }
+            }
+            ".." => {
+                out.pop();
+                if last {
+                    out.push("");
+                }
+            }
+            s => out.push(s),
+        }
+    }
+    format!("/{}{}", out.join("/"), query)
+}
+
+impl core::fmt::Display for Url {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}://{}{}", if self.https { "https" } else { "http" }, self.host_header(), self.path)
+    }
+}
@@ -1,4 +1,3 @@
-#[allow(dead_code)]
 pub const AUTHENTICATE_TIMEOUT: u32 = 10_000; // time in ms that a process has to respond to an authentication request
 
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive)]
@@ -11,7 +10,17 @@ pub(crate) enum Opcode {
     /// Create a connection to the target server.
     Lookup = 1,
 
-    /// Create an authenticated connection to the target server.
+    /// Create an authenticated connection to the target server, by answering the challenge in the
+    /// `AuthenticateRequest` returned by an earlier `Lookup` from the same process.
+    ///
+    /// # Message Types
+    ///
+    ///     * MutableLend of an `AuthenticatedLookup`
+    ///
+    /// # Return Values
+    ///
+    /// The buffer is replaced with a `Return::CID` if the response is correct, or `Return::Failure`
+    /// otherwise. A challenge can only be answered once, within `AUTHENTICATE_TIMEOUT` ms.
     AuthenticatedLookup = 2,
 
     /// unregister a server, given its cryptographically unique SID.
@@ -50,6 +59,21 @@ pub(crate) enum Opcode {
     /// }
     /// ```
     BlockingConnect = 6,
+
+    /// List every registered name, along with how many connections each has handed out. SIDs are
+    /// never revealed. The list includes servers that lookups flatly deny, so only processes that
+    /// registered a name during the trusted init may ask for it.
+    ///
+    /// # Message Types
+    ///
+    ///     * MutableLend of a `NameList`
+    ///
+    /// # Return Values
+    ///
+    /// The list is filled in with up to `NAME_LIST_LEN` names, in order, starting from the `start`th,
+    /// and `total` is set to the number of registered names. If the caller may not list names, the
+    /// list is left empty and `denied` is set.
+    ListNames = 7,
 }
 
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
@@ -76,6 +100,40 @@ pub(crate) enum Return {
 pub(crate) struct Registration {
     pub name: xous_ipc::String<64>,
     pub conn_limit: Option<u32>,
+    /// if set, connections beyond `conn_limit` can be made by proving knowledge of this secret
+    pub auth_key: Option<[u8; 32]>,
+}
+
+/// Number of names returned by one `ListNames` request
+pub(crate) const NAME_LIST_LEN: usize = 16;
+
+/// A registered name, as reported by `XousNames::list_names()`
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone)]
+pub struct NameInfo {
+    pub name: xous_ipc::String<64>,
+    /// raw PID of the process that registered the name
+    pub pid: Option<u8>,
+    /// number of unauthenticated (inherently trusted) connections handed out
+    pub current_conns: u32,
+    /// limit on the unauthenticated connections; `None` if unlimited
+    pub max_conns: Option<u32>,
+    /// whether the server accepts authenticated connections beyond `max_conns`
+    pub has_key: bool,
+    /// number of authenticated connections handed out
+    pub auth_conns: u32,
+    /// true if the name was registered before `TrustedInitDone` first reported that all the
+    /// trusted connections were claimed; a name registered later came from code that started after
+    /// the trusted boot, and deserves a closer look
+    pub trusted: bool,
+}
+
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct NameList {
+    pub start: u32,
+    pub total: u32,
+    pub list: [Option<NameInfo>; NAME_LIST_LEN],
+    /// set by the server if the caller isn't trusted
+    pub denied: bool,
 }
 
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
@@ -87,15 +145,15 @@ pub(crate) struct Disconnect {
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub(crate) struct AuthenticatedLookup {
     pub name: xous_ipc::String<64>,
-    pub pubkey_id: [u8; 20], // 160-bit pubkey ID encoded in network order (big endian)
-    pub response: [u32; 8],
+    pub pubkey_id: [u8; 20], // 160-bit key ID encoded in network order (big endian)
+    pub response: [u32; 8],  // HMAC-SHA256 of the challenge and name, see `auth::response()`
 }
 
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 #[repr(C)]
 pub(crate) struct AuthenticateRequest {
     pub name: xous_ipc::String<64>, // a copy of the originally requested lookup
-    pub pubkey_id: [u8; 20],        // 160-bit pubkey ID encoded in network order (big endian)
+    pub pubkey_id: [u8; 20],        // 160-bit key ID encoded in network order (big endian)
     pub challenge: [u32; 4],
 }
 
@@ -0,0 +1,94 @@
+//! The challenge-response used for authenticated lookups.
+//!
+//! A server that accepts authenticated connections registers a 256-bit secret with its name. A
+//! client that knows the secret answers a lookup challenge with HMAC-SHA256(secret, challenge || name),
+//! where the challenge words are serialized little-endian. The secret is identified by `key_id()`,
+//! so a client can tell which key it is being asked for without the key being revealed.
+//!
+//! The software `sha2` crate is used under another name, because the `sha2` crate in this tree is the
+//! hardware engine, which itself depends on the name server.
+
+use hmac::{Hmac, Mac};
+use sha2_soft::{Digest, Sha256};
+
+pub const AUTH_KEY_LEN: usize = 32;
+
+fn hmac_sha256(key: &[u8; AUTH_KEY_LEN], parts: &[&[u8]]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    for part in parts {
+        mac.update(part);
+    }
+    let mut ret = [0u8; 32];
+    ret.copy_from_slice(&mac.finalize().into_bytes());
+    ret
+}
+
+/// Returns the identifier of `key` that is sent along with a challenge: the first 160 bits of its hash.
+pub fn key_id(key: &[u8; AUTH_KEY_LEN]) -> [u8; 20] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"xous-names key id");
+    hasher.update(key);
+    let mut id = [0u8; 20];
+    id.copy_from_slice(&hasher.finalize()[..20]);
+    id
+}
+
+/// Computes the answer to `challenge` for a lookup of `name`
+pub fn response(key: &[u8; AUTH_KEY_LEN], challenge: &[u32; 4], name: &str) -> [u32; 8] {
+    let mut challenge_bytes = [0u8; 16];
+    for (chunk, word) in challenge_bytes.chunks_mut(4).zip(challenge.iter()) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    let mac = hmac_sha256(key, &[&challenge_bytes, name.as_bytes()]);
+    let mut ret = [0u32; 8];
+    for (word, chunk) in ret.iter_mut().zip(mac.chunks(4)) {
+        *word = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    ret
+}
+
+/// Compares two responses in constant time, so a failed attempt doesn't reveal how close it came
+pub fn responses_match(a: &[u32; 8], b: &[u32; 8]) -> bool {
+    a.iter().zip(b.iter()).fold(0u32, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn sha256() {
+        assert_eq!(hex(&Sha256::digest(b"abc")), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(
+            hex(&Sha256::digest(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn hmac() {
+        // RFC 4231 test case 2, with the key zero-padded to the block size, which HMAC does anyway
+        let mut key = [0u8; AUTH_KEY_LEN];
+        key[..4].copy_from_slice(b"Jefe");
+        assert_eq!(
+            hex(&hmac_sha256(&key, &[b"what do ya want ", b"for nothing?"])),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn challenge_response() {
+        let key = [7u8; AUTH_KEY_LEN];
+        let challenge = [1, 2, 3, 4];
+        let good = response(&key, &challenge, "server");
+        assert!(responses_match(&good, &response(&key, &challenge, "server")));
+        assert!(!responses_match(&good, &response(&key, &challenge, "servers")));
+        assert!(!responses_match(&good, &response(&key, &[1, 2, 3, 5], "server")));
+        assert!(!responses_match(&good, &response(&[8u8; AUTH_KEY_LEN], &challenge, "server")));
+        assert_ne!(key_id(&key), key_id(&[8u8; AUTH_KEY_LEN]));
+    }
+}
@@ -3,6 +3,7 @@
 //! Detailed docs are parked under Structs/XousNames down below
 
 pub mod api;
+pub mod auth;
 
 use api::Disconnect;
 use core::fmt::Write;
@@ -56,10 +57,32 @@ impl XousNames {
         &self,
         name: &str,
         max_conns: Option<u32>,
+    ) -> Result<xous::SID, xous::Error> {
+        self.register_name_inner(name, max_conns, None)
+    }
+
+    /// Registers a server that also accepts connections from processes that know `key`, once its
+    /// `max_conns` trusted connections are taken. Use `Some(0)` to only allow authenticated connections.
+    /// Clients connect with `request_authenticated_connection()`.
+    pub fn register_name_with_key(
+        &self,
+        name: &str,
+        max_conns: Option<u32>,
+        key: &[u8; auth::AUTH_KEY_LEN],
+    ) -> Result<xous::SID, xous::Error> {
+        self.register_name_inner(name, max_conns, Some(*key))
+    }
+
+    fn register_name_inner(
+        &self,
+        name: &str,
+        max_conns: Option<u32>,
+        auth_key: Option<[u8; auth::AUTH_KEY_LEN]>,
     ) -> Result<xous::SID, xous::Error> {
         let mut registration = api::Registration {
             name: String::<64>::new(),
             conn_limit: max_conns,
+            auth_key,
         };
         // could also do String::from_str() but in this case we want things to fail if the string is too long.
         write!(registration.name, "{}", name).expect("name probably too long");
@@ -75,8 +98,7 @@ impl XousNames {
                 xous::create_server_with_sid(sid).expect("can't auto-register server");
                 Ok(sid)
             }
-            api::Return::Failure => Err(xous::Error::InternalError),
-            _ => unimplemented!("unimplemented return codes"),
+            _ => Err(xous::Error::InternalError),
         }
     }
 
@@ -93,7 +115,7 @@ impl XousNames {
 
         match buf.to_original().unwrap() {
             api::Return::CID((cid, token)) => Ok((cid, token)),
-            // api::Return::AuthenticateRequest(_) => Err(xous::Error::AccessDenied),
+            api::Return::AuthenticateRequest(_) => Err(xous::Error::AccessDenied),
             _ => Err(xous::Error::ServerNotFound),
         }
     }
@@ -123,7 +145,7 @@ impl XousNames {
 
         match buf.to_original().unwrap() {
             api::Return::CID((cid, _)) => Ok(cid),
-            // api::Return::AuthenticateRequest(_) => Err(xous::Error::AccessDenied),
+            api::Return::AuthenticateRequest(_) => Err(xous::Error::AccessDenied),
             _ => Err(xous::Error::ServerNotFound),
         }
     }
@@ -164,10 +186,84 @@ impl XousNames {
         }
     }
 
-    // todo:
-    // pub fn authenticated_connection(&self, name: &str, key: Authkey)
-    // this function will create an authenticated connection, if such are allowed
-    // it's intended for use by dynamically-loaded third-party apps. As of Xous 0.8 this isn't supported, so it's just a "todo"
+    /// Lists every registered name, sorted by name, with the connections each has handed out and
+    /// whether it was registered during the trusted boot. This is meant for auditing a running
+    /// system for unexpected or squatted servers, so only processes that registered a name during
+    /// the trusted boot may call it; others get `AccessDenied`.
+    pub fn list_names(&self) -> Result<Vec<api::NameInfo>, xous::Error> {
+        collect_names(|start| {
+            let request = api::NameList {
+                start,
+                total: 0,
+                list: Default::default(),
+                denied: false,
+            };
+            let mut buf = Buffer::into_buf(request).or(Err(xous::Error::InternalError))?;
+            buf.lend_mut(self.conn, api::Opcode::ListNames.to_usize().unwrap())
+                .or(Err(xous::Error::InternalError))?;
+            Ok(buf.to_original::<api::NameList, _>().unwrap())
+        })
+    }
+
+    /// Connects to a server registered with `register_name_with_key()`, proving knowledge of `key` if
+    /// the server has no trusted connections left. This is intended for dynamically-loaded apps, which
+    /// start after the trusted connections have all been claimed.
+    ///
+    /// Returns `AccessDenied` if the server rejects the key, and `ServerNotFound` if there is no such
+    /// server, or it can't be connected to at all.
+    pub fn request_authenticated_connection(
+        &self,
+        name: &str,
+        key: &[u8; auth::AUTH_KEY_LEN],
+    ) -> Result<xous::CID, xous::Error> {
+        let mut lookup_name = xous_ipc::String::<64>::new();
+        write!(lookup_name, "{}", name).expect("name problably too long");
+
+        let mut buf = Buffer::into_buf(lookup_name).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, api::Opcode::Lookup.to_u32().unwrap())
+            .or(Err(xous::Error::InternalError))?;
+
+        let request = match buf.to_original().unwrap() {
+            api::Return::CID((cid, _)) => return Ok(cid),
+            api::Return::AuthenticateRequest(request) => request,
+            _ => return Err(xous::Error::ServerNotFound),
+        };
+        // the name server checks that the key is the one it asked for, along with the response
+        let lookup = api::AuthenticatedLookup {
+            name: request.name,
+            pubkey_id: auth::key_id(key),
+            response: auth::response(key, &request.challenge, name),
+        };
+        let mut buf = Buffer::into_buf(lookup).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, api::Opcode::AuthenticatedLookup.to_u32().unwrap())
+            .or(Err(xous::Error::InternalError))?;
+
+        match buf.to_original().unwrap() {
+            api::Return::CID((cid, _)) => Ok(cid),
+            _ => Err(xous::Error::AccessDenied),
+        }
+    }
+}
+
+/// Pages through the name list, with `fetch` returning the page that begins at the given index
+fn collect_names<F>(mut fetch: F) -> Result<Vec<api::NameInfo>, xous::Error>
+where
+    F: FnMut(u32) -> Result<api::NameList, xous::Error>,
+{
+    let mut names = Vec::new();
+    loop {
+        let page = fetch(names.len() as u32)?;
+        if page.denied {
+            return Err(xous::Error::AccessDenied);
+        }
+        let before = names.len();
+        names.extend(page.list.iter().flatten().copied());
+        // stop at the end of the table, or if it shrank while we were paging through it
+        if names.len() == before || names.len() >= page.total as usize {
+            break;
+        }
+    }
+    Ok(names)
 }
 
 use core::sync::atomic::{AtomicU32, Ordering};
@@ -182,3 +278,64 @@ impl Drop for XousNames {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::{NameInfo, NameList, NAME_LIST_LEN};
+
+    fn name(i: usize) -> NameInfo {
+        NameInfo {
+            name: String::<64>::from_str(&format!("server {:03}", i)),
+            pid: Some(2),
+            current_conns: 0,
+            max_conns: None,
+            has_key: false,
+            auth_conns: 0,
+            trusted: true,
+        }
+    }
+
+    /// Answers a `ListNames` request the way the name server does
+    fn page(table: &[NameInfo], start: u32) -> NameList {
+        let mut names = NameList { start, total: table.len() as u32, list: Default::default(), denied: false };
+        for (dest, src) in names.list.iter_mut().zip(table.iter().skip(start as usize)) {
+            *dest = Some(*src);
+        }
+        names
+    }
+
+    fn names_of(list: &[NameInfo]) -> Vec<&str> {
+        list.iter().map(|n| n.name.as_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn list_names_paging() {
+        for &len in [0, 1, NAME_LIST_LEN, NAME_LIST_LEN + 1, 3 * NAME_LIST_LEN - 1].iter() {
+            let table: Vec<NameInfo> = (0..len).map(name).collect();
+            let mut requests = 0;
+            let list = collect_names(|start| {
+                requests += 1;
+                Ok(page(&table, start))
+            })
+            .unwrap();
+            assert_eq!(names_of(&list), names_of(&table));
+            assert_eq!(requests, ((len + NAME_LIST_LEN - 1) / NAME_LIST_LEN).max(1));
+        }
+
+        // the table shrinks between pages: what was fetched is kept, and paging stops
+        let mut table: Vec<NameInfo> = (0..2 * NAME_LIST_LEN + 4).map(name).collect();
+        let list = collect_names(|start| {
+            let ret = page(&table, start);
+            table.truncate(NAME_LIST_LEN);
+            Ok(ret)
+        })
+        .unwrap();
+        assert_eq!(list.len(), NAME_LIST_LEN);
+
+        assert_eq!(collect_names(|_| Err(xous::Error::InternalError)).unwrap_err(), xous::Error::InternalError);
+        let table: Vec<NameInfo> = (0..4).map(name).collect();
+        let denied = collect_names(|start| Ok(NameList { denied: true, ..page(&table, start) }));
+        assert_eq!(denied.unwrap_err(), xous::Error::AccessDenied);
+    }
+}
@@ -3,6 +3,7 @@
 
 mod api;
 use api::*;
+mod auth;
 
 use num_traits::FromPrimitive;
 use xous::{msg_blocking_scalar_unpack, MessageEnvelope};
@@ -11,6 +12,7 @@ use xous_ipc::{Buffer, String};
 use log::{error, info};
 
 use std::collections::HashMap;
+use std::time::Instant;
 
 #[derive(PartialEq)]
 #[repr(C)]
@@ -104,23 +106,36 @@ I'll take safety over speed in this case.
 Eventually, we shall endeavor to remove Heapless entirely, once we have a `libstd` in place
 and we can use heap-allocated Rust primitives...
 */
+/// A server's authentication key, kept out of the table dumps in the logs
+#[derive(Copy, Clone)]
+struct AuthKey([u8; auth::AUTH_KEY_LEN]);
+impl core::fmt::Debug for AuthKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "AuthKey(..)")
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 struct Connection {
     pub sid: xous::SID,
     pub current_conns: u32, // number of unauthenticated (inherentely trusted) connections
     pub max_conns: Option<u32>, // if None, unlimited connections allowed
-    pub _allow_authenticate: bool,
-    pub _auth_conns: u32,        // number of authenticated connections
+    pub auth_key: Option<AuthKey>, // if set, connections beyond `max_conns` can be made by proving knowledge of this key
+    pub auth_conns: u32,         // number of authenticated connections
     pub token: Option<[u32; 4]>, // a random number that must be presented to allow for disconnection for single-connection servers
+    pub pid: Option<xous::PID>,  // the process that registered the name
+    pub trusted: bool,           // registered before the trusted init was done
 }
 #[derive(Debug)]
 struct CheckedHashMap {
     pub map: HashMap<XousServerName, Connection>,
+    pub init_done: bool, // latched the first time `trusted_init_done()` succeeds
 }
 impl CheckedHashMap {
     pub fn new() -> Self {
         CheckedHashMap {
             map: HashMap::new(),
+            init_done: false,
         }
     }
     pub fn insert(
@@ -128,6 +143,8 @@ impl CheckedHashMap {
         name: XousServerName,
         sid: xous::SID,
         max_conns: Option<u32>,
+        auth_key: Option<[u8; auth::AUTH_KEY_LEN]>,
+        pid: Option<xous::PID>,
     ) -> Result<(), xous::Error> {
         let token = if max_conns == Some(1) {
             // for the special case of 1-connection servers, provision a one-time use token for disconnects
@@ -145,9 +162,11 @@ impl CheckedHashMap {
                 sid,
                 current_conns: 0,
                 max_conns,
-                _allow_authenticate: false, // for now, we don't support authenticated connections
-                _auth_conns: 0,
+                auth_key: auth_key.map(AuthKey),
+                auth_conns: 0,
                 token,
+                pid,
+                trusted: !self.init_done,
             },
         );
         Ok(())
@@ -205,7 +224,22 @@ impl CheckedHashMap {
         }
     }
 
-    pub fn trusted_init_done(&self) -> bool {
+    /// Returns the key that a client can use to connect to `name` once its trusted connections are taken
+    pub fn auth_key(&self, name: &XousServerName) -> Option<[u8; auth::AUTH_KEY_LEN]> {
+        self.map.get(name).and_then(|entry| entry.auth_key).map(|key| key.0)
+    }
+
+    /// Connects a client that has proven knowledge of the server's key. These connections don't count
+    /// against the trusted connection limit.
+    pub fn connect_authenticated(&mut self, name: &XousServerName) -> Option<xous::SID> {
+        let entry = self.map.get_mut(name)?;
+        entry.auth_key?;
+        entry.auth_conns += 1;
+        log::trace!("{} now has {} authenticated connections", name, entry.auth_conns);
+        Some(entry.sid)
+    }
+
+    pub fn trusted_init_done(&mut self) -> bool {
         let mut trusted_done = true;
         for (name, entry) in self.map.iter() {
             if let Some(max) = entry.max_conns {
@@ -220,9 +254,38 @@ impl CheckedHashMap {
                 }
             }
         }
+        if trusted_done && !self.init_done {
+            info!("trusted init done with {} names registered", self.map.len());
+            self.init_done = true;
+        }
         trusted_done
     }
 
+    /// Returns whether `pid` registered a name before the trusted init was done, which marks it as
+    /// part of the boot image.
+    pub fn is_trusted_process(&self, pid: Option<xous::PID>) -> bool {
+        pid.is_some() && self.map.values().any(|entry| entry.trusted && entry.pid == pid)
+    }
+
+    /// Describes every registered name, sorted by name
+    pub fn list(&self) -> Vec<NameInfo> {
+        let mut list: Vec<NameInfo> = self
+            .map
+            .iter()
+            .map(|(name, entry)| NameInfo {
+                name: String::<64>::from_str(name.to_str()),
+                pid: entry.pid.map(|pid| pid.get()),
+                current_conns: entry.current_conns,
+                max_conns: entry.max_conns,
+                has_key: entry.auth_key.is_some(),
+                auth_conns: entry.auth_conns,
+                trusted: entry.trusted,
+            })
+            .collect();
+        list.sort_by(|a, b| a.name.as_str().unwrap_or("").cmp(b.name.as_str().unwrap_or("")));
+        list
+    }
+
     // this function is slightly unsafe because we can't guarantee that the presenter of the SID
     // has actually discarded the SID. However, we don't currently anticipate using this path a lot.
     // If it does get used in security-critical routes, it should be refactored to regenerate the SID
@@ -261,6 +324,17 @@ impl CheckedHashMap {
     }
 }
 
+/// A challenge issued by `Lookup` to a process, which it must answer with an `AuthenticatedLookup`
+struct PendingChallenge {
+    challenge: [u32; 4],
+    issued: Instant,
+}
+impl PendingChallenge {
+    fn expired(&self) -> bool {
+        self.issued.elapsed().as_millis() >= AUTHENTICATE_TIMEOUT as u128
+    }
+}
+
 fn name_from_msg(env: &MessageEnvelope) -> Result<XousServerName, ConnectError> {
     let msg = env
         .body
@@ -371,6 +445,10 @@ fn main() -> ! {
     //let mut name_table = FnvIndexMap::<XousServerName, xous::SID, 128>::new();
     let mut name_table = CheckedHashMap::new();
 
+    // Outstanding authentication challenges. A challenge can only be answered by the process it was
+    // issued to, and only once.
+    let mut pending_auth: HashMap<(xous::PID, XousServerName), PendingChallenge> = HashMap::new();
+
     info!("started");
     loop {
         let mut msg = xous::receive_message(name_server).unwrap();
@@ -395,7 +473,13 @@ fn main() -> ! {
                     let new_sid =
                         xous::create_server_id().expect("create server failed, maybe OOM?");
                     name_table
-                        .insert(name, new_sid, registration.conn_limit)
+                        .insert(
+                            name,
+                            new_sid,
+                            registration.conn_limit,
+                            registration.auth_key,
+                            msg.sender.pid(),
+                        )
                         .expect("register name failure, maybe out of HashMap capacity?");
                     log::trace!("request successful, SID is {:?}", new_sid);
                     should_connect = true;
@@ -503,24 +587,35 @@ fn main() -> ! {
                             response = api::Return::Failure
                         }
                     }
+                } else if let Some(key) = name_table.auth_key(&name) {
+                    // the trusted connections are taken, but the server accepts clients that know its key
+                    let sender_pid = msg
+                        .sender
+                        .pid()
+                        .expect("can't extract sender PID on Lookup");
+                    // the challenge is drawn from the kernel's TRNG-seeded ID generator, like SIDs are
+                    let (c1, c2, c3, c4) = xous::create_server_id().unwrap().to_u32();
+                    pending_auth.retain(|_, pending| !pending.expired());
+                    pending_auth.insert(
+                        (sender_pid, name),
+                        PendingChallenge {
+                            challenge: [c1, c2, c3, c4],
+                            issued: Instant::now(),
+                        },
+                    );
+                    log::trace!("Lookup for '{}' requires authentication", name);
+                    response = api::Return::AuthenticateRequest(AuthenticateRequest {
+                        name: name_string,
+                        pubkey_id: auth::key_id(&key),
+                        challenge: [c1, c2, c3, c4],
+                    });
                 } else {
                     log::debug!("Can't find request '{}' in table, dumping table:", name);
                     for (_name, conn) in name_table.map.iter() {
                         log::debug!("{:?}", conn);
                     }
-                    // no authenticate remedy currently supported, but we'd put that code somewhere around here eventually.
-                    let (c1, c2, c3, c4) = xous::create_server_id().unwrap().to_u32();
-                    let auth_request = AuthenticateRequest {
-                        name: String::<64>::from_str(
-                            name_string
-                                .as_str()
-                                .expect("couldn't convert server name to string"),
-                        ),
-                        pubkey_id: [0; 20], // placeholder
-                        challenge: [c1, c2, c3, c4],
-                    };
                     d11ctimeout.hosted_delay();
-                    response = api::Return::AuthenticateRequest(auth_request) // this code just exists to exercise the return path
+                    response = api::Return::Failure
                 }
                 buffer
                     .replace(response)
@@ -528,11 +623,50 @@ fn main() -> ! {
             }
             Some(api::Opcode::AuthenticatedLookup) => {
                 let mem = msg.body.memory_message_mut().unwrap();
-                let buffer = unsafe { Buffer::from_memory_message_mut(mem) };
-                let auth_lookup: AuthenticatedLookup = buffer.to_original().unwrap();
-                info!("AuthenticatedLookup request {:?}", auth_lookup);
-                error!("AuthenticatedLookup not yet implemented");
-                unimplemented!("AuthenticatedLookup not yet implemented");
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(mem) };
+                let auth_lookup = buffer.to_original::<AuthenticatedLookup, _>().unwrap();
+                let name = XousServerName::from_str(
+                    auth_lookup
+                        .name
+                        .as_str()
+                        .expect("couldn't convert server name to string"),
+                );
+                let sender_pid = msg
+                    .sender
+                    .pid()
+                    .expect("can't extract sender PID on AuthenticatedLookup");
+                log::trace!("AuthenticatedLookup request for '{}'", name);
+
+                // the challenge is consumed whether or not the response is correct, so each one only gets one guess
+                let verified = match (pending_auth.remove(&(sender_pid, name)), name_table.auth_key(&name)) {
+                    (Some(pending), Some(key)) => {
+                        !pending.expired()
+                            && auth_lookup.pubkey_id == auth::key_id(&key)
+                            && auth::responses_match(
+                                &auth_lookup.response,
+                                &auth::response(&key, &pending.challenge, name.to_str()),
+                            )
+                    }
+                    _ => false,
+                };
+                let mut response = api::Return::Failure;
+                if verified {
+                    if let Some(server_sid) = name_table.connect_authenticated(&name) {
+                        match xous::connect_for_process(sender_pid, server_sid) {
+                            Ok(xous::Result::ConnectionID(connection_id)) => {
+                                log::trace!("authenticated lookup success, returning connection {}", connection_id);
+                                response = api::Return::CID((connection_id, None));
+                            }
+                            result => log::error!("couldn't broker authenticated connection: {:?}", result),
+                        }
+                    }
+                } else {
+                    info!("authentication for '{}' failed, waiting for deterministic timeout", name);
+                    d11ctimeout.deterministic_busy_wait();
+                }
+                buffer
+                    .replace(response)
+                    .expect("AuthenticatedLookup can't serialize return value");
             }
             Some(api::Opcode::TrustedInitDone) => {
                 if name_table.trusted_init_done() {
@@ -541,6 +675,24 @@ fn main() -> ! {
                     xous::return_scalar(msg.sender, 0).expect("couldn't return trusted_init_done");
                 }
             }
+            Some(api::Opcode::ListNames) => {
+                let mem = msg.body.memory_message_mut().unwrap();
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(mem) };
+                let mut names = buffer.to_original::<NameList, _>().unwrap();
+                // the list includes sealed servers, which a lookup would deny without a trace
+                if !name_table.is_trusted_process(msg.sender.pid()) {
+                    log::warn!("rejecting ListNames from untrusted {:?}", msg.sender.pid());
+                    names.denied = true;
+                    buffer.replace(names).expect("ListNames can't serialize return value");
+                    continue;
+                }
+                let list = name_table.list();
+                names.total = list.len() as u32;
+                for (dest, src) in names.list.iter_mut().zip(list.iter().skip(names.start as usize)) {
+                    *dest = Some(*src);
+                }
+                buffer.replace(names).expect("ListNames can't serialize return value");
+            }
             Some(api::Opcode::Disconnect) => {
                 let mem = msg.body.memory_message_mut().unwrap();
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(mem) };
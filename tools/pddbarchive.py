@@ -0,0 +1,180 @@
+#! /usr/bin/env python3
+import argparse
+import os
+import struct
+import sys
+
+from Crypto.Protocol.KDF import bcrypt, HKDF
+from Crypto.Hash import SHA256
+from rfc8452 import AES_GCM_SIV
+
+import logging
+
+# see services/pddb/src/archive.rs for a description of the format
+ARCHIVE_MAGIC = b'PDDBARC\0'
+ARCHIVE_VERSION = 1
+ARCHIVE_HEADER_LEN = 48
+ARCHIVE_CHUNK_LEN = 64 * 1024
+ARCHIVE_FINAL_FLAG = 0x8000_0000
+ARCHIVE_TAG_LEN = 16
+PASSWORD_LEN = 72
+
+REC_END = 0x00
+REC_DICT = 0x01
+REC_KEY = 0x02
+
+BCRYPT_ALPHABET = b'./ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789'
+
+def bcrypt_raw(password, cost, salt):
+    # the device feeds bcrypt the password plus a NUL terminator, truncated to 72 bytes, which is
+    # exactly what pycryptodome does as well. pycryptodome only hands back the encoded hash, so
+    # undo the bcrypt flavor of base64 to recover the 23 bytes that are carried in it.
+    pw = password.encode('utf-8')[:PASSWORD_LEN]
+    encoded = bcrypt(pw, cost, salt)[-31:]
+    bits = 0
+    nbits = 0
+    raw = bytearray()
+    for c in encoded:
+        bits = (bits << 6) | BCRYPT_ALPHABET.index(c)
+        nbits += 6
+        if nbits >= 8:
+            nbits -= 8
+            raw.append((bits >> nbits) & 0xff)
+    assert len(raw) == 23
+    return bytes(raw)
+
+def archive_key(password, cost, salt):
+    return HKDF(bcrypt_raw(password, cost, salt), 32, salt, SHA256, context=b'pddb archive key')
+
+def archive_nonce(prefix, counter, last):
+    return prefix + counter.to_bytes(4, 'big') + bytes([1 if last else 0])
+
+class ArchiveReader:
+    def __init__(self, f, password):
+        self.f = f
+        self.header = f.read(ARCHIVE_HEADER_LEN)
+        if len(self.header) != ARCHIVE_HEADER_LEN or self.header[:8] != ARCHIVE_MAGIC:
+            raise ValueError('not a PDDB archive')
+        (version, cost) = struct.unpack('<II', self.header[8:16])
+        if version != ARCHIVE_VERSION:
+            raise ValueError('unsupported archive version {}'.format(version))
+        if cost < 4 or cost > 31:
+            raise ValueError('archive bcrypt cost {} is out of range'.format(cost))
+        self.salt = self.header[16:32]
+        self.nonce_prefix = self.header[32:39]
+        logging.info('deriving archive key (bcrypt cost {})...'.format(cost))
+        self.key = archive_key(password, cost, self.salt)
+        self.counter = 0
+        self.last_seen = False
+        self.pt = b''
+        self.pos = 0
+        self.next_chunk()
+
+    def next_chunk(self):
+        if self.last_seen:
+            raise ValueError('archive records run past the final chunk')
+        raw_len = self.f.read(4)
+        if len(raw_len) != 4:
+            raise ValueError('archive is truncated')
+        raw_len = int.from_bytes(raw_len, 'little')
+        last = (raw_len & ARCHIVE_FINAL_FLAG) != 0
+        ct_len = raw_len & ~ARCHIVE_FINAL_FLAG
+        if ct_len < ARCHIVE_TAG_LEN or ct_len > ARCHIVE_CHUNK_LEN + ARCHIVE_TAG_LEN:
+            raise ValueError('archive chunk has an invalid length')
+        ct = self.f.read(ct_len)
+        if len(ct) != ct_len:
+            raise ValueError('archive is truncated')
+        cipher = AES_GCM_SIV(self.key, archive_nonce(self.nonce_prefix, self.counter, last))
+        try:
+            self.pt = cipher.decrypt(ct, self.header)
+        except Exception:
+            raise ValueError('archive authentication failed (wrong password, or corrupted archive)')
+        self.pos = 0
+        self.last_seen = last
+        self.counter += 1
+
+    def pull(self, n):
+        data = bytearray()
+        while len(data) < n:
+            if self.pos == len(self.pt):
+                self.next_chunk()
+            avail = min(len(self.pt) - self.pos, n - len(data))
+            data += self.pt[self.pos:self.pos + avail]
+            self.pos += avail
+        return bytes(data)
+
+    def pull_name(self):
+        name_len = self.pull(1)[0]
+        return self.pull(name_len).decode('utf-8')
+
+    def records(self):
+        # yields ('dict', name), and ('key', name, reserved, age, data)
+        while True:
+            tag = self.pull(1)[0]
+            if tag == REC_DICT:
+                yield ('dict', self.pull_name())
+            elif tag == REC_KEY:
+                name = self.pull_name()
+                (reserved, age, length) = struct.unpack('<QIQ', self.pull(20))
+                yield ('key', name, reserved, age, self.pull(length))
+            elif tag == REC_END:
+                if not self.last_seen or self.pos != len(self.pt):
+                    raise ValueError('archive end record is misplaced')
+                return
+            else:
+                raise ValueError('archive contains an unknown record type {}'.format(tag))
+
+def safe_name(name):
+    # dict and key names may contain path separators; keep them from escaping the output directory
+    return name.replace('%', '%25').replace('/', '%2F').replace('\\', '%5C').replace('..', '%2E%2E')
+
+def main():
+    parser = argparse.ArgumentParser(description="List, verify or extract PDDB backup archives")
+    parser.add_argument(
+        "archive", help="archive file, as created by `Pddb::export_basis()`", type=str
+    )
+    parser.add_argument(
+        "--password", required=True, help="archive password", type=str
+    )
+    parser.add_argument(
+        "--extract", required=False, help="extract keys into this directory as <dict>/<key>", type=str, metavar=('dir')
+    )
+    parser.add_argument(
+        "--loglevel", required=False, help="set logging level (INFO/DEBUG/WARNING/ERROR)", type=str, default="INFO",
+    )
+    args = parser.parse_args()
+
+    numeric_level = getattr(logging, args.loglevel.upper(), None)
+    if not isinstance(numeric_level, int):
+        raise ValueError('Invalid log level: %s' % args.loglevel)
+    logging.basicConfig(level=numeric_level)
+
+    with open(args.archive, 'rb') as f:
+        try:
+            reader = ArchiveReader(f, args.password)
+            dict_name = None
+            num_keys = 0
+            for rec in reader.records():
+                if rec[0] == 'dict':
+                    dict_name = rec[1]
+                    print('dict {}'.format(dict_name))
+                    if args.extract:
+                        os.makedirs(os.path.join(args.extract, safe_name(dict_name)), exist_ok=True)
+                else:
+                    (_, name, reserved, age, data) = rec
+                    if dict_name is None:
+                        raise ValueError('archive has a key outside of a dictionary')
+                    print('  key {} len {} reserved {} age {}'.format(name, len(data), reserved, age))
+                    num_keys += 1
+                    if args.extract:
+                        with open(os.path.join(args.extract, safe_name(dict_name), safe_name(name)), 'wb') as out:
+                            out.write(data)
+            if len(f.read(1)) != 0:
+                logging.warning('trailing data found after the end of the archive')
+        except ValueError as e:
+            logging.error(str(e))
+            sys.exit(1)
+    logging.info('archive verified OK, {} keys'.format(num_keys))
+
+if __name__ == "__main__":
+    main()
@@ -121,6 +121,28 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .value_name("kernel output image")
                 .help("kernel output image"),
         )
+        .arg(
+            Arg::with_name("app-image")
+                .long("app-image")
+                .takes_value(true)
+                .value_name("app image")
+                .help("app ELF image, to be loaded from the PDDB by the app loader"),
+        )
+        .arg(
+            Arg::with_name("app-key")
+                .long("app-key")
+                .takes_value(true)
+                .help("app signing key")
+                .value_name("app signing key")
+                .default_value(DEVKEY_PATH),
+        )
+        .arg(
+            Arg::with_name("app-output")
+                .long("app-output")
+                .takes_value(true)
+                .value_name("app output image")
+                .help("app output image"),
+        )
         .arg(
             Arg::with_name("defile").help(
                 "patch the resulting image, to create a test file to catch signature failure",
@@ -172,5 +194,25 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             matches.is_present("defile"),
         )?;
     }
+
+    if let Some(app_output) = matches.value_of("app-output") {
+        let app_key = matches.value_of("app-key").expect("no app key specified");
+        let app_image = matches
+            .value_of("app-image")
+            .expect("no app image specified");
+
+        let app_pkey = load_pem(app_key)?;
+        if app_pkey.tag != "PRIVATE KEY" {
+            println!("App key was a {}, not a PRIVATE KEY", app_pkey.tag);
+            Err("invalid app private key type")?;
+        }
+        println!("Signing app");
+        image_sign(
+            &app_image,
+            &app_output,
+            &app_pkey,
+            matches.is_present("defile"),
+        )?;
+    }
     Ok(())
 }
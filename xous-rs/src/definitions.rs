@@ -381,6 +381,255 @@ impl core::fmt::Display for MemoryType {
     }
 }
 
+/// How urgently a thread should run. Whenever the kernel picks a thread to run, or a
+/// thread to hand a message to, it takes the highest-priority one that is ready, and
+/// goes round-robin between threads of equal priority.
+///
+/// A thread that never blocks will starve every thread of a lower priority, so the
+/// priorities above `Normal` are meant for threads that spend most of their time
+/// waiting on messages or interrupts, and only processes that are part of the boot
+/// image may use them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum ThreadPriority {
+    /// Housekeeping that should only run when nothing else wants to.
+    Low = 0,
+
+    /// The priority every thread starts out with.
+    Normal = 1,
+
+    /// Latency-sensitive work, such as handling input.
+    High = 2,
+
+    /// Work with hard deadlines, such as keeping audio buffers full.
+    Realtime = 3,
+}
+
+impl ThreadPriority {
+    pub fn from_usize(arg: usize) -> Option<Self> {
+        match arg {
+            0 => Some(ThreadPriority::Low),
+            1 => Some(ThreadPriority::Normal),
+            2 => Some(ThreadPriority::High),
+            3 => Some(ThreadPriority::Realtime),
+            _ => None,
+        }
+    }
+}
+
+impl Default for ThreadPriority {
+    fn default() -> Self {
+        ThreadPriority::Normal
+    }
+}
+
+/// The resources held by a process, as returned by `process_stats()`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct ProcessStats {
+    /// Pages of RAM owned by the process, including its heap, thread stacks,
+    /// and any memory that has been moved to it. Memory that is only being lent
+    /// to the process is counted against its owner. This is `None` in hosted
+    /// mode, where the kernel doesn't manage memory.
+    pub mapped_pages: Option<usize>,
+
+    /// The current size of the heap, in bytes.
+    pub heap_size: usize,
+
+    /// The number of threads in the process.
+    pub threads: u8,
+
+    /// Threads that are waiting for their turn to run.
+    pub ready_threads: u8,
+
+    /// Threads that are blocked, for example in `receive_message()` or waiting
+    /// for a server to respond.
+    pub blocked_threads: u8,
+
+    /// The number of servers the process has created.
+    pub servers: usize,
+
+    /// The number of connections the process holds to servers.
+    pub connections: usize,
+
+    /// Messages that have been sent to the process' servers but not yet received.
+    pub queued_messages: usize,
+}
+
+impl ProcessStats {
+    fn from_args(src: &[usize]) -> Self {
+        ProcessStats {
+            mapped_pages: if src[0] == usize::MAX {
+                None
+            } else {
+                Some(src[0])
+            },
+            heap_size: src[1],
+            threads: src[2] as u8,
+            ready_threads: (src[2] >> 8) as u8,
+            blocked_threads: (src[2] >> 16) as u8,
+            servers: src[3],
+            connections: src[4],
+            queued_messages: src[5],
+        }
+    }
+
+    fn to_args(&self) -> [usize; 7] {
+        [
+            self.mapped_pages.unwrap_or(usize::MAX),
+            self.heap_size,
+            self.threads as usize
+                | (self.ready_threads as usize) << 8
+                | (self.blocked_threads as usize) << 16,
+            self.servers,
+            self.connections,
+            self.queued_messages,
+            0,
+        ]
+    }
+}
+
+/// What happened in an `IpcTraceEvent`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum IpcTraceKind {
+    /// A `Scalar` message was sent.
+    Send = 0,
+
+    /// A `BlockingScalar` message was sent.
+    BlockingSend = 1,
+
+    /// Memory was moved to a server.
+    Move = 2,
+
+    /// Memory was lent to a server to read.
+    Lend = 3,
+
+    /// Memory was lent to a server to read and write.
+    MutableLend = 4,
+
+    /// A server thread was handed a message.
+    Receive = 5,
+
+    /// A server responded to a blocking message, or returned lent memory.
+    Return = 6,
+}
+
+impl IpcTraceKind {
+    pub fn from_usize(arg: usize) -> Option<Self> {
+        match arg {
+            0 => Some(IpcTraceKind::Send),
+            1 => Some(IpcTraceKind::BlockingSend),
+            2 => Some(IpcTraceKind::Move),
+            3 => Some(IpcTraceKind::Lend),
+            4 => Some(IpcTraceKind::MutableLend),
+            5 => Some(IpcTraceKind::Receive),
+            6 => Some(IpcTraceKind::Return),
+            _ => None,
+        }
+    }
+
+    /// Returns the kind of event for sending `message`
+    pub fn from_message(message: &crate::Message) -> Self {
+        match message {
+            crate::Message::Scalar(_) => IpcTraceKind::Send,
+            crate::Message::BlockingScalar(_) => IpcTraceKind::BlockingSend,
+            crate::Message::Move(_) => IpcTraceKind::Move,
+            crate::Message::Borrow(_) => IpcTraceKind::Lend,
+            crate::Message::MutableBorrow(_) => IpcTraceKind::MutableLend,
+        }
+    }
+}
+
+impl core::fmt::Display for IpcTraceKind {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match *self {
+            IpcTraceKind::Send => write!(fmt, "send"),
+            IpcTraceKind::BlockingSend => write!(fmt, "bsend"),
+            IpcTraceKind::Move => write!(fmt, "move"),
+            IpcTraceKind::Lend => write!(fmt, "lend"),
+            IpcTraceKind::MutableLend => write!(fmt, "mlend"),
+            IpcTraceKind::Receive => write!(fmt, "recv"),
+            IpcTraceKind::Return => write!(fmt, "ret"),
+        }
+    }
+}
+
+/// One entry of the kernel's IPC trace buffer, as returned by `ipc_trace_event()`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct IpcTraceEvent {
+    /// When the event was recorded, in milliseconds since the kernel started. On
+    /// hardware this advances with the preemption tick.
+    pub timestamp_ms: usize,
+
+    /// The message's opcode. A return doesn't say which message it answers, so
+    /// this is zero for returns.
+    pub opcode: usize,
+
+    /// The first word of the SID of the server the message was sent to. This
+    /// is enough to tell servers apart, but unlike the whole SID it can't be
+    /// used to connect to the server.
+    pub sid_prefix: u32,
+
+    pub kind: IpcTraceKind,
+
+    /// The process that made the call: the client when sending or lending, and
+    /// the server when receiving or returning.
+    pub pid: PID,
+
+    /// The thread that made the call.
+    pub tid: u8,
+
+    /// The process on the other end of the call, if it's known.
+    pub peer: Option<PID>,
+}
+
+impl IpcTraceEvent {
+    fn from_args(src: &[usize]) -> Option<Self> {
+        Some(IpcTraceEvent {
+            timestamp_ms: src[0],
+            opcode: src[1],
+            sid_prefix: src[2] as u32,
+            kind: IpcTraceKind::from_usize(src[6] & 0xff)?,
+            pid: PID::new((src[6] >> 8) as u8)?,
+            tid: (src[6] >> 16) as u8,
+            peer: PID::new((src[6] >> 24) as u8),
+        })
+    }
+
+    fn to_args(&self) -> [usize; 7] {
+        [
+            self.timestamp_ms,
+            self.opcode,
+            self.sid_prefix as usize,
+            0,
+            0,
+            0,
+            self.kind as usize
+                | (self.pid.get() as usize) << 8
+                | (self.tid as usize) << 16
+                | (self.peer.map(|p| p.get()).unwrap_or(0) as usize) << 24,
+        ]
+    }
+}
+
+/// Formats the event as a line of a trace dump, e.g. `1234ms bsend 5:2 -> 7 op 3 sid 0123abcd`
+impl core::fmt::Display for IpcTraceEvent {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            fmt,
+            "{}ms {} {}:{} -> ",
+            self.timestamp_ms, self.kind, self.pid, self.tid
+        )?;
+        match self.peer {
+            Some(peer) => write!(fmt, "{}", peer)?,
+            None => write!(fmt, "?")?,
+        }
+        write!(fmt, " op {} sid {:08x}", self.opcode, self.sid_prefix)
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, PartialEq)]
 pub enum Result {
@@ -436,6 +685,15 @@ pub enum Result {
     /// the caller.
     NewProcess(ProcessStartup),
 
+    /// A scalar with five values
+    Scalar5(usize, usize, usize, usize, usize),
+
+    /// The resources held by a process.
+    ProcessStats(ProcessStats),
+
+    /// An event from the kernel's IPC trace buffer.
+    IpcTraceEvent(IpcTraceEvent),
+
     UnknownResult(usize, usize, usize, usize, usize, usize, usize),
 }
 
@@ -499,6 +757,9 @@ impl Result {
                 0,
             ],
             Result::NewProcess(p) => Self::add_opcode(19, p.into()),
+            Result::Scalar5(a, b, c, d, e) => [20, *a, *b, *c, *d, *e, 0, 0],
+            Result::ProcessStats(stats) => Self::add_opcode(21, stats.to_args()),
+            Result::IpcTraceEvent(event) => Self::add_opcode(22, event.to_args()),
             Result::UnknownResult(arg1, arg2, arg3, arg4, arg5, arg6, arg7) => {
                 [usize::MAX, *arg1, *arg2, *arg3, *arg4, *arg5, *arg6, *arg7]
             }
@@ -576,6 +837,14 @@ impl Result {
             17 => Result::None,
             18 => Result::MemoryReturned(MemorySize::new(src[1]), MemorySize::new(src[2])),
             19 => Result::NewProcess(src.into()),
+            20 => Result::Scalar5(src[1], src[2], src[3], src[4], src[5]),
+            21 => Result::ProcessStats(ProcessStats::from_args(&src[1..])),
+            22 => match IpcTraceEvent::from_args(&src[1..]) {
+                Some(event) => Result::IpcTraceEvent(event),
+                None => {
+                    Result::UnknownResult(src[0], src[1], src[2], src[3], src[4], src[5], src[6])
+                }
+            },
             _ => Result::UnknownResult(src[0], src[1], src[2], src[3], src[4], src[5], src[6]),
         }
     }
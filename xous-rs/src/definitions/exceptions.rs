@@ -11,6 +11,8 @@ pub enum ExceptionType {
     InstructionPageFault = 7,
     LoadPageFault = 8,
     StorePageFault = 9,
+    /// A load or store hit the guard page at the bottom of a thread's stack
+    StackOverflow = 10,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -25,6 +27,7 @@ pub enum Exception {
     InstructionPageFault(usize /* epc */, usize /* addr */),
     LoadPageFault(usize /* epc */, usize /* addr */),
     StorePageFault(usize /* epc */, usize /* addr */),
+    StackOverflow(usize /* epc */, usize /* addr */),
     Unknown(usize, usize, usize),
 }
 
@@ -47,6 +50,7 @@ impl Exception {
             7 /*ExceptionType::InstructionPageFault as usize*/ => Exception::InstructionPageFault(a1, a2),
             8 /*ExceptionType::LoadPageFault as usize*/ => Exception::LoadPageFault(a1, a2),
             9 /*ExceptionType::StorePageFault as usize*/ => Exception::StorePageFault(a1, a2),
+            10 /*ExceptionType::StackOverflow as usize*/ => Exception::StackOverflow(a1, a2),
             _ => Exception::Unknown(a0, a1, a2),
         }
     }
@@ -63,6 +67,7 @@ impl Exception {
             | Exception::InstructionPageFault(pc, _)
             | Exception::LoadPageFault(pc, _)
             | Exception::StorePageFault(pc, _)
+            | Exception::StackOverflow(pc, _)
             | Exception::Unknown(_, pc, _) => pc,
         }
     }
@@ -77,7 +82,8 @@ impl Exception {
             | Exception::StoreAccessFault(_, address)
             | Exception::InstructionPageFault(_, address)
             | Exception::LoadPageFault(_, address)
-            | Exception::StorePageFault(_, address) => Some(address),
+            | Exception::StorePageFault(_, address)
+            | Exception::StackOverflow(_, address) => Some(address),
             _ => None,
         }
     }
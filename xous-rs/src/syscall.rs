@@ -1,7 +1,8 @@
 use crate::{
-    pid_from_usize, CpuID, Error, MemoryAddress, MemoryFlags, MemoryMessage, MemoryRange,
-    MemorySize, MemoryType, Message, MessageEnvelope, MessageSender, ProcessArgs, ProcessInit,
-    Result, ScalarMessage, SysCallResult, ThreadInit, CID, PID, SID, TID,
+    pid_from_usize, CpuID, Error, IpcTraceEvent, MemoryAddress, MemoryFlags, MemoryMessage,
+    MemoryRange, MemorySize, MemoryType, Message, MessageEnvelope, MessageSender, ProcessArgs,
+    ProcessInit, ProcessStats, Result, ScalarMessage, SysCallResult, ThreadInit, ThreadPriority,
+    CID, PID, SID, TID,
 };
 use core::convert::{TryFrom, TryInto};
 /* https://github.com/betrusted-io/xous-core/issues/90
@@ -425,6 +426,98 @@ pub enum SysCall {
         usize, /* stack pointer */
     ),
 
+    /// Returns the name that the given process was given in the boot image. The
+    /// name is packed into the five words of a `Scalar5`, in order and padded with
+    /// zeroes; a process without a name returns all zeroes.
+    ///
+    /// # Errors
+    ///
+    /// * **ProcessNotFound**: The process doesn't exist
+    GetProcessName(PID),
+
+    /// Sets the priority of one of the calling process' threads. New threads
+    /// start out at `ThreadPriority::Normal`. Only processes that are part of
+    /// the boot image may raise a thread above `Normal`.
+    ///
+    /// # Errors
+    ///
+    /// * **AccessDenied**: The priority is above `Normal`, and the caller isn't
+    ///   part of the boot image
+    /// * **ThreadNotAvailable**: The thread ID is not valid
+    SetThreadPriority(TID, ThreadPriority),
+
+    /// Returns the resources held by the given process, such as how much memory
+    /// it has mapped, what its threads are doing, and how many servers and
+    /// connections it has. A process may always ask about itself; only the
+    /// processes in the boot image may ask about others.
+    ///
+    /// # Errors
+    ///
+    /// * **AccessDenied**: The process is another one, and the caller was
+    ///   created after boot
+    /// * **ProcessNotFound**: The process doesn't exist
+    GetProcessStats(PID),
+
+    /// Like `ReceiveMessage`, but gives up after the given number of
+    /// milliseconds if no message arrives. The deadline is checked on the
+    /// kernel's preemption tick, so on hardware it is rounded up to a multiple
+    /// of `BASE_QUANTA_MS`.
+    ///
+    /// # Returns
+    ///
+    /// * **Message**: A valid message from the queue
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The given SID is not active or has terminated
+    /// * **Timeout**: No message arrived before the deadline
+    /// * **OutOfMemory**: The kernel's table of pending timeouts is full
+    /// * **BlockedProcess**: When running in Hosted mode, this indicates that this
+    ///                       thread is blocking.
+    ReceiveMessageTimeout(SID, usize /* timeout in ms */),
+
+    /// Sends a `BlockingScalar` message, giving up if no reply has arrived
+    /// after the given number of milliseconds. The deadline covers both
+    /// waiting in the server's queue and waiting for the server to answer.
+    ///
+    /// A message that times out while still queued is delivered to the server
+    /// as a plain `Scalar`. If the server had already taken it, the server's
+    /// eventual reply succeeds but is discarded. Either way, the sender is
+    /// never resumed by a late reply.
+    ///
+    /// # Returns
+    ///
+    /// * **Scalar1**: The server returned a single value
+    /// * **Scalar2**: The server returned two values
+    ///
+    /// # Errors
+    ///
+    /// * **ServerNotFound**: The server does not exist so the connection is now invalid
+    /// * **ServerQueueFull**: The server's queue is full, so the message couldn't be sent
+    /// * **Timeout**: The server didn't reply before the deadline
+    /// * **OutOfMemory**: The kernel's table of pending timeouts is full
+    /// * **BlockedProcess**: When running in Hosted mode, this indicates that this
+    ///                       thread is blocking.
+    SendMessageTimeout(CID, ScalarMessage, usize /* timeout in ms */),
+
+    /// Reads an event from the kernel's IPC trace buffer. Events are numbered
+    /// from zero in the order they were recorded, and the buffer only keeps the
+    /// most recent ones. This is only available when the kernel is built with
+    /// the `ipc-trace` feature, and only to processes started by the kernel.
+    ///
+    /// # Returns
+    ///
+    /// * **IpcTraceEvent**: The event with the given sequence number
+    /// * **Scalar2**: The event is not in the buffer. The values are the
+    ///                sequence number of the oldest event that is, and the
+    ///                sequence number the next event will get.
+    ///
+    /// # Errors
+    ///
+    /// * **AccessDenied**: The caller isn't allowed to read the trace
+    /// * **UnhandledSyscall**: The kernel was built without `ipc-trace`
+    GetIpcTraceEvent(usize /* sequence number */),
+
     /// This syscall does not exist. It captures all possible
     /// arguments so detailed analysis can be performed.
     Invalid(usize, usize, usize, usize, usize, usize, usize),
@@ -468,6 +561,12 @@ pub enum SysCallNumber {
     Disconnect = 35,
     JoinThread = 36,
     SetExceptionHandler = 37,
+    GetProcessName = 38,
+    SetThreadPriority = 39,
+    GetProcessStats = 40,
+    ReceiveMessageTimeout = 41,
+    SendMessageTimeout = 42,
+    GetIpcTraceEvent = 43,
     Invalid,
 }
 
@@ -511,6 +610,12 @@ impl SysCallNumber {
             35 => Disconnect,
             36 => JoinThread,
             37 => SetExceptionHandler,
+            38 => GetProcessName,
+            39 => SetThreadPriority,
+            40 => GetProcessStats,
+            41 => ReceiveMessageTimeout,
+            42 => SendMessageTimeout,
+            43 => GetIpcTraceEvent,
             _ => Invalid,
         }
     }
@@ -865,6 +970,69 @@ impl SysCall {
                 0,
                 0,
             ],
+            SysCall::GetProcessName(pid) => [
+                SysCallNumber::GetProcessName as usize,
+                pid.get() as usize,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::SetThreadPriority(tid, priority) => [
+                SysCallNumber::SetThreadPriority as usize,
+                *tid as usize,
+                *priority as usize,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::GetProcessStats(pid) => [
+                SysCallNumber::GetProcessStats as usize,
+                pid.get() as usize,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
+            SysCall::ReceiveMessageTimeout(sid, timeout) => {
+                let s = sid.to_u32();
+                [
+                    SysCallNumber::ReceiveMessageTimeout as usize,
+                    s.0 as _,
+                    s.1 as _,
+                    s.2 as _,
+                    s.3 as _,
+                    *timeout,
+                    0,
+                    0,
+                ]
+            }
+            SysCall::SendMessageTimeout(cid, sc, timeout) => [
+                SysCallNumber::SendMessageTimeout as usize,
+                *cid as usize,
+                sc.id as usize,
+                sc.arg1,
+                sc.arg2,
+                sc.arg3,
+                sc.arg4,
+                *timeout,
+            ],
+            SysCall::GetIpcTraceEvent(seq) => [
+                SysCallNumber::GetIpcTraceEvent as usize,
+                *seq,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ],
             SysCall::Invalid(a1, a2, a3, a4, a5, a6, a7) => [
                 SysCallNumber::Invalid as usize,
                 *a1,
@@ -1028,6 +1196,32 @@ impl SysCall {
             SysCallNumber::Disconnect => SysCall::Disconnect(a1 as _),
             SysCallNumber::JoinThread => SysCall::JoinThread(a1 as _),
             SysCallNumber::SetExceptionHandler => SysCall::SetExceptionHandler(a1 as _, a2 as _),
+            SysCallNumber::GetProcessName => {
+                SysCall::GetProcessName(PID::new(a1 as _).ok_or(Error::InvalidSyscall)?)
+            }
+            SysCallNumber::SetThreadPriority => SysCall::SetThreadPriority(
+                a1 as _,
+                ThreadPriority::from_usize(a2).ok_or(Error::InvalidSyscall)?,
+            ),
+            SysCallNumber::GetProcessStats => {
+                SysCall::GetProcessStats(PID::new(a1 as _).ok_or(Error::InvalidSyscall)?)
+            }
+            SysCallNumber::ReceiveMessageTimeout => SysCall::ReceiveMessageTimeout(
+                SID::from_u32(a1 as _, a2 as _, a3 as _, a4 as _),
+                a5,
+            ),
+            SysCallNumber::SendMessageTimeout => SysCall::SendMessageTimeout(
+                a1 as _,
+                ScalarMessage {
+                    id: a2 as _,
+                    arg1: a3,
+                    arg2: a4,
+                    arg3: a5,
+                    arg4: a6,
+                },
+                a7,
+            ),
+            SysCallNumber::GetIpcTraceEvent => SysCall::GetIpcTraceEvent(a1),
             SysCallNumber::Invalid => SysCall::Invalid(a1, a2, a3, a4, a5, a6, a7),
         })
     }
@@ -1760,6 +1954,174 @@ pub fn join_thread(tid: TID) -> core::result::Result<usize, Error> {
         }
     })
 }
+/// The longest process name that `process_name()` can return; longer names are truncated
+pub const PROCESS_NAME_LEN: usize = 5 * core::mem::size_of::<usize>();
+
+/// Returns the name of the given process, as recorded in the boot image. This is how
+/// servers tell their callers apart by something more stable than a PID.
+///
+/// # Errors
+///
+/// * **ProcessNotFound**: The process doesn't exist
+/// * **UnhandledSyscall**: The kernel doesn't track process names, as in hosted mode
+pub fn process_name(pid: PID) -> core::result::Result<crate::String<PROCESS_NAME_LEN>, Error> {
+    rsyscall(SysCall::GetProcessName(pid)).and_then(|result| {
+        if let Result::Scalar5(w0, w1, w2, w3, w4) = result {
+            let mut bytes = [0u8; PROCESS_NAME_LEN];
+            for (chunk, word) in bytes
+                .chunks_mut(core::mem::size_of::<usize>())
+                .zip([w0, w1, w2, w3, w4].iter())
+            {
+                chunk.copy_from_slice(&word.to_le_bytes());
+            }
+            let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+            // a name that was cut short may end in the middle of a character
+            let name = match core::str::from_utf8(&bytes[..len]) {
+                Ok(name) => name,
+                Err(e) => core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap(),
+            };
+            let mut ret = crate::String::new();
+            ret.append(name).ok();
+            Ok(ret)
+        } else if let Result::Error(e) = result {
+            Err(e)
+        } else {
+            Err(Error::InternalError)
+        }
+    })
+}
+
+/// Sets the priority of one of the current process' threads, such as the one
+/// returned by `current_tid()`. See `ThreadPriority` for how the kernel uses it.
+///
+/// # Errors
+///
+/// * **AccessDenied**: The priority is above `Normal`, and the current process
+///   isn't part of the boot image
+/// * **ThreadNotAvailable**: The thread ID is not valid
+pub fn set_thread_priority(tid: TID, priority: ThreadPriority) -> core::result::Result<(), Error> {
+    rsyscall(SysCall::SetThreadPriority(tid, priority)).and_then(|result| {
+        if let Result::Ok = result {
+            Ok(())
+        } else if let Result::Error(e) = result {
+            Err(e)
+        } else {
+            Err(Error::InternalError)
+        }
+    })
+}
+
+/// Returns the resources held by the given process. Together with `process_name()`,
+/// this is enough to build a process listing. Any process may ask about itself,
+/// but only the processes that were part of the boot image may ask about others.
+/// Memory use isn't tracked in hosted mode, so `mapped_pages` is `None` there.
+///
+/// # Errors
+///
+/// * **AccessDenied**: `pid` is another process, and the caller was created after boot
+/// * **ProcessNotFound**: The process doesn't exist
+pub fn process_stats(pid: PID) -> core::result::Result<ProcessStats, Error> {
+    rsyscall(SysCall::GetProcessStats(pid)).and_then(|result| {
+        if let Result::ProcessStats(stats) = result {
+            Ok(stats)
+        } else if let Result::Error(e) = result {
+            Err(e)
+        } else {
+            Err(Error::InternalError)
+        }
+    })
+}
+
+/// Suspend the current thread until a message is received or `timeout_ms`
+/// milliseconds have passed, whichever comes first. On hardware the timeout is
+/// rounded up to the kernel's preemption tick.
+///
+/// # Errors
+///
+/// * **Timeout**: No message arrived before the deadline
+/// * **ServerNotFound**: The server doesn't exist
+pub fn receive_message_timeout(
+    server: SID,
+    timeout_ms: usize,
+) -> core::result::Result<MessageEnvelope, Error> {
+    rsyscall(SysCall::ReceiveMessageTimeout(server, timeout_ms)).and_then(|result| {
+        if let Result::Message(envelope) = result {
+            Ok(envelope)
+        } else if let Result::Error(e) = result {
+            Err(e)
+        } else {
+            Err(Error::InternalError)
+        }
+    })
+}
+
+/// Send a `BlockingScalar` message, giving up if the server hasn't replied
+/// within `timeout_ms` milliseconds. If the server replies after that, the
+/// reply is discarded.
+///
+/// Only `BlockingScalar` messages can be sent this way: lent memory can't be
+/// taken back from a server that is still using it, so any other kind of
+/// message is rejected with `InvalidSyscall` without being sent.
+///
+/// # Errors
+///
+/// * **Timeout**: The server didn't reply before the deadline
+/// * **ServerQueueFull**: The server's queue is full
+/// * **ServerNotFound**: The server does not exist so the connection is now invalid
+/// * **InvalidSyscall**: The message isn't a `BlockingScalar`
+pub fn send_message_timeout(
+    connection: CID,
+    message: Message,
+    timeout_ms: usize,
+) -> core::result::Result<Result, Error> {
+    let scalar = match message {
+        Message::BlockingScalar(scalar) => scalar,
+        _ => return Err(Error::InvalidSyscall),
+    };
+    match rsyscall(SysCall::SendMessageTimeout(connection, scalar, timeout_ms)) {
+        Ok(Result::Scalar1(a)) => Ok(Result::Scalar1(a)),
+        Ok(Result::Scalar2(a, b)) => Ok(Result::Scalar2(a, b)),
+        Ok(Result::Error(e)) => Err(e),
+        Err(e) => Err(e),
+        v => panic!("Unexpected return value: {:?}", v),
+    }
+}
+
+/// Returns the range of sequence numbers held by the kernel's IPC trace
+/// buffer, as `(oldest, next)`. The buffer is empty when the two are equal.
+///
+/// # Errors
+///
+/// * **AccessDenied**: The caller isn't allowed to read the trace
+/// * **UnhandledSyscall**: The kernel was built without the `ipc-trace` feature
+pub fn ipc_trace_range() -> core::result::Result<(usize, usize), Error> {
+    rsyscall(SysCall::GetIpcTraceEvent(usize::MAX)).and_then(|result| {
+        if let Result::Scalar2(oldest, next) = result {
+            Ok((oldest, next))
+        } else if let Result::Error(e) = result {
+            Err(e)
+        } else {
+            Err(Error::InternalError)
+        }
+    })
+}
+
+/// Returns the IPC trace event with the given sequence number, or `None` if it
+/// has already been overwritten or hasn't happened yet.
+///
+/// # Errors
+///
+/// * **AccessDenied**: The caller isn't allowed to read the trace
+/// * **UnhandledSyscall**: The kernel was built without the `ipc-trace` feature
+pub fn ipc_trace_event(seq: usize) -> core::result::Result<Option<IpcTraceEvent>, Error> {
+    rsyscall(SysCall::GetIpcTraceEvent(seq)).and_then(|result| match result {
+        Result::IpcTraceEvent(event) => Ok(Some(event)),
+        Result::Scalar2(_, _) => Ok(None),
+        Result::Error(e) => Err(e),
+        _ => Err(Error::InternalError),
+    })
+}
+
 /* https://github.com/betrusted-io/xous-core/issues/90
 static EXCEPTION_HANDLER: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
 fn handle_exception(exception_type: usize, arg1: usize, arg2: usize) -> isize {